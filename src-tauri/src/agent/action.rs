@@ -1,11 +1,11 @@
 #![allow(dead_code)]
 
-use crate::capture::{capture_primary_screen, Screenshot};
+use crate::capture::{Screenshot, ScreenshotConfig};
 use crate::input::{
-    is_dangerous_key_combination, parse_key, parse_modifier, KeyboardController, Modifier,
-    MouseButton, MouseController, ScrollDirection,
+    is_dangerous_key_combination, parse_modifier, Modifier, MouseButton, ScrollDirection,
 };
 use crate::llm::provider::{LlmResponse, ToolUse};
+use super::desktop::Desktop;
 use super::retry::{RetryContext, RetryError};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -136,6 +136,15 @@ pub enum Action {
         timeout_ms: Option<u32>,
         description: String,
     },
+    /// A sub-goal of the task plan was achieved; `step` defaults to the current one
+    StepDone {
+        #[serde(default)]
+        step: Option<usize>,
+    },
+    /// The task plan no longer fits and should be made again
+    Replan {
+        reason: String,
+    },
 }
 
 fn default_button() -> String {
@@ -319,6 +328,14 @@ pub fn from_tool_use(tool_use: &ToolUse) -> Result<Action, ActionError> {
             let message = get_string(input, "message")?;
             Ok(Action::Error { message })
         }
+        "step_done" => {
+            let step = input.get("step").and_then(|v| v.as_u64()).map(|n| n as usize);
+            Ok(Action::StepDone { step })
+        }
+        "replan" => {
+            let reason = get_string(input, "reason")?;
+            Ok(Action::Replan { reason })
+        }
         _ => Err(ActionError::UnknownAction(tool_use.name.clone())),
     }
 }
@@ -469,15 +486,26 @@ fn extract_json(text: &str) -> Result<String, ActionError> {
     Ok(text[start..end].to_string())
 }
 
+/// Execute an action on the real desktop with the default click delay
 pub async fn execute_action(
     action: &Action,
     confirm_dangerous: bool,
+) -> Result<ActionResult, ActionError> {
+    execute_action_on(&Desktop::system(), action, confirm_dangerous).await
+}
+
+/// Execute an action on the given desktop with the default click delay
+pub async fn execute_action_on(
+    desktop: &Desktop,
+    action: &Action,
+    confirm_dangerous: bool,
 ) -> Result<ActionResult, ActionError> {
     execute_action_with_delay(
         action,
         confirm_dangerous,
         std::time::Duration::from_millis(50),
         None,
+        desktop,
     )
     .await
 }
@@ -487,6 +515,7 @@ pub async fn execute_action_with_delay(
     confirm_dangerous: bool,
     click_delay: std::time::Duration,
     bounds: Option<ScreenBounds>,
+    desktop: &Desktop,
 ) -> Result<ActionResult, ActionError> {
     match action {
         Action::Click { x, y, button } => {
@@ -501,10 +530,8 @@ pub async fn execute_action_with_delay(
             let button_str = button.clone();
             let delay = click_delay;
 
-            tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.click_at_with_delay(x, y, btn, delay)
-            })
+            let input = desktop.input.clone();
+            tokio::task::spawn_blocking(move || input.click_at(x, y, btn, delay))
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
 
@@ -527,11 +554,11 @@ pub async fn execute_action_with_delay(
             let (x, y) = if let Some(b) = bounds { b.transform(*x, *y) } else { (*x, *y) };
             let delay = click_delay;
 
+            let input = desktop.input.clone();
             tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.move_to(x, y)?;
+                input.move_to(x, y)?;
                 std::thread::sleep(delay);
-                mouse.double_click(MouseButton::Left)
+                input.double_click(MouseButton::Left)
             })
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
@@ -550,10 +577,8 @@ pub async fn execute_action_with_delay(
         Action::Move { x, y } => {
             let (x, y) = if let Some(b) = bounds { b.transform(*x, *y) } else { (*x, *y) };
 
-            tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.move_to(x, y)
-            })
+            let input = desktop.input.clone();
+            tokio::task::spawn_blocking(move || input.move_to(x, y))
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
 
@@ -573,10 +598,8 @@ pub async fn execute_action_with_delay(
             let text_preview = truncate_string(text, 50);
             let text_len = text.len();
 
-            tokio::task::spawn_blocking(move || {
-                let mut keyboard = KeyboardController::new()?;
-                keyboard.type_text(&text_clone)
-            })
+            let input = desktop.input.clone();
+            tokio::task::spawn_blocking(move || input.type_text(&text_clone))
             .await
             .map_err(|e| ActionError::KeyboardError(crate::input::KeyboardError::ActionError(e.to_string())))??;
 
@@ -611,16 +634,8 @@ pub async fn execute_action_with_delay(
             let key_clone = key.clone();
             let mods_clone = mods.clone();
 
-            tokio::task::spawn_blocking(move || {
-                let mut keyboard = KeyboardController::new()?;
-                if mods_clone.is_empty() {
-                    let k = parse_key(&key_clone)?;
-                    keyboard.key_press(k)?;
-                } else {
-                    keyboard.key_with_modifiers(&key_clone, &mods_clone)?;
-                }
-                Ok::<(), ActionError>(())
-            })
+            let input = desktop.input.clone();
+            tokio::task::spawn_blocking(move || input.key(&key_clone, &mods_clone))
             .await
             .map_err(|e| ActionError::KeyboardError(crate::input::KeyboardError::ActionError(e.to_string())))??;
 
@@ -657,11 +672,11 @@ pub async fn execute_action_with_delay(
             let direction_str = direction.clone();
             let delay = click_delay;
 
+            let input = desktop.input.clone();
             tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.move_to(x, y)?;
+                input.move_to(x, y)?;
                 std::thread::sleep(delay);
-                mouse.scroll(dir, amount)
+                input.scroll(dir, amount)
             })
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
@@ -713,10 +728,8 @@ pub async fn execute_action_with_delay(
             let (sx, sy) = if let Some(b) = bounds { b.transform(*start_x, *start_y) } else { (*start_x, *start_y) };
             let (ex, ey) = if let Some(b) = bounds { b.transform(*end_x, *end_y) } else { (*end_x, *end_y) };
 
-            tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.drag(sx, sy, ex, ey, btn, duration)
-            })
+            let input = desktop.input.clone();
+            tokio::task::spawn_blocking(move || input.drag(sx, sy, ex, ey, btn, duration))
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
 
@@ -737,10 +750,10 @@ pub async fn execute_action_with_delay(
         Action::TripleClick { x, y } => {
             let (x, y) = if let Some(b) = bounds { b.transform(*x, *y) } else { (*x, *y) };
 
+            let input = desktop.input.clone();
             tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.move_to(x, y)?;
-                mouse.triple_click(MouseButton::Left)
+                input.move_to(x, y)?;
+                input.triple_click(MouseButton::Left)
             })
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
@@ -759,9 +772,9 @@ pub async fn execute_action_with_delay(
         Action::RightClick { x, y } => {
             let (x, y) = if let Some(b) = bounds { b.transform(*x, *y) } else { (*x, *y) };
 
+            let input = desktop.input.clone();
             tokio::task::spawn_blocking(move || {
-                let mut mouse = MouseController::new()?;
-                mouse.click_at(x, y, MouseButton::Right)
+                input.click_at(x, y, MouseButton::Right, Duration::from_millis(50))
             })
             .await
             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))??;
@@ -803,6 +816,17 @@ pub async fn execute_action_with_delay(
             tool_use_id: None,
         }),
 
+        // The agent loop updates the plan; nothing happens on screen
+        Action::StepDone { .. } | Action::Replan { .. } => Ok(ActionResult {
+            success: true,
+            completed: false,
+            message: None,
+            retry_count: 0,
+            action_type: if matches!(action, Action::StepDone { .. }) { "step_done" } else { "replan" }.to_string(),
+            details: None,
+            tool_use_id: None,
+        }),
+
         Action::Error { message } => Ok(ActionResult {
             success: false,
             completed: true,
@@ -858,7 +882,7 @@ pub async fn execute_action_with_delay(
                     });
                 }
 
-                let result = Box::pin(execute_action(sub_action, confirm_dangerous)).await?;
+                let result = Box::pin(execute_action_on(desktop, sub_action, confirm_dangerous)).await?;
 
                 if !result.success {
                     return Ok(ActionResult {
@@ -919,7 +943,8 @@ pub async fn execute_action_with_delay(
             log::info!("Waiting for: {} (timeout: {}ms)", description, timeout);
 
             // Capture baseline screenshot for change detection
-            let baseline = tokio::task::spawn_blocking(capture_primary_screen)
+            let screen = desktop.screen.clone();
+            let baseline = tokio::task::spawn_blocking(move || screen.capture(&ScreenshotConfig::default()))
                 .await
                 .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))?;
 
@@ -932,7 +957,8 @@ pub async fn execute_action_with_delay(
                     while tokio::time::Instant::now() < deadline {
                         tokio::time::sleep(poll_interval).await;
 
-                        let current = tokio::task::spawn_blocking(capture_primary_screen)
+                        let screen = desktop.screen.clone();
+                        let current = tokio::task::spawn_blocking(move || screen.capture(&ScreenshotConfig::default()))
                             .await
                             .map_err(|e| ActionError::MouseError(crate::input::MouseError::ActionError(e.to_string())))?;
 
@@ -1011,9 +1037,18 @@ impl Action {
                 | Action::Type { .. }
                 | Action::Key { .. }
                 | Action::Scroll { .. }
+                | Action::Drag { .. }
         )
     }
 
+    /// Returns true if running this action a second time does no harm when
+    /// the first run seemed to have no effect. Keystrokes, scrolls and drags
+    /// may have worked without changing the screen (or not yet), so repeating
+    /// them could type text twice or submit a form again.
+    pub fn is_safe_to_repeat(&self) -> bool {
+        matches!(self, Action::Click { .. } | Action::Move { .. })
+    }
+
     /// Check if this action can be reversed
     pub fn is_reversible(&self) -> bool {
         match self {
@@ -1046,6 +1081,9 @@ impl Action {
             Action::Wait { .. } => false,
             Action::WaitForElement { .. } => false,
             Action::Batch { .. } => false,
+            // Plan bookkeeping has no effect on screen
+            Action::StepDone { .. } => false,
+            Action::Replan { .. } => false,
         }
     }
 
@@ -1173,17 +1211,27 @@ impl Action {
             Action::Error { message } => {
                 format!("Error: {}", truncate_string(message, 50))
             }
+            Action::StepDone { step: Some(step) } => format!("Plan step {} done", step),
+            Action::StepDone { step: None } => "Plan step done".to_string(),
+            Action::Replan { reason } => {
+                format!("Replan: {}", truncate_string(reason, 50))
+            }
         }
     }
 }
 
 /// Execute an action with retry logic.
-/// Automatically retries failed actions or actions that don't produce
-/// visible screen changes.
+/// Automatically retries failed actions, and clicks that don't produce
+/// visible screen changes. When the screen still has not changed (after the
+/// retries, or at once for actions that aren't safe to repeat) the result
+/// message says so, letting the LLM correct course.
 pub async fn execute_action_with_retry(
     action: &Action,
     confirm_dangerous: bool,
+    click_delay: Duration,
+    screen_bounds: Option<ScreenBounds>,
     retry_ctx: &mut RetryContext,
+    desktop: &Desktop,
 ) -> Result<ActionResult, ActionError> {
     // Reset retry context for this action
     retry_ctx.reset();
@@ -1191,11 +1239,11 @@ pub async fn execute_action_with_retry(
     loop {
         // Capture before state for actions that should change the screen
         if action.should_verify_effect() {
-            retry_ctx.capture_before()?;
+            retry_ctx.capture_before().await?;
         }
 
         // Execute the action
-        let mut result = execute_action(action, confirm_dangerous).await?;
+        let mut result = execute_action_with_delay(action, confirm_dangerous, click_delay, screen_bounds, desktop).await?;
 
         // If action failed and we can retry
         if !result.success {
@@ -1207,6 +1255,7 @@ pub async fn execute_action_with_retry(
                     retry_ctx.max_retries,
                     action
                 );
+                retry_ctx.notify_retry("action failed").await;
                 tokio::time::sleep(retry_ctx.retry_delay).await;
                 continue;
             }
@@ -1219,7 +1268,18 @@ pub async fn execute_action_with_retry(
             // Wait a bit for UI to update
             tokio::time::sleep(Duration::from_millis(200)).await;
 
-            if !retry_ctx.screen_changed()? {
+            if !retry_ctx.screen_changed().await? {
+                if !action.is_safe_to_repeat() {
+                    log::warn!("Action completed but no screen change detected; not repeating it: {:?}", action);
+                    let note = "Screen unchanged after this action. It was not repeated; \
+                                check whether it took effect before doing it again";
+                    result.message = Some(match result.message.take() {
+                        Some(message) => format!("{}. {}", message, note),
+                        None => note.to_string(),
+                    });
+                    result.retry_count = retry_ctx.attempt;
+                    return Ok(result);
+                }
                 if retry_ctx.should_retry() {
                     retry_ctx.increment();
                     log::warn!(
@@ -1228,10 +1288,19 @@ pub async fn execute_action_with_retry(
                         retry_ctx.max_retries,
                         action
                     );
+                    retry_ctx.notify_retry("no visible effect").await;
                     tokio::time::sleep(retry_ctx.retry_delay).await;
                     continue;
                 }
                 log::warn!("Action completed but no screen change detected after {} retries", retry_ctx.attempt);
+                let note = format!(
+                    "No screen change detected after {} retries; check that the target is correct",
+                    retry_ctx.attempt
+                );
+                result.message = Some(match result.message.take() {
+                    Some(message) => format!("{}. {}", message, note),
+                    None => note,
+                });
             }
         }
 
@@ -1655,6 +1724,7 @@ mod tests {
                 id: "tool_1".to_string(),
                 name: "click".to_string(),
                 input: json!({"x": 100, "y": 200}),
+                signature: None,
             },
             reasoning: None,
         };
@@ -1686,6 +1756,7 @@ mod tests {
                 id: "tool_2".to_string(),
                 name: "complete".to_string(),
                 input: json!({"message": "done"}),
+                signature: None,
             },
             reasoning: None,
         };
@@ -1702,6 +1773,7 @@ mod tests {
             id: "t1".to_string(),
             name: "click".to_string(),
             input: json!({"x": 10, "y": 20, "button": "middle"}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         match action {
@@ -1720,6 +1792,7 @@ mod tests {
             id: "t2".to_string(),
             name: "double_click".to_string(),
             input: json!({"x": 5, "y": 10}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         assert!(matches!(action, Action::DoubleClick { x: 5, y: 10 }));
@@ -1731,6 +1804,7 @@ mod tests {
             id: "t3".to_string(),
             name: "move".to_string(),
             input: json!({"x": 300, "y": 400}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         assert!(matches!(action, Action::Move { x: 300, y: 400 }));
@@ -1742,6 +1816,7 @@ mod tests {
             id: "t4".to_string(),
             name: "type".to_string(),
             input: json!({"text": "test input"}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         assert!(matches!(action, Action::Type { ref text } if text == "test input"));
@@ -1753,6 +1828,7 @@ mod tests {
             id: "t5".to_string(),
             name: "key".to_string(),
             input: json!({"key": "a", "modifiers": ["ctrl", "shift"]}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         match action {
@@ -1770,6 +1846,7 @@ mod tests {
             id: "t6".to_string(),
             name: "scroll".to_string(),
             input: json!({"x": 100, "y": 200, "direction": "up", "amount": 7}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         match action {
@@ -1789,6 +1866,7 @@ mod tests {
             id: "t7".to_string(),
             name: "scroll".to_string(),
             input: json!({"x": 0, "y": 0, "direction": "down"}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         match action {
@@ -1803,6 +1881,7 @@ mod tests {
             id: "t8".to_string(),
             name: "complete".to_string(),
             input: json!({"message": "all done"}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         assert!(matches!(action, Action::Complete { ref message } if message == "all done"));
@@ -1814,6 +1893,7 @@ mod tests {
             id: "t9".to_string(),
             name: "error".to_string(),
             input: json!({"message": "something went wrong"}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         assert!(matches!(action, Action::Error { ref message } if message == "something went wrong"));
@@ -1825,6 +1905,7 @@ mod tests {
             id: "t10".to_string(),
             name: "teleport".to_string(),
             input: json!({"x": 1, "y": 2}),
+            signature: None,
         };
         let result = from_tool_use(&tu);
         assert!(result.is_err());
@@ -1837,6 +1918,7 @@ mod tests {
             id: "t11".to_string(),
             name: "click".to_string(),
             input: json!({"x": 100}), // missing y
+            signature: None,
         };
         let result = from_tool_use(&tu);
         assert!(result.is_err());
@@ -1848,6 +1930,7 @@ mod tests {
             id: "t12".to_string(),
             name: "click".to_string(),
             input: json!({"x": 50, "y": 60}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         match action {
@@ -1862,6 +1945,7 @@ mod tests {
             id: "t13".to_string(),
             name: "key".to_string(),
             input: json!({"key": "enter"}),
+            signature: None,
         };
         let action = from_tool_use(&tu).unwrap();
         match action {
@@ -1879,6 +1963,7 @@ mod tests {
         assert!(Action::Key { key: "a".into(), modifiers: vec![] }.should_verify_effect());
         assert!(Action::Scroll { x: 0, y: 0, direction: "up".into(), amount: 1 }.should_verify_effect());
         assert!(Action::DoubleClick { x: 0, y: 0 }.should_verify_effect());
+        assert!(Action::Drag { start_x: 0, start_y: 0, end_x: 1, end_y: 1, button: "left".into(), duration_ms: 100 }.should_verify_effect());
         assert!(!Action::Complete { message: "done".into() }.should_verify_effect());
         assert!(!Action::Error { message: "err".into() }.should_verify_effect());
         assert!(!Action::Wait { duration_ms: 100 }.should_verify_effect());
@@ -2033,6 +2118,7 @@ mod tests {
                 id: "id1".to_string(),
                 name: "click".to_string(),
                 input: json!({"x": 1, "y": 2}),
+                signature: None,
             },
             reasoning: None,
         };
@@ -2383,4 +2469,132 @@ mod tests {
         assert_eq!(parsed["status"], "error");
         assert_eq!(parsed["action"], "click");
     }
+
+    // ── execution against a simulated desktop ────────────────────────────
+
+    use super::super::fake_desktop::{FakeDesktop, InputEvent};
+    use crate::input::{InputDriver, Modifier};
+    use std::sync::Arc;
+
+    async fn run_on(fake: &FakeDesktop, action: Action) -> Result<ActionResult, ActionError> {
+        execute_action_on(&fake.desktop(), &action, true).await
+    }
+
+    #[tokio::test]
+    async fn test_execute_click_on_fake_desktop() {
+        let fake = FakeDesktop::new(100, 100);
+        let result = run_on(&fake, Action::Click { x: 10, y: 20, button: "left".into() }).await.unwrap();
+        assert!(result.success);
+        assert_eq!(fake.clicks(), vec![(10, 20)]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_applies_screen_bounds() {
+        let fake = FakeDesktop::new(200, 100);
+        let bounds = ScreenBounds::new(100, 50, 200, 100);
+        execute_action_with_delay(
+            &Action::RightClick { x: 10, y: 10 },
+            false,
+            Duration::from_millis(1),
+            Some(bounds),
+            &fake.desktop(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fake.events().last(),
+            Some(&InputEvent::Click { x: 20, y: 20, button: MouseButton::Right })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_type_and_key_on_fake_desktop() {
+        let fake = FakeDesktop::new(50, 50);
+        run_on(&fake, Action::Type { text: "hello".into() }).await.unwrap();
+        run_on(&fake, Action::Key { key: "s".into(), modifiers: vec!["ctrl".into()] }).await.unwrap();
+        assert_eq!(fake.typed_text(), "hello");
+        assert_eq!(
+            fake.events().last(),
+            Some(&InputEvent::Key { key: "s".into(), modifiers: vec![Modifier::Ctrl] })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dangerous_key_is_not_sent_to_desktop() {
+        let fake = FakeDesktop::new(50, 50);
+        let err = run_on(&fake, Action::Key { key: "q".into(), modifiers: vec!["cmd".into()] }).await;
+        assert!(matches!(err, Err(ActionError::RequiresConfirmation(_))));
+        assert!(fake.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batch_uses_injected_desktop() {
+        let fake = FakeDesktop::new(50, 50);
+        let batch = Action::Batch {
+            actions: vec![
+                Action::Click { x: 1, y: 2, button: "left".into() },
+                Action::Type { text: "abc".into() },
+            ],
+        };
+        let result = run_on(&fake, batch).await.unwrap();
+        assert!(result.success);
+        assert_eq!(fake.clicks(), vec![(1, 2)]);
+        assert_eq!(fake.typed_text(), "abc");
+    }
+
+    #[tokio::test]
+    async fn test_retry_when_screen_does_not_change() {
+        let fake = FakeDesktop::new(20, 20);
+        fake.freeze();
+        let mut ctx = RetryContext::new(2, 0, true).with_screen_source(Arc::new(fake.clone()));
+        let action = Action::Click { x: 5, y: 5, button: "left".into() };
+
+        let result = execute_action_with_retry(&action, false, Duration::from_millis(1), None, &mut ctx, &fake.desktop()).await.unwrap();
+        assert_eq!(result.retry_count, 2);
+        assert_eq!(fake.clicks().len(), 3);
+        assert!(result.message.unwrap().contains("No screen change detected"));
+    }
+
+    #[tokio::test]
+    async fn test_keystrokes_are_not_repeated_when_screen_does_not_change() {
+        let fake = FakeDesktop::new(20, 20);
+        fake.freeze();
+        let mut ctx = RetryContext::new(2, 0, true).with_screen_source(Arc::new(fake.clone()));
+
+        let action = Action::Type { text: "hello".into() };
+        let result = execute_action_with_retry(&action, false, Duration::from_millis(1), None, &mut ctx, &fake.desktop()).await.unwrap();
+        assert_eq!(result.retry_count, 0);
+        assert_eq!(fake.typed_text(), "hello");
+        assert!(result.message.unwrap().contains("Screen unchanged"));
+
+        let action = Action::Key { key: "enter".into(), modifiers: vec![] };
+        let result = execute_action_with_retry(&action, false, Duration::from_millis(1), None, &mut ctx, &fake.desktop()).await.unwrap();
+        assert_eq!(result.retry_count, 0);
+        assert!(result.message.unwrap().contains("not repeated"));
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_when_screen_changes() {
+        let fake = FakeDesktop::new(20, 20);
+        let mut ctx = RetryContext::new(2, 0, true).with_screen_source(Arc::new(fake.clone()));
+        let action = Action::Click { x: 5, y: 5, button: "left".into() };
+
+        let result = execute_action_with_retry(&action, false, Duration::from_millis(1), None, &mut ctx, &fake.desktop()).await.unwrap();
+        assert_eq!(result.retry_count, 0);
+        assert_eq!(fake.clicks().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_element_sees_change_on_fake_screen() {
+        let fake = FakeDesktop::new(20, 20);
+        let other = fake.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let _ = other.type_text("x");
+        });
+
+        let action = Action::WaitForElement { timeout_ms: Some(3000), description: "dialog".into() };
+        let result = run_on(&fake, action).await.unwrap();
+        assert!(result.message.unwrap().starts_with("Screen changed"));
+    }
 }
@@ -0,0 +1,163 @@
+//! Limits on how much a task, or a whole day of tasks, may spend.
+
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Which limit stopped the task
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BudgetExceeded {
+    Tokens { used: u64, limit: u64 },
+    TaskCost { spent_usd: f64, limit_usd: f64 },
+    DailyCost { spent_usd: f64, limit_usd: f64 },
+}
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetExceeded::Tokens { used, limit } => {
+                write!(f, "Token budget exceeded ({} > {})", used, limit)
+            }
+            BudgetExceeded::TaskCost { spent_usd, limit_usd } => {
+                write!(f, "Task cost limit reached (${:.4} of ${:.2})", spent_usd, limit_usd)
+            }
+            BudgetExceeded::DailyCost { spent_usd, limit_usd } => {
+                write!(f, "Daily cost limit reached (${:.4} of ${:.2})", spent_usd, limit_usd)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpendDay {
+    pub date: NaiveDate,
+    pub spent_usd: f64,
+}
+
+impl SpendDay {
+    fn today() -> Self {
+        Self {
+            date: Local::now().date_naive(),
+            spent_usd: 0.0,
+        }
+    }
+}
+
+struct SpendState {
+    path: Option<PathBuf>,
+    day: SpendDay,
+}
+
+/// Dollars spent on LLM calls today, across every task.
+///
+/// Kept in a small file once one is set, so the daily cap still holds after
+/// a restart. The total starts over at local midnight.
+#[derive(Clone)]
+pub struct DailySpend {
+    state: Arc<parking_lot::Mutex<SpendState>>,
+}
+
+impl DailySpend {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(parking_lot::Mutex::new(SpendState {
+                path: None,
+                day: SpendDay::today(),
+            })),
+        }
+    }
+
+    /// Where the total is kept: `spend.json` beside the given config file
+    pub fn path_beside_config(config_path: &Path) -> PathBuf {
+        let dir = config_path.parent().unwrap_or_else(|| Path::new("."));
+        dir.join("spend.json")
+    }
+
+    /// Keep the total in `path` from now on, picking up what was already
+    /// recorded there today
+    pub fn set_file(&self, path: Option<PathBuf>) {
+        let mut state = self.state.lock();
+        let stored = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|content| serde_json::from_str::<SpendDay>(&content).ok());
+        if let Some(stored) = stored.filter(|day| day.date == state.day.date) {
+            state.day.spent_usd = state.day.spent_usd.max(stored.spent_usd);
+        }
+        state.path = path;
+    }
+
+    pub fn add(&self, usd: f64) {
+        self.add_on(Local::now().date_naive(), usd);
+    }
+
+    pub fn today(&self) -> SpendDay {
+        self.on(Local::now().date_naive())
+    }
+
+    fn add_on(&self, date: NaiveDate, usd: f64) {
+        if usd <= 0.0 {
+            return;
+        }
+        let mut state = self.state.lock();
+        if state.day.date != date {
+            state.day = SpendDay { date, spent_usd: 0.0 };
+        }
+        state.day.spent_usd += usd;
+
+        if let Some(path) = &state.path {
+            let saved = serde_json::to_string(&state.day)
+                .map_err(|e| e.to_string())
+                .and_then(|content| fs::write(path, content).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                log::warn!("Failed to save daily spend: {}", e);
+            }
+        }
+    }
+
+    fn on(&self, date: NaiveDate) -> SpendDay {
+        let state = self.state.lock();
+        if state.day.date == date {
+            state.day
+        } else {
+            SpendDay { date, spent_usd: 0.0 }
+        }
+    }
+}
+
+impl Default for DailySpend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_spend_persists_and_rolls_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spend.json");
+        let today = Local::now().date_naive();
+
+        let spend = DailySpend::new();
+        spend.set_file(Some(path.clone()));
+        spend.add(0.25);
+        spend.add(0.5);
+        assert!((spend.today().spent_usd - 0.75).abs() < 1e-9);
+
+        let restarted = DailySpend::new();
+        restarted.set_file(Some(path));
+        assert!((restarted.today().spent_usd - 0.75).abs() < 1e-9);
+
+        let tomorrow = today.succ_opt().unwrap();
+        assert_eq!(restarted.on(tomorrow).spent_usd, 0.0);
+        restarted.add_on(tomorrow, 0.1);
+        assert!((restarted.on(tomorrow).spent_usd - 0.1).abs() < 1e-9);
+        assert_eq!(restarted.on(today).spent_usd, 0.0);
+    }
+}
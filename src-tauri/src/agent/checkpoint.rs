@@ -0,0 +1,172 @@
+//! Crash-safe snapshot of a running task.
+//!
+//! The agent loop writes a checkpoint at the start of every iteration, so the
+//! file always reflects the last fully finished step. The release build aborts
+//! on panic, so nothing can be flushed on the way down; whatever was written
+//! last is what `resume_task` continues from.
+
+use super::conversation::ConversationHistory;
+use super::history::ActionRecord;
+use super::state::ExecutionMode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("Failed to access checkpoint: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to parse checkpoint: {0}")]
+    ParseError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskCheckpoint {
+    /// Session the task was recorded under, so a resume keeps appending to it
+    pub session_id: Option<String>,
+    pub instruction: String,
+    #[serde(default)]
+    pub mode: ExecutionMode,
+    /// Number of iterations already completed
+    pub iteration: u32,
+    pub max_iterations: u32,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// What the task had cost when it was saved, in USD
+    #[serde(default)]
+    pub cost_usd: f64,
+    /// Conversation so far, without screenshots
+    pub conversation: ConversationHistory,
+    /// Undo history, oldest first
+    #[serde(default)]
+    pub actions: Vec<ActionRecord>,
+    pub saved_at: DateTime<Utc>,
+}
+
+/// What the frontend needs to offer resuming an interrupted task
+#[derive(Debug, Clone, Serialize)]
+pub struct InterruptedTask {
+    pub instruction: String,
+    pub iteration: u32,
+    pub max_iterations: u32,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl From<&TaskCheckpoint> for InterruptedTask {
+    fn from(checkpoint: &TaskCheckpoint) -> Self {
+        Self {
+            instruction: checkpoint.instruction.clone(),
+            iteration: checkpoint.iteration,
+            max_iterations: checkpoint.max_iterations,
+            saved_at: checkpoint.saved_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Keep the checkpoint as `checkpoint.json` beside the given config file
+    pub fn beside_config(config_path: &Path) -> Self {
+        let dir = config_path.parent().unwrap_or_else(|| Path::new("."));
+        Self::new(dir.join("checkpoint.json"))
+    }
+
+    #[cfg(test)]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn save(&self, checkpoint: &TaskCheckpoint) -> Result<(), CheckpointError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string(checkpoint)?;
+
+        // Replace atomically so an abort mid-write keeps the previous checkpoint
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn load(&self) -> Result<Option<TaskCheckpoint>, CheckpointError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.path)?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    pub fn clear(&self) -> Result<(), CheckpointError> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::action::Action;
+    use std::sync::Arc;
+
+    fn checkpoint() -> TaskCheckpoint {
+        let mut conversation = ConversationHistory::new();
+        conversation.set_original_instruction("open notes".to_string());
+        conversation.add_user_message("open notes", Some(Arc::new("c2NyZWVu".to_string())), Some(10), Some(10));
+        conversation.add_assistant_message("{\"action\":\"click\",\"x\":1,\"y\":2}");
+        conversation.add_tool_result(true, None, None);
+
+        TaskCheckpoint {
+            session_id: Some("abc".to_string()),
+            instruction: "open notes".to_string(),
+            mode: ExecutionMode::Normal,
+            iteration: 1,
+            max_iterations: 20,
+            input_tokens: 100,
+            output_tokens: 20,
+            cost_usd: 0.01,
+            conversation: conversation.without_screenshots(),
+            actions: vec![ActionRecord::new(Action::Type { text: "hi".to_string() }, true)],
+            saved_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_save_load_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CheckpointStore::new(dir.path().join("checkpoint.json"));
+        assert!(store.load().unwrap().is_none());
+
+        store.save(&checkpoint()).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        assert_eq!(loaded.iteration, 1);
+        assert_eq!(loaded.conversation.len(), 3);
+        assert_eq!(loaded.conversation.original_instruction(), Some("open notes"));
+        assert_eq!(loaded.actions.len(), 1);
+
+        store.clear().unwrap();
+        assert!(store.load().unwrap().is_none());
+        store.clear().unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_omits_screenshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CheckpointStore::new(dir.path().join("checkpoint.json"));
+        store.save(&checkpoint()).unwrap();
+        let raw = fs::read_to_string(store.path()).unwrap();
+        assert!(!raw.contains("c2NyZWVu"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Keeps long tasks from losing track of what they already did: messages that
+//! fall out of the conversation window are folded into a "progress so far" note.
+
+use super::action::{from_tool_use, parse_action};
+use super::conversation::{ConversationHistory, Message};
+use crate::llm::{LlmError, LlmProvider, LlmResponse, TokenMetrics, ToolUse};
+
+/// Most lines a digest keeps; older ones are counted rather than listed
+const MAX_DIGEST_LINES: usize = 30;
+/// Longest result or error message carried into a digest line
+const MAX_DETAIL_CHARS: usize = 100;
+
+fn shorten(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// What an assistant message asked for, in a few words
+fn describe_response(content: &str, tool_use: Option<&ToolUse>) -> String {
+    // Older histories stored tool calls as their JSON on the last line, after any reasoning
+    let tool_use = tool_use.cloned().or_else(|| {
+        content
+            .lines()
+            .last()
+            .and_then(|line| serde_json::from_str::<ToolUse>(line).ok())
+    });
+    let action = match tool_use {
+        Some(tool_use) => from_tool_use(&tool_use).ok(),
+        None => parse_action(content).ok().map(|parsed| parsed.action),
+    };
+    match action {
+        Some(action) => action.describe(),
+        None => format!("Unparseable response: {}", shorten(content, 60)),
+    }
+}
+
+/// One line per step in `evicted`: the action, then how it went
+fn step_lines(evicted: &[Message]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pending: Option<String> = None;
+
+    for message in evicted {
+        match message {
+            Message::User { .. } => {}
+            Message::Assistant { content, tool_use } => {
+                if let Some(step) = pending.replace(describe_response(content, tool_use.as_ref())) {
+                    lines.push(format!("- {}", step));
+                }
+            }
+            Message::ToolResult { success, message, error, .. } => {
+                let step = pending.take().unwrap_or_else(|| "Previous action".to_string());
+                let outcome = match (success, message, error) {
+                    (true, Some(message), _) if !message.trim().is_empty() => {
+                        format!("ok ({})", shorten(message, MAX_DETAIL_CHARS))
+                    }
+                    (true, _, _) => "ok".to_string(),
+                    (false, _, error) => format!(
+                        "failed ({})",
+                        shorten(error.as_deref().unwrap_or("unknown error"), MAX_DETAIL_CHARS)
+                    ),
+                };
+                lines.push(format!("- {}: {}", step, outcome));
+            }
+        }
+    }
+    if let Some(step) = pending {
+        lines.push(format!("- {}", step));
+    }
+    lines
+}
+
+/// Deterministic note: the previous one with a line added for every evicted
+/// step, trimmed to the most recent `MAX_DIGEST_LINES`
+pub fn digest(previous: Option<&str>, evicted: &[Message]) -> String {
+    let mut lines: Vec<String> = previous
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('('))
+        .map(str::to_string)
+        .collect();
+    let earlier = previous
+        .and_then(|note| note.lines().next())
+        .and_then(|line| line.strip_prefix('('))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|count| count.parse::<usize>().ok())
+        .unwrap_or(0);
+    lines.extend(step_lines(evicted));
+
+    let dropped = lines.len().saturating_sub(MAX_DIGEST_LINES);
+    let kept = lines.split_off(dropped);
+    if earlier + dropped > 0 {
+        format!("({} earlier steps not listed)\n{}", earlier + dropped, kept.join("\n"))
+    } else {
+        kept.join("\n")
+    }
+}
+
+fn summary_request(previous: Option<&str>, evicted: &[Message]) -> String {
+    format!(
+        "The oldest steps of this task are about to be dropped from the conversation. Write a short note \
+         of the progress so far, so the work can continue without them: what has been done, what failed, \
+         and anything found out that is still needed (names, values, where things are on screen).\n\n\
+         Note so far:\n{}\n\nSteps being dropped:\n{}\n\n\
+         Reply with the updated note only, at most ten short lines. Do not call a tool or return an action.",
+        previous.unwrap_or("(none yet)"),
+        step_lines(evicted).join("\n")
+    )
+}
+
+/// Ask `provider` to fold `evicted` into the previous note
+pub async fn summarize(
+    provider: &dyn LlmProvider,
+    instruction: &str,
+    previous: Option<&str>,
+    evicted: &[Message],
+    screen_width: u32,
+    screen_height: u32,
+) -> Result<(String, TokenMetrics), LlmError> {
+    let mut conversation = ConversationHistory::new();
+    conversation.set_original_instruction(instruction.to_string());
+    conversation.add_user_message(&summary_request(previous, evicted), None, None, None);
+
+    let (response, metrics) = provider
+        .send_with_history(&conversation, screen_width, screen_height, Box::new(|_| {}))
+        .await?;
+    let note = match &response {
+        LlmResponse::Text(text) => text.trim(),
+        LlmResponse::ToolUse { reasoning, .. } => reasoning.as_deref().unwrap_or_default().trim(),
+    };
+    if note.is_empty() {
+        return Err(LlmError::ParseError("Summary reply was empty".to_string()));
+    }
+    Ok((note.to_string(), metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(content: &str, success: bool, detail: &str) -> Vec<Message> {
+        vec![
+            Message::User {
+                instruction: "Here is the current screenshot.".to_string(),
+                screenshot_base64: None,
+                screen_width: None,
+                screen_height: None,
+            },
+            Message::Assistant { content: content.to_string(), tool_use: None },
+            Message::ToolResult {
+                success,
+                message: success.then(|| detail.to_string()),
+                error: (!success).then(|| detail.to_string()),
+                tool_use_id: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_digest_lists_actions_and_results() {
+        let mut evicted = step(
+            "Opening the menu\n{\"id\":\"1\",\"name\":\"click\",\"input\":{\"x\":10,\"y\":20}}",
+            true,
+            "",
+        );
+        evicted.extend(step(r#"{"action": "type", "text": "hello"}"#, false, "Window lost focus"));
+        evicted.push(Message::Assistant { content: "not an action".to_string(), tool_use: None });
+
+        let note = digest(Some("- Launched Notes: ok"), &evicted);
+        let lines: Vec<&str> = note.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "- Launched Notes: ok");
+        assert!(lines[1].starts_with("- Click") && lines[1].ends_with(": ok"));
+        assert!(lines[2].ends_with(": failed (Window lost focus)"));
+        assert_eq!(lines[3], "- Unparseable response: not an action");
+    }
+
+    #[test]
+    fn test_digest_stays_bounded() {
+        let mut note = None;
+        for _ in 0..4 {
+            let evicted: Vec<Message> = (0..10)
+                .flat_map(|i| step(&format!(r#"{{"action": "scroll", "x": {}, "y": 0, "direction": "down", "amount": 3}}"#, i), true, ""))
+                .collect();
+            note = Some(digest(note.as_deref(), &evicted));
+        }
+        let note = note.unwrap();
+        assert_eq!(note.lines().count(), MAX_DIGEST_LINES + 1);
+        assert!(note.starts_with("(10 earlier steps not listed)"));
+    }
+}
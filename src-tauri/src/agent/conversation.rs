@@ -1,5 +1,8 @@
 #![allow(dead_code)]
 
+use super::action::ActionResult;
+use super::plan::TaskPlan;
+use crate::llm::{LlmResponse, ToolUse};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::sync::Arc;
@@ -22,8 +25,12 @@ pub enum Message {
         #[serde(skip_serializing_if = "Option::is_none")]
         screen_height: Option<u32>,
     },
-    /// Assistant response with the action JSON
-    Assistant { content: String },
+    /// Assistant response: the action JSON, or the reasoning that came with a tool call
+    Assistant {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tool_use: Option<ToolUse>,
+    },
     /// Result of executing a tool/action
     ToolResult {
         success: bool,
@@ -31,6 +38,9 @@ pub enum Message {
         message: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         error: Option<String>,
+        /// The tool call this answers, when the action came from one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tool_use_id: Option<String>,
     },
 }
 
@@ -41,6 +51,15 @@ pub struct ConversationHistory {
     /// The original user instruction for this task
     #[serde(skip_serializing_if = "Option::is_none")]
     original_instruction: Option<String>,
+    /// Running summary of the messages that no longer fit in the window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    progress_note: Option<String>,
+    /// Messages pushed out of the window and not yet folded into the note
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    evicted: Vec<Message>,
+    /// Sub-goals from the planner, when planning is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plan: Option<TaskPlan>,
     /// Current iteration number (set by the agent loop for progress context)
     #[serde(skip)]
     pub iteration: Option<u32>,
@@ -55,6 +74,9 @@ impl ConversationHistory {
         Self {
             messages: VecDeque::new(),
             original_instruction: None,
+            progress_note: None,
+            evicted: Vec::new(),
+            plan: None,
             iteration: None,
             max_iterations: None,
         }
@@ -70,6 +92,41 @@ impl ConversationHistory {
         self.original_instruction.as_deref()
     }
 
+    /// Sets the summary of earlier progress shown to the model.
+    pub fn set_progress_note(&mut self, note: Option<String>) {
+        self.progress_note = note;
+    }
+
+    /// Gets the summary of earlier progress if anything has been evicted.
+    pub fn progress_note(&self) -> Option<&str> {
+        self.progress_note.as_deref()
+    }
+
+    /// Returns true if messages were evicted since the last `take_evicted`.
+    pub fn has_evicted(&self) -> bool {
+        !self.evicted.is_empty()
+    }
+
+    /// Takes the messages evicted since the last call, oldest first.
+    pub fn take_evicted(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Sets the task plan shown to the executor.
+    pub fn set_plan(&mut self, plan: Option<TaskPlan>) {
+        self.plan = plan;
+    }
+
+    /// Gets the task plan if one was made.
+    pub fn plan(&self) -> Option<&TaskPlan> {
+        self.plan.as_ref()
+    }
+
+    /// Gets the task plan for updating.
+    pub fn plan_mut(&mut self) -> Option<&mut TaskPlan> {
+        self.plan.as_mut()
+    }
+
     /// Adds a message to the conversation history.
     /// Automatically truncates if history exceeds MAX_HISTORY_LENGTH.
     pub fn add_message(&mut self, message: Message) {
@@ -97,23 +154,70 @@ impl ConversationHistory {
     pub fn add_assistant_message(&mut self, content: &str) {
         self.add_message(Message::Assistant {
             content: content.to_string(),
+            tool_use: None,
         });
     }
 
-    /// Adds a tool result message.
+    /// Adds a provider response, keeping a tool call as a tool call
+    pub fn add_assistant_response(&mut self, response: &LlmResponse) {
+        let message = match response {
+            LlmResponse::ToolUse { tool_use, reasoning } => Message::Assistant {
+                content: reasoning.clone().unwrap_or_default(),
+                tool_use: Some(tool_use.clone()),
+            },
+            LlmResponse::Text(text) => Message::Assistant {
+                content: text.clone(),
+                tool_use: None,
+            },
+        };
+        self.add_message(message);
+    }
+
+    /// Adds a tool result message. It answers the tool call in the message
+    /// before it, if that message made one.
     pub fn add_tool_result(
         &mut self,
         success: bool,
         message: Option<String>,
         error: Option<String>,
     ) {
+        let tool_use_id = self.pending_tool_use_id().map(str::to_string);
         self.add_message(Message::ToolResult {
             success,
             message,
             error,
+            tool_use_id,
         });
     }
 
+    /// Adds the result of an executed action, for the tool call it names
+    /// (see `ActionResult::with_tool_use_id`) or else the pending one
+    pub fn add_action_result(&mut self, result: &ActionResult) {
+        let tool_use_id = result
+            .tool_use_id
+            .clone()
+            .or_else(|| self.pending_tool_use_id().map(str::to_string));
+        let (message, error) = if result.success {
+            (result.message.clone(), None)
+        } else {
+            (None, result.message.clone())
+        };
+        self.add_message(Message::ToolResult {
+            success: result.success,
+            message,
+            error,
+            tool_use_id,
+        });
+    }
+
+    /// The id of the tool call in the last message, while it has no result
+    pub fn pending_tool_use_id(&self) -> Option<&str> {
+        match self.messages.back() {
+            Some(Message::Assistant { tool_use: Some(tool_use), .. }) => Some(tool_use.id.as_str()),
+            _ => None,
+        }
+    }
+
     /// Returns all messages as a contiguous slice.
     pub fn get_messages(&mut self) -> &[Message] {
         self.messages.make_contiguous();
@@ -125,6 +229,17 @@ impl ConversationHistory {
         self.messages.iter()
     }
 
+    /// Returns a copy of the history with screenshots dropped from user messages.
+    pub fn without_screenshots(&self) -> Self {
+        let mut copy = self.clone();
+        for message in copy.messages.iter_mut() {
+            if let Message::User { screenshot_base64, .. } = message {
+                *screenshot_base64 = None;
+            }
+        }
+        copy
+    }
+
     /// Returns the number of messages in history.
     pub fn len(&self) -> usize {
         self.messages.len()
@@ -139,18 +254,27 @@ impl ConversationHistory {
     pub fn clear(&mut self) {
         self.messages.clear();
         self.original_instruction = None;
+        self.progress_note = None;
+        self.evicted.clear();
+        self.plan = None;
         self.iteration = None;
         self.max_iterations = None;
     }
 
     /// Truncates history to MAX_HISTORY_LENGTH, keeping most recent messages.
     /// Always preserves the first message (original instruction) if possible.
+    /// Removed messages are kept, without screenshots, until `take_evicted`.
     fn truncate_to_max(&mut self) {
         if self.messages.len() > MAX_HISTORY_LENGTH {
             // Keep first message (original context) and most recent messages
             let first = self.messages.pop_front().unwrap();
             let excess = self.messages.len() - (MAX_HISTORY_LENGTH - 1);
-            drop(self.messages.drain(..excess));
+            self.evicted.extend(self.messages.drain(..excess).map(|mut message| {
+                if let Message::User { screenshot_base64, .. } = &mut message {
+                    *screenshot_base64 = None;
+                }
+                message
+            }));
             self.messages.push_front(first);
         }
     }
@@ -158,7 +282,7 @@ impl ConversationHistory {
     /// Gets the last assistant message if available.
     pub fn last_assistant_message(&self) -> Option<&str> {
         self.messages.iter().rev().find_map(|m| {
-            if let Message::Assistant { content } = m {
+            if let Message::Assistant { content, .. } = m {
                 Some(content.as_str())
             } else {
                 None
@@ -215,7 +339,7 @@ mod tests {
 
         assert_eq!(conv.len(), 1);
         match &conv.get_messages()[0] {
-            Message::Assistant { content } => {
+            Message::Assistant { content, .. } => {
                 assert!(content.contains("click"));
             }
             _ => panic!("Expected Assistant message"),
@@ -233,6 +357,7 @@ mod tests {
                 success,
                 message,
                 error,
+                ..
             } => {
                 assert!(*success);
                 assert_eq!(message.as_deref(), Some("Clicked successfully"));
@@ -276,6 +401,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_truncation_keeps_evicted_messages() {
+        let mut conv = ConversationHistory::new();
+        for i in 0..22 {
+            let screenshot = Some(Arc::new(format!("image {}", i)));
+            conv.add_user_message(&format!("Message {}", i), screenshot, None, None);
+        }
+
+        assert!(conv.has_evicted());
+        let evicted = conv.take_evicted();
+        let texts: Vec<&str> = evicted
+            .iter()
+            .map(|m| match m {
+                Message::User { instruction, screenshot_base64, .. } => {
+                    assert!(screenshot_base64.is_none());
+                    instruction.as_str()
+                }
+                _ => panic!("Expected User message"),
+            })
+            .collect();
+        assert_eq!(texts, vec!["Message 1", "Message 2"]);
+        assert!(!conv.has_evicted());
+
+        conv.set_progress_note(Some("Opened the menu".to_string()));
+        let json = serde_json::to_string(&conv).unwrap();
+        let deserialized: ConversationHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.progress_note(), Some("Opened the menu"));
+    }
+
     #[test]
     fn test_original_instruction() {
         let mut conv = ConversationHistory::new();
@@ -310,5 +464,53 @@ mod tests {
 
         assert_eq!(deserialized.len(), 2);
         assert_eq!(deserialized.original_instruction(), Some("Test"));
+        assert!(deserialized.plan().is_none());
+
+        conv.set_plan(TaskPlan::parse("1. Open the menu\n2. Click Save"));
+        let json = serde_json::to_string(&conv).unwrap();
+        let deserialized: ConversationHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.plan().unwrap().goals.len(), 2);
+    }
+
+    #[test]
+    fn test_tool_results_answer_the_pending_tool_use() {
+        let mut conv = ConversationHistory::new();
+        conv.add_assistant_response(&LlmResponse::ToolUse {
+            tool_use: ToolUse {
+                id: "toolu_1".to_string(),
+                name: "click".to_string(),
+                input: serde_json::json!({"x": 1, "y": 2}),
+                signature: None,
+            },
+            reasoning: Some("Opening the menu".to_string()),
+        });
+        assert_eq!(conv.pending_tool_use_id(), Some("toolu_1"));
+        conv.add_tool_result(false, None, Some("Window lost focus".to_string()));
+        assert_eq!(conv.pending_tool_use_id(), None);
+
+        // Text responses leave nothing to answer
+        conv.add_assistant_response(&LlmResponse::Text("{\"action\": \"wait\"}".to_string()));
+        conv.add_tool_result(true, None, None);
+
+        let ids: Vec<Option<&str>> = conv
+            .messages()
+            .filter_map(|m| match m {
+                Message::ToolResult { tool_use_id, .. } => Some(tool_use_id.as_deref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec![Some("toolu_1"), None]);
+        match &conv.get_messages()[0] {
+            Message::Assistant { content, tool_use } => {
+                assert_eq!(content, "Opening the menu");
+                assert_eq!(tool_use.as_ref().unwrap().name, "click");
+            }
+            _ => panic!("Expected Assistant message"),
+        }
+
+        // Sessions saved before tool calls were kept still load
+        let old = r#"{"messages":[{"type":"assistant","content":"{}"},{"type":"tool_result","success":true}]}"#;
+        let loaded: ConversationHistory = serde_json::from_str(old).unwrap();
+        assert_eq!(loaded.len(), 2);
     }
 }
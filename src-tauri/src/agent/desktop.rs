@@ -0,0 +1,28 @@
+use crate::capture::{PrimaryScreen, ScreenSource};
+use crate::input::{EnigoDriver, InputDriver};
+use std::sync::Arc;
+
+/// The screen the agent looks at and the input devices it drives.
+/// Swapping these out lets the agent loop run against a simulated desktop.
+#[derive(Clone)]
+pub struct Desktop {
+    pub screen: Arc<dyn ScreenSource>,
+    pub input: Arc<dyn InputDriver>,
+}
+
+impl Desktop {
+    pub fn new(screen: Arc<dyn ScreenSource>, input: Arc<dyn InputDriver>) -> Self {
+        Self { screen, input }
+    }
+
+    /// The real primary monitor and the system mouse and keyboard
+    pub fn system() -> Self {
+        Self::new(Arc::new(PrimaryScreen), Arc::new(EnigoDriver))
+    }
+}
+
+impl Default for Desktop {
+    fn default() -> Self {
+        Self::system()
+    }
+}
@@ -0,0 +1,413 @@
+use super::budget::BudgetExceeded;
+use super::loop_runner::QueueProgressEvent;
+use super::queue::{InstructionQueue, SkippedItem};
+use super::state::{AgentState, RecordedAction};
+use super::stuck::{Recovery, StuckPattern};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::io::Write;
+#[cfg(test)]
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize};
+
+/// Events produced by the agent loop.
+///
+/// Serializes as `{"event": "<name>", "payload": ...}` where the name matches
+/// the Tauri event the frontend listens for.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "payload", rename_all = "kebab-case")]
+pub enum AgentEvent {
+    AgentState(Box<AgentState>),
+    LlmChunk(String),
+    ParseError(String),
+    ConfirmationRequired(String),
+    TokenBudgetExceeded { total_tokens: u64, max_tokens: u64 },
+    /// A per-task or per-day dollar limit stopped the task
+    CostBudgetExceeded(BudgetExceeded),
+    RetryInfo(String),
+    /// The task was going round in circles, and what the loop did about it
+    StuckDetected { pattern: StuckPattern, recovery: Recovery },
+    InstructionCompleted { instruction: String, success: bool },
+    /// Actions captured so far in recording mode, without screenshots
+    RecordedActions(Vec<RecordedAction>),
+    QueueItemStarted(QueueProgressEvent),
+    QueueItemCompleted(QueueProgressEvent),
+    QueueItemFailed(QueueProgressEvent),
+    /// A queue item that will not run, with the reason
+    QueueItemSkipped(SkippedItem),
+    QueueUpdate(InstructionQueue),
+    ShowCoordinate { x: i32, y: i32, action_type: String },
+    ShowActionIndicator(serde_json::Value),
+    /// Cursor indicator at absolute screen coordinates
+    ShowCursorIndicator { x: i32, y: i32, action_type: String },
+    HideCursorIndicator { hide_after_ms: u64 },
+}
+
+impl AgentEvent {
+    /// Event name, identical to the serialized `event` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgentEvent::AgentState(_) => "agent-state",
+            AgentEvent::LlmChunk(_) => "llm-chunk",
+            AgentEvent::ParseError(_) => "parse-error",
+            AgentEvent::ConfirmationRequired(_) => "confirmation-required",
+            AgentEvent::TokenBudgetExceeded { .. } => "token-budget-exceeded",
+            AgentEvent::CostBudgetExceeded(_) => "cost-budget-exceeded",
+            AgentEvent::RetryInfo(_) => "retry-info",
+            AgentEvent::StuckDetected { .. } => "stuck-detected",
+            AgentEvent::InstructionCompleted { .. } => "instruction-completed",
+            AgentEvent::RecordedActions(_) => "recorded-actions",
+            AgentEvent::QueueItemStarted(_) => "queue-item-started",
+            AgentEvent::QueueItemCompleted(_) => "queue-item-completed",
+            AgentEvent::QueueItemFailed(_) => "queue-item-failed",
+            AgentEvent::QueueItemSkipped(_) => "queue-item-skipped",
+            AgentEvent::QueueUpdate(_) => "queue-update",
+            AgentEvent::ShowCoordinate { .. } => "show-coordinate",
+            AgentEvent::ShowActionIndicator(_) => "show-action-indicator",
+            AgentEvent::ShowCursorIndicator { .. } => "show-cursor-indicator",
+            AgentEvent::HideCursorIndicator { .. } => "hide-cursor-indicator",
+        }
+    }
+}
+
+/// Receives events from the agent loop
+pub trait AgentEventSink: Send + Sync {
+    fn emit(&self, event: AgentEvent);
+
+    /// Whether someone can answer confirmation requests.
+    /// When false, actions that need confirmation are denied immediately.
+    fn supports_confirmation(&self) -> bool {
+        false
+    }
+}
+
+/// Forwards events to the Tauri frontend and drives the cursor overlay window
+pub struct TauriEventSink {
+    app_handle: AppHandle,
+}
+
+impl TauriEventSink {
+    pub fn new(app_handle: AppHandle) -> Self {
+        Self { app_handle }
+    }
+
+    fn show_cursor_indicator(&self, x: i32, y: i32, action_type: &str) {
+        let Some(overlay) = self.app_handle.get_webview_window("cursor-overlay") else {
+            return;
+        };
+
+        // Get available monitors
+        let Ok(monitors) = overlay.available_monitors() else {
+            return;
+        };
+
+        // Find the monitor containing the point
+        let target_monitor = monitors
+            .iter()
+            .find(|m| {
+                let pos = m.position();
+                let size = m.size();
+                x >= pos.x && x < pos.x + size.width as i32 &&
+                y >= pos.y && y < pos.y + size.height as i32
+            })
+            .or_else(|| monitors.first());
+
+        if let Some(monitor) = target_monitor {
+            let monitor_pos = monitor.position();
+            let monitor_size = monitor.size();
+
+            // Position overlay to cover the monitor
+            let _ = overlay.set_position(PhysicalPosition::new(monitor_pos.x, monitor_pos.y));
+            let _ = overlay.set_size(PhysicalSize::new(monitor_size.width, monitor_size.height));
+
+            // Calculate relative position
+            let relative_x = x - monitor_pos.x;
+            let relative_y = y - monitor_pos.y;
+
+            // Show overlay and emit position
+            let _ = overlay.show();
+
+            #[derive(Clone, Serialize)]
+            struct CursorPayload {
+                x: i32,
+                y: i32,
+                action_type: String,
+            }
+
+            let _ = overlay.emit("show-cursor-indicator", CursorPayload {
+                x: relative_x,
+                y: relative_y,
+                action_type: action_type.to_string(),
+            });
+        }
+    }
+
+    fn hide_cursor_indicator(&self, hide_after: Duration) {
+        if let Some(overlay) = self.app_handle.get_webview_window("cursor-overlay") {
+            let _ = overlay.emit("hide-cursor-indicator", ());
+            // Hide the window once the fade-out animation has finished
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(hide_after).await;
+                let _ = overlay.hide();
+            });
+        }
+    }
+}
+
+impl AgentEventSink for TauriEventSink {
+    fn emit(&self, event: AgentEvent) {
+        let name = event.name();
+        let app = &self.app_handle;
+        let _ = match &event {
+            AgentEvent::AgentState(state) => app.emit(name, state),
+            AgentEvent::LlmChunk(text)
+            | AgentEvent::ParseError(text)
+            | AgentEvent::ConfirmationRequired(text)
+            | AgentEvent::RetryInfo(text) => app.emit(name, text),
+            AgentEvent::TokenBudgetExceeded { total_tokens, max_tokens } => app.emit(
+                name,
+                serde_json::json!({
+                    "total_tokens": total_tokens,
+                    "max_tokens": max_tokens,
+                }),
+            ),
+            AgentEvent::CostBudgetExceeded(exceeded) => app.emit(name, exceeded),
+            AgentEvent::StuckDetected { pattern, recovery } => app.emit(
+                name,
+                serde_json::json!({
+                    "pattern": pattern,
+                    "message": pattern.to_string(),
+                    "recovery": recovery,
+                }),
+            ),
+            AgentEvent::InstructionCompleted { instruction, success } => app.emit(
+                name,
+                serde_json::json!({
+                    "instruction": instruction,
+                    "success": success,
+                }),
+            ),
+            AgentEvent::QueueItemStarted(progress)
+            | AgentEvent::QueueItemCompleted(progress)
+            | AgentEvent::QueueItemFailed(progress) => app.emit(name, progress),
+            AgentEvent::QueueItemSkipped(item) => app.emit(name, item),
+            AgentEvent::RecordedActions(actions) => app.emit(name, actions),
+            AgentEvent::QueueUpdate(queue) => app.emit(name, queue),
+            AgentEvent::ShowCoordinate { x, y, action_type } => app.emit(
+                name,
+                serde_json::json!({
+                    "x": x,
+                    "y": y,
+                    "action_type": action_type,
+                }),
+            ),
+            AgentEvent::ShowActionIndicator(payload) => app.emit(name, payload),
+            AgentEvent::ShowCursorIndicator { x, y, action_type } => {
+                self.show_cursor_indicator(*x, *y, action_type);
+                Ok(())
+            }
+            AgentEvent::HideCursorIndicator { hide_after_ms } => {
+                self.hide_cursor_indicator(Duration::from_millis(*hide_after_ms));
+                Ok(())
+            }
+        };
+    }
+
+    fn supports_confirmation(&self) -> bool {
+        true
+    }
+}
+
+/// Writes each event as one JSON line, e.g. to stdout for the CLI.
+/// Screenshots are left out of `agent-state` payloads to keep lines small.
+pub struct JsonLinesSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLinesSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+}
+
+impl AgentEventSink for JsonLinesSink {
+    fn emit(&self, event: AgentEvent) {
+        let event = match event {
+            AgentEvent::AgentState(mut state) => {
+                state.last_screenshot = None;
+                AgentEvent::AgentState(state)
+            }
+            other => other,
+        };
+
+        let Ok(line) = serde_json::to_string(&event) else {
+            return;
+        };
+        let mut writer = self.writer.lock();
+        let _ = writeln!(writer, "{}", line);
+        let _ = writer.flush();
+    }
+}
+
+/// Keeps every event in memory so tests can assert on what the loop emitted
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    events: Arc<Mutex<Vec<AgentEvent>>>,
+    confirmations: bool,
+}
+
+#[cfg(test)]
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report that confirmations can be answered, so the loop waits for
+    /// `AgentStateManager::send_confirmation` instead of denying
+    pub fn with_confirmations(mut self) -> Self {
+        self.confirmations = true;
+        self
+    }
+
+    pub fn events(&self) -> Vec<AgentEvent> {
+        self.events.lock().clone()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.events.lock().iter().map(|e| e.name()).collect()
+    }
+
+    pub fn count(&self, name: &str) -> usize {
+        self.events.lock().iter().filter(|e| e.name() == name).count()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().clear();
+    }
+}
+
+#[cfg(test)]
+impl AgentEventSink for RecordingSink {
+    fn emit(&self, event: AgentEvent) {
+        self.events.lock().push(event);
+    }
+
+    fn supports_confirmation(&self) -> bool {
+        self.confirmations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn progress() -> QueueProgressEvent {
+        QueueProgressEvent {
+            current_index: 0,
+            total: 1,
+            current_instruction: "task".to_string(),
+            status: "running".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_name_matches_serialized_tag() {
+        let events = vec![
+            AgentEvent::AgentState(Box::default()),
+            AgentEvent::LlmChunk("a".to_string()),
+            AgentEvent::ParseError("b".to_string()),
+            AgentEvent::ConfirmationRequired("c".to_string()),
+            AgentEvent::TokenBudgetExceeded { total_tokens: 2, max_tokens: 1 },
+            AgentEvent::CostBudgetExceeded(BudgetExceeded::DailyCost { spent_usd: 5.2, limit_usd: 5.0 }),
+            AgentEvent::RetryInfo("d".to_string()),
+            AgentEvent::StuckDetected {
+                pattern: StuckPattern::NoProgress { steps: 5 },
+                recovery: Recovery::Hint,
+            },
+            AgentEvent::InstructionCompleted { instruction: "e".to_string(), success: true },
+            AgentEvent::QueueItemStarted(progress()),
+            AgentEvent::QueueItemCompleted(progress()),
+            AgentEvent::QueueItemFailed(progress()),
+            AgentEvent::QueueItemSkipped(SkippedItem {
+                id: "1".to_string(),
+                instruction: "task".to_string(),
+                reason: "Dependency 'setup' failed".to_string(),
+            }),
+            AgentEvent::QueueUpdate(InstructionQueue::new()),
+            AgentEvent::ShowCoordinate { x: 1, y: 2, action_type: "click".to_string() },
+            AgentEvent::ShowActionIndicator(serde_json::json!({ "action": "type" })),
+            AgentEvent::ShowCursorIndicator { x: 1, y: 2, action_type: "move".to_string() },
+            AgentEvent::HideCursorIndicator { hide_after_ms: 150 },
+        ];
+
+        for event in events {
+            let value = serde_json::to_value(&event).unwrap();
+            assert_eq!(value["event"], event.name());
+        }
+    }
+
+    #[test]
+    fn test_json_lines_sink_strips_screenshot() {
+        let buffer = SharedBuffer::default();
+        let sink = JsonLinesSink::new(Box::new(buffer.clone()));
+
+        let state = AgentState {
+            last_screenshot: Some(Arc::new("base64data".to_string())),
+            ..Default::default()
+        };
+        sink.emit(AgentEvent::AgentState(Box::new(state)));
+        sink.emit(AgentEvent::LlmChunk("hello".to_string()));
+
+        let output = String::from_utf8(buffer.0.lock().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["event"], "agent-state");
+        assert!(first["payload"]["last_screenshot"].is_null());
+
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["event"], "llm-chunk");
+        assert_eq!(second["payload"], "hello");
+    }
+
+    #[test]
+    fn test_recording_sink_shares_events_between_clones() {
+        let sink = RecordingSink::new();
+        let handle = sink.clone();
+
+        sink.emit(AgentEvent::ParseError("bad".to_string()));
+        sink.emit(AgentEvent::LlmChunk("x".to_string()));
+        sink.emit(AgentEvent::LlmChunk("y".to_string()));
+
+        assert_eq!(handle.names(), vec!["parse-error", "llm-chunk", "llm-chunk"]);
+        assert_eq!(handle.count("llm-chunk"), 2);
+        assert!(!handle.supports_confirmation());
+
+        handle.clear();
+        assert!(sink.events().is_empty());
+        assert!(RecordingSink::new().with_confirmations().supports_confirmation());
+    }
+}
@@ -0,0 +1,266 @@
+//! In-memory desktop for tests: renders a small image that changes whenever
+//! input is applied and records every input event it receives.
+
+use super::desktop::Desktop;
+use crate::capture::{CaptureError, ScreenSource, Screenshot, ScreenshotConfig};
+use crate::input::{
+    parse_key, InputDriver, KeyboardError, Modifier, MouseButton, MouseError, ScrollDirection,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    Move { x: i32, y: i32 },
+    Click { x: i32, y: i32, button: MouseButton },
+    DoubleClick { x: i32, y: i32, button: MouseButton },
+    TripleClick { x: i32, y: i32, button: MouseButton },
+    Scroll { x: i32, y: i32, direction: ScrollDirection, amount: i32 },
+    Drag { start_x: i32, start_y: i32, end_x: i32, end_y: i32, button: MouseButton },
+    Type(String),
+    Key { key: String, modifiers: Vec<Modifier> },
+}
+
+struct FakeState {
+    width: u32,
+    height: u32,
+    cursor: (i32, i32),
+    /// Bumped on every input so consecutive screenshots differ
+    frame: u32,
+    frozen: bool,
+    pending_capture_failures: u32,
+    captures: u32,
+    events: Vec<InputEvent>,
+}
+
+#[derive(Clone)]
+pub struct FakeDesktop {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeDesktop {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FakeState {
+                width,
+                height,
+                cursor: (0, 0),
+                frame: 0,
+                frozen: false,
+                pending_capture_failures: 0,
+                captures: 0,
+                events: Vec::new(),
+            })),
+        }
+    }
+
+    /// A `Desktop` backed by this fake for both screen and input
+    pub fn desktop(&self) -> Desktop {
+        Desktop::new(Arc::new(self.clone()), Arc::new(self.clone()))
+    }
+
+    /// Stop input from changing what is on screen
+    pub fn freeze(&self) {
+        self.state.lock().unwrap().frozen = true;
+    }
+
+    /// Make the next `count` captures fail
+    pub fn fail_next_captures(&self, count: u32) {
+        self.state.lock().unwrap().pending_capture_failures = count;
+    }
+
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.state.lock().unwrap().events.clone()
+    }
+
+    pub fn clicks(&self) -> Vec<(i32, i32)> {
+        self.events()
+            .into_iter()
+            .filter_map(|e| match e {
+                InputEvent::Click { x, y, .. } => Some((x, y)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn typed_text(&self) -> String {
+        self.events()
+            .into_iter()
+            .filter_map(|e| match e {
+                InputEvent::Type(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn capture_count(&self) -> u32 {
+        self.state.lock().unwrap().captures
+    }
+
+    fn record(&self, event: InputEvent) {
+        let mut state = self.state.lock().unwrap();
+        if let InputEvent::Move { x, y } = event {
+            state.cursor = (x, y);
+        }
+        if !state.frozen {
+            state.frame = state.frame.wrapping_add(1);
+        }
+        state.events.push(event);
+    }
+
+    fn cursor(&self) -> (i32, i32) {
+        self.state.lock().unwrap().cursor
+    }
+}
+
+impl ScreenSource for FakeDesktop {
+    fn capture(&self, _config: &ScreenshotConfig) -> Result<Screenshot, CaptureError> {
+        let (width, height, frame, cursor) = {
+            let mut state = self.state.lock().unwrap();
+            state.captures += 1;
+            if state.pending_capture_failures > 0 {
+                state.pending_capture_failures -= 1;
+                return Err(CaptureError::CaptureError("simulated capture failure".to_string()));
+            }
+            (state.width, state.height, state.frame, state.cursor)
+        };
+
+        let shade = (frame.wrapping_mul(37) % 256) as u8;
+        let image = RgbaImage::from_fn(width, height, |x, y| {
+            let near_cursor = (x as i32 - cursor.0).abs() <= 1 && (y as i32 - cursor.1).abs() <= 1;
+            if near_cursor {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([shade, 64, 255 - shade, 255])
+            }
+        });
+
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image).write_to(&mut buffer, ImageFormat::Png)?;
+
+        Ok(Screenshot {
+            width,
+            height,
+            physical_width: width,
+            physical_height: height,
+            base64: Arc::new(STANDARD.encode(buffer.into_inner())),
+        })
+    }
+}
+
+impl InputDriver for FakeDesktop {
+    fn move_to(&self, x: i32, y: i32) -> Result<(), MouseError> {
+        self.record(InputEvent::Move { x, y });
+        Ok(())
+    }
+
+    fn click(&self, button: MouseButton) -> Result<(), MouseError> {
+        let (x, y) = self.cursor();
+        self.record(InputEvent::Click { x, y, button });
+        Ok(())
+    }
+
+    fn double_click(&self, button: MouseButton) -> Result<(), MouseError> {
+        let (x, y) = self.cursor();
+        self.record(InputEvent::DoubleClick { x, y, button });
+        Ok(())
+    }
+
+    fn triple_click(&self, button: MouseButton) -> Result<(), MouseError> {
+        let (x, y) = self.cursor();
+        self.record(InputEvent::TripleClick { x, y, button });
+        Ok(())
+    }
+
+    fn scroll(&self, direction: ScrollDirection, amount: i32) -> Result<(), MouseError> {
+        let (x, y) = self.cursor();
+        self.record(InputEvent::Scroll { x, y, direction, amount });
+        Ok(())
+    }
+
+    fn drag(
+        &self,
+        start_x: i32,
+        start_y: i32,
+        end_x: i32,
+        end_y: i32,
+        button: MouseButton,
+        _duration_ms: u32,
+    ) -> Result<(), MouseError> {
+        self.record(InputEvent::Drag { start_x, start_y, end_x, end_y, button });
+        self.state.lock().unwrap().cursor = (end_x, end_y);
+        Ok(())
+    }
+
+    fn type_text(&self, text: &str) -> Result<(), KeyboardError> {
+        self.record(InputEvent::Type(text.to_string()));
+        Ok(())
+    }
+
+    fn key(&self, key: &str, modifiers: &[Modifier]) -> Result<(), KeyboardError> {
+        // Reject the same keys the real driver would
+        parse_key(key)?;
+        self.record(InputEvent::Key {
+            key: key.to_string(),
+            modifiers: modifiers.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_changes_rendered_screen() {
+        let fake = FakeDesktop::new(32, 24);
+        let config = ScreenshotConfig::default();
+        let before = fake.capture(&config).unwrap();
+        fake.move_to(5, 5).unwrap();
+        fake.click(MouseButton::Left).unwrap();
+        let after = fake.capture(&config).unwrap();
+
+        assert_eq!(after.width, 32);
+        assert_eq!(after.height, 24);
+        assert_ne!(before.base64, after.base64);
+        assert_eq!(fake.clicks(), vec![(5, 5)]);
+        assert_eq!(fake.capture_count(), 2);
+    }
+
+    #[test]
+    fn test_frozen_screen_does_not_change() {
+        let fake = FakeDesktop::new(16, 16);
+        fake.freeze();
+        let config = ScreenshotConfig::default();
+        let before = fake.capture(&config).unwrap();
+        fake.type_text("hello").unwrap();
+        let after = fake.capture(&config).unwrap();
+
+        assert_eq!(before.base64, after.base64);
+        assert_eq!(fake.typed_text(), "hello");
+    }
+
+    #[test]
+    fn test_simulated_capture_failures() {
+        let fake = FakeDesktop::new(8, 8);
+        fake.fail_next_captures(2);
+        let config = ScreenshotConfig::default();
+        assert!(fake.capture(&config).is_err());
+        assert!(fake.capture(&config).is_err());
+        assert!(fake.capture(&config).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let fake = FakeDesktop::new(8, 8);
+        assert!(fake.key("not-a-key", &[]).is_err());
+        assert!(fake.key("a", &[Modifier::Ctrl]).is_ok());
+        assert_eq!(
+            fake.events(),
+            vec![InputEvent::Key { key: "a".to_string(), modifiers: vec![Modifier::Ctrl] }]
+        );
+    }
+}
@@ -1,11 +1,14 @@
 #![allow(dead_code)]
 
 use super::action::Action;
+use super::session_store::SessionStore;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 
 // ===== Session History (for export/logging) =====
 
@@ -18,32 +21,81 @@ pub struct ActionEntry {
     pub action_details: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub screenshot_base64: Option<Arc<String>>,
+    /// Screenshot location relative to the stored session directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screenshot_file: Option<String>,
     pub llm_response: String,
     pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub result_message: Option<String>,
+    /// How many times the action was retried before this outcome
+    #[serde(default)]
+    pub retry_count: u32,
+    /// Cost of the LLM calls that led to this entry, when the price is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionMetrics {
     pub total_input_tokens: u64,
     pub total_output_tokens: u64,
+    /// None when the model's price is unknown
+    #[serde(default)]
+    pub total_cost_usd: Option<f64>,
     pub total_iterations: u32,
     pub duration_seconds: f64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerdictOutcome {
+    Confirmed,
+    Rejected,
+    /// The verifier could not be asked or gave no usable answer; the completion stood
+    Unavailable,
+}
+
+/// What the completion verifier made of a claimed completion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionVerdict {
+    pub timestamp: DateTime<Utc>,
+    pub iteration: u32,
+    pub verifier: String,
+    pub outcome: VerdictOutcome,
+    pub rationale: String,
+}
+
+/// Template a session was started from and the values it was filled in with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateRun {
+    pub template_id: String,
+    pub template_name: String,
+    pub values: BTreeMap<String, String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionHistory {
     pub session_id: String,
+    /// Instruction as sent to the agent, with template variables resolved
     pub instruction: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<TemplateRun>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
     pub started_at: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ended_at: Option<DateTime<Utc>>,
     pub entries: Vec<ActionEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verdicts: Vec<CompletionVerdict>,
     pub metrics: SessionMetrics,
     pub final_status: String,
+    /// Cost of LLM calls not yet attributed to an entry
+    #[serde(skip)]
+    unattributed_cost: Option<f64>,
 }
 
 impl SessionHistory {
@@ -51,27 +103,39 @@ impl SessionHistory {
         Self {
             session_id: uuid::Uuid::new_v4().to_string(),
             instruction,
+            template: None,
+            provider: None,
             started_at: Utc::now(),
             ended_at: None,
             entries: Vec::new(),
+            verdicts: Vec::new(),
             metrics: SessionMetrics {
                 total_input_tokens: 0,
                 total_output_tokens: 0,
+                total_cost_usd: None,
                 total_iterations: 0,
                 duration_seconds: 0.0,
             },
             final_status: "running".to_string(),
+            unattributed_cost: None,
         }
     }
 
-    pub fn add_entry(&mut self, entry: ActionEntry) {
+    pub fn add_entry(&mut self, mut entry: ActionEntry) {
+        if entry.cost_usd.is_none() {
+            entry.cost_usd = self.unattributed_cost.take();
+        }
         self.metrics.total_iterations = entry.iteration;
         self.entries.push(entry);
     }
 
-    pub fn update_metrics(&mut self, input_tokens: u64, output_tokens: u64) {
+    pub fn update_metrics(&mut self, input_tokens: u64, output_tokens: u64, cost_usd: Option<f64>) {
         self.metrics.total_input_tokens += input_tokens;
         self.metrics.total_output_tokens += output_tokens;
+        if let Some(cost) = cost_usd {
+            self.metrics.total_cost_usd = Some(self.metrics.total_cost_usd.unwrap_or(0.0) + cost);
+            self.unattributed_cost = Some(self.unattributed_cost.unwrap_or(0.0) + cost);
+        }
     }
 
     pub fn complete(&mut self, status: &str) {
@@ -94,8 +158,15 @@ impl SessionHistory {
             obj.insert("iteration".into(), serde_json::Value::Number(entry.iteration.into()));
             obj.insert("action_type".into(), serde_json::Value::String(entry.action_type.clone()));
             obj.insert("action_details".into(), entry.action_details.clone());
+            if let Some(ref file) = entry.screenshot_file {
+                obj.insert("screenshot_file".into(), serde_json::Value::String(file.clone()));
+            }
             obj.insert("llm_response".into(), serde_json::Value::String(entry.llm_response.clone()));
             obj.insert("success".into(), serde_json::Value::Bool(entry.success));
+            obj.insert("retry_count".into(), serde_json::Value::Number(entry.retry_count.into()));
+            if let Some(cost) = entry.cost_usd {
+                obj.insert("cost_usd".into(), serde_json::json!(cost));
+            }
             if let Some(ref msg) = entry.error_message {
                 obj.insert("error_message".into(), serde_json::Value::String(msg.clone()));
             }
@@ -108,11 +179,20 @@ impl SessionHistory {
         let mut obj = serde_json::Map::new();
         obj.insert("session_id".into(), serde_json::Value::String(self.session_id.clone()));
         obj.insert("instruction".into(), serde_json::Value::String(self.instruction.clone()));
+        if let Some(ref template) = self.template {
+            obj.insert("template".into(), serde_json::to_value(template).unwrap_or_default());
+        }
+        if let Some(ref provider) = self.provider {
+            obj.insert("provider".into(), serde_json::Value::String(provider.clone()));
+        }
         obj.insert("started_at".into(), serde_json::to_value(&self.started_at).unwrap_or_default());
         if let Some(ref ended) = self.ended_at {
             obj.insert("ended_at".into(), serde_json::to_value(ended).unwrap_or_default());
         }
         obj.insert("entries".into(), serde_json::Value::Array(entries));
+        if !self.verdicts.is_empty() {
+            obj.insert("verdicts".into(), serde_json::to_value(&self.verdicts).unwrap_or_default());
+        }
         obj.insert("metrics".into(), serde_json::to_value(&self.metrics).unwrap_or_default());
         obj.insert("final_status".into(), serde_json::Value::String(self.final_status.clone()));
         serde_json::Value::Object(obj)
@@ -123,6 +203,18 @@ impl SessionHistory {
 
         output.push_str(&format!("Session: {}\n", self.started_at.format("%Y-%m-%d %H:%M:%S UTC")));
         output.push_str(&format!("Instruction: \"{}\"\n", self.instruction));
+        if let Some(template) = &self.template {
+            let values = template
+                .values
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(", ");
+            output.push_str(&format!("Template: {} ({})\n", template.template_name, values));
+        }
+        if let Some(provider) = &self.provider {
+            output.push_str(&format!("Provider: {}\n", provider));
+        }
         output.push_str(&format!("Status: {}\n", self.final_status));
         output.push('\n');
 
@@ -163,13 +255,39 @@ impl SessionHistory {
             if let Some(err) = &entry.error_message {
                 output.push_str(&format!(" ({})", err));
             }
+            if entry.retry_count > 0 {
+                output.push_str(&format!(" [retried {}x]", entry.retry_count));
+            }
+            if let Some(cost) = entry.cost_usd {
+                output.push_str(&format!("\n    Cost: ${:.4}", cost));
+            }
             output.push_str("\n\n");
         }
 
+        if !self.verdicts.is_empty() {
+            output.push_str("--- Completion Checks ---\n");
+            for verdict in &self.verdicts {
+                let outcome = match verdict.outcome {
+                    VerdictOutcome::Confirmed => "Confirmed",
+                    VerdictOutcome::Rejected => "Rejected",
+                    VerdictOutcome::Unavailable => "Unavailable",
+                };
+                output.push_str(&format!(
+                    "[{}] {} by {}: {}\n",
+                    verdict.iteration, outcome, verdict.verifier, verdict.rationale
+                ));
+            }
+            output.push('\n');
+        }
+
         output.push_str("--- Metrics ---\n");
         output.push_str(&format!("Total Iterations: {}\n", self.metrics.total_iterations));
         output.push_str(&format!("Input Tokens: {}\n", self.metrics.total_input_tokens));
         output.push_str(&format!("Output Tokens: {}\n", self.metrics.total_output_tokens));
+        match self.metrics.total_cost_usd {
+            Some(cost) => output.push_str(&format!("Cost: ${:.4}\n", cost)),
+            None => output.push_str("Cost: unknown (no price for this model)\n"),
+        }
         output.push_str(&format!("Duration: {:.2}s\n", self.metrics.duration_seconds));
 
         if let Some(ended) = self.ended_at {
@@ -180,41 +298,192 @@ impl SessionHistory {
     }
 }
 
+/// Writes session files on blocking threads so the session lock is never
+/// held across disk I/O
+#[derive(Clone, Default)]
+struct SessionWriter {
+    /// Numbers snapshots in the order they were taken
+    taken: Arc<AtomicU64>,
+    /// Newest snapshot on disk. Held while writing, so writes never overlap
+    /// and an older snapshot can't replace a newer one.
+    written: Arc<parking_lot::Mutex<u64>>,
+    in_flight: Arc<parking_lot::Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl SessionWriter {
+    fn save(&self, store: SessionStore, session: SessionHistory) {
+        let snapshot = self.taken.fetch_add(1, Ordering::Relaxed) + 1;
+        let written = self.written.clone();
+        self.spawn(move || {
+            let mut written = written.lock();
+            if *written > snapshot {
+                return;
+            }
+            if let Err(e) = store.save(&session) {
+                log::warn!("Failed to save session {}: {}", session.session_id, e);
+            }
+            *written = snapshot;
+        });
+    }
+
+    fn save_screenshot(&self, store: SessionStore, session_id: String, entry_number: usize, screenshot: Arc<String>) {
+        self.spawn(move || {
+            if let Err(e) = store.save_screenshot(&session_id, entry_number, &screenshot) {
+                log::warn!("Failed to save screenshot for session {}: {}", session_id, e);
+            }
+        });
+    }
+
+    fn spawn(&self, write: impl FnOnce() + Send + 'static) {
+        let handle = tokio::task::spawn_blocking(write);
+        let mut in_flight = self.in_flight.lock();
+        in_flight.retain(|h| !h.is_finished());
+        in_flight.push(handle);
+    }
+
+    /// Wait for every write started so far
+    async fn flush(&self) {
+        let handles = std::mem::take(&mut *self.in_flight.lock());
+        for handle in handles {
+            let _ = handle.await;
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct HistoryManager {
     current_session: Arc<RwLock<Option<SessionHistory>>>,
+    /// Where sessions are written as they run, if anywhere
+    store: Arc<parking_lot::RwLock<Option<SessionStore>>>,
+    writer: SessionWriter,
 }
 
 impl HistoryManager {
     pub fn new() -> Self {
         Self {
             current_session: Arc::new(RwLock::new(None)),
+            store: Arc::new(parking_lot::RwLock::new(None)),
+            writer: SessionWriter::default(),
+        }
+    }
+
+    /// Persist sessions to the given store from now on (or stop, with `None`)
+    pub fn set_store(&self, store: Option<SessionStore>) {
+        *self.store.write() = store;
+    }
+
+    pub fn store(&self) -> Option<SessionStore> {
+        self.store.read().clone()
+    }
+
+    /// Queue a write of the session as it is now
+    fn persist(&self, session: &SessionHistory) {
+        if let Some(store) = self.store() {
+            self.writer.save(store, session.clone());
         }
     }
 
     pub async fn start_session(&self, instruction: String) {
         let mut session = self.current_session.write().await;
-        *session = Some(SessionHistory::new(instruction));
+        let new_session = SessionHistory::new(instruction);
+        self.persist(&new_session);
+        *session = Some(new_session);
+    }
+
+    /// Continue a stored session, or start a fresh one if it can't be loaded
+    pub async fn resume_session(&self, session_id: &str, instruction: String) {
+        {
+            let mut current = self.current_session.write().await;
+            if let Some(session) = current.as_mut().filter(|s| s.session_id == session_id) {
+                session.final_status = "running".to_string();
+                session.ended_at = None;
+                self.persist(session);
+                return;
+            }
+        }
+
+        // The stored copy may still have writes on the way
+        self.writer.flush().await;
+        let stored = self.store().and_then(|store| store.load(session_id, false).ok());
+        match stored {
+            Some(mut resumed) => {
+                resumed.final_status = "running".to_string();
+                resumed.ended_at = None;
+                self.persist(&resumed);
+                *self.current_session.write().await = Some(resumed);
+            }
+            None => self.start_session(instruction).await,
+        }
+    }
+
+    pub async fn set_template(&self, template: TemplateRun) {
+        let mut session = self.current_session.write().await;
+        if let Some(ref mut s) = *session {
+            s.template = Some(template);
+            self.persist(s);
+        }
     }
 
-    pub async fn add_entry(&self, entry: ActionEntry) {
+    pub async fn set_provider(&self, provider: String) {
         let mut session = self.current_session.write().await;
         if let Some(ref mut s) = *session {
+            s.provider = Some(provider);
+            self.persist(s);
+        }
+    }
+
+    pub async fn add_entry(&self, mut entry: ActionEntry) {
+        let mut session = self.current_session.write().await;
+        if let Some(ref mut s) = *session {
+            if let (Some(store), Some(screenshot)) = (self.store(), &entry.screenshot_base64) {
+                let entry_number = s.entries.len() + 1;
+                entry.screenshot_file = Some(SessionStore::screenshot_file(entry_number, screenshot));
+                self.writer
+                    .save_screenshot(store, s.session_id.clone(), entry_number, screenshot.clone());
+            }
             s.add_entry(entry);
+            self.persist(s);
         }
     }
 
-    pub async fn update_metrics(&self, input_tokens: u64, output_tokens: u64) {
+    pub async fn update_metrics(&self, input_tokens: u64, output_tokens: u64, cost_usd: Option<f64>) {
         let mut session = self.current_session.write().await;
         if let Some(ref mut s) = *session {
-            s.update_metrics(input_tokens, output_tokens);
+            s.update_metrics(input_tokens, output_tokens, cost_usd);
         }
     }
 
-    pub async fn complete_session(&self, status: &str) {
+    pub async fn add_verdict(&self, verdict: CompletionVerdict) {
         let mut session = self.current_session.write().await;
         if let Some(ref mut s) = *session {
+            s.verdicts.push(verdict);
+            self.persist(s);
+        }
+    }
+
+    /// Finish the session; its files are all written by the time this returns
+    pub async fn complete_session(&self, status: &str) {
+        let session_id = {
+            let mut session = self.current_session.write().await;
+            let Some(ref mut s) = *session else {
+                return;
+            };
             s.complete(status);
+            self.persist(s);
+            s.session_id.clone()
+        };
+        self.writer.flush().await;
+
+        if let Some(store) = self.store() {
+            let retention = tokio::task::spawn_blocking(move || store.enforce_retention(Some(&session_id))).await;
+            match retention {
+                Ok(Ok(deleted)) if !deleted.is_empty() => {
+                    log::info!("Removed {} old session(s) to stay within the storage limit", deleted.len());
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => log::warn!("Failed to apply session retention: {}", e),
+                Err(e) => log::warn!("Failed to apply session retention: {}", e),
+            }
         }
     }
 
@@ -223,6 +492,11 @@ impl HistoryManager {
         session.clone()
     }
 
+    pub async fn session_id(&self) -> Option<String> {
+        let session = self.current_session.read().await;
+        session.as_ref().map(|s| s.session_id.clone())
+    }
+
     pub async fn get_entry_count(&self) -> usize {
         let session = self.current_session.read().await;
         session.as_ref().map(|s| s.entries.len()).unwrap_or(0)
@@ -321,6 +595,11 @@ impl ActionHistory {
         self.records.push_back(record);
     }
 
+    /// Iterate over the records, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &ActionRecord> {
+        self.records.iter()
+    }
+
     /// Remove and return the last action record
     pub fn pop_last(&mut self) -> Option<ActionRecord> {
         self.records.pop_back()
@@ -428,4 +707,72 @@ mod tests {
         assert_eq!(history.len(), 1);
         assert!(!history.can_undo()); // Can't undo a click
     }
+
+    #[test]
+    fn test_session_export_includes_retry_count() {
+        let mut session = SessionHistory::new("open settings".to_string());
+        session.add_entry(ActionEntry {
+            timestamp: Utc::now(),
+            iteration: 1,
+            action_type: "click".to_string(),
+            action_details: serde_json::json!({"action": "click", "x": 1, "y": 2}),
+            screenshot_base64: None,
+            screenshot_file: None,
+            llm_response: String::new(),
+            success: true,
+            error_message: None,
+            result_message: None,
+            retry_count: 2,
+            cost_usd: None,
+        });
+
+        assert_eq!(session.to_json(false)["entries"][0]["retry_count"], 2);
+        assert_eq!(session.to_json(true)["entries"][0]["retry_count"], 2);
+        assert!(session.to_text().contains("[retried 2x]"));
+    }
+
+    #[test]
+    fn test_session_export_includes_cost() {
+        let mut session = SessionHistory::new("open settings".to_string());
+        assert!(session.to_text().contains("Cost: unknown"));
+
+        session.update_metrics(1000, 100, Some(0.0045));
+        session.update_metrics(1000, 100, Some(0.0045));
+        session.add_entry(ActionEntry {
+            timestamp: Utc::now(),
+            iteration: 1,
+            action_type: "click".to_string(),
+            action_details: serde_json::json!({"action": "click", "x": 1, "y": 2}),
+            screenshot_base64: None,
+            screenshot_file: None,
+            llm_response: String::new(),
+            success: true,
+            error_message: None,
+            result_message: None,
+            retry_count: 0,
+            cost_usd: None,
+        });
+
+        let json = session.to_json(false);
+        assert!((json["entries"][0]["cost_usd"].as_f64().unwrap() - 0.009).abs() < 1e-9);
+        assert!((json["metrics"]["total_cost_usd"].as_f64().unwrap() - 0.009).abs() < 1e-9);
+        let text = session.to_text();
+        assert!(text.contains("    Cost: $0.0090"));
+        assert!(text.contains("Cost: $0.0090\n"));
+    }
+
+    #[test]
+    fn test_session_export_includes_template() {
+        let mut session = SessionHistory::new("Invoice Acme".to_string());
+        session.template = Some(TemplateRun {
+            template_id: "t1".to_string(),
+            template_name: "Invoice".to_string(),
+            values: BTreeMap::from([("customer".to_string(), "Acme".to_string())]),
+        });
+
+        let json = session.to_json(false);
+        assert_eq!(json["instruction"], "Invoice Acme");
+        assert_eq!(json["template"]["values"]["customer"], "Acme");
+        assert!(session.to_text().contains("Template: Invoice (customer=Acme)"));
+    }
 }
@@ -1,26 +1,40 @@
 #![allow(dead_code, unused_variables)]
 
-use super::action::{execute_action_with_delay, parse_llm_response_with_reasoning, Action, ActionError, ScreenBounds};
+use super::action::{
+    execute_action_with_delay, execute_action_with_retry, parse_llm_response_with_reasoning, Action, ActionError,
+    ScreenBounds,
+};
+use super::budget::BudgetExceeded;
+use super::checkpoint::{CheckpointStore, TaskCheckpoint};
+use super::context;
 use super::conversation::ConversationHistory;
 use super::delay::DelayController;
-use super::history::{ActionEntry, ActionHistory, ActionRecord};
-use super::queue::{QueueFailureMode, QueueManager};
+use super::desktop::Desktop;
+use super::events::{AgentEvent, AgentEventSink, JsonLinesSink, TauriEventSink};
+use super::history::{ActionEntry, ActionHistory, ActionRecord, CompletionVerdict, TemplateRun, VerdictOutcome};
+use super::plan;
+use super::queue::{QueueFailureMode, QueueManager, RunOverrides, SkippedItem};
+use super::replay::{screen_difference_off_thread, DivergenceMode, ReplayOptions, ReplayOutcome};
 use super::recovery::{
     classify_capture_error, classify_llm_error, retry_with_policy, ErrorClassification,
     RetryPolicy,
 };
-use super::state::{AgentStateManager, AgentStatus, ConfirmationResponse, ExecutionMode};
-use crate::capture::{capture_primary_screen_with_config, CaptureError, Screenshot, ScreenshotConfig};
+use super::retry::{RetryContext, RetryObserver};
+use super::state::{AgentStateManager, AgentStatus, ConfirmationResponse, ExecutionMode, RecordedAction};
+use super::stuck::{Recovery, StuckDetector, StuckPattern, SCREEN_CHANGE_THRESHOLD};
+use super::verifier;
+use crate::capture::{CaptureError, Screenshot, ScreenshotConfig};
+use crate::config::pricing::{self, ModelPrice};
 use crate::config::Config;
 use crate::llm::{
-    AnthropicProvider, GlmProvider, LlmProvider, OllamaProvider, OpenAICompatibleProvider,
-    OpenAIProvider, OpenRouterProvider,
+    AnthropicProvider, AzureAuth, AzureOpenAIProvider, GeminiProvider, GlmProvider, LlmError, LlmProvider, LlmResponse, OllamaProvider, OpenAICompatibleProvider,
+    OpenAIProvider, OpenRouterProvider, TokenMetrics,
 };
+use async_trait::async_trait;
 use chrono::Utc;
 use serde::Serialize;
-use serde_json::json;
 use std::sync::Arc;
-use tauri::{AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize};
+use tauri::AppHandle;
 use thiserror::Error;
 use tokio::sync::RwLock;
 use tokio::time::{sleep, timeout, Duration, Instant};
@@ -28,12 +42,6 @@ use tokio::time::{sleep, timeout, Duration, Instant};
 /// Minimum interval between state emissions to avoid flooding the frontend
 const STATE_EMISSION_MIN_INTERVAL_MS: u64 = 50;
 
-#[derive(Clone, Serialize)]
-struct HistoryEvent {
-    instruction: String,
-    success: bool,
-}
-
 #[derive(Error, Debug)]
 pub enum LoopError {
     #[error("Capture error: {0}")]
@@ -54,9 +62,17 @@ pub enum LoopError {
     TooManyErrors(u32),
     #[error("Queue item failed: {0}")]
     QueueItemFailed(String),
+    #[error("Timed out after {0}s")]
+    TimedOut(u64),
+    #[error("{0}")]
+    BudgetExceeded(BudgetExceeded),
+    #[error("Replay diverged from the recording at step {step} ({:.0}% of the screen differs)", .difference * 100.0)]
+    ReplayDiverged { step: usize, difference: f32 },
+    #[error("Agent is stuck: {0}")]
+    Stuck(StuckPattern),
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct QueueProgressEvent {
     pub current_index: usize,
     pub total: usize,
@@ -66,10 +82,36 @@ pub struct QueueProgressEvent {
 
 const MAX_CONSECUTIVE_ERRORS: u32 = 3;
 
+/// Puts the agent into `Retrying` while an action is being retried
+struct RetryStatusReporter {
+    state: AgentStateManager,
+    events: Arc<dyn AgentEventSink>,
+}
+
+#[async_trait]
+impl RetryObserver for RetryStatusReporter {
+    async fn on_retry(&self, attempt: u32, max_retries: u32, reason: &str) {
+        self.state.set_status(AgentStatus::Retrying).await;
+        self.events.emit(AgentEvent::RetryInfo(format!(
+            "Retrying action ({}/{}): {}",
+            attempt, max_retries, reason
+        )));
+        let state = self.state.get_state().await;
+        self.events.emit(AgentEvent::AgentState(Box::new(state)));
+    }
+}
+
 pub struct AgentLoop {
     state: AgentStateManager,
     config: Config,
-    app_handle: AppHandle,
+    events: Arc<dyn AgentEventSink>,
+    desktop: Desktop,
+    /// Provider to use instead of the one named in config
+    provider: Option<Arc<dyn LlmProvider>>,
+    /// Where progress is saved after each iteration so the task can be resumed
+    checkpoints: Option<CheckpointStore>,
+    /// Template the instruction was resolved from, recorded in the session
+    template: Option<TemplateRun>,
     queue: Option<QueueManager>,
     preview_mode: bool,
     action_history: Arc<RwLock<ActionHistory>>,
@@ -84,13 +126,48 @@ impl AgentLoop {
         config: Config,
         app_handle: AppHandle,
         action_history: Arc<RwLock<ActionHistory>>,
+    ) -> Self {
+        Self::with_event_sink(
+            state,
+            config,
+            Arc::new(TauriEventSink::new(app_handle)),
+            action_history,
+        )
+    }
+
+    /// Create an agent loop that is not attached to a Tauri app.
+    /// Progress events are printed to stdout as JSON lines and confirmation
+    /// requests are denied, since there is nobody to answer them.
+    pub fn new_headless(
+        state: AgentStateManager,
+        config: Config,
+        action_history: Arc<RwLock<ActionHistory>>,
+    ) -> Self {
+        Self::with_event_sink(
+            state,
+            config,
+            Arc::new(JsonLinesSink::stdout()),
+            action_history,
+        )
+    }
+
+    /// Create an agent loop that reports to a custom event sink
+    pub fn with_event_sink(
+        state: AgentStateManager,
+        config: Config,
+        events: Arc<dyn AgentEventSink>,
+        action_history: Arc<RwLock<ActionHistory>>,
     ) -> Self {
         let preview_mode = config.general.preview_mode;
         let delay_controller = DelayController::new(config.general.speed_multiplier);
         Self {
             state,
             config,
-            app_handle,
+            events,
+            desktop: Desktop::system(),
+            provider: None,
+            checkpoints: None,
+            template: None,
             queue: None,
             preview_mode,
             action_history,
@@ -104,19 +181,37 @@ impl AgentLoop {
         self
     }
 
-    fn create_provider(&self) -> Result<Box<dyn LlmProvider>, LoopError> {
-        let provider_name = &self.config.general.default_provider;
-        let connect_timeout = Duration::from_secs(self.config.general.connect_timeout_secs);
-        let response_timeout = Duration::from_secs(self.config.general.response_timeout_secs);
+    /// Capture from and send input to the given desktop instead of the real one
+    pub fn with_desktop(mut self, desktop: Desktop) -> Self {
+        self.desktop = desktop;
+        self
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: CheckpointStore) -> Self {
+        self.checkpoints = Some(checkpoints);
+        self
+    }
+
+    pub fn with_template(mut self, template: TemplateRun) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Send requests to the given provider instead of creating one from config
+    pub fn with_provider(mut self, provider: Arc<dyn LlmProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Build the provider selected in `config`
+    fn create_provider(config: &Config) -> Result<Box<dyn LlmProvider>, LoopError> {
+        let provider_name = &config.general.default_provider;
+        let connect_timeout = Duration::from_secs(config.general.connect_timeout_secs);
+        let response_timeout = Duration::from_secs(config.general.response_timeout_secs);
 
         match provider_name.as_str() {
             "ollama" => {
-                let config = self
-                    .config
-                    .providers
-                    .ollama
-                    .as_ref()
-                    .ok_or(LoopError::NoProvider)?;
+                let config = config.providers.ollama.as_ref().ok_or(LoopError::NoProvider)?;
                 Ok(Box::new(OllamaProvider::with_timeouts(
                     config.host.clone(),
                     config.model.clone(),
@@ -126,12 +221,7 @@ impl AgentLoop {
                 )))
             }
             "anthropic" => {
-                let config = self
-                    .config
-                    .providers
-                    .anthropic
-                    .as_ref()
-                    .ok_or(LoopError::NoProvider)?;
+                let config = config.providers.anthropic.as_ref().ok_or(LoopError::NoProvider)?;
                 Ok(Box::new(AnthropicProvider::with_timeouts(
                     config.api_key.clone(),
                     config.model.clone(),
@@ -141,12 +231,7 @@ impl AgentLoop {
                 )))
             }
             "openai" => {
-                let config = self
-                    .config
-                    .providers
-                    .openai
-                    .as_ref()
-                    .ok_or(LoopError::NoProvider)?;
+                let config = config.providers.openai.as_ref().ok_or(LoopError::NoProvider)?;
                 Ok(Box::new(OpenAIProvider::with_timeouts(
                     config.api_key.clone(),
                     config.model.clone(),
@@ -156,12 +241,7 @@ impl AgentLoop {
                 )))
             }
             "openrouter" => {
-                let config = self
-                    .config
-                    .providers
-                    .openrouter
-                    .as_ref()
-                    .ok_or(LoopError::NoProvider)?;
+                let config = config.providers.openrouter.as_ref().ok_or(LoopError::NoProvider)?;
                 Ok(Box::new(OpenRouterProvider::with_timeouts(
                     config.api_key.clone(),
                     config.model.clone(),
@@ -171,25 +251,46 @@ impl AgentLoop {
                 )))
             }
             "glm" => {
-                let config = self
-                    .config
-                    .providers
-                    .glm
-                    .as_ref()
-                    .ok_or(LoopError::NoProvider)?;
+                let config = config.providers.glm.as_ref().ok_or(LoopError::NoProvider)?;
                 Ok(Box::new(GlmProvider::new(
                     config.api_key.clone(),
                     config.model.clone(),
                     config.temperature,
                 )))
             }
+            "gemini" => {
+                let config = config.providers.gemini.as_ref().ok_or(LoopError::NoProvider)?;
+                let provider = GeminiProvider::with_timeouts(
+                    config.api_key.clone(),
+                    config.model.clone(),
+                    config.temperature,
+                    connect_timeout,
+                    response_timeout,
+                );
+                Ok(Box::new(match &config.base_url {
+                    Some(base_url) => provider.with_base_url(base_url),
+                    None => provider,
+                }))
+            }
+            "azure-openai" => {
+                let config = config.providers.azure_openai.as_ref().ok_or(LoopError::NoProvider)?;
+                let auth = match (&config.api_key, &config.bearer_token) {
+                    (Some(key), _) => AzureAuth::ApiKey(key.clone()),
+                    (None, Some(token)) => AzureAuth::Bearer(token.clone()),
+                    (None, None) => return Err(LoopError::NoProvider),
+                };
+                Ok(Box::new(AzureOpenAIProvider::with_timeouts(
+                    config.endpoint.clone(),
+                    config.deployment.clone(),
+                    config.api_version.clone(),
+                    auth,
+                    config.temperature,
+                    connect_timeout,
+                    response_timeout,
+                )))
+            }
             "openai-compatible" => {
-                let config = self
-                    .config
-                    .providers
-                    .openai_compatible
-                    .as_ref()
-                    .ok_or(LoopError::NoProvider)?;
+                let config = config.providers.openai_compatible.as_ref().ok_or(LoopError::NoProvider)?;
                 Ok(Box::new(OpenAICompatibleProvider::new(
                     config.base_url.clone(),
                     config.api_key.clone(),
@@ -209,40 +310,465 @@ impl AgentLoop {
         self.run_with_mode(instruction, ExecutionMode::Recording).await
     }
 
+    /// Continue an interrupted task from its last checkpoint.
+    /// The conversation picks up where it left off, with a fresh screenshot.
+    pub async fn resume(&self, checkpoint: TaskCheckpoint) -> Result<(), LoopError> {
+        let instruction = checkpoint.instruction.clone();
+        let mode = checkpoint.mode;
+        self.run_task(instruction, mode, Some(checkpoint), &RunOverrides::default()).await
+    }
+
     async fn run_with_mode(&self, instruction: String, mode: ExecutionMode) -> Result<(), LoopError> {
-        let provider = self.create_provider()?;
-        let max_iterations = self.config.general.max_iterations;
+        self.run_task(instruction, mode, None, &RunOverrides::default()).await
+    }
+
+    async fn run_task(
+        &self,
+        instruction: String,
+        mode: ExecutionMode,
+        resume_from: Option<TaskCheckpoint>,
+        overrides: &RunOverrides,
+    ) -> Result<(), LoopError> {
+        let provider: Arc<dyn LlmProvider> = match &self.provider {
+            Some(provider) if !overrides.changes_provider() => provider.clone(),
+            _ => Arc::from(Self::create_provider(&self.config_with(overrides)?)?),
+        };
         let confirm_dangerous = self.config.general.confirm_dangerous_actions;
         let show_overlay = self.config.general.show_coordinate_overlay;
 
-        // Initialize conversation history for this task
-        let mut conversation = ConversationHistory::new();
-        conversation.set_original_instruction(instruction.clone());
+        let (mut conversation, max_iterations) = match resume_from {
+            Some(checkpoint) => {
+                // Restore undo history so earlier actions can still be reverted
+                let (can_undo, last_undoable) = {
+                    let mut history = self.action_history.write().await;
+                    history.clear();
+                    for record in checkpoint.actions {
+                        history.push(record);
+                    }
+                    (history.can_undo(), history.get_last_undoable_description())
+                };
 
-        // Clear action history for new session
-        {
-            let mut history = self.action_history.write().await;
-            history.clear();
-        }
+                self.state
+                    .resume_with_mode(
+                        instruction.clone(),
+                        checkpoint.max_iterations,
+                        mode,
+                        checkpoint.iteration,
+                        checkpoint.input_tokens,
+                        checkpoint.output_tokens,
+                    )
+                    .await;
+                self.state.restore_task_cost(checkpoint.cost_usd);
+                match &checkpoint.session_id {
+                    Some(id) => self.state.history().resume_session(id, instruction.clone()).await,
+                    None => self.state.history().start_session(instruction.clone()).await,
+                }
+                self.state.update_undo_state(can_undo, last_undoable).await;
 
-        self.state.start_with_mode(instruction.clone(), max_iterations, mode).await;
-        self.state.update_undo_state(false, None).await;
+                let mut conversation = checkpoint.conversation;
+                conversation.set_original_instruction(instruction.clone());
+                self.state.set_plan(conversation.plan().cloned()).await;
+                (conversation, checkpoint.max_iterations)
+            }
+            None => {
+                let max_iterations = overrides.max_iterations.unwrap_or(self.config.general.max_iterations);
+
+                // Initialize conversation history for this task
+                let mut conversation = ConversationHistory::new();
+                conversation.set_original_instruction(instruction.clone());
+
+                // Clear action history for new session
+                {
+                    let mut history = self.action_history.write().await;
+                    history.clear();
+                }
+
+                self.state.start_with_mode(instruction.clone(), max_iterations, mode).await;
+                self.state.update_undo_state(false, None).await;
+                (conversation, max_iterations)
+            }
+        };
+        self.state.history().set_provider(provider.name().to_string()).await;
+        if let Some(template) = &self.template {
+            self.state.history().set_template(template.clone()).await;
+        }
         self.emit_state_update_immediate().await;
 
-        let result = self.run_loop(&*provider, &instruction, max_iterations, confirm_dangerous, show_overlay, &mut conversation).await;
+        let run = self.run_loop(&*provider, &instruction, max_iterations, confirm_dangerous, show_overlay, &mut conversation);
+        let result = match overrides.timeout() {
+            Some(limit) => match tokio::time::timeout(limit, run).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let error = LoopError::TimedOut(limit.as_secs());
+                    self.state.set_error(error.to_string()).await;
+                    self.emit_state_update_immediate().await;
+                    Err(error)
+                }
+            },
+            None => run.await,
+        };
 
         // Complete the history session with final status
         let status = match &result {
             Ok(_) => "completed",
             Err(LoopError::Stopped) => "stopped",
             Err(LoopError::MaxIterations) => "max_iterations",
+            Err(LoopError::BudgetExceeded(_)) => "budget_exceeded",
+            Err(LoopError::Stuck(_)) => "stuck",
             Err(_) => "error",
         };
         self.state.history().complete_session(status).await;
 
+        // A finished task has nothing left to resume
+        if matches!(result, Ok(()) | Err(LoopError::MaxIterations)) {
+            if let Some(checkpoints) = &self.checkpoints {
+                if let Err(e) = checkpoints.clear() {
+                    log::warn!("Failed to clear checkpoint: {}", e);
+                }
+            }
+        }
+
         result
     }
 
+    /// Config with a run's provider and model overrides applied
+    fn config_with(&self, overrides: &RunOverrides) -> Result<Config, LoopError> {
+        let mut config = self.config.clone();
+        if let Some(provider) = &overrides.provider {
+            config.general.default_provider = provider.clone();
+        }
+        if let Some(model) = &overrides.model {
+            let provider = config.general.default_provider.clone();
+            if !config.update_provider_model(&provider, model) {
+                return Err(LoopError::NoProvider);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Save everything needed to pick the task up again after a crash
+    async fn save_checkpoint(
+        &self,
+        instruction: &str,
+        max_iterations: u32,
+        conversation: &ConversationHistory,
+    ) {
+        let Some(checkpoints) = &self.checkpoints else {
+            return;
+        };
+
+        let (_tps, input_tokens, output_tokens) = self.state.get_token_metrics();
+        let actions = self.action_history.read().await.iter().cloned().collect();
+        let checkpoint = TaskCheckpoint {
+            session_id: self.state.history().session_id().await,
+            instruction: instruction.to_string(),
+            mode: self.state.get_execution_mode().await,
+            iteration: self.state.get_iteration(),
+            max_iterations,
+            input_tokens,
+            output_tokens,
+            cost_usd: self.state.get_task_cost(),
+            conversation: conversation.without_screenshots(),
+            actions,
+            saved_at: Utc::now(),
+        };
+
+        if let Err(e) = checkpoints.save(&checkpoint) {
+            log::warn!("Failed to save checkpoint: {}", e);
+        }
+    }
+
+    /// Add an LLM call's tokens and cost to the task totals
+    async fn record_usage(&self, metrics: &TokenMetrics, price: Option<ModelPrice>) {
+        // Update metrics atomically (no await needed)
+        self.state.update_metrics(
+            metrics.tokens_per_second(),
+            metrics.input_tokens,
+            metrics.output_tokens,
+        );
+        let cost = price.map(|price| {
+            price.cost(metrics.input_tokens, metrics.output_tokens)
+                + price.cache_cost(metrics.cache_read_tokens, metrics.cache_write_tokens)
+        });
+        if let Some(cost) = cost {
+            self.state.add_cost(cost);
+        }
+        self.state
+            .history()
+            .update_metrics(metrics.input_tokens, metrics.output_tokens, cost)
+            .await;
+    }
+
+    /// Make the task plan, or replace the rest of it when `replan_reason` is given.
+    /// A failed planner call leaves the plan as it was.
+    async fn update_plan(
+        &self,
+        provider: &dyn LlmProvider,
+        instruction: &str,
+        screenshot: &Screenshot,
+        conversation: &mut ConversationHistory,
+        replan_reason: Option<&str>,
+        price: Option<ModelPrice>,
+    ) -> Result<(), LlmError> {
+        let previous = replan_reason.and(conversation.plan());
+        let (new_plan, metrics) = plan::request_plan(provider, instruction, screenshot, previous, replan_reason).await?;
+        self.record_usage(&metrics, price).await;
+
+        let updated = match (replan_reason, conversation.plan().cloned()) {
+            (Some(_), Some(mut current)) => {
+                current.revise(new_plan);
+                current
+            }
+            _ => new_plan,
+        };
+        log::info!("Task plan ({} sub-goals, revision {})", updated.goals.len(), updated.revision);
+        self.state.set_plan(Some(updated.clone())).await;
+        conversation.set_plan(Some(updated));
+        Ok(())
+    }
+
+    /// Ask the verifier whether the task is really done, and record what it said.
+    /// Returns its reasons when it disagrees; when it can't be asked the completion stands.
+    async fn check_completion(
+        &self,
+        provider: &dyn LlmProvider,
+        instruction: &str,
+        claim: &str,
+        iteration: u32,
+    ) -> Option<String> {
+        let general = &self.config.general;
+        let dedicated = if general.verifier_provider.is_some() || general.verifier_model.is_some() {
+            let overrides = RunOverrides {
+                provider: general.verifier_provider.clone(),
+                model: general.verifier_model.clone(),
+                ..Default::default()
+            };
+            Some(self.config_with(&overrides).and_then(|config| Self::create_provider(&config)))
+        } else {
+            None
+        };
+
+        let (verifier_name, result) = match &dedicated {
+            Some(Err(e)) => (
+                general.verifier_provider.clone().unwrap_or_else(|| provider.name().to_string()),
+                Err(format!("Verifier is not configured: {}", e)),
+            ),
+            _ => {
+                let verifier: &dyn LlmProvider = match &dedicated {
+                    Some(Ok(verifier)) => verifier.as_ref(),
+                    _ => provider,
+                };
+                let result = match self.capture_with_retry().await {
+                    Ok(screenshot) => verifier::verify_completion(verifier, instruction, claim, &screenshot)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Ok((_, metrics)) = &result {
+                    let price = pricing::price_for(&self.config.pricing, verifier.name(), verifier.model());
+                    self.record_usage(metrics, price).await;
+                }
+                (verifier.name().to_string(), result.map(|(verdict, _)| verdict))
+            }
+        };
+
+        let (outcome, rationale) = match result {
+            Ok(verdict) if verdict.satisfied => (VerdictOutcome::Confirmed, verdict.rationale),
+            Ok(verdict) => (VerdictOutcome::Rejected, verdict.rationale),
+            Err(e) => {
+                log::warn!("Could not verify completion, accepting it: {}", e);
+                (VerdictOutcome::Unavailable, e)
+            }
+        };
+        log::info!("Completion check by {}: {:?} ({})", verifier_name, outcome, rationale);
+        self.state
+            .history()
+            .add_verdict(CompletionVerdict {
+                timestamp: Utc::now(),
+                iteration,
+                verifier: verifier_name,
+                outcome,
+                rationale: rationale.clone(),
+            })
+            .await;
+
+        (outcome == VerdictOutcome::Rejected).then_some(rationale)
+    }
+
+    /// Fold messages that dropped out of the conversation into its progress note.
+    /// Falls back to a plain digest when the model cannot summarize them.
+    async fn update_progress_note(
+        &self,
+        provider: &dyn LlmProvider,
+        conversation: &mut ConversationHistory,
+        screenshot: &Screenshot,
+        price: Option<ModelPrice>,
+    ) {
+        let evicted = conversation.take_evicted();
+        let previous = conversation.progress_note().map(str::to_string);
+
+        let summary = if self.config.general.summarize_with_llm {
+            let instruction = conversation.original_instruction().unwrap_or_default();
+            let summary = context::summarize(
+                provider,
+                instruction,
+                previous.as_deref(),
+                &evicted,
+                screenshot.width,
+                screenshot.height,
+            )
+            .await;
+            match summary {
+                Ok((note, metrics)) => {
+                    self.record_usage(&metrics, price).await;
+                    Some(note)
+                }
+                Err(e) => {
+                    log::warn!("Could not summarize earlier steps, keeping a digest instead: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let note = summary.unwrap_or_else(|| context::digest(previous.as_deref(), &evicted));
+        conversation.set_progress_note(Some(note));
+    }
+
+    /// Take the next step on the escalation ladder for a stuck task, starting at
+    /// `from` and skipping steps that are not available. Returns the step taken;
+    /// `Recovery::Abort` means the task should end.
+    async fn recover_from_stuck(
+        &self,
+        pattern: &StuckPattern,
+        from: Recovery,
+        fallback: &mut Option<Box<dyn LlmProvider>>,
+    ) -> Recovery {
+        let general = &self.config.general;
+        let mut step = from;
+        let taken = loop {
+            match step {
+                Recovery::Hint => break step,
+                Recovery::SwitchModel => {
+                    let configured = general.stuck_fallback_provider.is_some() || general.stuck_fallback_model.is_some();
+                    if configured && fallback.is_none() {
+                        let overrides = RunOverrides {
+                            provider: general.stuck_fallback_provider.clone(),
+                            model: general.stuck_fallback_model.clone(),
+                            ..Default::default()
+                        };
+                        match self.config_with(&overrides).and_then(|config| Self::create_provider(&config)) {
+                            Ok(stronger) => {
+                                log::info!(
+                                    "Handing stuck task to {} model {}",
+                                    stronger.name(),
+                                    stronger.model().unwrap_or("(default)")
+                                );
+                                *fallback = Some(stronger);
+                                break step;
+                            }
+                            Err(e) => log::warn!("Stuck fallback model is not available: {}", e),
+                        }
+                    }
+                }
+                Recovery::AskUser => {
+                    if self.events.supports_confirmation() {
+                        let question = format!("The agent seems stuck ({}). Let it keep trying?", pattern);
+                        self.state.reset_confirmation_channel().await;
+                        self.state.set_pending_action(Some(question.clone())).await;
+                        self.state.set_status(AgentStatus::AwaitingConfirmation).await;
+                        self.emit_state_update_immediate().await;
+                        self.events.emit(AgentEvent::ConfirmationRequired(question));
+
+                        let response = timeout(Duration::from_secs(30), self.state.await_confirmation()).await;
+                        self.state.set_pending_action(None).await;
+                        if let Ok(Some(ConfirmationResponse::Confirmed)) = response {
+                            self.state.set_status(AgentStatus::Running).await;
+                            self.emit_state_update_immediate().await;
+                            break step;
+                        }
+                        break Recovery::Abort;
+                    }
+                }
+                Recovery::Abort => break step,
+            }
+            step = step.next();
+        };
+
+        log::warn!("Agent is stuck ({}), recovering with {:?}", pattern, taken);
+        self.events.emit(AgentEvent::StuckDetected {
+            pattern: pattern.clone(),
+            recovery: taken,
+        });
+        taken
+    }
+
+    /// Ask the user to confirm a dangerous action and wait up to 30 seconds
+    /// for the answer. Sinks without a user to ask (e.g. headless runs) deny
+    /// outright. Returns whether the action was confirmed.
+    async fn confirm_with_user(&self, msg: &str) -> bool {
+        // Reset confirmation channel for fresh state
+        self.state.reset_confirmation_channel().await;
+
+        // Set pending action and status
+        self.state.set_pending_action(Some(msg.to_string())).await;
+        self.state.set_status(AgentStatus::AwaitingConfirmation).await;
+        self.emit_state_update_immediate().await;
+
+        // Emit confirmation request to frontend
+        self.events.emit(AgentEvent::ConfirmationRequired(msg.to_string()));
+
+        let response = if self.events.supports_confirmation() {
+            timeout(Duration::from_secs(30), self.state.await_confirmation()).await
+        } else {
+            Ok(Some(ConfirmationResponse::Denied))
+        };
+
+        // Clear pending action
+        self.state.set_pending_action(None).await;
+
+        let confirmed = matches!(response, Ok(Some(ConfirmationResponse::Confirmed)));
+        if confirmed {
+            self.state.set_status(AgentStatus::Running).await;
+            self.emit_state_update_immediate().await;
+        }
+        confirmed
+    }
+
+    /// Leave the agent idle after a dangerous action was not confirmed
+    async fn action_denied(&self) -> LoopError {
+        self.state.set_status(AgentStatus::Idle).await;
+        self.state.set_error("Action denied or timed out".to_string()).await;
+        self.emit_state_update_immediate().await;
+        LoopError::ActionDenied
+    }
+
+    /// The first budget the task has gone over, if any
+    fn budget_exceeded(&self) -> Option<BudgetExceeded> {
+        let general = &self.config.general;
+        if let Some(limit) = general.max_tokens_per_task.filter(|&limit| limit > 0) {
+            let (_tps, input_tokens, output_tokens) = self.state.get_token_metrics();
+            let used = input_tokens + output_tokens;
+            if used > limit {
+                return Some(BudgetExceeded::Tokens { used, limit });
+            }
+        }
+        if let Some(limit_usd) = general.max_cost_per_task.filter(|&limit| limit > 0.0) {
+            let spent_usd = self.state.get_task_cost();
+            if spent_usd >= limit_usd {
+                return Some(BudgetExceeded::TaskCost { spent_usd, limit_usd });
+            }
+        }
+        if let Some(limit_usd) = general.max_cost_per_day.filter(|&limit| limit > 0.0) {
+            let spent_usd = self.state.spend().today().spent_usd;
+            if spent_usd >= limit_usd {
+                return Some(BudgetExceeded::DailyCost { spent_usd, limit_usd });
+            }
+        }
+        None
+    }
+
     async fn run_loop(
         &self,
         provider: &dyn LlmProvider,
@@ -257,7 +783,52 @@ impl AgentLoop {
         let delay_controller = DelayController::new(speed_multiplier);
         let target_iteration_delay = delay_controller.iteration_delay();
 
+        // Verify that actions change the screen and retry the ones that don't
+        let general = &self.config.general;
+        let mut retry_ctx = RetryContext::new(
+            general.max_retries,
+            general.retry_delay_ms,
+            general.enable_self_correction,
+        )
+        .with_screen_source(self.desktop.screen.clone())
+        .with_capture_config(self.screenshot_config())
+        .with_observer(Arc::new(RetryStatusReporter {
+            state: self.state.clone(),
+            events: self.events.clone(),
+        }));
+
+        let recording = self.state.get_execution_mode().await == ExecutionMode::Recording;
+
+        // Set when the executor asks for a new plan
+        let mut replan_reason: Option<String> = None;
+        let mut planner_failed = false;
+
+        let mut price = pricing::price_for(&self.config.pricing, provider.name(), provider.model());
+        if price.is_none() {
+            log::info!(
+                "No price known for {} model {}; cost will not be tracked",
+                provider.name(),
+                provider.model().unwrap_or("(default)")
+            );
+        }
+
+        // A resumed task already has a conversation; its first new message
+        // has to tell the model that the screen may have changed since
+        let mut resuming = !conversation.is_empty();
+
+        // Watch for the task going round in circles; each time it does, the
+        // next step up the ladder is taken
+        let mut stuck = StuckDetector::new();
+        let mut next_recovery = Recovery::Hint;
+        let mut last_record: Option<ActionRecord> = None;
+        let mut previous_screen: Option<Arc<String>> = None;
+        // Stronger model the task was handed to after getting stuck
+        let mut fallback: Option<Box<dyn LlmProvider>> = None;
+
         loop {
+            // Everything up to the previous iteration is settled, so save it
+            self.save_checkpoint(instruction, max_iterations, conversation).await;
+
             // Check if should stop
             if self.state.should_stop() {
                 self.state.set_status(AgentStatus::Idle).await;
@@ -300,23 +871,18 @@ impl AgentLoop {
                 self.emit_state_update_immediate().await;
             }
 
-            // Check token budget
-            if let Some(max_tokens) = self.config.general.max_tokens_per_task {
-                if max_tokens > 0 {
-                    let (_tps, input_tokens, output_tokens) = self.state.get_token_metrics();
-                    let total_tokens = input_tokens + output_tokens;
-                    if total_tokens > max_tokens {
-                        let _ = self.app_handle.emit("token-budget-exceeded", json!({
-                            "total_tokens": total_tokens,
-                            "max_tokens": max_tokens,
-                        }));
-                        self.state
-                            .set_error(format!("Token budget exceeded ({} > {})", total_tokens, max_tokens))
-                            .await;
-                        self.emit_state_update_immediate().await;
-                        return Err(LoopError::MaxIterations);
-                    }
+            // Check token and cost budgets
+            if let Some(exceeded) = self.budget_exceeded() {
+                match exceeded {
+                    BudgetExceeded::Tokens { used, limit } => self.events.emit(AgentEvent::TokenBudgetExceeded {
+                        total_tokens: used,
+                        max_tokens: limit,
+                    }),
+                    _ => self.events.emit(AgentEvent::CostBudgetExceeded(exceeded.clone())),
                 }
+                self.state.set_error(exceeded.to_string()).await;
+                self.emit_state_update_immediate().await;
+                return Err(LoopError::BudgetExceeded(exceeded));
             }
 
             // Check iteration limit (now uses atomic, no await needed)
@@ -340,17 +906,67 @@ impl AgentLoop {
                 }
             };
 
+            // See whether the last action got anywhere before asking for the next one
+            let mut stuck_hint = None;
+            if let Some(record) = last_record.take().filter(|_| self.config.general.stuck_detection && !recording) {
+                let difference = match previous_screen.clone() {
+                    Some(previous) => screen_difference_off_thread(previous, screenshot.base64.clone()).await,
+                    None => None,
+                };
+                let changed = difference.map_or(true, |difference| difference > SCREEN_CHANGE_THRESHOLD);
+                if let Some(pattern) = stuck.observe(&record, changed) {
+                    stuck.clear();
+                    let recovery = self.recover_from_stuck(&pattern, next_recovery, &mut fallback).await;
+                    if recovery == Recovery::Abort {
+                        self.state.set_error(LoopError::Stuck(pattern.clone()).to_string()).await;
+                        self.emit_state_update_immediate().await;
+                        return Err(LoopError::Stuck(pattern));
+                    }
+                    if let (Recovery::SwitchModel, Some(stronger)) = (recovery, &fallback) {
+                        price = pricing::price_for(&self.config.pricing, stronger.name(), stronger.model());
+                    }
+                    next_recovery = recovery.next();
+                    stuck_hint = Some(pattern.hint());
+                }
+            }
+            previous_screen = Some(screenshot.base64.clone());
+            let provider: &dyn LlmProvider = fallback.as_deref().unwrap_or(provider);
+
             // Set iteration/max_iterations for progress context in system prompt
             conversation.iteration = Some(iteration);
             conversation.max_iterations = Some(max_iterations);
 
+            // Plan before the first action, and again whenever the executor asks
+            let wants_plan = conversation.plan().is_none() && !planner_failed;
+            if self.config.general.enable_planner && !recording && (wants_plan || replan_reason.is_some()) {
+                let reason = replan_reason.take();
+                if let Err(e) = self
+                    .update_plan(provider, instruction, &screenshot, conversation, reason.as_deref(), price)
+                    .await
+                {
+                    // Without a plan the executor works from the instruction alone
+                    log::warn!("Planning failed, continuing without a new plan: {}", e);
+                    planner_failed = true;
+                }
+                self.emit_state_update().await;
+            }
+
             // Add user message with current screenshot to conversation
             // First message includes full instruction; subsequent messages use a short continuation prompt
             let user_text = if conversation.is_empty() {
                 instruction.to_string()
+            } else if resuming {
+                resuming = false;
+                "The task was interrupted and has now been resumed. Here is the current screenshot; \
+                 check what has already been done before continuing."
+                    .to_string()
             } else {
                 "Here is the current screenshot. Continue working on the task.".to_string()
             };
+            let user_text = match stuck_hint {
+                Some(hint) => format!("{}\n\n{}", hint, user_text),
+                None => user_text,
+            };
             conversation.add_user_message(
                 &user_text,
                 Some(screenshot.base64.clone()),
@@ -358,6 +974,11 @@ impl AgentLoop {
                 Some(screenshot.height),
             );
 
+            // Steps that no longer fit in the window live on in the progress note
+            if conversation.has_evicted() {
+                self.update_progress_note(provider, conversation, &screenshot, price).await;
+            }
+
             // Store screenshot in state for frontend preview
             self.state
                 .set_last_screenshot(screenshot.base64.clone())
@@ -365,9 +986,9 @@ impl AgentLoop {
             self.emit_state_update().await;
 
             // Create callback for chunk streaming
-            let app_handle = self.app_handle.clone();
+            let events = self.events.clone();
             let on_chunk: Box<dyn Fn(&str) + Send + Sync> = Box::new(move |chunk: &str| {
-                let _ = app_handle.emit("llm-chunk", chunk.to_string());
+                events.emit(AgentEvent::LlmChunk(chunk.to_string()));
             });
 
             // Track LLM response time for adaptive delay
@@ -384,11 +1005,7 @@ impl AgentLoop {
                 .await;
 
             let (response, metrics) = match llm_result {
-                Ok((resp, met)) => {
-                    // Reset consecutive errors on success
-                    self.state.reset_consecutive_errors();
-                    (resp, met)
-                }
+                Ok((resp, met)) => (resp, met),
                 Err(e) => {
                     self.state.increment_consecutive_errors();
                     self.state.set_error(e.to_string()).await;
@@ -418,35 +1035,33 @@ impl AgentLoop {
                 }
             };
 
-            // Add assistant response to conversation
+            // Add assistant response to conversation, tool call included
             let response_str = response.to_string_repr();
-            conversation.add_assistant_message(&response_str);
+            conversation.add_assistant_response(&response);
 
             let llm_elapsed = llm_start.elapsed();
 
-            // Update metrics atomically (no await needed)
-            self.state.update_metrics(
-                metrics.tokens_per_second(),
-                metrics.input_tokens,
-                metrics.output_tokens,
-            );
-            self.state.history().update_metrics(metrics.input_tokens, metrics.output_tokens).await;
+            self.record_usage(&metrics, price).await;
 
             // Parse action with reasoning extraction
-            let action = match parse_llm_response_with_reasoning(&response) {
+            let (action, reasoning) = match parse_llm_response_with_reasoning(&response) {
                 Ok(parsed) => {
+                    // A usable response ends the error streak; unparseable ones
+                    // count towards it so a confused model cannot loop forever
+                    self.state.reset_consecutive_errors();
+
                     // Store reasoning for UI display
-                    self.state.set_last_reasoning(parsed.reasoning).await;
-                    parsed.action
+                    self.state.set_last_reasoning(parsed.reasoning.clone()).await;
+                    (parsed.action, parsed.reasoning)
                 }
                 Err(parse_err) => {
                     self.state.increment_consecutive_errors();
 
                     // Emit parse error feedback
-                    let _ = self.app_handle.emit(
-                        "parse-error",
-                        format!("Failed to parse LLM response: {}", parse_err),
-                    );
+                    self.events.emit(AgentEvent::ParseError(format!(
+                        "Failed to parse LLM response: {}",
+                        parse_err
+                    )));
 
                     // Add parse error feedback to conversation so LLM knows its response was unparseable
                     conversation.add_tool_result(
@@ -478,6 +1093,33 @@ impl AgentLoop {
             // Emit state once after LLM + action parsing (batched update)
             self.emit_state_update().await;
 
+            // Plan bookkeeping happens here rather than on screen
+            match &action {
+                Action::StepDone { step } => {
+                    let outcome = match conversation.plan_mut() {
+                        Some(plan) => plan.mark_done(*step),
+                        None => Err("There is no plan for this task".to_string()),
+                    };
+                    self.state.set_plan(conversation.plan().cloned()).await;
+                    match outcome {
+                        Ok(note) => conversation.add_tool_result(true, Some(note), None),
+                        Err(e) => conversation.add_tool_result(false, None, Some(e)),
+                    }
+                    continue;
+                }
+                Action::Replan { reason } => {
+                    if self.config.general.enable_planner {
+                        replan_reason = Some(reason.clone());
+                        planner_failed = false;
+                        conversation.add_tool_result(true, Some("A new plan will be made from the next screenshot".to_string()), None);
+                    } else {
+                        conversation.add_tool_result(false, None, Some("Planning is turned off for this task".to_string()));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
             // Skip execution in preview mode
             if self.preview_mode {
                 // In preview mode, check if action would be a completion
@@ -491,6 +1133,20 @@ impl AgentLoop {
                 continue;
             }
 
+            // A claimed completion only counts once the verifier agrees
+            if let Action::Complete { message } = &action {
+                if self.config.general.verify_completion && !recording {
+                    if let Some(rationale) = self.check_completion(provider, instruction, message, iteration).await {
+                        conversation.add_tool_result(
+                            false,
+                            None,
+                            Some(format!("A check of the screen found the task is not complete yet: {}", rationale)),
+                        );
+                        continue;
+                    }
+                }
+            }
+
             // Emit coordinate to overlay if enabled
             if show_overlay {
                 self.emit_coordinate(&action);
@@ -509,21 +1165,77 @@ impl AgentLoop {
             let action_type = Self::get_action_type(&action);
 
             // Build screen bounds for HiDPI coordinate scaling (B2)
-            let screen_bounds = if screenshot.physical_width > 0 && screenshot.physical_height > 0 {
-                Some(ScreenBounds::new(
-                    screenshot.width,
-                    screenshot.height,
-                    screenshot.physical_width,
-                    screenshot.physical_height,
-                ))
-            } else {
-                None
+            let screen_bounds = Self::screen_bounds(&screenshot);
+
+            // Dangerous actions pause for user confirmation before they run
+            let active_status = self.state.get_status().await;
+            let mut confirm_action = confirm_dangerous;
+            let execution = loop {
+                let result = execute_action_with_retry(
+                    &action,
+                    confirm_action,
+                    delay_controller.click_delay(),
+                    screen_bounds,
+                    &mut retry_ctx,
+                    &self.desktop,
+                )
+                .await;
+                let Err(ActionError::RequiresConfirmation(msg)) = result else {
+                    break result;
+                };
+
+                // Record confirmation-required action to history
+                let entry = ActionEntry {
+                    timestamp: Utc::now(),
+                    iteration,
+                    action_type: action_type.clone(),
+                    action_details: action_value.clone(),
+                    screenshot_base64: Some(screenshot.base64.clone()),
+                    screenshot_file: None,
+                    llm_response: response_str.clone(),
+                    success: false,
+                    error_message: Some(format!("Requires confirmation: {}", msg)),
+                    result_message: None,
+                    retry_count: 0,
+                    cost_usd: None,
+                };
+                self.state.history().add_entry(entry).await;
+
+                if !self.confirm_with_user(&msg).await {
+                    // User denied, no response, or timeout - abort
+                    conversation.add_tool_result(
+                        false,
+                        None,
+                        Some(format!("Action requires confirmation: {}", msg)),
+                    );
+                    return Err(self.action_denied().await);
+                }
+
+                if self.state.should_stop() {
+                    // Hide cursor indicator after confirmation period
+                    self.hide_cursor_indicator().await;
+                    self.state.set_status(AgentStatus::Idle).await;
+                    self.emit_state_update_immediate().await;
+                    return Err(LoopError::Stopped);
+                }
+
+                // Run the confirmed action again without the safety check
+                confirm_action = false;
             };
 
-            match execute_action_with_delay(&action, confirm_dangerous, delay_controller.click_delay(), screen_bounds).await {
+            if retry_ctx.attempt > 0 {
+                self.state.set_status(active_status).await;
+                self.emit_state_update_immediate().await;
+            }
+
+            match execution {
                 Ok(result) => {
-                    // Add successful tool result to conversation
-                    conversation.add_tool_result(true, result.message.clone(), None);
+                    // Add successful tool result to conversation, answering the tool call
+                    let result = match &response {
+                        LlmResponse::ToolUse { tool_use, .. } => result.with_tool_use_id(tool_use.id.clone()),
+                        LlmResponse::Text(_) => result,
+                    };
+                    conversation.add_action_result(&result);
 
 
                     // Record successful action to history
@@ -533,10 +1245,13 @@ impl AgentLoop {
                         action_type: action_type.clone(),
                         action_details: action_value.clone(),
                         screenshot_base64: Some(screenshot.base64.clone()),
+                        screenshot_file: None,
                         llm_response: response_str.clone(),
                         success: true,
                         error_message: None,
                         result_message: result.message.clone(),
+                        retry_count: result.retry_count,
+                        cost_usd: None,
                     };
                     self.state.history().add_entry(entry).await;
 
@@ -555,6 +1270,7 @@ impl AgentLoop {
                     // Record successful action in history (unless it's a terminal action)
                     if !result.completed {
                         let record = ActionRecord::new(action.clone(), true);
+                        last_record = Some(record.clone());
                         let mut history = self.action_history.write().await;
                         history.push(record);
 
@@ -563,6 +1279,13 @@ impl AgentLoop {
                         let last_undoable = history.get_last_undoable_description();
                         drop(history);
                         self.state.update_undo_state(can_undo, last_undoable).await;
+
+                        if recording {
+                            self.state
+                                .add_recorded_action(action.clone(), reasoning, Some(screenshot.base64.clone()))
+                                .await;
+                            self.emit_recorded_actions().await;
+                        }
                         self.emit_state_update().await;
                     }
 
@@ -571,106 +1294,43 @@ impl AgentLoop {
                         self.state.complete(result.message).await;
                         self.emit_state_update_immediate().await;
                         // Emit history event for successful completion
-                        let _ = self.app_handle.emit(
-                            "instruction-completed",
-                            HistoryEvent {
-                                instruction: instruction.to_string(),
-                                success: true,
-                            },
-                        );
+                        self.events.emit(AgentEvent::InstructionCompleted {
+                            instruction: instruction.to_string(),
+                            success: true,
+                        });
                         return Ok(());
                     }
                 }
-                Err(ActionError::RequiresConfirmation(msg)) => {
-                    // Add pending confirmation to conversation
-                    conversation.add_tool_result(
-                        false,
-                        None,
-                        Some(format!("Action requires confirmation: {}", msg)),
-                    );
+                Err(e) => {
+                    // Add error to conversation
+                    conversation.add_tool_result(false, None, Some(e.to_string()));
 
-                    // Record confirmation-required action to history
+                    // Record failed action to history
                     let entry = ActionEntry {
                         timestamp: Utc::now(),
                         iteration,
                         action_type: action_type.clone(),
                         action_details: action_value.clone(),
                         screenshot_base64: Some(screenshot.base64.clone()),
+                        screenshot_file: None,
                         llm_response: response_str.clone(),
                         success: false,
-                        error_message: Some(format!("Requires confirmation: {}", msg)),
+                        error_message: Some(e.to_string()),
                         result_message: None,
+                        retry_count: retry_ctx.attempt,
+                        cost_usd: None,
                     };
                     self.state.history().add_entry(entry).await;
 
-                    // Reset confirmation channel for fresh state
-                    self.state.reset_confirmation_channel().await;
-
-                    // Set pending action and status
-                    self.state.set_pending_action(Some(msg.clone())).await;
-                    self.state.set_status(AgentStatus::AwaitingConfirmation).await;
-                    self.emit_state_update_immediate().await;
-
-                    // Emit confirmation request to frontend
-                    let _ = self.app_handle.emit("confirmation-required", msg);
-
-                    // Wait for user response with 30 second timeout
-                    let confirmation_timeout = Duration::from_secs(30);
-                    let response = timeout(confirmation_timeout, self.state.await_confirmation()).await;
-
-                    // Clear pending action
-                    self.state.set_pending_action(None).await;
-
-                    match response {
-                        Ok(Some(ConfirmationResponse::Confirmed)) => {
-                            // User confirmed, continue execution
-                            self.state.set_status(AgentStatus::Running).await;
-                            self.emit_state_update_immediate().await;
-                        }
-                        Ok(Some(ConfirmationResponse::Denied)) | Ok(None) | Err(_) => {
-                            // User denied, no response, or timeout - abort
-                            self.state.set_status(AgentStatus::Idle).await;
-                            self.state.set_error("Action denied or timed out".to_string()).await;
-                            self.emit_state_update_immediate().await;
-                            return Err(LoopError::ActionDenied);
-                        }
-                    }
-
-                    // Hide cursor indicator after confirmation period
-                    self.hide_cursor_indicator().await;
-
-                    if self.state.should_stop() {
-                        self.state.set_status(AgentStatus::Idle).await;
-                        self.emit_state_update_immediate().await;
-                        return Err(LoopError::Stopped);
-                    }
-                }
-                Err(e) => {
-                    // Add error to conversation
-                    conversation.add_tool_result(false, None, Some(e.to_string()));
-
-                    // Record failed action to history
-                    let entry = ActionEntry {
-                        timestamp: Utc::now(),
-                        iteration,
-                        action_type: action_type.clone(),
-                        action_details: action_value.clone(),
-                        screenshot_base64: Some(screenshot.base64.clone()),
-                        llm_response: response_str.clone(),
-                        success: false,
-                        error_message: Some(e.to_string()),
-                        result_message: None,
-                    };
-                    self.state.history().add_entry(entry).await;
-
-                    // Increment consecutive errors
-                    self.state.increment_consecutive_errors();
+                    // Increment consecutive errors
+                    self.state.increment_consecutive_errors();
 
                     // Hide cursor indicator on error
                     self.hide_cursor_indicator().await;
 
                     // Record failed action in undo history
                     let record = ActionRecord::new(action.clone(), false);
+                    last_record = Some(record.clone());
                     let mut history = self.action_history.write().await;
                     history.push(record);
                     drop(history);
@@ -690,13 +1350,10 @@ impl AgentLoop {
                     self.emit_state_update_immediate().await;
 
                     // Emit history event for failed completion
-                    let _ = self.app_handle.emit(
-                        "instruction-completed",
-                        HistoryEvent {
-                            instruction: instruction.to_string(),
-                            success: false,
-                        },
-                    );
+                    self.events.emit(AgentEvent::InstructionCompleted {
+                        instruction: instruction.to_string(),
+                        success: false,
+                    });
 
                     return Err(e.into());
                 }
@@ -711,6 +1368,275 @@ impl AgentLoop {
         }
     }
 
+    /// Play back a recording without calling the LLM.
+    ///
+    /// At the checkpoints set in config the live screen is compared with the
+    /// one captured when the step was recorded. If they differ too much the
+    /// replay either fails with `LoopError::ReplayDiverged` or hands the task to
+    /// the LLM, which continues from the current screen with the replayed
+    /// steps in its conversation.
+    pub async fn replay(
+        &self,
+        instruction: String,
+        steps: Vec<RecordedAction>,
+    ) -> Result<ReplayOutcome, LoopError> {
+        let options = ReplayOptions::from_config(&self.config.general);
+        let total_steps = steps.len();
+
+        self.action_history.write().await.clear();
+        self.state
+            .start_with_mode(instruction.clone(), total_steps as u32, ExecutionMode::Normal)
+            .await;
+        self.state.history().set_provider("replay".to_string()).await;
+        self.state.update_undo_state(false, None).await;
+        self.emit_state_update_immediate().await;
+
+        let (index, difference) = match self.replay_steps(&steps, &options).await {
+            Ok(None) => {
+                self.state.complete(Some("Replay finished".to_string())).await;
+                self.emit_state_update_immediate().await;
+                self.state.history().complete_session("completed").await;
+                return Ok(ReplayOutcome {
+                    total_steps,
+                    steps_replayed: total_steps,
+                    diverged_at: None,
+                    difference: None,
+                    handed_off: false,
+                });
+            }
+            Ok(Some(divergence)) => divergence,
+            Err(e) => {
+                let status = match e {
+                    LoopError::Stopped => "stopped",
+                    _ => "error",
+                };
+                self.state.history().complete_session(status).await;
+                return Err(e);
+            }
+        };
+
+        if options.on_divergence == DivergenceMode::Stop {
+            let error = LoopError::ReplayDiverged {
+                step: index + 1,
+                difference,
+            };
+            self.state.set_error(error.to_string()).await;
+            self.emit_state_update_immediate().await;
+            self.state.history().complete_session("error").await;
+            return Err(error);
+        }
+
+        log::info!(
+            "Replay diverged at step {} of {}, handing over to the LLM",
+            index + 1,
+            total_steps
+        );
+        let checkpoint = self
+            .handoff_checkpoint(&instruction, &steps[..index], total_steps, difference)
+            .await;
+        self.run_task(instruction, ExecutionMode::Normal, Some(checkpoint), &RunOverrides::default())
+            .await?;
+
+        Ok(ReplayOutcome {
+            total_steps,
+            steps_replayed: index,
+            diverged_at: Some(index + 1),
+            difference: Some(difference),
+            handed_off: true,
+        })
+    }
+
+    /// Run recorded steps in order. Stops before the first checked step whose
+    /// screen no longer matches the recording and returns its index together
+    /// with the fraction of the screen that differed.
+    async fn replay_steps(
+        &self,
+        steps: &[RecordedAction],
+        options: &ReplayOptions,
+    ) -> Result<Option<(usize, f32)>, LoopError> {
+        let delay_controller = DelayController::new(self.config.general.speed_multiplier);
+        let confirm_dangerous = self.config.general.confirm_dangerous_actions;
+
+        for (index, step) in steps.iter().enumerate() {
+            while self.state.should_pause() && !self.state.should_stop() {
+                sleep(Duration::from_millis(100)).await;
+            }
+            if self.state.should_stop() {
+                self.state.set_status(AgentStatus::Idle).await;
+                self.emit_state_update_immediate().await;
+                return Err(LoopError::Stopped);
+            }
+
+            let iteration = self.state.increment_iteration();
+            let screenshot = match self.capture_with_retry().await {
+                Ok(s) => s,
+                Err(e) => {
+                    self.state.set_error(e.to_string()).await;
+                    self.emit_state_update_immediate().await;
+                    return Err(e.into());
+                }
+            };
+            self.state.set_last_screenshot(screenshot.base64.clone()).await;
+
+            if options.is_checkpoint(index) {
+                let difference = match step.screenshot_base64.clone() {
+                    Some(recorded) => screen_difference_off_thread(recorded, screenshot.base64.clone()).await,
+                    None => None,
+                };
+                if let Some(difference) = difference {
+                    if difference > options.divergence_threshold {
+                        return Ok(Some((index, difference)));
+                    }
+                }
+            }
+
+            let action_value = serde_json::to_value(&step.action).unwrap_or_default();
+            self.state.set_last_action(action_value.to_string()).await;
+            self.emit_state_update().await;
+
+            // Dangerous steps ask for confirmation, as they do in a normal run
+            let mut confirm_action = confirm_dangerous;
+            let result = loop {
+                let result = execute_action_with_delay(
+                    &step.action,
+                    confirm_action,
+                    delay_controller.click_delay(),
+                    Self::screen_bounds(&screenshot),
+                    &self.desktop,
+                )
+                .await;
+                let Err(ActionError::RequiresConfirmation(msg)) = result else {
+                    break result;
+                };
+
+                let entry = ActionEntry {
+                    timestamp: Utc::now(),
+                    iteration,
+                    action_type: Self::get_action_type(&step.action),
+                    action_details: action_value.clone(),
+                    screenshot_base64: Some(screenshot.base64.clone()),
+                    screenshot_file: None,
+                    llm_response: String::new(),
+                    success: false,
+                    error_message: Some(format!("Requires confirmation: {}", msg)),
+                    result_message: None,
+                    retry_count: 0,
+                    cost_usd: None,
+                };
+                self.state.history().add_entry(entry).await;
+
+                if !self.confirm_with_user(&msg).await {
+                    return Err(self.action_denied().await);
+                }
+                if self.state.should_stop() {
+                    self.state.set_status(AgentStatus::Idle).await;
+                    self.emit_state_update_immediate().await;
+                    return Err(LoopError::Stopped);
+                }
+                confirm_action = false;
+            };
+
+            let entry = ActionEntry {
+                timestamp: Utc::now(),
+                iteration,
+                action_type: Self::get_action_type(&step.action),
+                action_details: action_value,
+                screenshot_base64: Some(screenshot.base64.clone()),
+                screenshot_file: None,
+                llm_response: String::new(),
+                success: result.is_ok(),
+                error_message: result.as_ref().err().map(|e| e.to_string()),
+                result_message: result.as_ref().ok().and_then(|r| r.message.clone()),
+                retry_count: 0,
+                cost_usd: None,
+            };
+            self.state.history().add_entry(entry).await;
+
+            match result {
+                Ok(result) if !result.completed => {
+                    let mut history = self.action_history.write().await;
+                    history.push(ActionRecord::new(step.action.clone(), true));
+                    let can_undo = history.can_undo();
+                    let last_undoable = history.get_last_undoable_description();
+                    drop(history);
+                    self.state.update_undo_state(can_undo, last_undoable).await;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.state.set_error(e.to_string()).await;
+                    self.emit_state_update_immediate().await;
+                    return Err(e.into());
+                }
+            }
+
+            sleep(delay_controller.iteration_delay()).await;
+        }
+
+        Ok(None)
+    }
+
+    /// Conversation for the LLM to take over a diverged replay: the replayed
+    /// steps as its own earlier actions, and a note on where the screen changed
+    async fn handoff_checkpoint(
+        &self,
+        instruction: &str,
+        replayed: &[RecordedAction],
+        total_steps: usize,
+        difference: f32,
+    ) -> TaskCheckpoint {
+        let mut conversation = ConversationHistory::new();
+        conversation.set_original_instruction(instruction.to_string());
+        if !replayed.is_empty() {
+            conversation.add_user_message(instruction, None, None, None);
+            for (i, step) in replayed.iter().enumerate() {
+                let action = serde_json::to_string(&step.action).unwrap_or_default();
+                conversation.add_assistant_message(&action);
+                let note = (i + 1 == replayed.len()).then(|| {
+                    format!(
+                        "These steps were replayed from a recording. Step {} of {} was not run because \
+                         {:.0}% of the screen no longer matches the recording; continue the task from here.",
+                        replayed.len() + 1,
+                        total_steps,
+                        difference * 100.0
+                    )
+                });
+                conversation.add_tool_result(true, note, None);
+            }
+        }
+
+        let actions = self.action_history.read().await.iter().cloned().collect();
+        // The session continues, so the LLM's steps number on from the replayed
+        // ones and still get the full iteration budget
+        let iteration = self.state.get_iteration();
+        TaskCheckpoint {
+            session_id: self.state.history().session_id().await,
+            instruction: instruction.to_string(),
+            mode: ExecutionMode::Normal,
+            iteration,
+            max_iterations: self.config.general.max_iterations + iteration,
+            input_tokens: 0,
+            output_tokens: 0,
+            cost_usd: 0.0,
+            conversation,
+            actions,
+            saved_at: Utc::now(),
+        }
+    }
+
+    /// Screen bounds for HiDPI coordinate scaling
+    fn screen_bounds(screenshot: &Screenshot) -> Option<ScreenBounds> {
+        if screenshot.physical_width > 0 && screenshot.physical_height > 0 {
+            Some(ScreenBounds::new(
+                screenshot.width,
+                screenshot.height,
+                screenshot.physical_width,
+                screenshot.physical_height,
+            ))
+        } else {
+            None
+        }
+    }
+
     /// Build screenshot config from user settings
     fn screenshot_config(&self) -> ScreenshotConfig {
         ScreenshotConfig {
@@ -727,8 +1653,9 @@ impl AgentLoop {
 
         let result = retry_with_policy(&policy, classify_capture_error, || {
             let cfg = config.clone();
+            let screen = self.desktop.screen.clone();
             async move {
-                tokio::task::spawn_blocking(move || capture_primary_screen_with_config(&cfg))
+                tokio::task::spawn_blocking(move || screen.capture(&cfg))
                     .await
                     .map_err(|e| CaptureError::CaptureError(e.to_string()))?
             }
@@ -737,9 +1664,10 @@ impl AgentLoop {
 
         if result.attempts > 1 {
             self.state.increment_retry().await;
-            let _ = self
-                .app_handle
-                .emit("retry-info", format!("Screenshot captured after {} attempts", result.attempts));
+            self.events.emit(AgentEvent::RetryInfo(format!(
+                "Screenshot captured after {} attempts",
+                result.attempts
+            )));
         }
 
         result.result
@@ -761,6 +1689,8 @@ impl AgentLoop {
             Action::Error { .. } => "error".to_string(),
             Action::Batch { .. } => "batch".to_string(),
             Action::WaitForElement { .. } => "wait_for_element".to_string(),
+            Action::StepDone { .. } => "step_done".to_string(),
+            Action::Replan { .. } => "replan".to_string(),
         }
     }
 
@@ -773,6 +1703,8 @@ impl AgentLoop {
         let failure_mode = QueueFailureMode::from(self.config.general.queue_failure_mode.as_str());
         let queue_delay = Duration::from_millis(self.config.general.queue_delay_ms as u64);
 
+        // Items cut off by a restart run again, from their checkpoint if one was saved
+        let interrupted = queue.resume_interrupted().await;
         queue.set_processing(true).await;
 
         let total = queue.total_count().await;
@@ -789,11 +1721,17 @@ impl AgentLoop {
                 return Err(LoopError::Stopped);
             }
 
-            // Get next pending item
-            let item = match queue.get_next().await {
+            // Get the next item whose dependencies are met
+            let (next, skipped) = queue.next_runnable().await;
+            self.emit_skipped(skipped);
+            let item = match next {
                 Some(item) => item,
                 None => {
                     // No more items to process
+                    let skipped = queue.skipped_items().await;
+                    if !skipped.is_empty() {
+                        log::info!("Queue finished with {} skipped item(s)", skipped.len());
+                    }
                     queue.set_processing(false).await;
                     self.state.set_status(AgentStatus::Completed).await;
                     self.state.set_queue_info(total, total, false).await;
@@ -807,15 +1745,12 @@ impl AgentLoop {
             let instruction = item.instruction.clone();
 
             // Emit queue progress
-            let _ = self.app_handle.emit(
-                "queue-item-started",
-                QueueProgressEvent {
-                    current_index,
-                    total,
-                    current_instruction: instruction.to_string(),
-                    status: "running".to_string(),
-                },
-            );
+            self.events.emit(AgentEvent::QueueItemStarted(QueueProgressEvent {
+                current_index,
+                total,
+                current_instruction: instruction.to_string(),
+                status: "running".to_string(),
+            }));
 
             // Mark as running
             queue.mark_current_running().await;
@@ -823,24 +1758,62 @@ impl AgentLoop {
             self.emit_state_update().await;
             self.emit_queue_update().await;
 
-            // Run the instruction
-            let result = self.run(instruction.clone()).await;
+            // Run the instruction, re-running it on failure as often as the item allows
+            let mut checkpoint = interrupted
+                .contains(&item.id)
+                .then(|| self.checkpoint_for(&instruction))
+                .flatten();
+            let mut attempt = 0;
+            let result = loop {
+                let result = match checkpoint.take() {
+                    Some(checkpoint) => {
+                        let mode = checkpoint.mode;
+                        self.run_task(instruction.clone(), mode, Some(checkpoint), &item.overrides).await
+                    }
+                    None => self.run_task(instruction.clone(), ExecutionMode::Normal, None, &item.overrides).await,
+                };
+                queue.add_current_cost(self.state.get_task_cost()).await;
+                let retryable = !matches!(
+                    result,
+                    Ok(()) | Err(LoopError::Stopped) | Err(LoopError::ActionDenied) | Err(LoopError::BudgetExceeded(_))
+                );
+                if !retryable || attempt >= item.overrides.retries || self.state.should_stop() {
+                    break result;
+                }
+
+                attempt += 1;
+                if let Err(e) = &result {
+                    let message = format!(
+                        "Queue item failed ({}), retrying ({}/{})",
+                        e, attempt, item.overrides.retries
+                    );
+                    log::warn!("{}", message);
+                    self.events.emit(AgentEvent::RetryInfo(message));
+                }
+                queue.mark_current_running().await;
+                self.emit_queue_update().await;
+                if queue_delay.as_millis() > 0 {
+                    sleep(queue_delay).await;
+                }
+            };
 
             match result {
                 Ok(()) => {
                     queue.mark_current_completed(Some("Completed".to_string())).await;
-                    let _ = self.app_handle.emit(
-                        "queue-item-completed",
-                        QueueProgressEvent {
-                            current_index,
-                            total,
-                            current_instruction: instruction,
-                            status: "completed".to_string(),
-                        },
-                    );
+                    self.events.emit(AgentEvent::QueueItemCompleted(QueueProgressEvent {
+                        current_index,
+                        total,
+                        current_instruction: instruction,
+                        status: "completed".to_string(),
+                    }));
+                    if let Some(branch) = &item.rules.on_success {
+                        let skipped = queue.apply_branch(&item.id, branch).await;
+                        self.emit_skipped(skipped);
+                    }
                 }
                 Err(LoopError::Stopped) => {
-                    // User stopped - exit immediately
+                    // User stopped - exit immediately, leaving the item to resume
+                    queue.mark_current_interrupted().await;
                     queue.set_processing(false).await;
                     self.emit_queue_update().await;
                     return Err(LoopError::Stopped);
@@ -849,49 +1822,57 @@ impl AgentLoop {
                     let error_msg = e.to_string();
                     queue.mark_current_failed(error_msg.clone()).await;
 
-                    let _ = self.app_handle.emit(
-                        "queue-item-failed",
-                        QueueProgressEvent {
-                            current_index,
-                            total,
-                            current_instruction: instruction,
-                            status: "failed".to_string(),
-                        },
-                    );
+                    self.events.emit(AgentEvent::QueueItemFailed(QueueProgressEvent {
+                        current_index,
+                        total,
+                        current_instruction: instruction,
+                        status: "failed".to_string(),
+                    }));
+
+                    // Once the daily limit is hit every other item would fail the same way
+                    if matches!(e, LoopError::BudgetExceeded(BudgetExceeded::DailyCost { .. })) {
+                        queue.set_processing(false).await;
+                        self.state.set_queue_info(current_index + 1, total, false).await;
+                        self.emit_state_update_immediate().await;
+                        self.emit_queue_update().await;
+                        return Err(e);
+                    }
 
-                    match failure_mode {
-                        QueueFailureMode::Stop => {
-                            queue.set_processing(false).await;
-                            self.state.set_queue_info(current_index + 1, total, false).await;
-                            self.emit_state_update_immediate().await;
-                            self.emit_queue_update().await;
-                            return Err(LoopError::QueueItemFailed(error_msg));
-                        }
-                        QueueFailureMode::Continue => {
-                            // Continue to next item
-                        }
+                    // An item's own failure branch takes precedence over the queue-wide mode;
+                    // without one, dependents are skipped on the next pick
+                    if let Some(branch) = &item.rules.on_failure {
+                        let skipped = queue.apply_branch(&item.id, branch).await;
+                        self.emit_skipped(skipped);
+                    } else if failure_mode == QueueFailureMode::Stop {
+                        queue.set_processing(false).await;
+                        self.state.set_queue_info(current_index + 1, total, false).await;
+                        self.emit_state_update_immediate().await;
+                        self.emit_queue_update().await;
+                        return Err(LoopError::QueueItemFailed(error_msg));
                     }
                 }
             }
 
-            // Advance to next item
-            if !queue.advance().await {
-                // No more items
-                queue.set_processing(false).await;
-                self.state.set_status(AgentStatus::Completed).await;
-                self.state.set_queue_info(total, total, false).await;
-                self.emit_state_update_immediate().await;
-                self.emit_queue_update().await;
-                return Ok(());
-            }
-
             // Delay between queue items
-            if queue_delay.as_millis() > 0 {
+            if queue_delay.as_millis() > 0 && queue.has_pending().await {
                 sleep(queue_delay).await;
             }
         }
     }
 
+    /// The saved checkpoint, if it belongs to `instruction`
+    fn checkpoint_for(&self, instruction: &str) -> Option<TaskCheckpoint> {
+        let checkpoint = self.checkpoints.as_ref()?.load().ok()??;
+        (checkpoint.instruction == instruction).then_some(checkpoint)
+    }
+
+    fn emit_skipped(&self, skipped: Vec<SkippedItem>) {
+        for item in skipped {
+            log::info!("Skipping queue item '{}': {}", item.instruction, item.reason);
+            self.events.emit(AgentEvent::QueueItemSkipped(item));
+        }
+    }
+
     /// Emit state update with debouncing (min 50ms between emissions).
     /// Use `emit_state_update_immediate` for status transitions that must be seen immediately.
     async fn emit_state_update(&self) {
@@ -908,7 +1889,7 @@ impl AgentLoop {
         };
         if should_emit {
             let state = self.state.get_state().await;
-            let _ = self.app_handle.emit("agent-state", state);
+            self.events.emit(AgentEvent::AgentState(Box::new(state)));
         }
     }
 
@@ -920,7 +1901,18 @@ impl AgentLoop {
             *last = Instant::now();
         }
         let state = self.state.get_state().await;
-        let _ = self.app_handle.emit("agent-state", state);
+        self.events.emit(AgentEvent::AgentState(Box::new(state)));
+    }
+
+    async fn emit_recorded_actions(&self) {
+        let actions = self
+            .state
+            .get_recorded_actions()
+            .await
+            .iter()
+            .map(RecordedAction::without_screenshot)
+            .collect();
+        self.events.emit(AgentEvent::RecordedActions(actions));
     }
 
     fn emit_coordinate(&self, action: &Action) {
@@ -932,14 +1924,11 @@ impl AgentLoop {
             _ => return,
         };
 
-        let _ = self.app_handle.emit(
-            "show-coordinate",
-            json!({
-                "x": x,
-                "y": y,
-                "action_type": action_type
-            }),
-        );
+        self.events.emit(AgentEvent::ShowCoordinate {
+            x,
+            y,
+            action_type: action_type.to_string(),
+        });
     }
 
     fn emit_action_indicator(&self, action: &Action) {
@@ -1014,10 +2003,10 @@ impl AgentLoop {
                 "count": actions.len(),
                 "label": format!("batch ({} actions)", actions.len())
             }),
-            Action::Complete { .. } | Action::Error { .. } => return,
+            Action::Complete { .. } | Action::Error { .. } | Action::StepDone { .. } | Action::Replan { .. } => return,
         };
 
-        let _ = self.app_handle.emit("show-action-indicator", payload);
+        self.events.emit(AgentEvent::ShowActionIndicator(payload));
     }
 
     async fn show_cursor_indicator(&self, action: &Action) {
@@ -1029,65 +2018,815 @@ impl AgentLoop {
             _ => return,
         };
 
-        if let Some(overlay) = self.app_handle.get_webview_window("cursor-overlay") {
-            // Get available monitors
-            if let Ok(monitors) = overlay.available_monitors() {
-                // Find the monitor containing the point
-                let target_monitor = monitors
-                    .iter()
-                    .find(|m| {
-                        let pos = m.position();
-                        let size = m.size();
-                        x >= pos.x && x < pos.x + size.width as i32 &&
-                        y >= pos.y && y < pos.y + size.height as i32
-                    })
-                    .or_else(|| monitors.first());
-
-                if let Some(monitor) = target_monitor {
-                    let monitor_pos = monitor.position();
-                    let monitor_size = monitor.size();
-
-                    // Position overlay to cover the monitor
-                    let _ = overlay.set_position(PhysicalPosition::new(monitor_pos.x, monitor_pos.y));
-                    let _ = overlay.set_size(PhysicalSize::new(monitor_size.width, monitor_size.height));
-
-                    // Calculate relative position
-                    let relative_x = x - monitor_pos.x;
-                    let relative_y = y - monitor_pos.y;
-
-                    // Show overlay and emit position
-                    let _ = overlay.show();
-
-                    #[derive(Clone, Serialize)]
-                    struct CursorPayload {
-                        x: i32,
-                        y: i32,
-                        action_type: String,
-                    }
-
-                    let _ = overlay.emit("show-cursor-indicator", CursorPayload {
-                        x: relative_x,
-                        y: relative_y,
-                        action_type: action_type.to_string(),
-                    });
-                }
-            }
-        }
+        self.events.emit(AgentEvent::ShowCursorIndicator {
+            x,
+            y,
+            action_type: action_type.to_string(),
+        });
     }
 
     async fn hide_cursor_indicator(&self) {
-        if let Some(overlay) = self.app_handle.get_webview_window("cursor-overlay") {
-            let _ = overlay.emit("hide-cursor-indicator", ());
-            // Wait for animation
-            sleep(self.delay_controller.cursor_hide_delay()).await;
-            let _ = overlay.hide();
-        }
+        self.events.emit(AgentEvent::HideCursorIndicator {
+            hide_after_ms: self.delay_controller.cursor_hide_delay().as_millis() as u64,
+        });
     }
 
     async fn emit_queue_update(&self) {
         if let Some(queue) = &self.queue {
             let queue_state = queue.get_state().await;
-            let _ = self.app_handle.emit("queue-update", queue_state);
+            self.events.emit(AgentEvent::QueueUpdate(queue_state));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::conversation::Message;
+    use crate::agent::events::RecordingSink;
+    use crate::agent::fake_desktop::{FakeDesktop, InputEvent};
+    use crate::agent::queue::{QueueBranch, QueueItemRules, QueueItemStatus};
+    use crate::input::{InputDriver, Modifier};
+    use crate::llm::mock::ScriptedProvider;
+    use serde_json::json;
+
+    struct Harness {
+        agent: AgentLoop,
+        state: AgentStateManager,
+        events: RecordingSink,
+        fake: FakeDesktop,
+        provider: Arc<ScriptedProvider>,
+    }
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.general.max_iterations = 5;
+        config.general.speed_multiplier = 3.0;
+        config.general.show_coordinate_overlay = false;
+        config
+    }
+
+    fn harness(provider: ScriptedProvider, config: Config, events: RecordingSink) -> Harness {
+        let state = AgentStateManager::new();
+        let fake = FakeDesktop::new(64, 48);
+        let provider = Arc::new(provider);
+        let agent = AgentLoop::with_event_sink(
+            state.clone(),
+            config,
+            Arc::new(events.clone()),
+            Arc::new(RwLock::new(ActionHistory::default())),
+        )
+        .with_desktop(fake.desktop())
+        .with_provider(provider.clone());
+        Harness {
+            agent,
+            state,
+            events,
+            fake,
+            provider,
+        }
+    }
+
+    fn last_tool_result(history: &ConversationHistory) -> Option<(bool, Option<String>)> {
+        history
+            .messages()
+            .filter_map(|m| match m {
+                Message::ToolResult { success, error, .. } => Some((*success, error.clone())),
+                _ => None,
+            })
+            .last()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_click_then_complete() {
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 10, "y": 20}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        h.agent.run("click the button".to_string()).await.unwrap();
+
+        assert_eq!(h.fake.clicks(), vec![(10, 20)]);
+        assert_eq!(h.provider.call_count(), 2);
+        let histories = h.provider.histories();
+        assert_eq!(histories[0].original_instruction(), Some("click the button"));
+        assert_eq!(last_tool_result(&histories[1]), Some((true, None)));
+        assert_eq!(h.state.get_state().await.status, AgentStatus::Completed);
+        assert_eq!(h.events.count("instruction-completed"), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parse_error_is_fed_back_to_llm() {
+        let provider = ScriptedProvider::new().malformed().complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        h.agent.run("task".to_string()).await.unwrap();
+
+        assert_eq!(h.events.count("parse-error"), 1);
+        let (success, error) = last_tool_result(&h.provider.histories()[1]).unwrap();
+        assert!(!success);
+        assert!(error.unwrap().contains("could not be parsed"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_consecutive_parse_errors_stop_the_loop() {
+        let provider = ScriptedProvider::new()
+            .malformed()
+            .malformed()
+            .malformed()
+            .complete("never reached");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        let result = h.agent.run("task".to_string()).await;
+
+        assert!(matches!(result, Err(LoopError::TooManyErrors(3))));
+        assert_eq!(h.provider.call_count(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parse_error_streak_resets_after_valid_action() {
+        let provider = ScriptedProvider::new()
+            .malformed()
+            .malformed()
+            .tool_use("type", json!({"text": "hi"}))
+            .malformed()
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        h.agent.run("task".to_string()).await.unwrap();
+        assert_eq!(h.fake.typed_text(), "hi");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retryable_errors_hit_consecutive_limit() {
+        let provider = ScriptedProvider::new()
+            .server_error(503)
+            .server_error(502)
+            .server_error(500)
+            .complete("never reached");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        let result = h.agent.run("task".to_string()).await;
+
+        assert!(matches!(result, Err(LoopError::TooManyErrors(3))));
+        assert_eq!(h.provider.call_count(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit_waits_and_retries() {
+        let provider = ScriptedProvider::new().rate_limited().complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        let started = Instant::now();
+        h.agent.run("task".to_string()).await.unwrap();
+
+        assert_eq!(h.provider.call_count(), 2);
+        assert!(started.elapsed() >= Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fatal_error_aborts_immediately() {
+        let provider = ScriptedProvider::new()
+            .error(crate::llm::LlmError::ApiError("invalid x-api-key".to_string()))
+            .complete("never reached");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        let result = h.agent.run("task".to_string()).await;
+
+        assert!(matches!(result, Err(LoopError::LlmError(_))));
+        assert_eq!(h.provider.call_count(), 1);
+        assert_eq!(h.provider.remaining(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_budget_is_enforced() {
+        let provider = ScriptedProvider::new()
+            .with_token_usage(80, 30)
+            .tool_use("click", json!({"x": 1, "y": 1}))
+            .complete("never reached");
+        let mut config = test_config();
+        config.general.max_tokens_per_task = Some(100);
+        let h = harness(provider, config, RecordingSink::new());
+
+        let result = h.agent.run("task".to_string()).await;
+
+        assert!(matches!(
+            result,
+            Err(LoopError::BudgetExceeded(BudgetExceeded::Tokens { used: 110, limit: 100 }))
+        ));
+        assert_eq!(h.provider.call_count(), 1);
+        assert_eq!(h.events.count("token-budget-exceeded"), 1);
+        let session = h.state.history().get_session().await.unwrap();
+        assert_eq!(session.final_status, "budget_exceeded");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cost_budgets_are_enforced() {
+        use crate::config::pricing::{ModelPrice, PriceEntry};
+
+        // 80 input tokens at $5000/Mtok is $0.40 per call
+        let mut config = test_config();
+        config.pricing = vec![PriceEntry {
+            provider: "mock".to_string(),
+            model: "*".to_string(),
+            price: ModelPrice::new(5000.0, 0.0),
+        }];
+        config.general.max_cost_per_task = Some(1.0);
+        let provider = ScriptedProvider::new()
+            .with_token_usage(80, 30)
+            .tool_use("click", json!({"x": 1, "y": 1}))
+            .tool_use("click", json!({"x": 2, "y": 2}))
+            .tool_use("click", json!({"x": 3, "y": 3}))
+            .complete("never reached");
+        let h = harness(provider, config.clone(), RecordingSink::new());
+
+        let result = h.agent.run("task".to_string()).await;
+
+        assert!(matches!(result, Err(LoopError::BudgetExceeded(BudgetExceeded::TaskCost { .. }))));
+        assert_eq!(h.provider.call_count(), 3);
+        assert_eq!(h.events.count("cost-budget-exceeded"), 1);
+        assert!((h.state.get_state().await.total_cost_usd - 1.2).abs() < 1e-9);
+        assert!((h.state.spend().today().spent_usd - 1.2).abs() < 1e-9);
+        let session = h.state.history().get_session().await.unwrap();
+        assert!((session.metrics.total_cost_usd.unwrap() - 1.2).abs() < 1e-9);
+        assert!((session.entries[0].cost_usd.unwrap() - 0.4).abs() < 1e-9);
+
+        // The daily limit counts what earlier tasks spent
+        config.general.max_cost_per_task = None;
+        config.general.max_cost_per_day = Some(1.0);
+        let provider = ScriptedProvider::new().complete("never reached");
+        let h2 = harness(provider, config, RecordingSink::new());
+        h2.state.spend().add(1.2);
+
+        let result = h2.agent.run("task".to_string()).await;
+
+        assert!(matches!(result, Err(LoopError::BudgetExceeded(BudgetExceeded::DailyCost { .. }))));
+        assert_eq!(h2.provider.call_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_planner_sub_goals() {
+        let provider = ScriptedProvider::new()
+            .text("1. Open the File menu\n2. Click Save")
+            .tool_use("click", json!({"x": 1, "y": 1}))
+            .tool_use("step_done", json!({}))
+            .tool_use("replan", json!({"reason": "a dialog is in the way"}))
+            .text("1. Close the dialog\n2. Click Save")
+            .complete("saved");
+        let mut config = test_config();
+        config.general.max_iterations = 10;
+        config.general.enable_planner = true;
+        let h = harness(provider, config, RecordingSink::new());
+
+        h.agent.run("save the file".to_string()).await.unwrap();
+
+        let histories = h.provider.histories();
+        assert_eq!(histories.len(), 6);
+        assert!(histories[0].plan().is_none());
+        assert_eq!(histories[1].plan().unwrap().current().unwrap().1.description, "Open the File menu");
+        assert_eq!(histories[3].plan().unwrap().current().unwrap().0, 2);
+        let (tool_ok, _) = last_tool_result(&histories[3]).unwrap();
+        assert!(tool_ok);
+
+        let plan = h.state.get_state().await.plan.unwrap();
+        assert_eq!(plan.revision, 1);
+        let goals: Vec<(&str, bool)> = plan.goals.iter().map(|g| (g.description.as_str(), g.done)).collect();
+        assert_eq!(
+            goals,
+            vec![("Open the File menu", true), ("Close the dialog", false), ("Click Save", false)]
+        );
+        assert_eq!(histories[5].plan(), Some(&plan));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_completion_verifier_can_reject() {
+        let provider = ScriptedProvider::new()
+            .complete("saved the file")
+            .text("NO - the Save dialog is still open.")
+            .tool_use("click", json!({"x": 5, "y": 5}))
+            .complete("saved the file")
+            .text("YES. The dialog is gone and the title shows the file name.");
+        let mut config = test_config();
+        config.general.verify_completion = true;
+        let h = harness(provider, config, RecordingSink::new());
+
+        h.agent.run("save the file".to_string()).await.unwrap();
+
+        assert_eq!(h.provider.call_count(), 5);
+        let histories = h.provider.histories();
+        let (success, error) = last_tool_result(&histories[2]).unwrap();
+        assert!(!success);
+        assert!(error.unwrap().contains("the Save dialog is still open"));
+
+        let session = h.state.history().get_session().await.unwrap();
+        let outcomes: Vec<VerdictOutcome> = session.verdicts.iter().map(|v| v.outcome).collect();
+        assert_eq!(outcomes, vec![VerdictOutcome::Rejected, VerdictOutcome::Confirmed]);
+        assert_eq!(session.final_status, "completed");
+        assert!(session.to_text().contains("Rejected by mock: the Save dialog is still open."));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_evicted_steps_are_summarized() {
+        // Seven steps fill the 20-message window; the eighth pushes the first out
+        let mut provider = ScriptedProvider::new();
+        for i in 0..7 {
+            provider = provider.tool_use("click", json!({"x": 8 * i, "y": 5}));
+        }
+        let provider = provider
+            .text("Clicked along the toolbar, nothing opened yet.")
+            .tool_use("click", json!({"x": 30, "y": 30}))
+            .complete("done");
+        let mut config = test_config();
+        config.general.max_iterations = 10;
+        config.general.summarize_with_llm = true;
+        let h = harness(provider, config, RecordingSink::new());
+
+        h.agent.run("find the toolbar button".to_string()).await.unwrap();
+
+        let histories = h.provider.histories();
+        assert_eq!(histories.len(), 10);
+        let request = histories[7]
+            .messages()
+            .find_map(|m| match m {
+                Message::User { instruction, .. } => Some(instruction.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert!(request.contains("Steps being dropped:\n- Click left at (0, 5): ok"));
+        assert!(histories[7].progress_note().is_none());
+        assert_eq!(histories[8].progress_note(), Some("Clicked along the toolbar, nothing opened yet."));
+        assert_eq!(histories[8].len(), 20);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stuck_task_gets_hint_then_aborts() {
+        let mut provider = ScriptedProvider::new();
+        for _ in 0..8 {
+            provider = provider.tool_use("click", json!({"x": 5, "y": 5}));
+        }
+        let mut config = test_config();
+        config.general.max_iterations = 20;
+        config.general.enable_self_correction = false;
+        let h = harness(provider, config, RecordingSink::new());
+        // Park the cursor on the target so nothing at all changes on screen
+        h.fake.move_to(5, 5).unwrap();
+        h.fake.freeze();
+
+        let result = h.agent.run("open the menu".to_string()).await;
+
+        // Three clicks without a change earn a hint; three more, with no stronger
+        // model and nobody to ask, end the task
+        assert!(matches!(result, Err(LoopError::Stuck(StuckPattern::Repeating { times: 3, .. }))));
+        assert_eq!(h.provider.call_count(), 6);
+        let hinted = h.provider.histories()[3]
+            .messages()
+            .filter_map(|m| match m {
+                Message::User { instruction, .. } => Some(instruction.clone()),
+                _ => None,
+            })
+            .last()
+            .unwrap();
+        assert!(hinted.starts_with("Your last 3 actions were all"));
+
+        let recoveries: Vec<Recovery> = h
+            .events
+            .events()
+            .iter()
+            .filter_map(|e| match e {
+                AgentEvent::StuckDetected { recovery, .. } => Some(*recovery),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(recoveries, vec![Recovery::Hint, Recovery::Abort]);
+        let session = h.state.history().get_session().await.unwrap();
+        assert_eq!(session.final_status, "stuck");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dangerous_action_denied_without_confirmation() {
+        let provider = ScriptedProvider::new()
+            .tool_use("key", json!({"key": "q", "modifiers": ["cmd"]}))
+            .complete("never reached");
+        let h = harness(provider, test_config(), RecordingSink::new());
+
+        let result = h.agent.run("quit".to_string()).await;
+
+        assert!(matches!(result, Err(LoopError::ActionDenied)));
+        assert_eq!(h.events.count("confirmation-required"), 1);
+        assert!(h.fake.events().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_confirmed_action_is_executed() {
+        let provider = ScriptedProvider::new()
+            .tool_use("key", json!({"key": "q", "modifiers": ["cmd"]}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new().with_confirmations());
+
+        let Harness {
+            agent,
+            state,
+            fake,
+            provider,
+            ..
+        } = h;
+        let run = tokio::spawn(async move { agent.run("quit".to_string()).await });
+
+        while state.get_state().await.status != AgentStatus::AwaitingConfirmation {
+            sleep(Duration::from_millis(10)).await;
+        }
+        state
+            .send_confirmation(ConfirmationResponse::Confirmed)
+            .await
+            .unwrap();
+
+        run.await.unwrap().unwrap();
+        assert_eq!(
+            fake.events(),
+            vec![InputEvent::Key {
+                key: "q".into(),
+                modifiers: vec![Modifier::Meta],
+            }]
+        );
+        assert_eq!(last_tool_result(&provider.histories()[1]), Some((true, None)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_action_without_visible_effect_is_retried() {
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 5, "y": 5}))
+            .complete("done");
+        let mut config = test_config();
+        config.general.max_retries = 2;
+        config.general.retry_delay_ms = 10;
+        let h = harness(provider, config, RecordingSink::new());
+        h.fake.freeze();
+
+        h.agent.run("click".to_string()).await.unwrap();
+
+        assert_eq!(h.fake.clicks().len(), 3);
+        assert_eq!(h.events.count("retry-info"), 2);
+        assert!(h.events.events().iter().any(|e| matches!(
+            e,
+            AgentEvent::AgentState(state) if state.status == AgentStatus::Retrying
+        )));
+
+        let session = h.state.history().get_session().await.unwrap();
+        assert_eq!(session.entries[0].retry_count, 2);
+        let state = h.state.get_state().await;
+        assert_eq!(state.status, AgentStatus::Completed);
+        assert_eq!(state.total_retries, 2);
+
+        // The LLM is told the click had no effect so it can correct itself
+        let (success, _) = last_tool_result(&h.provider.histories()[1]).unwrap();
+        assert!(success);
+        let feedback = h.provider.histories()[1]
+            .messages()
+            .find_map(|m| match m {
+                Message::ToolResult { message, .. } => message.clone(),
+                _ => None,
+            })
+            .unwrap();
+        assert!(feedback.contains("No screen change detected"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_retries_when_self_correction_disabled() {
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 5, "y": 5}))
+            .complete("done");
+        let mut config = test_config();
+        config.general.enable_self_correction = false;
+        let h = harness(provider, config, RecordingSink::new());
+        h.fake.freeze();
+
+        h.agent.run("click".to_string()).await.unwrap();
+
+        assert_eq!(h.fake.clicks().len(), 1);
+        assert_eq!(h.events.count("retry-info"), 0);
+        let session = h.state.history().get_session().await.unwrap();
+        assert_eq!(session.entries[0].retry_count, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_session_is_written_to_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 3, "y": 4}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+        let store = crate::agent::session_store::SessionStore::new(dir.path().to_path_buf());
+        h.state.history().set_store(Some(store.clone()));
+
+        h.agent.run("click once".to_string()).await.unwrap();
+
+        let sessions = store.list(&Default::default()).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].final_status, "completed");
+        assert_eq!(sessions[0].provider.as_deref(), Some("mock"));
+
+        let saved = store.load(&sessions[0].session_id, true).unwrap();
+        assert_eq!(saved.entries.len(), 2);
+        assert_eq!(saved.entries[0].screenshot_file.as_deref(), Some("screenshots/0001.png"));
+        assert!(saved.entries[1].screenshot_base64.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_checkpoint_cleared_after_completion() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CheckpointStore::new(dir.path().join("checkpoint.json"));
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 3, "y": 4}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+        let agent = h.agent.with_checkpoints(store.clone());
+
+        agent.run("click once".to_string()).await.unwrap();
+
+        assert!(store.load().unwrap().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_run_keeps_checkpoint_and_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CheckpointStore::new(dir.path().join("checkpoint.json"));
+
+        // First run clicks once, then dies on a fatal provider error
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 3, "y": 4}))
+            .error(crate::llm::LlmError::ApiError("invalid api key".to_string()));
+        let h = harness(provider, test_config(), RecordingSink::new());
+        let agent = h.agent.with_checkpoints(store.clone());
+        assert!(agent.run("click twice".to_string()).await.is_err());
+
+        let checkpoint = store.load().unwrap().expect("checkpoint kept after failure");
+        assert_eq!(checkpoint.instruction, "click twice");
+        assert_eq!(checkpoint.iteration, 1);
+        assert_eq!(checkpoint.input_tokens, 10);
+        assert_eq!(checkpoint.conversation.len(), 3);
+        assert_eq!(checkpoint.actions.len(), 1);
+
+        // A fresh loop picks up from the checkpoint
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 5, "y": 6}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+        let agent = h.agent.with_checkpoints(store.clone());
+        agent.resume(checkpoint).await.unwrap();
+
+        assert_eq!(h.fake.clicks(), vec![(5, 6)]);
+        let first = &h.provider.histories()[0];
+        assert_eq!(first.len(), 4);
+        match first.messages().last() {
+            Some(Message::User { instruction, screenshot_base64, .. }) => {
+                assert!(instruction.contains("resumed"));
+                assert!(screenshot_base64.is_some());
+            }
+            other => panic!("expected resume prompt, got {:?}", other),
+        }
+
+        assert_eq!(h.state.get_iteration(), 3);
+        let (_, input_tokens, _) = h.state.get_token_metrics();
+        assert_eq!(input_tokens, 30);
+        assert!(store.load().unwrap().is_none());
+    }
+
+    // ── recording and replay ─────────────────────────────────────
+
+    /// Record a click followed by typing on a fresh fake desktop
+    async fn record_click_and_type() -> Vec<RecordedAction> {
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 10, "y": 20}))
+            .tool_use("type", json!({"text": "hi"}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+        h.agent.run_recording("fill the form".to_string()).await.unwrap();
+        h.state.get_recorded_actions().await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recording_stores_actions_with_screenshots() {
+        let provider = ScriptedProvider::new()
+            .tool_use("click", json!({"x": 10, "y": 20}))
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+        h.agent.run_recording("click it".to_string()).await.unwrap();
+
+        let recorded = h.state.get_recorded_actions().await;
+        assert_eq!(recorded.len(), 1);
+        assert!(matches!(recorded[0].action, Action::Click { x: 10, y: 20, .. }));
+        assert!(recorded[0].screenshot_base64.is_some());
+
+        let sent = h.events.events().into_iter().find_map(|e| match e {
+            AgentEvent::RecordedActions(actions) => Some(actions),
+            _ => None,
+        });
+        let sent = sent.expect("recorded-actions emitted");
+        assert!(sent[0].screenshot_base64.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_runs_without_llm() {
+        let steps = record_click_and_type().await;
+        assert_eq!(steps.len(), 2);
+
+        let h = harness(ScriptedProvider::new(), test_config(), RecordingSink::new());
+        let outcome = h.agent.replay("fill the form".to_string(), steps).await.unwrap();
+
+        assert_eq!(outcome.steps_replayed, 2);
+        assert!(outcome.diverged_at.is_none());
+        assert_eq!(h.fake.clicks(), vec![(10, 20)]);
+        assert_eq!(h.fake.typed_text(), "hi");
+        assert_eq!(h.provider.call_count(), 0);
+        assert_eq!(h.state.get_status().await, AgentStatus::Completed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_asks_before_dangerous_steps() {
+        let steps = vec![RecordedAction {
+            action: Action::Key { key: "q".to_string(), modifiers: vec!["cmd".to_string()] },
+            reasoning: None,
+            timestamp: 0,
+            iteration: 1,
+            screenshot_base64: None,
+        }];
+
+        let h = harness(ScriptedProvider::new(), test_config(), RecordingSink::new());
+        let result = h.agent.replay("quit".to_string(), steps.clone()).await;
+        assert!(matches!(result, Err(LoopError::ActionDenied)));
+        assert!(h.fake.events().is_empty());
+
+        let h = harness(ScriptedProvider::new(), test_config(), RecordingSink::new().with_confirmations());
+        let Harness { agent, state, fake, .. } = h;
+        let replay = tokio::spawn(async move { agent.replay("quit".to_string(), steps).await });
+        while state.get_state().await.status != AgentStatus::AwaitingConfirmation {
+            sleep(Duration::from_millis(10)).await;
+        }
+        state.send_confirmation(ConfirmationResponse::Confirmed).await.unwrap();
+
+        let outcome = replay.await.unwrap().unwrap();
+        assert_eq!(outcome.steps_replayed, 1);
+        assert_eq!(fake.events(), vec![InputEvent::Key { key: "q".into(), modifiers: vec![Modifier::Meta] }]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_stops_on_divergence() {
+        let steps = record_click_and_type().await;
+
+        let h = harness(ScriptedProvider::new(), test_config(), RecordingSink::new());
+        // Something else changed the screen before the replay started
+        for _ in 0..3 {
+            h.fake.type_text("x").unwrap();
         }
+
+        let result = h.agent.replay("fill the form".to_string(), steps).await;
+
+        assert!(matches!(result, Err(LoopError::ReplayDiverged { step: 1, .. })));
+        assert!(h.fake.clicks().is_empty());
+        assert_eq!(h.provider.call_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_hands_off_to_llm_on_divergence() {
+        let steps = record_click_and_type().await;
+
+        let mut config = test_config();
+        config.general.replay_on_divergence = "llm".to_string();
+        let provider = ScriptedProvider::new().complete("typed it myself");
+        let h = harness(provider, config, RecordingSink::new());
+        // The click runs but no longer changes the screen, so step 2 diverges
+        h.fake.freeze();
+
+        let outcome = h.agent.replay("fill the form".to_string(), steps).await.unwrap();
+
+        assert!(outcome.handed_off);
+        assert_eq!(outcome.steps_replayed, 1);
+        assert_eq!(outcome.diverged_at, Some(2));
+        assert_eq!(h.fake.clicks(), vec![(10, 20)]);
+        assert_eq!(h.fake.typed_text(), "");
+
+        let history = &h.provider.histories()[0];
+        assert_eq!(history.len(), 4);
+        let (success, _) = last_tool_result(history).unwrap();
+        assert!(success);
+
+        // The LLM's step is numbered after the two the replay used
+        assert_eq!(h.state.get_iteration(), 3);
+        let session = h.state.history().get_session().await.unwrap();
+        let iterations: Vec<u32> = session.entries.iter().map(|e| e.iteration).collect();
+        assert_eq!(iterations, vec![1, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stopped_queue_item_is_interrupted() {
+        let provider = ScriptedProvider::new().tool_use("key", json!({"key": "q", "modifiers": ["cmd"]}));
+        let h = harness(provider, test_config(), RecordingSink::new().with_confirmations());
+        let queue = QueueManager::new();
+        queue.add("quit".to_string()).await;
+        queue.add("never reached".to_string()).await;
+
+        let Harness { agent, state, .. } = h;
+        let agent = agent.with_queue(queue.clone());
+        let run = tokio::spawn(async move { agent.run_queue().await });
+        while state.get_state().await.status != AgentStatus::AwaitingConfirmation {
+            sleep(Duration::from_millis(10)).await;
+        }
+        state.request_stop();
+        state.send_confirmation(ConfirmationResponse::Confirmed).await.unwrap();
+
+        assert!(matches!(run.await.unwrap(), Err(LoopError::Stopped)));
+        let statuses: Vec<_> = queue.get_state().await.items.iter().map(|item| item.status).collect();
+        assert_eq!(statuses, vec![QueueItemStatus::Interrupted, QueueItemStatus::Pending]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_runs_cleanup_and_skips_dependents_on_failure() {
+        let provider = ScriptedProvider::new()
+            .error(crate::llm::LlmError::ApiError("invalid x-api-key".to_string()))
+            .complete("cleaned up")
+            .complete("done");
+        let h = harness(provider, test_config(), RecordingSink::new());
+        let queue = QueueManager::new();
+        let setup = queue.add("set up".to_string()).await;
+        let uses_setup = queue.add("use setup".to_string()).await;
+        queue.add("independent".to_string()).await;
+        let cleanup = queue.add("clean up".to_string()).await;
+        queue
+            .set_rules(&cleanup, QueueItemRules { triggered_only: true, ..Default::default() })
+            .await
+            .unwrap();
+        queue
+            .set_rules(&setup, QueueItemRules {
+                on_failure: Some(QueueBranch::RunCleanup { item_id: cleanup.clone() }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        queue
+            .set_rules(&uses_setup, QueueItemRules { depends_on: vec![setup.clone()], ..Default::default() })
+            .await
+            .unwrap();
+
+        // The failure branch overrides the default Stop failure mode
+        h.agent.with_queue(queue.clone()).run_queue().await.unwrap();
+
+        let instructions: Vec<String> = h
+            .provider
+            .histories()
+            .iter()
+            .filter_map(|history| history.original_instruction().map(str::to_string))
+            .collect();
+        assert_eq!(instructions, vec!["set up", "clean up", "independent"]);
+        let skipped = queue.skipped_items().await;
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].id, uses_setup);
+        assert_eq!(skipped[0].reason, "Dependency 'set up' failed");
+        assert_eq!(h.events.count("queue-item-skipped"), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_item_overrides() {
+        let provider = ScriptedProvider::new()
+            // flaky: fails once, then succeeds on the retry
+            .error(crate::llm::LlmError::ApiError("invalid x-api-key".to_string()))
+            .complete("done")
+            // slow: the rate limit wait outlasts its timeout
+            .rate_limited()
+            // short: one iteration allowed
+            .tool_use("click", json!({"x": 1, "y": 1}));
+        let mut config = test_config();
+        config.general.queue_failure_mode = "continue".to_string();
+        let h = harness(provider, config, RecordingSink::new());
+
+        let queue = QueueManager::new();
+        let ids = queue
+            .add_multiple(vec!["flaky".to_string(), "slow".to_string(), "short".to_string()])
+            .await;
+        let overrides = [
+            RunOverrides { retries: 1, ..Default::default() },
+            RunOverrides { timeout_secs: Some(10), ..Default::default() },
+            RunOverrides { max_iterations: Some(1), ..Default::default() },
+        ];
+        for (id, overrides) in ids.iter().zip(overrides) {
+            queue.set_overrides(id, overrides).await.unwrap();
+        }
+
+        h.agent.with_queue(queue.clone()).run_queue().await.unwrap();
+
+        let items = queue.get_all().await;
+        assert_eq!(items[0].status, QueueItemStatus::Completed);
+        assert_eq!(items[0].attempts, 2);
+        assert_eq!(items[1].status, QueueItemStatus::Failed);
+        assert_eq!(items[1].error.as_deref(), Some("Timed out after 10s"));
+        assert_eq!(items[2].status, QueueItemStatus::Failed);
+        assert_eq!(items[2].error.as_deref(), Some("Max iterations reached"));
+        assert_eq!(h.provider.remaining(), 0);
+        let queue_retries = h
+            .events
+            .events()
+            .into_iter()
+            .filter(|e| matches!(e, AgentEvent::RetryInfo(m) if m.starts_with("Queue item failed")))
+            .count();
+        assert_eq!(queue_retries, 1);
     }
 }
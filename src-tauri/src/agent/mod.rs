@@ -1,12 +1,25 @@
 pub mod action;
+pub mod budget;
+pub mod checkpoint;
+pub mod context;
 pub mod conversation;
 pub mod delay;
+pub mod desktop;
+pub mod events;
+#[cfg(test)]
+pub mod fake_desktop;
 pub mod history;
 pub mod loop_runner;
+pub mod plan;
 pub mod queue;
 pub mod recovery;
+pub mod replay;
 pub mod retry;
+pub mod scheduler;
+pub mod session_store;
 pub mod state;
+pub mod stuck;
+pub mod verifier;
 
 pub use delay::*;
 pub use history::*;
@@ -0,0 +1,243 @@
+//! Optional planning phase: the instruction is broken into numbered sub-goals
+//! before the first action, and the executor works through them one by one.
+
+use super::conversation::ConversationHistory;
+use crate::capture::Screenshot;
+use crate::llm::{LlmError, LlmProvider, LlmResponse, TokenMetrics};
+use serde::{Deserialize, Serialize};
+
+/// Longest plan the planner may produce; anything after this is dropped
+const MAX_SUB_GOALS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubGoal {
+    pub description: String,
+    #[serde(default)]
+    pub done: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskPlan {
+    pub goals: Vec<SubGoal>,
+    /// How many times the plan has been replaced since the first one
+    #[serde(default)]
+    pub revision: u32,
+}
+
+impl TaskPlan {
+    /// Read a numbered list (`1. ...` or `1) ...`) out of planner output.
+    /// Returns None when there is no numbered line at all.
+    pub fn parse(text: &str) -> Option<Self> {
+        let goals: Vec<SubGoal> = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim().trim_start_matches(['*', '-']).trim_start();
+                let digits = line.find(|c: char| !c.is_ascii_digit())?;
+                if digits == 0 {
+                    return None;
+                }
+                let rest = line[digits..].strip_prefix(['.', ')'])?.trim();
+                // Models like to bold whole items
+                let description = rest
+                    .strip_prefix("**")
+                    .and_then(|bold| bold.strip_suffix("**"))
+                    .unwrap_or(rest)
+                    .trim();
+                (!description.is_empty()).then(|| SubGoal {
+                    description: description.to_string(),
+                    done: false,
+                })
+            })
+            .take(MAX_SUB_GOALS)
+            .collect();
+
+        (!goals.is_empty()).then_some(Self { goals, revision: 0 })
+    }
+
+    /// The first sub-goal that is not done yet, with its 1-based number
+    pub fn current(&self) -> Option<(usize, &SubGoal)> {
+        self.goals
+            .iter()
+            .enumerate()
+            .find(|(_, goal)| !goal.done)
+            .map(|(i, goal)| (i + 1, goal))
+    }
+
+    /// Mark a sub-goal done by its 1-based number, or the current one.
+    /// Returns a note for the executor on what comes next.
+    pub fn mark_done(&mut self, step: Option<usize>) -> Result<String, String> {
+        let number = match step {
+            Some(number) => number,
+            None => self.current().map(|(number, _)| number).ok_or("Every step of the plan is already done")?,
+        };
+        let goal = number
+            .checked_sub(1)
+            .and_then(|i| self.goals.get_mut(i))
+            .ok_or_else(|| format!("The plan has no step {}", number))?;
+        goal.done = true;
+
+        Ok(match self.current() {
+            Some((next, goal)) => format!("Step {} done. Next: {}. {}", number, next, goal.description),
+            None => format!("Step {} done. Every step of the plan is done; check the task is finished.", number),
+        })
+    }
+
+    /// Replace the remaining sub-goals with a new plan, keeping the ones already done
+    pub fn revise(&mut self, remaining: TaskPlan) {
+        self.goals.retain(|goal| goal.done);
+        let room = MAX_SUB_GOALS.saturating_sub(self.goals.len()).max(1);
+        self.goals.extend(remaining.goals.into_iter().take(room));
+        self.revision += 1;
+    }
+
+    /// Plan section of the system prompt. `tools` selects how the plan
+    /// actions are described: as tools, or as JSON actions.
+    pub fn prompt_section(&self, tools: bool) -> String {
+        let mut section = String::from("\n\n## Plan");
+
+        let completed: Vec<String> = self
+            .goals
+            .iter()
+            .enumerate()
+            .filter(|(_, goal)| goal.done)
+            .map(|(i, goal)| format!("{}. {}", i + 1, goal.description))
+            .collect();
+        if !completed.is_empty() {
+            section.push_str(&format!("\nCompleted:\n{}", completed.join("\n")));
+        }
+
+        match self.current() {
+            Some((number, goal)) => {
+                section.push_str(&format!("\nCurrent sub-goal: {}. {}", number, goal.description));
+                let remaining: Vec<String> = self
+                    .goals
+                    .iter()
+                    .enumerate()
+                    .skip(number)
+                    .map(|(i, goal)| format!("{}. {}", i + 1, goal.description))
+                    .collect();
+                if !remaining.is_empty() {
+                    section.push_str(&format!("\nAfter that:\n{}", remaining.join("\n")));
+                }
+            }
+            None => section.push_str("\nAll sub-goals are done. Check the task is finished, then complete it."),
+        }
+
+        if tools {
+            section.push_str(
+                "\nWork on the current sub-goal. Use the \"step_done\" tool once it is achieved, \
+                 or the \"replan\" tool if the plan no longer fits what is on screen.",
+            );
+        } else {
+            section.push_str(
+                "\nWork on the current sub-goal. Once it is achieved, respond with \
+                 {\"action\": \"step_done\"}; if the plan no longer fits what is on screen, respond with \
+                 {\"action\": \"replan\", \"reason\": \"why\"}.",
+            );
+        }
+        section
+    }
+}
+
+fn planning_request(previous: Option<&TaskPlan>, reason: Option<&str>) -> String {
+    let format = "Reply with a numbered list only, one sub-goal per line, for example:\n\
+                  1. Open the Settings app\n\
+                  2. Go to the Display section\n\
+                  Do not call a tool or return an action yet.";
+
+    match previous {
+        Some(plan) => {
+            let done: Vec<String> = plan
+                .goals
+                .iter()
+                .filter(|goal| goal.done)
+                .map(|goal| format!("- {}", goal.description))
+                .collect();
+            let done = if done.is_empty() { "- nothing yet".to_string() } else { done.join("\n") };
+            format!(
+                "The plan for this task no longer fits: {}\n\nAlready done:\n{}\n\n\
+                 Looking at the current screen, write a new plan for what is left, at most {} sub-goals. {}",
+                reason.unwrap_or("no reason given"),
+                done,
+                MAX_SUB_GOALS,
+                format
+            )
+        }
+        None => format!(
+            "Before acting, break the task into a short plan of at most {} concrete sub-goals, \
+             starting from what is on the screen now. {}",
+            MAX_SUB_GOALS, format
+        ),
+    }
+}
+
+/// Ask the model for a plan, or for a revised one when `previous` is given.
+/// The result holds only the new sub-goals; merge it with `TaskPlan::revise`.
+pub async fn request_plan(
+    provider: &dyn LlmProvider,
+    instruction: &str,
+    screenshot: &Screenshot,
+    previous: Option<&TaskPlan>,
+    reason: Option<&str>,
+) -> Result<(TaskPlan, TokenMetrics), LlmError> {
+    let mut conversation = ConversationHistory::new();
+    conversation.set_original_instruction(instruction.to_string());
+    conversation.add_user_message(
+        &planning_request(previous, reason),
+        Some(screenshot.base64.clone()),
+        Some(screenshot.width),
+        Some(screenshot.height),
+    );
+
+    let (response, metrics) = provider
+        .send_with_history(&conversation, screenshot.width, screenshot.height, Box::new(|_| {}))
+        .await?;
+    let text = match &response {
+        LlmResponse::Text(text) => text.as_str(),
+        LlmResponse::ToolUse { reasoning, .. } => reasoning.as_deref().unwrap_or_default(),
+    };
+    let plan = TaskPlan::parse(text)
+        .ok_or_else(|| LlmError::ParseError("Planner reply did not contain a numbered list of sub-goals".to_string()))?;
+    Ok((plan, metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numbered_list() {
+        let plan = TaskPlan::parse(
+            "Here is the plan:\n1. Open Safari\n2) **Go to example.com**\n\n- 3. Click Sign in\nThat's it.",
+        )
+        .unwrap();
+        let goals: Vec<&str> = plan.goals.iter().map(|g| g.description.as_str()).collect();
+        assert_eq!(goals, vec!["Open Safari", "Go to example.com", "Click Sign in"]);
+        assert!(TaskPlan::parse("I'll click the button").is_none());
+        assert!(TaskPlan::parse("2024 was a year").is_none());
+    }
+
+    #[test]
+    fn test_mark_done_and_revise() {
+        let mut plan = TaskPlan::parse("1. Open Mail\n2. Write draft\n3. Send").unwrap();
+        assert_eq!(plan.current().unwrap().0, 1);
+
+        assert_eq!(plan.mark_done(None).unwrap(), "Step 1 done. Next: 2. Write draft");
+        assert!(plan.mark_done(Some(7)).is_err());
+        let section = plan.prompt_section(true);
+        assert!(section.contains("Completed:\n1. Open Mail"));
+        assert!(section.contains("Current sub-goal: 2. Write draft"));
+        assert!(section.contains("After that:\n3. Send"));
+
+        plan.revise(TaskPlan::parse("1. Close the popup\n2. Write draft\n3. Send").unwrap());
+        assert_eq!(plan.revision, 1);
+        assert_eq!(plan.goals.len(), 4);
+        assert_eq!(plan.current().unwrap(), (2, &SubGoal { description: "Close the popup".to_string(), done: false }));
+
+        for _ in 0..3 {
+            plan.mark_done(None).unwrap();
+        }
+        assert!(plan.current().is_none());
+        assert!(plan.mark_done(None).is_err());
+    }
+}
@@ -1,17 +1,46 @@
 #![allow(dead_code)]
 
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+#[derive(Error, Debug, PartialEq)]
+pub enum QueueError {
+    #[error("Queue item not found: {0}")]
+    UnknownItem(String),
+    #[error("A queue item cannot depend on or branch to itself")]
+    SelfReference,
+    #[error("Dependencies would form a cycle")]
+    DependencyCycle,
+    #[error("{0} must be at least 1")]
+    InvalidOverride(&'static str),
+}
+
+#[derive(Error, Debug)]
+pub enum QueueStoreError {
+    #[error("Failed to access queue file: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to parse queue file: {0}")]
+    ParseError(#[from] serde_json::Error),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum QueueItemStatus {
     Pending,
     Running,
     Completed,
     Failed,
+    /// Not run; `skip_reason` says why
+    Skipped,
+    /// Was running when the app exited; runs again when the queue resumes
+    Interrupted,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -35,6 +64,80 @@ impl From<&str> for QueueFailureMode {
     }
 }
 
+/// What happens after an item succeeds or fails
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum QueueBranch {
+    /// Run the given item next, then carry on in queue order
+    RunCleanup { item_id: String },
+    /// Skip every item that depends on this one, directly or indirectly
+    SkipDependents,
+    /// Continue from the given item, skipping the pending items in between
+    JumpTo { item_id: String },
+}
+
+impl QueueBranch {
+    fn target(&self) -> Option<&str> {
+        match self {
+            QueueBranch::RunCleanup { item_id } | QueueBranch::JumpTo { item_id } => Some(item_id),
+            QueueBranch::SkipDependents => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueItemRules {
+    /// Items that must complete before this one runs
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_success: Option<QueueBranch>,
+    /// When set, a failure is handled here instead of by the queue failure mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_failure: Option<QueueBranch>,
+    /// Only runs when another item's branch points at it
+    #[serde(default)]
+    pub triggered_only: bool,
+}
+
+/// Settings that replace the configured ones while a single item runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_iterations: Option<u32>,
+    /// Wall-clock limit for one attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// How many times a failed item is run again before it counts as failed
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Model for `provider`, or for the default provider when none is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl RunOverrides {
+    pub fn validate(&self) -> Result<(), QueueError> {
+        if self.max_iterations == Some(0) {
+            return Err(QueueError::InvalidOverride("max_iterations"));
+        }
+        if self.timeout_secs == Some(0) {
+            return Err(QueueError::InvalidOverride("timeout_secs"));
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn changes_provider(&self) -> bool {
+        self.provider.is_some() || self.model.is_some()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueuedInstruction {
     pub id: String,
@@ -42,6 +145,18 @@ pub struct QueuedInstruction {
     pub status: QueueItemStatus,
     pub result: Option<String>,
     pub error: Option<String>,
+    #[serde(flatten)]
+    pub rules: QueueItemRules,
+    #[serde(default)]
+    pub overrides: RunOverrides,
+    /// Times the item has been started, retries included
+    #[serde(default)]
+    pub attempts: u32,
+    /// What running the item has cost in USD, over all attempts
+    #[serde(default)]
+    pub cost_usd: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<String>,
 }
 
 impl QueuedInstruction {
@@ -52,20 +167,39 @@ impl QueuedInstruction {
             status: QueueItemStatus::Pending,
             result: None,
             error: None,
+            rules: QueueItemRules::default(),
+            overrides: RunOverrides::default(),
+            attempts: 0,
+            cost_usd: 0.0,
+            skip_reason: None,
         }
     }
 }
 
+/// An item that was skipped while the queue ran
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkippedItem {
+    pub id: String,
+    pub instruction: String,
+    pub reason: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstructionQueue {
     pub items: VecDeque<QueuedInstruction>,
     pub current_index: usize,
     pub is_processing: bool,
     pub failure_mode: QueueFailureMode,
+    /// What the items run so far have cost in USD
+    #[serde(default)]
+    pub total_cost_usd: f64,
     #[serde(default)]
     pending_count: usize,
     #[serde(default)]
     completed_count: usize,
+    /// Branch target to run before resuming queue order
+    #[serde(default)]
+    next_item: Option<String>,
 }
 
 impl Default for InstructionQueue {
@@ -75,8 +209,10 @@ impl Default for InstructionQueue {
             current_index: 0,
             is_processing: false,
             failure_mode: QueueFailureMode::default(),
+            total_cost_usd: 0.0,
             pending_count: 0,
             completed_count: 0,
+            next_item: None,
         }
     }
 }
@@ -98,29 +234,84 @@ impl InstructionQueue {
         instructions.into_iter().map(|i| self.add(i)).collect()
     }
 
+    pub fn add_with_rules(&mut self, instruction: String, rules: QueueItemRules) -> Result<String, QueueError> {
+        let id = self.add(instruction);
+        if let Err(e) = self.set_rules(&id, rules) {
+            self.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Replace an item's dependencies and branches
+    pub fn set_rules(&mut self, id: &str, rules: QueueItemRules) -> Result<(), QueueError> {
+        let pos = self.position(id).ok_or_else(|| QueueError::UnknownItem(id.to_string()))?;
+        let referenced = rules
+            .depends_on
+            .iter()
+            .map(String::as_str)
+            .chain(rules.on_success.as_ref().and_then(QueueBranch::target))
+            .chain(rules.on_failure.as_ref().and_then(QueueBranch::target));
+        for other in referenced {
+            if other == id {
+                return Err(QueueError::SelfReference);
+            }
+            if self.position(other).is_none() {
+                return Err(QueueError::UnknownItem(other.to_string()));
+            }
+        }
+
+        let previous = std::mem::replace(&mut self.items[pos].rules, rules);
+        if self.depends_on_transitively(id, id) {
+            self.items[pos].rules = previous;
+            return Err(QueueError::DependencyCycle);
+        }
+        Ok(())
+    }
+
     pub fn remove(&mut self, id: &str) -> bool {
         if let Some(pos) = self.items.iter().position(|item| item.id == id) {
             // Don't allow removing currently running item
             if pos == self.current_index && self.is_processing {
                 return false;
             }
-            let status = self.items[pos].status;
-            self.items.remove(pos);
-            match status {
-                QueueItemStatus::Pending => self.pending_count = self.pending_count.saturating_sub(1),
-                QueueItemStatus::Completed => self.completed_count = self.completed_count.saturating_sub(1),
-                _ => {}
-            }
-            // Adjust current_index if needed
-            if pos < self.current_index && self.current_index > 0 {
-                self.current_index -= 1;
-            }
+            self.remove_at(pos);
             true
         } else {
             false
         }
     }
 
+    /// Remove the item at `pos` along with every dependency and branch that
+    /// points at it
+    fn remove_at(&mut self, pos: usize) {
+        let Some(removed) = self.items.remove(pos) else {
+            return;
+        };
+        let id = removed.id.as_str();
+        for item in self.items.iter_mut() {
+            item.rules.depends_on.retain(|dep| dep != id);
+            if item.rules.on_success.as_ref().and_then(QueueBranch::target) == Some(id) {
+                item.rules.on_success = None;
+            }
+            if item.rules.on_failure.as_ref().and_then(QueueBranch::target) == Some(id) {
+                item.rules.on_failure = None;
+            }
+        }
+        if self.next_item.as_deref() == Some(id) {
+            self.next_item = None;
+        }
+        match removed.status {
+            QueueItemStatus::Pending => self.pending_count = self.pending_count.saturating_sub(1),
+            QueueItemStatus::Completed => self.completed_count = self.completed_count.saturating_sub(1),
+            _ => {}
+        }
+        // Adjust current_index if needed
+        if pos < self.current_index && self.current_index > 0 {
+            self.current_index -= 1;
+        }
+    }
+
     pub fn get_next(&mut self) -> Option<&QueuedInstruction> {
         // Find next pending item starting from current_index
         for i in self.current_index..self.items.len() {
@@ -132,6 +323,191 @@ impl InstructionQueue {
         None
     }
 
+    pub fn set_overrides(&mut self, id: &str, overrides: RunOverrides) -> Result<(), QueueError> {
+        overrides.validate()?;
+        let pos = self.position(id).ok_or_else(|| QueueError::UnknownItem(id.to_string()))?;
+        self.items[pos].overrides = overrides;
+        Ok(())
+    }
+
+    /// Pick the next item to run and make it current.
+    ///
+    /// A pending branch target goes first as soon as its dependencies have
+    /// completed; otherwise the first pending item whose dependencies have.
+    /// Items that can no longer run are marked skipped along the way and
+    /// returned with the reason.
+    pub fn next_runnable(&mut self) -> (Option<QueuedInstruction>, Vec<SkippedItem>) {
+        let mut skipped = self.skip_blocked();
+
+        let forced = self.next_item.as_deref().and_then(|id| self.position(id));
+        let next = match forced {
+            Some(pos) if self.is_ready(pos) => {
+                self.next_item = None;
+                Some(pos)
+            }
+            // Still waiting on dependencies: other items run in the meantime
+            Some(pos) if self.items[pos].status == QueueItemStatus::Pending => None,
+            Some(pos) => {
+                log::warn!(
+                    "Branch target '{}' is {:?} and can't run, continuing in queue order",
+                    self.items[pos].instruction,
+                    self.items[pos].status
+                );
+                self.next_item = None;
+                None
+            }
+            None => None,
+        };
+        let next = next.or_else(|| {
+            (0..self.items.len()).find(|&pos| !self.items[pos].rules.triggered_only && self.is_ready(pos))
+        });
+        if let Some(pos) = next {
+            self.current_index = pos;
+            return (Some(self.items[pos].clone()), skipped);
+        }
+
+        // Nothing can run: whatever is still pending never will
+        let waiting = self.next_item.take();
+        for pos in 0..self.items.len() {
+            let item = &self.items[pos];
+            if item.status == QueueItemStatus::Pending
+                && item.rules.triggered_only
+                && waiting.as_deref() != Some(item.id.as_str())
+            {
+                skipped.push(self.skip(pos, "Never triggered by another item".to_string()));
+            }
+        }
+        skipped.extend(self.skip_blocked());
+        for pos in 0..self.items.len() {
+            if self.items[pos].status == QueueItemStatus::Pending {
+                skipped.push(self.skip(pos, "Dependencies can never be satisfied".to_string()));
+            }
+        }
+        (None, skipped)
+    }
+
+    /// Apply a success or failure branch of the item with `from_id`
+    pub fn apply_branch(&mut self, from_id: &str, branch: &QueueBranch) -> Vec<SkippedItem> {
+        let Some(from) = self.position(from_id) else {
+            return Vec::new();
+        };
+        let reason = |verb: &str| format!("{} by '{}'", verb, self.items[from].instruction);
+
+        match branch {
+            QueueBranch::SkipDependents => {
+                let reason = reason("Skipped");
+                let dependents: Vec<usize> = (0..self.items.len())
+                    .filter(|&pos| {
+                        self.items[pos].status == QueueItemStatus::Pending
+                            && self.depends_on_transitively(&self.items[pos].id, from_id)
+                    })
+                    .collect();
+                dependents.into_iter().map(|pos| self.skip(pos, reason.clone())).collect()
+            }
+            QueueBranch::RunCleanup { item_id } => {
+                self.next_item = Some(item_id.clone());
+                Vec::new()
+            }
+            QueueBranch::JumpTo { item_id } => {
+                let reason = reason("Jumped over");
+                let Some(target) = self.position(item_id) else {
+                    return Vec::new();
+                };
+                self.next_item = Some(item_id.clone());
+                let passed: Vec<usize> = (from + 1..target)
+                    .filter(|&pos| {
+                        self.items[pos].status == QueueItemStatus::Pending && !self.items[pos].rules.triggered_only
+                    })
+                    .collect();
+                passed.into_iter().map(|pos| self.skip(pos, reason.clone())).collect()
+            }
+        }
+    }
+
+    pub fn skipped_items(&self) -> Vec<SkippedItem> {
+        self.items
+            .iter()
+            .filter(|item| item.status == QueueItemStatus::Skipped)
+            .map(|item| SkippedItem {
+                id: item.id.clone(),
+                instruction: item.instruction.clone(),
+                reason: item.skip_reason.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.items.iter().position(|item| item.id == id)
+    }
+
+    fn is_ready(&self, pos: usize) -> bool {
+        let item = &self.items[pos];
+        item.status == QueueItemStatus::Pending
+            && item.rules.depends_on.iter().all(|dep| {
+                self.position(dep)
+                    .is_some_and(|d| self.items[d].status == QueueItemStatus::Completed)
+            })
+    }
+
+    /// Whether `id` depends on `target` through any chain of dependencies
+    fn depends_on_transitively(&self, id: &str, target: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack = vec![id.to_string()];
+        while let Some(current) = stack.pop() {
+            let Some(pos) = self.position(&current) else {
+                continue;
+            };
+            for dep in &self.items[pos].rules.depends_on {
+                if dep == target {
+                    return true;
+                }
+                if seen.insert(dep.clone()) {
+                    stack.push(dep.clone());
+                }
+            }
+        }
+        false
+    }
+
+    /// Skip pending items whose dependencies failed or were skipped, until none are left
+    fn skip_blocked(&mut self) -> Vec<SkippedItem> {
+        let mut skipped = Vec::new();
+        loop {
+            let blocked = (0..self.items.len()).find_map(|pos| {
+                if self.items[pos].status != QueueItemStatus::Pending {
+                    return None;
+                }
+                self.items[pos].rules.depends_on.iter().find_map(|dep| {
+                    let dep = &self.items[self.position(dep)?];
+                    let verb = match dep.status {
+                        QueueItemStatus::Failed => "failed",
+                        QueueItemStatus::Skipped => "was skipped",
+                        _ => return None,
+                    };
+                    Some((pos, format!("Dependency '{}' {}", dep.instruction, verb)))
+                })
+            });
+            match blocked {
+                Some((pos, reason)) => skipped.push(self.skip(pos, reason)),
+                None => return skipped,
+            }
+        }
+    }
+
+    fn skip(&mut self, pos: usize, reason: String) -> SkippedItem {
+        let item = &mut self.items[pos];
+        if item.status == QueueItemStatus::Pending {
+            self.pending_count = self.pending_count.saturating_sub(1);
+        }
+        item.status = QueueItemStatus::Skipped;
+        item.skip_reason = Some(reason.clone());
+        SkippedItem {
+            id: item.id.clone(),
+            instruction: item.instruction.clone(),
+            reason,
+        }
+    }
+
     pub fn get_current(&self) -> Option<&QueuedInstruction> {
         self.items.get(self.current_index)
     }
@@ -142,6 +518,7 @@ impl InstructionQueue {
                 self.pending_count = self.pending_count.saturating_sub(1);
             }
             item.status = QueueItemStatus::Running;
+            item.attempts += 1;
         }
     }
 
@@ -153,6 +530,14 @@ impl InstructionQueue {
         }
     }
 
+    /// Charge the cost of a run to the current item and the queue
+    pub fn add_current_cost(&mut self, usd: f64) {
+        if let Some(item) = self.items.get_mut(self.current_index) {
+            item.cost_usd += usd;
+            self.total_cost_usd += usd;
+        }
+    }
+
     pub fn mark_current_failed(&mut self, error: String) {
         if let Some(item) = self.items.get_mut(self.current_index) {
             item.status = QueueItemStatus::Failed;
@@ -160,6 +545,15 @@ impl InstructionQueue {
         }
     }
 
+    /// The current item was stopped part way; it runs again on the next resume
+    pub fn mark_current_interrupted(&mut self) {
+        if let Some(item) = self.items.get_mut(self.current_index) {
+            if item.status == QueueItemStatus::Running {
+                item.status = QueueItemStatus::Interrupted;
+            }
+        }
+    }
+
     pub fn advance(&mut self) -> bool {
         if self.current_index + 1 < self.items.len() {
             self.current_index += 1;
@@ -175,11 +569,14 @@ impl InstructionQueue {
         self.is_processing = false;
         self.pending_count = 0;
         self.completed_count = 0;
+        self.total_cost_usd = 0.0;
+        self.next_item = None;
     }
 
     pub fn clear_pending(&mut self) {
-        self.items.retain(|item| item.status != QueueItemStatus::Pending);
-        self.pending_count = 0;
+        while let Some(pos) = self.items.iter().position(|item| item.status == QueueItemStatus::Pending) {
+            self.remove_at(pos);
+        }
     }
 
     pub fn reorder(&mut self, ids: Vec<String>) -> bool {
@@ -244,34 +641,193 @@ impl InstructionQueue {
     pub fn has_pending(&self) -> bool {
         self.pending_count > 0
     }
+
+    pub fn interrupted_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.status == QueueItemStatus::Interrupted)
+            .count()
+    }
+
+    /// Fix up a queue loaded from disk: nothing is running any more, so the
+    /// item that was is marked interrupted
+    fn recover(&mut self) {
+        for item in self.items.iter_mut() {
+            if item.status == QueueItemStatus::Running {
+                item.status = QueueItemStatus::Interrupted;
+            }
+        }
+        self.is_processing = false;
+        self.pending_count = self.count(QueueItemStatus::Pending);
+        self.completed_count = self.count(QueueItemStatus::Completed);
+    }
+
+    /// Put interrupted items back to pending, returning their ids
+    pub fn resume_interrupted(&mut self) -> Vec<String> {
+        let mut ids = Vec::new();
+        for item in self.items.iter_mut() {
+            if item.status == QueueItemStatus::Interrupted {
+                item.status = QueueItemStatus::Pending;
+                ids.push(item.id.clone());
+            }
+        }
+        self.pending_count += ids.len();
+        ids
+    }
+
+    fn count(&self, status: QueueItemStatus) -> usize {
+        self.items.iter().filter(|item| item.status == status).count()
+    }
+}
+
+/// The queue as a JSON file, rewritten on every change
+#[derive(Debug, Clone)]
+pub struct QueueStore {
+    path: PathBuf,
+}
+
+impl QueueStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Keep the queue as `queue.json` beside the given config file
+    pub fn beside_config(config_path: &Path) -> Self {
+        let dir = config_path.parent().unwrap_or_else(|| Path::new("."));
+        Self::new(dir.join("queue.json"))
+    }
+
+    pub fn save(&self, queue: &InstructionQueue) -> Result<(), QueueStoreError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(queue)?;
+
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Load the saved queue with any running item marked interrupted
+    pub fn load(&self) -> Result<Option<InstructionQueue>, QueueStoreError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.path)?;
+        let mut queue: InstructionQueue = serde_json::from_str(&content)?;
+        queue.recover();
+        Ok(Some(queue))
+    }
 }
 
 #[derive(Clone)]
 pub struct QueueManager {
     queue: Arc<RwLock<InstructionQueue>>,
+    store: Option<QueueStore>,
+    /// Numbers each change so an older snapshot never overwrites a newer one
+    changes: Arc<AtomicU64>,
+    /// Change number of the snapshot on disk; held while a save runs
+    saved: Arc<parking_lot::Mutex<u64>>,
 }
 
 impl QueueManager {
     pub fn new() -> Self {
         Self {
             queue: Arc::new(RwLock::new(InstructionQueue::new())),
+            store: None,
+            changes: Arc::new(AtomicU64::new(0)),
+            saved: Arc::new(parking_lot::Mutex::new(0)),
         }
     }
 
+    /// Restore the queue saved in `store` and keep saving every change to it
+    pub fn with_store(store: QueueStore) -> Self {
+        let queue = match store.load() {
+            Ok(queue) => queue.unwrap_or_default(),
+            Err(e) => {
+                log::warn!("Failed to restore queue, starting empty: {}", e);
+                InstructionQueue::new()
+            }
+        };
+        Self {
+            queue: Arc::new(RwLock::new(queue)),
+            store: Some(store),
+            ..Self::new()
+        }
+    }
+
+    /// Apply a change, then save a snapshot of the result on a blocking
+    /// thread once the lock is released
+    async fn update<R>(&self, change: impl FnOnce(&mut InstructionQueue) -> R) -> R {
+        let (result, snapshot) = {
+            let mut queue = self.queue.write().await;
+            let result = change(&mut queue);
+            let snapshot = self
+                .store
+                .is_some()
+                .then(|| (self.changes.fetch_add(1, Ordering::Relaxed) + 1, queue.clone()));
+            (result, snapshot)
+        };
+        let (Some(store), Some((change, snapshot))) = (self.store.clone(), snapshot) else {
+            return result;
+        };
+
+        let saved = self.saved.clone();
+        let save = tokio::task::spawn_blocking(move || {
+            let mut saved = saved.lock();
+            if *saved > change {
+                return Ok(());
+            }
+            *saved = change;
+            store.save(&snapshot)
+        });
+        match save.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Failed to save queue: {}", e),
+            Err(e) => log::warn!("Failed to save queue: {}", e),
+        }
+        result
+    }
+
     pub async fn add(&self, instruction: String) -> String {
-        self.queue.write().await.add(instruction)
+        self.update(|q| q.add(instruction)).await
     }
 
     pub async fn add_multiple(&self, instructions: Vec<String>) -> Vec<String> {
-        self.queue.write().await.add_multiple(instructions)
+        self.update(|q| q.add_multiple(instructions)).await
+    }
+
+    pub async fn add_with_rules(&self, instruction: String, rules: QueueItemRules) -> Result<String, QueueError> {
+        self.update(|q| q.add_with_rules(instruction, rules)).await
+    }
+
+    pub async fn set_rules(&self, id: &str, rules: QueueItemRules) -> Result<(), QueueError> {
+        self.update(|q| q.set_rules(id, rules)).await
+    }
+
+    pub async fn set_overrides(&self, id: &str, overrides: RunOverrides) -> Result<(), QueueError> {
+        self.update(|q| q.set_overrides(id, overrides)).await
     }
 
     pub async fn remove(&self, id: &str) -> bool {
-        self.queue.write().await.remove(id)
+        self.update(|q| q.remove(id)).await
+    }
+
+    pub async fn next_runnable(&self) -> (Option<QueuedInstruction>, Vec<SkippedItem>) {
+        self.update(|q| q.next_runnable()).await
+    }
+
+    pub async fn apply_branch(&self, from_id: &str, branch: &QueueBranch) -> Vec<SkippedItem> {
+        self.update(|q| q.apply_branch(from_id, branch)).await
+    }
+
+    pub async fn skipped_items(&self) -> Vec<SkippedItem> {
+        self.queue.read().await.skipped_items()
     }
 
     pub async fn get_next(&self) -> Option<QueuedInstruction> {
-        self.queue.write().await.get_next().cloned()
+        self.update(|q| q.get_next().cloned()).await
     }
 
     pub async fn get_current(&self) -> Option<QueuedInstruction> {
@@ -279,31 +835,39 @@ impl QueueManager {
     }
 
     pub async fn mark_current_running(&self) {
-        self.queue.write().await.mark_current_running();
+        self.update(|q| q.mark_current_running()).await;
     }
 
     pub async fn mark_current_completed(&self, result: Option<String>) {
-        self.queue.write().await.mark_current_completed(result);
+        self.update(|q| q.mark_current_completed(result)).await;
+    }
+
+    pub async fn add_current_cost(&self, usd: f64) {
+        self.update(|q| q.add_current_cost(usd)).await;
     }
 
     pub async fn mark_current_failed(&self, error: String) {
-        self.queue.write().await.mark_current_failed(error);
+        self.update(|q| q.mark_current_failed(error)).await;
+    }
+
+    pub async fn mark_current_interrupted(&self) {
+        self.update(|q| q.mark_current_interrupted()).await;
     }
 
     pub async fn advance(&self) -> bool {
-        self.queue.write().await.advance()
+        self.update(|q| q.advance()).await
     }
 
     pub async fn clear(&self) {
-        self.queue.write().await.clear();
+        self.update(|q| q.clear()).await;
     }
 
     pub async fn clear_pending(&self) {
-        self.queue.write().await.clear_pending();
+        self.update(|q| q.clear_pending()).await;
     }
 
     pub async fn reorder(&self, ids: Vec<String>) -> bool {
-        self.queue.write().await.reorder(ids)
+        self.update(|q| q.reorder(ids)).await
     }
 
     pub async fn get_all(&self) -> Vec<QueuedInstruction> {
@@ -315,7 +879,7 @@ impl QueueManager {
     }
 
     pub async fn set_processing(&self, processing: bool) {
-        self.queue.write().await.is_processing = processing;
+        self.update(|q| q.is_processing = processing).await;
     }
 
     pub async fn is_processing(&self) -> bool {
@@ -323,7 +887,7 @@ impl QueueManager {
     }
 
     pub async fn set_failure_mode(&self, mode: QueueFailureMode) {
-        self.queue.write().await.failure_mode = mode;
+        self.update(|q| q.failure_mode = mode).await;
     }
 
     pub async fn get_failure_mode(&self) -> QueueFailureMode {
@@ -334,6 +898,14 @@ impl QueueManager {
         self.queue.read().await.has_pending()
     }
 
+    pub async fn interrupted_count(&self) -> usize {
+        self.queue.read().await.interrupted_count()
+    }
+
+    pub async fn resume_interrupted(&self) -> Vec<String> {
+        self.update(|q| q.resume_interrupted()).await
+    }
+
     pub async fn pending_count(&self) -> usize {
         self.queue.read().await.pending_count()
     }
@@ -443,4 +1015,225 @@ mod tests {
         assert_eq!(items[1].instruction, "First");
         assert_eq!(items[2].instruction, "Second");
     }
+
+    fn depends_on(ids: &[&String]) -> QueueItemRules {
+        QueueItemRules {
+            depends_on: ids.iter().map(|id| id.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Run the queue to the end, failing the items in `failing`
+    fn run_all(queue: &mut InstructionQueue, failing: &[&str]) -> (Vec<String>, Vec<SkippedItem>) {
+        let mut ran = Vec::new();
+        let mut skipped = Vec::new();
+        loop {
+            let (next, newly_skipped) = queue.next_runnable();
+            skipped.extend(newly_skipped);
+            let Some(item) = next else {
+                return (ran, skipped);
+            };
+            queue.mark_current_running();
+            let failed = failing.contains(&item.instruction.as_str());
+            if failed {
+                queue.mark_current_failed("error".to_string());
+            } else {
+                queue.mark_current_completed(None);
+            }
+            let branch = if failed { &item.rules.on_failure } else { &item.rules.on_success };
+            if let Some(branch) = branch {
+                skipped.extend(queue.apply_branch(&item.id, branch));
+            }
+            ran.push(item.instruction);
+        }
+    }
+
+    #[test]
+    fn test_set_rules_validation() {
+        let mut queue = InstructionQueue::new();
+        let a = queue.add("A".to_string());
+        let b = queue.add("B".to_string());
+
+        assert!(queue.set_rules(&b, depends_on(&[&a])).is_ok());
+        assert_eq!(queue.set_rules(&a, depends_on(&[&b])), Err(QueueError::DependencyCycle));
+        assert!(queue.items[0].rules.depends_on.is_empty());
+        assert_eq!(queue.set_rules(&a, depends_on(&[&a])), Err(QueueError::SelfReference));
+        assert_eq!(
+            queue.add_with_rules("C".to_string(), depends_on(&[&"missing".to_string()])),
+            Err(QueueError::UnknownItem("missing".to_string()))
+        );
+        assert_eq!(queue.total_count(), 2);
+
+        // Removing an item drops references to it
+        queue.remove(&a);
+        assert!(queue.items[0].rules.depends_on.is_empty());
+    }
+
+    #[test]
+    fn test_dependencies_run_first_and_failures_skip_dependents() {
+        let mut queue = InstructionQueue::new();
+        let report = queue.add("report".to_string());
+        let login = queue.add("login".to_string());
+        let export = queue.add("export".to_string());
+        queue.set_rules(&report, depends_on(&[&export])).unwrap();
+        queue.set_rules(&export, depends_on(&[&login])).unwrap();
+        queue.add("unrelated".to_string());
+
+        let (ran, skipped) = run_all(&mut queue, &["login"]);
+
+        assert_eq!(ran, vec!["login", "unrelated"]);
+        let reasons: Vec<_> = skipped.iter().map(|s| (s.instruction.as_str(), s.reason.as_str())).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("export", "Dependency 'login' failed"),
+                ("report", "Dependency 'export' was skipped"),
+            ]
+        );
+        assert_eq!(queue.skipped_items().len(), 2);
+        assert!(!queue.has_pending());
+    }
+
+    #[test]
+    fn test_cleanup_and_jump_branches() {
+        let mut queue = InstructionQueue::new();
+        let open = queue.add("open app".to_string());
+        queue.add("fill form".to_string());
+        let submit = queue.add("submit".to_string());
+        queue.add("close app".to_string());
+        let cleanup = queue.add("discard draft".to_string());
+        queue
+            .set_rules(&cleanup, QueueItemRules { triggered_only: true, ..Default::default() })
+            .unwrap();
+        queue
+            .set_rules(&open, QueueItemRules {
+                on_success: Some(QueueBranch::JumpTo { item_id: submit.clone() }),
+                ..Default::default()
+            })
+            .unwrap();
+        queue
+            .set_rules(&submit, QueueItemRules {
+                on_failure: Some(QueueBranch::RunCleanup { item_id: cleanup.clone() }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let (ran, skipped) = run_all(&mut queue, &["submit"]);
+
+        assert_eq!(ran, vec!["open app", "submit", "discard draft", "close app"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].instruction, "fill form");
+        assert_eq!(skipped[0].reason, "Jumped over by 'open app'");
+    }
+
+    #[test]
+    fn test_clear_pending_drops_references() {
+        let mut queue = InstructionQueue::new();
+        let done = queue.add("done".to_string());
+        let pending = queue.add("pending".to_string());
+        queue
+            .set_rules(&done, QueueItemRules {
+                on_failure: Some(QueueBranch::JumpTo { item_id: pending.clone() }),
+                ..Default::default()
+            })
+            .unwrap();
+        queue.next_runnable();
+        queue.mark_current_running();
+        queue.mark_current_completed(None);
+        queue.apply_branch(&done, &QueueBranch::RunCleanup { item_id: pending.clone() });
+
+        queue.clear_pending();
+
+        assert_eq!(queue.total_count(), 1);
+        assert_eq!(queue.pending_count(), 0);
+        assert!(queue.items[0].rules.on_failure.is_none());
+        assert!(queue.next_item.is_none());
+    }
+
+    #[test]
+    fn test_branch_target_waits_for_its_dependencies() {
+        let mut queue = InstructionQueue::new();
+        let start = queue.add("start".to_string());
+        let setup = queue.add("setup".to_string());
+        let report = queue.add("report".to_string());
+        queue
+            .set_rules(&report, QueueItemRules { triggered_only: true, ..depends_on(&[&setup]) })
+            .unwrap();
+        queue
+            .set_rules(&start, QueueItemRules {
+                on_success: Some(QueueBranch::RunCleanup { item_id: report.clone() }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let (ran, skipped) = run_all(&mut queue, &[]);
+
+        assert_eq!(ran, vec!["start", "setup", "report"]);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_skip_dependents_and_untriggered_items() {
+        let mut queue = InstructionQueue::new();
+        let check = queue.add("check logged in".to_string());
+        let login = queue.add("log in".to_string());
+        let recover = queue.add("reset password".to_string());
+        queue.add("open inbox".to_string());
+        queue
+            .set_rules(&check, QueueItemRules {
+                on_success: Some(QueueBranch::SkipDependents),
+                ..Default::default()
+            })
+            .unwrap();
+        queue.set_rules(&login, depends_on(&[&check])).unwrap();
+        queue
+            .set_rules(&recover, QueueItemRules { triggered_only: true, ..Default::default() })
+            .unwrap();
+
+        let (ran, skipped) = run_all(&mut queue, &[]);
+
+        assert_eq!(ran, vec!["check logged in", "open inbox"]);
+        let reasons: Vec<_> = skipped.iter().map(|s| (s.instruction.as_str(), s.reason.as_str())).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("log in", "Skipped by 'check logged in'"),
+                ("reset password", "Never triggered by another item"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queue_survives_restart_with_running_item_interrupted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = QueueStore::new(dir.path().join("queue.json"));
+
+        let queue = QueueManager::with_store(store.clone());
+        queue.add_multiple(vec!["First".to_string(), "Second".to_string(), "Third".to_string()]).await;
+        queue.next_runnable().await;
+        queue.mark_current_running().await;
+        queue.mark_current_completed(Some("Done".to_string())).await;
+        queue.next_runnable().await;
+        queue.mark_current_running().await;
+        queue.set_processing(true).await;
+        // App exits here
+
+        let restored = QueueManager::with_store(store);
+        let state = restored.get_state().await;
+        let statuses: Vec<_> = state.items.iter().map(|item| item.status).collect();
+        assert_eq!(
+            statuses,
+            vec![QueueItemStatus::Completed, QueueItemStatus::Interrupted, QueueItemStatus::Pending]
+        );
+        assert_eq!(state.items[0].result.as_deref(), Some("Done"));
+        assert!(!state.is_processing);
+        assert_eq!(restored.pending_count().await, 1);
+        assert_eq!(restored.completed_count().await, 1);
+
+        let resumed = restored.resume_interrupted().await;
+        assert_eq!(resumed, vec![state.items[1].id.clone()]);
+        assert_eq!(restored.pending_count().await, 2);
+        let (next, _) = restored.next_runnable().await;
+        assert_eq!(next.unwrap().instruction, "Second");
+    }
 }
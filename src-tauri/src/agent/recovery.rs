@@ -124,6 +124,9 @@ pub fn classify_llm_error(error: &LlmError) -> ErrorClassification {
 
         // Not configured is fatal
         LlmError::NotConfigured => ErrorClassification::Fatal,
+
+        // The same history will not fit any better on a retry
+        LlmError::ContextOverflow { .. } => ErrorClassification::Fatal,
     }
 }
 
@@ -0,0 +1,159 @@
+//! Playback of recorded actions without calling the LLM.
+//!
+//! A recording is a list of `RecordedAction`s, each holding the screen as it
+//! looked right before the step ran. During replay the live screen is compared
+//! against that screenshot at regular checkpoints; when the two drift too far
+//! apart the replay either stops or hands the task back to the model.
+
+use crate::config::GeneralConfig;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Both screens are scaled down to this grid before comparing
+const COMPARE_SIZE: u32 = 64;
+
+/// Per-channel difference below which two pixels count as equal,
+/// so compression noise does not register as a change
+const PIXEL_TOLERANCE: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivergenceMode {
+    /// Stop the replay with an error
+    Stop,
+    /// Let the LLM continue the task from the current screen
+    HandOff,
+}
+
+impl From<&str> for DivergenceMode {
+    fn from(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "llm" | "handoff" => Self::HandOff,
+            _ => Self::Stop,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// Compare screens every N steps; 0 disables comparisons
+    pub check_interval: u32,
+    /// Fraction of the screen that may differ before the replay diverges
+    pub divergence_threshold: f32,
+    pub on_divergence: DivergenceMode,
+}
+
+impl ReplayOptions {
+    pub fn from_config(config: &GeneralConfig) -> Self {
+        Self {
+            check_interval: config.replay_check_interval,
+            divergence_threshold: config.replay_divergence_threshold.clamp(0.0, 1.0),
+            on_divergence: DivergenceMode::from(config.replay_on_divergence.as_str()),
+        }
+    }
+
+    /// Whether the screen is checked before the step at `index` (0-based)
+    pub fn is_checkpoint(&self, index: usize) -> bool {
+        self.check_interval > 0 && index % self.check_interval as usize == 0
+    }
+}
+
+/// Result of a replay that ran to the end or was handed off to the LLM
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayOutcome {
+    pub total_steps: usize,
+    pub steps_replayed: usize,
+    /// 1-based step at which the screen stopped matching the recording
+    pub diverged_at: Option<usize>,
+    /// Fraction of the screen that differed at that step
+    pub difference: Option<f32>,
+    pub handed_off: bool,
+}
+
+/// Fraction of the screen (0.0-1.0) that differs between two base64 screenshots.
+/// Returns None when either image cannot be decoded.
+pub fn screen_difference(recorded: &str, live: &str) -> Option<f32> {
+    let recorded = thumbnail(recorded)?;
+    let live = thumbnail(live)?;
+
+    let changed = recorded
+        .pixels()
+        .zip(live.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .any(|(x, y)| x.abs_diff(*y) > PIXEL_TOLERANCE)
+        })
+        .count();
+
+    Some(changed as f32 / (COMPARE_SIZE * COMPARE_SIZE) as f32)
+}
+
+/// `screen_difference` on a blocking thread; decoding and scaling two full
+/// screenshots is too slow for the async executor
+pub async fn screen_difference_off_thread(recorded: Arc<String>, live: Arc<String>) -> Option<f32> {
+    tokio::task::spawn_blocking(move || screen_difference(&recorded, &live))
+        .await
+        .ok()
+        .flatten()
+}
+
+fn thumbnail(base64: &str) -> Option<image::RgbImage> {
+    let bytes = STANDARD.decode(base64).ok()?;
+    let image: DynamicImage = image::load_from_memory(&bytes).ok()?;
+    Some(
+        image
+            .resize_exact(COMPARE_SIZE, COMPARE_SIZE, FilterType::Triangle)
+            .to_rgb8(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32, color: impl Fn(u32, u32) -> Rgb<u8>) -> String {
+        let image = RgbImage::from_fn(width, height, color);
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut buffer, ImageFormat::Png)
+            .unwrap();
+        STANDARD.encode(buffer.into_inner())
+    }
+
+    #[test]
+    fn test_screen_difference() {
+        let grey = png(128, 96, |_, _| Rgb([120, 120, 120]));
+        let noisy = png(128, 96, |x, _| Rgb([120 + (x % 8) as u8, 120, 120]));
+        let half_white = png(128, 96, |x, _| {
+            if x < 64 { Rgb([255, 255, 255]) } else { Rgb([120, 120, 120]) }
+        });
+
+        assert_eq!(screen_difference(&grey, &grey), Some(0.0));
+        assert_eq!(screen_difference(&grey, &noisy), Some(0.0));
+        let half = screen_difference(&grey, &half_white).unwrap();
+        assert!((0.4..=0.6).contains(&half), "difference was {}", half);
+        assert_eq!(screen_difference(&grey, "not an image"), None);
+    }
+
+    #[test]
+    fn test_options_from_config() {
+        let mut config = crate::config::Config::default().general;
+        config.replay_check_interval = 3;
+        config.replay_on_divergence = "llm".to_string();
+        let options = ReplayOptions::from_config(&config);
+
+        assert_eq!(options.on_divergence, DivergenceMode::HandOff);
+        assert!(options.is_checkpoint(0));
+        assert!(!options.is_checkpoint(1));
+        assert!(options.is_checkpoint(3));
+
+        config.replay_check_interval = 0;
+        assert!(!ReplayOptions::from_config(&config).is_checkpoint(0));
+        assert_eq!(DivergenceMode::from("anything"), DivergenceMode::Stop);
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
-use crate::capture::{capture_primary_screen, CaptureError, Screenshot};
+use crate::capture::{CaptureError, PrimaryScreen, ScreenSource, Screenshot, ScreenshotConfig};
+use async_trait::async_trait;
+use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
 
@@ -10,12 +12,21 @@ pub enum RetryError {
     CaptureError(#[from] CaptureError),
 }
 
+/// Notified before each retry attempt so the caller can surface progress
+#[async_trait]
+pub trait RetryObserver: Send + Sync {
+    async fn on_retry(&self, attempt: u32, max_retries: u32, reason: &str);
+}
+
 pub struct RetryContext {
     pub max_retries: u32,
     pub retry_delay: Duration,
     pub attempt: u32,
     pub enabled: bool,
     last_screenshot: Option<Screenshot>,
+    screen: Arc<dyn ScreenSource>,
+    capture_config: ScreenshotConfig,
+    observer: Option<Arc<dyn RetryObserver>>,
 }
 
 impl RetryContext {
@@ -26,9 +37,29 @@ impl RetryContext {
             attempt: 0,
             enabled,
             last_screenshot: None,
+            screen: Arc::new(PrimaryScreen),
+            capture_config: ScreenshotConfig::default(),
+            observer: None,
         }
     }
 
+    /// Use a different screen for before/after comparisons
+    pub fn with_screen_source(mut self, screen: Arc<dyn ScreenSource>) -> Self {
+        self.screen = screen;
+        self
+    }
+
+    /// Capture with the same quality and size as the loop's own screenshots
+    pub fn with_capture_config(mut self, config: ScreenshotConfig) -> Self {
+        self.capture_config = config;
+        self
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn RetryObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
     pub fn should_retry(&self) -> bool {
         self.enabled && self.attempt < self.max_retries
     }
@@ -37,30 +68,46 @@ impl RetryContext {
         self.attempt += 1;
     }
 
+    /// Report the current attempt to the observer, if any
+    pub async fn notify_retry(&self, reason: &str) {
+        if let Some(observer) = &self.observer {
+            observer.on_retry(self.attempt, self.max_retries, reason).await;
+        }
+    }
+
     pub fn reset(&mut self) {
         self.attempt = 0;
         self.last_screenshot = None;
     }
 
-    pub fn capture_before(&mut self) -> Result<(), RetryError> {
+    pub async fn capture_before(&mut self) -> Result<(), RetryError> {
         if self.enabled {
-            self.last_screenshot = Some(capture_primary_screen()?);
+            self.last_screenshot = Some(self.capture().await?);
         }
         Ok(())
     }
 
-    pub fn screen_changed(&self) -> Result<bool, RetryError> {
+    pub async fn screen_changed(&self) -> Result<bool, RetryError> {
         if !self.enabled {
             return Ok(true);
         }
 
         if let Some(before) = &self.last_screenshot {
-            let after = capture_primary_screen()?;
+            let after = self.capture().await?;
             Ok(screenshots_differ(before, &after))
         } else {
             Ok(true) // No baseline, assume changed
         }
     }
+
+    /// Capture and encode on a blocking thread, off the async executor
+    async fn capture(&self) -> Result<Screenshot, CaptureError> {
+        let screen = self.screen.clone();
+        let config = self.capture_config.clone();
+        tokio::task::spawn_blocking(move || screen.capture(&config))
+            .await
+            .map_err(|e| CaptureError::CaptureError(e.to_string()))?
+    }
 }
 
 /// Compare two screenshots to detect if the screen changed.
@@ -113,4 +160,37 @@ mod tests {
         assert_eq!(ctx.attempt, 0);
         assert!(ctx.should_retry());
     }
+
+    /// Records the max width each capture was asked for
+    #[derive(Default)]
+    struct WidthRecorder(std::sync::Mutex<Vec<Option<u32>>>);
+
+    impl ScreenSource for WidthRecorder {
+        fn capture(&self, config: &ScreenshotConfig) -> Result<Screenshot, CaptureError> {
+            self.0.lock().unwrap().push(config.max_width);
+            Ok(Screenshot {
+                width: 1,
+                height: 1,
+                physical_width: 1,
+                physical_height: 1,
+                base64: Arc::new(String::new()),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_captures_use_the_given_config() {
+        let screen = Arc::new(WidthRecorder::default());
+        let config = ScreenshotConfig {
+            max_width: Some(640),
+            ..ScreenshotConfig::default()
+        };
+        let mut ctx = RetryContext::new(3, 0, true)
+            .with_screen_source(screen.clone())
+            .with_capture_config(config);
+
+        ctx.capture_before().await.unwrap();
+        assert!(!ctx.screen_changed().await.unwrap());
+        assert_eq!(*screen.0.lock().unwrap(), vec![Some(640), Some(640)]);
+    }
 }
@@ -0,0 +1,596 @@
+//! Runs the schedules stored in config and logs how each run ended.
+//!
+//! The scheduler wakes up periodically, works out which schedules came due
+//! since the last check and starts them one at a time through a
+//! `ScheduledTaskRunner`. Several missed fire times collapse into one run.
+
+use super::history::TemplateRun;
+use super::loop_runner::LoopError;
+use super::state::AgentStateManager;
+use crate::config::schedule::{BusyPolicy, CronExpr, Schedule, ScheduleSpec, ScheduleTarget};
+use crate::config::Config;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveDateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How often schedules are checked
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Runs kept in memory and on disk
+const MAX_RUN_LOG_ENTRIES: usize = 500;
+
+/// Starts the work a schedule points at
+#[async_trait]
+pub trait ScheduledTaskRunner: Send + Sync {
+    /// Run one instruction to completion
+    async fn run_instruction(&self, instruction: String, template: Option<TemplateRun>) -> Result<(), LoopError>;
+
+    /// Run instructions in order as a queue of their own, leaving the
+    /// user's queue alone
+    async fn run_queue(&self, instructions: Vec<String>) -> Result<(), LoopError>;
+
+    /// Whether a task cut short by a crash is waiting for the user to resume
+    /// or discard it; schedules count as busy until then
+    fn has_interrupted_task(&self) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Completed,
+    /// The agent was busy, or an interrupted task was waiting, and the
+    /// schedule skips busy runs
+    Skipped,
+    /// The agent was busy; the run waits until it is free
+    Deferred,
+    Stopped,
+    MaxIterations,
+    /// A token or dollar limit stopped the run
+    BudgetExceeded,
+    Denied,
+    Failed,
+}
+
+impl RunOutcome {
+    pub fn from_result(result: &Result<(), LoopError>) -> Self {
+        match result {
+            Ok(()) => Self::Completed,
+            Err(LoopError::Stopped) => Self::Stopped,
+            Err(LoopError::MaxIterations) => Self::MaxIterations,
+            Err(LoopError::BudgetExceeded(_)) => Self::BudgetExceeded,
+            Err(LoopError::ActionDenied) => Self::Denied,
+            Err(_) => Self::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRun {
+    pub schedule_id: String,
+    pub schedule_name: String,
+    /// When the scheduler decided the schedule was due
+    pub triggered_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    pub outcome: RunOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Recent runs, optionally mirrored to a JSON-lines file
+pub struct RunLog {
+    path: Option<PathBuf>,
+    entries: VecDeque<ScheduleRun>,
+}
+
+impl RunLog {
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Load the log kept as `schedule_runs.jsonl` beside the given config file
+    pub fn beside_config(config_path: &Path) -> Self {
+        let dir = config_path.parent().unwrap_or_else(|| Path::new("."));
+        Self::load(dir.join("schedule_runs.jsonl"))
+    }
+
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries: VecDeque<ScheduleRun> = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let overflow = entries.len().saturating_sub(MAX_RUN_LOG_ENTRIES);
+        entries.drain(..overflow);
+
+        let log = Self {
+            path: Some(path),
+            entries,
+        };
+        if overflow > 0 {
+            log.rewrite();
+        }
+        log
+    }
+
+    pub fn record(&mut self, run: ScheduleRun) {
+        if let Some(path) = &self.path {
+            let appended = serde_json::to_string(&run).ok().and_then(|line| {
+                let mut file = OpenOptions::new().create(true).append(true).open(path).ok()?;
+                writeln!(file, "{}", line).ok()
+            });
+            if appended.is_none() {
+                log::warn!("Failed to write schedule run log to {}", path.display());
+            }
+        }
+
+        self.entries.push_back(run);
+        if self.entries.len() > MAX_RUN_LOG_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Most recent runs first
+    pub fn recent(&self, limit: usize) -> Vec<ScheduleRun> {
+        self.entries.iter().rev().take(limit).cloned().collect()
+    }
+
+    fn rewrite(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let content: String = self
+            .entries
+            .iter()
+            .filter_map(|run| serde_json::to_string(run).ok())
+            .map(|line| line + "\n")
+            .collect();
+        if let Err(e) = fs::write(path, content) {
+            log::warn!("Failed to rewrite schedule run log: {}", e);
+        }
+    }
+}
+
+pub struct Scheduler {
+    config: Arc<RwLock<Config>>,
+    state: AgentStateManager,
+    runner: Arc<dyn ScheduledTaskRunner>,
+    log: Mutex<RunLog>,
+    /// Local time of the previous check; cron times up to here are handled
+    last_tick: Mutex<NaiveDateTime>,
+    /// Schedules that came due while the agent was busy, oldest first
+    deferred: Mutex<Vec<(String, DateTime<Utc>)>>,
+    /// Write `last_run` changes back to the config file
+    save_config: bool,
+}
+
+impl Scheduler {
+    pub fn new(config: Arc<RwLock<Config>>, state: AgentStateManager, runner: Arc<dyn ScheduledTaskRunner>) -> Self {
+        Self {
+            config,
+            state,
+            runner,
+            log: Mutex::new(RunLog::in_memory()),
+            last_tick: Mutex::new(Local::now().naive_local()),
+            deferred: Mutex::new(Vec::new()),
+            save_config: false,
+        }
+    }
+
+    pub fn with_run_log(mut self, log: RunLog) -> Self {
+        self.log = Mutex::new(log);
+        self
+    }
+
+    /// Save the config file whenever a schedule's `last_run` changes
+    pub fn saving_config(mut self) -> Self {
+        self.save_config = true;
+        self
+    }
+
+    pub fn recent_runs(&self, limit: usize) -> Vec<ScheduleRun> {
+        self.log.lock().recent(limit)
+    }
+
+    /// Check schedules every `TICK_INTERVAL` for as long as the app runs
+    pub fn spawn(self: Arc<Self>) {
+        tauri::async_runtime::spawn(async move {
+            loop {
+                tokio::time::sleep(TICK_INTERVAL).await;
+                self.tick(Local::now()).await;
+            }
+        });
+    }
+
+    /// Start every schedule that came due since the previous tick
+    pub async fn tick(&self, now: DateTime<Local>) {
+        let previous = std::mem::replace(&mut *self.last_tick.lock(), now.naive_local());
+        let now_utc = now.with_timezone(&Utc);
+        let schedules: Vec<Schedule> = self
+            .config
+            .read()
+            .await
+            .schedules
+            .iter()
+            .filter(|s| s.enabled)
+            .cloned()
+            .collect();
+
+        // Deferred runs go first, then whatever newly came due
+        let mut due: Vec<(Schedule, DateTime<Utc>)> = Vec::new();
+        let deferred = self.deferred.lock().clone();
+        for (id, triggered_at) in deferred {
+            match schedules.iter().find(|s| s.id == id) {
+                Some(schedule) => due.push((schedule.clone(), triggered_at)),
+                // Deleted or disabled while waiting
+                None => self.deferred.lock().retain(|(d, _)| *d != id),
+            }
+        }
+        for schedule in &schedules {
+            if is_due(schedule, previous, now) && !due.iter().any(|(s, _)| s.id == schedule.id) {
+                due.push((schedule.clone(), now_utc));
+            }
+        }
+
+        let interrupted = !due.is_empty() && self.runner.has_interrupted_task();
+        for (schedule, triggered_at) in due {
+            if interrupted {
+                self.handle_busy(&schedule, triggered_at, "An interrupted task is waiting to be resumed").await;
+                continue;
+            }
+            if !self.state.try_claim().await {
+                self.handle_busy(&schedule, triggered_at, "Agent was busy").await;
+                continue;
+            }
+            self.deferred.lock().retain(|(id, _)| *id != schedule.id);
+            self.run(&schedule, triggered_at).await;
+            self.state.release_claim().await;
+        }
+    }
+
+    async fn handle_busy(&self, schedule: &Schedule, triggered_at: DateTime<Utc>, reason: &str) {
+        match schedule.when_busy {
+            BusyPolicy::Skip => {
+                self.log_run(schedule, triggered_at, RunOutcome::Skipped, Some(reason.to_string()));
+                self.mark_fired(&schedule.id, triggered_at).await;
+            }
+            BusyPolicy::Defer => {
+                let mut deferred = self.deferred.lock();
+                if !deferred.iter().any(|(id, _)| *id == schedule.id) {
+                    deferred.push((schedule.id.clone(), triggered_at));
+                    drop(deferred);
+                    self.log_run(schedule, triggered_at, RunOutcome::Deferred, None);
+                }
+            }
+        }
+    }
+
+    async fn run(&self, schedule: &Schedule, triggered_at: DateTime<Utc>) {
+        log::info!("Running schedule '{}'", schedule.name);
+        self.mark_fired(&schedule.id, triggered_at).await;
+
+        let result = match &schedule.target {
+            ScheduleTarget::Template { template_id, variables } => {
+                let resolved = resolve_template(&*self.config.read().await, template_id, variables.clone());
+                match resolved {
+                    Ok((instruction, run)) => self.runner.run_instruction(instruction, Some(run)).await,
+                    Err(e) => {
+                        self.log_run(schedule, triggered_at, RunOutcome::Failed, Some(e));
+                        return;
+                    }
+                }
+            }
+            ScheduleTarget::Queue { instructions } => {
+                let instructions = instructions
+                    .iter()
+                    .filter(|i| !i.trim().is_empty())
+                    .cloned()
+                    .collect();
+                self.runner.run_queue(instructions).await
+            }
+        };
+
+        let error = result.as_ref().err().map(|e| e.to_string());
+        self.log_run(schedule, triggered_at, RunOutcome::from_result(&result), error);
+    }
+
+    fn log_run(&self, schedule: &Schedule, triggered_at: DateTime<Utc>, outcome: RunOutcome, error: Option<String>) {
+        let finished_at = (outcome != RunOutcome::Deferred).then(Utc::now);
+        self.log.lock().record(ScheduleRun {
+            schedule_id: schedule.id.clone(),
+            schedule_name: schedule.name.clone(),
+            triggered_at,
+            finished_at,
+            outcome,
+            error,
+        });
+    }
+
+    /// Remember when a schedule last fired so intervals count from there
+    async fn mark_fired(&self, id: &str, at: DateTime<Utc>) {
+        let mut config = self.config.write().await;
+        let Some(schedule) = config.schedules.iter_mut().find(|s| s.id == id) else {
+            return;
+        };
+        schedule.last_run = Some(at);
+        if self.save_config {
+            if let Err(e) = config.save() {
+                log::warn!("Failed to save schedule state: {}", e);
+            }
+        }
+    }
+}
+
+/// Fill in a saved template, returning the instruction and what to record in history
+pub fn resolve_template(
+    config: &Config,
+    template_id: &str,
+    values: HashMap<String, String>,
+) -> Result<(String, TemplateRun), String> {
+    let template = config
+        .templates
+        .iter()
+        .find(|t| t.id == template_id)
+        .ok_or_else(|| "Template not found".to_string())?;
+    let instruction = template.resolve(&values).map_err(|e| e.to_string())?;
+    let run = TemplateRun {
+        template_id: template.id.clone(),
+        template_name: template.name.clone(),
+        values: values.into_iter().collect(),
+    };
+    Ok((instruction, run))
+}
+
+/// Whether `schedule` has a fire time after `previous` and no later than `now`
+fn is_due(schedule: &Schedule, previous: NaiveDateTime, now: DateTime<Local>) -> bool {
+    match &schedule.spec {
+        ScheduleSpec::Cron { expression } => CronExpr::parse(expression)
+            .ok()
+            .and_then(|cron| cron.next_after(previous))
+            .is_some_and(|next| next <= now.naive_local()),
+        ScheduleSpec::Interval { minutes } => {
+            let anchor = schedule.last_run.unwrap_or(schedule.created_at);
+            anchor + ChronoDuration::minutes(*minutes as i64) <= now.with_timezone(&Utc)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::state::AgentStatus;
+    use crate::config::TaskTemplate;
+    use crate::config::template::{TemplateVariable, VariableType};
+
+    #[derive(Default)]
+    struct FakeRunner {
+        calls: Mutex<Vec<String>>,
+        fail_with_max_iterations: bool,
+        /// Agent status seen by each queue run, when `state` is set
+        statuses: Mutex<Vec<AgentStatus>>,
+        state: Option<AgentStateManager>,
+        interrupted: bool,
+    }
+
+    #[async_trait]
+    impl ScheduledTaskRunner for FakeRunner {
+        async fn run_instruction(&self, instruction: String, _template: Option<TemplateRun>) -> Result<(), LoopError> {
+            self.calls.lock().push(instruction);
+            if self.fail_with_max_iterations {
+                return Err(LoopError::MaxIterations);
+            }
+            Ok(())
+        }
+
+        async fn run_queue(&self, instructions: Vec<String>) -> Result<(), LoopError> {
+            self.calls.lock().push(format!("queue: {}", instructions.join(", ")));
+            if let Some(state) = &self.state {
+                let status = state.get_status().await;
+                self.statuses.lock().push(status);
+            }
+            Ok(())
+        }
+
+        fn has_interrupted_task(&self) -> bool {
+            self.interrupted
+        }
+    }
+
+    fn every_15_minutes(when_busy: BusyPolicy) -> Schedule {
+        let mut schedule = Schedule::new(
+            "check".to_string(),
+            ScheduleSpec::Interval { minutes: 15 },
+            ScheduleTarget::Queue { instructions: vec!["check inbox".to_string()] },
+        );
+        schedule.when_busy = when_busy;
+        schedule.created_at = Utc::now() - ChronoDuration::minutes(20);
+        schedule
+    }
+
+    fn scheduler(schedules: Vec<Schedule>, runner: Arc<FakeRunner>) -> (Scheduler, AgentStateManager) {
+        let config = Config {
+            schedules,
+            ..Default::default()
+        };
+        let state = AgentStateManager::new();
+        let scheduler = Scheduler::new(Arc::new(RwLock::new(config)), state.clone(), runner);
+        (scheduler, state)
+    }
+
+    fn outcomes(scheduler: &Scheduler) -> Vec<RunOutcome> {
+        let mut runs = scheduler.recent_runs(100);
+        runs.reverse();
+        runs.into_iter().map(|r| r.outcome).collect()
+    }
+
+    #[tokio::test]
+    async fn test_interval_schedule_runs_once_per_interval() {
+        let runner = Arc::new(FakeRunner::default());
+        let (scheduler, _) = scheduler(vec![every_15_minutes(BusyPolicy::Skip)], runner.clone());
+
+        scheduler.tick(Local::now()).await;
+        scheduler.tick(Local::now()).await;
+        assert_eq!(*runner.calls.lock(), vec!["queue: check inbox"]);
+        assert_eq!(outcomes(&scheduler), vec![RunOutcome::Completed]);
+
+        scheduler.tick(Local::now() + ChronoDuration::minutes(16)).await;
+        assert_eq!(runner.calls.lock().len(), 2);
+        assert!(scheduler.config.read().await.schedules[0].last_run.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_run_claims_the_agent_until_it_ends() {
+        let state = AgentStateManager::new();
+        let runner = Arc::new(FakeRunner {
+            state: Some(state.clone()),
+            ..Default::default()
+        });
+        let config = Config {
+            schedules: vec![every_15_minutes(BusyPolicy::Skip)],
+            ..Default::default()
+        };
+        let scheduler = Scheduler::new(Arc::new(RwLock::new(config)), state.clone(), runner.clone());
+
+        scheduler.tick(Local::now()).await;
+
+        assert_eq!(*runner.statuses.lock(), vec![AgentStatus::Running]);
+        assert_eq!(state.get_status().await, AgentStatus::Idle);
+        assert!(state.try_claim().await);
+        assert!(!state.try_claim().await);
+    }
+
+    #[tokio::test]
+    async fn test_claim_covers_the_gap_between_queue_items() {
+        let runner = Arc::new(FakeRunner::default());
+        let (scheduler, state) = scheduler(vec![every_15_minutes(BusyPolicy::Skip)], runner.clone());
+        assert!(state.try_claim().await);
+        // A queue item just finished and the next one hasn't started yet
+        state.set_status(AgentStatus::Completed).await;
+
+        assert!(state.is_busy().await);
+        scheduler.tick(Local::now()).await;
+        assert!(runner.calls.lock().is_empty());
+        assert_eq!(outcomes(&scheduler), vec![RunOutcome::Skipped]);
+
+        state.release_claim().await;
+        assert_eq!(state.get_status().await, AgentStatus::Completed);
+        assert!(!state.is_busy().await);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_task_holds_schedules_back() {
+        let runner = Arc::new(FakeRunner {
+            interrupted: true,
+            ..Default::default()
+        });
+        let (scheduler, _) = scheduler(
+            vec![every_15_minutes(BusyPolicy::Skip), every_15_minutes(BusyPolicy::Defer)],
+            runner.clone(),
+        );
+
+        scheduler.tick(Local::now()).await;
+
+        assert!(runner.calls.lock().is_empty());
+        assert_eq!(outcomes(&scheduler), vec![RunOutcome::Skipped, RunOutcome::Deferred]);
+        let runs = scheduler.recent_runs(10);
+        assert_eq!(runs[1].error.as_deref(), Some("An interrupted task is waiting to be resumed"));
+    }
+
+    #[tokio::test]
+    async fn test_busy_agent_skips_run() {
+        let runner = Arc::new(FakeRunner::default());
+        let (scheduler, state) = scheduler(vec![every_15_minutes(BusyPolicy::Skip)], runner.clone());
+        state.set_status(AgentStatus::Running).await;
+
+        scheduler.tick(Local::now()).await;
+        scheduler.tick(Local::now()).await;
+        state.set_status(AgentStatus::Idle).await;
+        scheduler.tick(Local::now()).await;
+
+        assert!(runner.calls.lock().is_empty());
+        assert_eq!(outcomes(&scheduler), vec![RunOutcome::Skipped]);
+    }
+
+    #[tokio::test]
+    async fn test_busy_agent_defers_run() {
+        let runner = Arc::new(FakeRunner::default());
+        let (scheduler, state) = scheduler(vec![every_15_minutes(BusyPolicy::Defer)], runner.clone());
+        state.set_status(AgentStatus::AwaitingConfirmation).await;
+
+        scheduler.tick(Local::now()).await;
+        scheduler.tick(Local::now()).await;
+        assert!(runner.calls.lock().is_empty());
+
+        state.set_status(AgentStatus::Completed).await;
+        scheduler.tick(Local::now()).await;
+
+        assert_eq!(runner.calls.lock().len(), 1);
+        assert_eq!(outcomes(&scheduler), vec![RunOutcome::Deferred, RunOutcome::Completed]);
+    }
+
+    #[tokio::test]
+    async fn test_cron_template_run_logs_loop_error() {
+        let runner = Arc::new(FakeRunner {
+            fail_with_max_iterations: true,
+            ..Default::default()
+        });
+        let template = TaskTemplate::new("Report".to_string(), "Export the {{period}} report".to_string())
+            .with_variables(vec![TemplateVariable {
+                name: "period".to_string(),
+                var_type: VariableType::String,
+                default: None,
+                required: true,
+                description: None,
+            }]);
+        let schedule = Schedule::new(
+            "daily report".to_string(),
+            ScheduleSpec::Cron { expression: "* * * * *".to_string() },
+            ScheduleTarget::Template {
+                template_id: template.id.clone(),
+                variables: HashMap::from([("period".to_string(), "daily".to_string())]),
+            },
+        );
+        let (scheduler, _) = scheduler(vec![schedule], runner.clone());
+        scheduler.config.write().await.templates.push(template);
+
+        scheduler.tick(Local::now() + ChronoDuration::minutes(2)).await;
+
+        assert_eq!(*runner.calls.lock(), vec!["Export the daily report"]);
+        let runs = scheduler.recent_runs(10);
+        assert_eq!(runs[0].outcome, RunOutcome::MaxIterations);
+        assert_eq!(runs[0].error.as_deref(), Some("Max iterations reached"));
+    }
+
+    #[test]
+    fn test_run_log_file_is_trimmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schedule_runs.jsonl");
+        let mut log = RunLog::load(path.clone());
+        for i in 0..MAX_RUN_LOG_ENTRIES + 5 {
+            log.record(ScheduleRun {
+                schedule_id: i.to_string(),
+                schedule_name: "s".to_string(),
+                triggered_at: Utc::now(),
+                finished_at: None,
+                outcome: RunOutcome::Completed,
+                error: None,
+            });
+        }
+
+        let reloaded = RunLog::load(path.clone());
+        assert_eq!(reloaded.recent(1)[0].schedule_id, (MAX_RUN_LOG_ENTRIES + 4).to_string());
+        assert_eq!(reloaded.entries.len(), MAX_RUN_LOG_ENTRIES);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), MAX_RUN_LOG_ENTRIES);
+    }
+}
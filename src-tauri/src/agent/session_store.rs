@@ -0,0 +1,457 @@
+//! On-disk library of past agent sessions.
+//!
+//! Each session gets its own directory under `sessions/` next to `config.toml`:
+//!
+//! ```text
+//! sessions/<session_id>/session.json
+//! sessions/<session_id>/screenshots/0001.jpg
+//! ```
+//!
+//! `session.json` holds the `SessionHistory` with screenshots stripped out;
+//! entries point at their image through `screenshot_file` instead.
+
+use super::history::SessionHistory;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+
+const SESSION_FILE: &str = "session.json";
+const SCREENSHOT_DIR: &str = "screenshots";
+
+#[derive(Error, Debug)]
+pub enum SessionStoreError {
+    #[error("Failed to access session files: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to parse session: {0}")]
+    ParseError(#[from] serde_json::Error),
+    #[error("Session not found: {0}")]
+    NotFound(String),
+    #[error("Invalid session id: {0}")]
+    InvalidId(String),
+}
+
+/// Lightweight description of a stored session for list views
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub instruction: String,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+    pub final_status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    pub total_iterations: u32,
+    pub entry_count: usize,
+    pub size_bytes: u64,
+}
+
+/// Criteria for narrowing down the session list. Empty fields match everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionFilter {
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// Only sessions started at or after this time
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    /// Only sessions started before this time
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl SessionFilter {
+    pub fn matches(&self, session: &SessionHistory) -> bool {
+        if let Some(status) = &self.status {
+            if !session.final_status.eq_ignore_ascii_case(status) {
+                return false;
+            }
+        }
+        if let Some(provider) = &self.provider {
+            match &session.provider {
+                Some(p) if p.eq_ignore_ascii_case(provider) => {}
+                _ => return false,
+            }
+        }
+        if let Some(from) = self.from {
+            if session.started_at < from {
+                return false;
+            }
+        }
+        if let Some(to) = self.to {
+            if session.started_at >= to {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    root: PathBuf,
+    /// Total size the library may grow to before old sessions are pruned
+    max_bytes: Option<u64>,
+}
+
+impl SessionStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            max_bytes: None,
+        }
+    }
+
+    /// Store sessions in a `sessions` directory beside the given config file
+    pub fn beside_config(config_path: &Path) -> Self {
+        let dir = config_path.parent().unwrap_or_else(|| Path::new("."));
+        Self::new(dir.join("sessions"))
+    }
+
+    /// Cap the library size in megabytes; 0 means unlimited
+    pub fn with_storage_limit_mb(mut self, limit_mb: u64) -> Self {
+        self.max_bytes = if limit_mb == 0 {
+            None
+        } else {
+            Some(limit_mb * 1024 * 1024)
+        };
+        self
+    }
+
+    fn session_dir(&self, session_id: &str) -> Result<PathBuf, SessionStoreError> {
+        let valid = !session_id.is_empty()
+            && session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(SessionStoreError::InvalidId(session_id.to_string()));
+        }
+        Ok(self.root.join(session_id))
+    }
+
+    /// Write the session metadata and entries, leaving screenshots out
+    pub fn save(&self, session: &SessionHistory) -> Result<(), SessionStoreError> {
+        let dir = self.session_dir(&session.session_id)?;
+        fs::create_dir_all(&dir)?;
+
+        let mut stripped = session.clone();
+        for entry in &mut stripped.entries {
+            entry.screenshot_base64 = None;
+        }
+        let content = serde_json::to_string_pretty(&stripped)?;
+
+        // Write to a temp file first so a crash never leaves a truncated session
+        let tmp = dir.join(format!("{}.tmp", SESSION_FILE));
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, dir.join(SESSION_FILE))?;
+        Ok(())
+    }
+
+    /// Save the screenshot for the session's `entry_number`th entry (counting
+    /// from 1) and return its path relative to the session directory. Iteration
+    /// numbers can repeat within a session, entry numbers can't.
+    pub fn save_screenshot(
+        &self,
+        session_id: &str,
+        entry_number: usize,
+        base64_data: &str,
+    ) -> Result<String, SessionStoreError> {
+        let file = Self::screenshot_file(entry_number, base64_data);
+        let path = self.session_dir(session_id)?.join(&file);
+
+        let bytes = STANDARD.decode(base64_data).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+        })?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, bytes)?;
+
+        Ok(file)
+    }
+
+    /// Path, relative to the session directory, that `save_screenshot` writes to
+    pub fn screenshot_file(entry_number: usize, base64_data: &str) -> String {
+        let extension = if base64_data.starts_with("iVBOR") { "png" } else { "jpg" };
+        format!("{}/{:04}.{}", SCREENSHOT_DIR, entry_number, extension)
+    }
+
+    /// Load a stored session, optionally reading its screenshots back in
+    pub fn load(
+        &self,
+        session_id: &str,
+        include_screenshots: bool,
+    ) -> Result<SessionHistory, SessionStoreError> {
+        let dir = self.session_dir(session_id)?;
+        let path = dir.join(SESSION_FILE);
+        if !path.exists() {
+            return Err(SessionStoreError::NotFound(session_id.to_string()));
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let mut session: SessionHistory = serde_json::from_str(&content)?;
+
+        if include_screenshots {
+            for entry in &mut session.entries {
+                if let Some(file) = &entry.screenshot_file {
+                    if let Ok(bytes) = fs::read(dir.join(file)) {
+                        entry.screenshot_base64 = Some(Arc::new(STANDARD.encode(bytes)));
+                    }
+                }
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// List stored sessions matching the filter, newest first
+    pub fn list(&self, filter: &SessionFilter) -> Result<Vec<SessionSummary>, SessionStoreError> {
+        let mut summaries = Vec::new();
+        if !self.root.exists() {
+            return Ok(summaries);
+        }
+
+        for dir_entry in fs::read_dir(&self.root)? {
+            let dir = dir_entry?.path();
+            let Some(session_id) = dir.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            // Skip directories that are not sessions or could not be parsed
+            let Ok(session) = self.load(session_id, false) else {
+                continue;
+            };
+            if !filter.matches(&session) {
+                continue;
+            }
+            summaries.push(SessionSummary {
+                session_id: session.session_id.clone(),
+                instruction: session.instruction.clone(),
+                started_at: session.started_at,
+                ended_at: session.ended_at,
+                final_status: session.final_status.clone(),
+                provider: session.provider.clone(),
+                total_iterations: session.metrics.total_iterations,
+                entry_count: session.entries.len(),
+                size_bytes: dir_size(&dir),
+            });
+        }
+
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.started_at));
+        Ok(summaries)
+    }
+
+    pub fn delete(&self, session_id: &str) -> Result<(), SessionStoreError> {
+        let dir = self.session_dir(session_id)?;
+        if !dir.join(SESSION_FILE).exists() {
+            return Err(SessionStoreError::NotFound(session_id.to_string()));
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    /// Total bytes used by the library
+    pub fn disk_usage(&self) -> u64 {
+        dir_size(&self.root)
+    }
+
+    /// Delete the oldest sessions until the library fits within its limit.
+    /// `keep` protects a session (usually the one still running) from removal.
+    /// Returns the ids of the deleted sessions.
+    pub fn enforce_retention(&self, keep: Option<&str>) -> Result<Vec<String>, SessionStoreError> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(Vec::new());
+        };
+
+        let mut sessions = self.list(&SessionFilter::default())?;
+        let mut total: u64 = sessions.iter().map(|s| s.size_bytes).sum();
+        let mut deleted = Vec::new();
+
+        // Oldest first
+        sessions.reverse();
+        for session in sessions {
+            if total <= max_bytes {
+                break;
+            }
+            if Some(session.session_id.as_str()) == keep {
+                continue;
+            }
+            self.delete(&session.session_id)?;
+            total = total.saturating_sub(session.size_bytes);
+            deleted.push(session.session_id);
+        }
+
+        Ok(deleted)
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| match e.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&e.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::history::ActionEntry;
+    use chrono::Duration;
+
+    // A 1x1 PNG
+    const PNG_1X1: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==";
+
+    fn session(instruction: &str, status: &str, provider: &str) -> SessionHistory {
+        let mut session = SessionHistory::new(instruction.to_string());
+        session.provider = Some(provider.to_string());
+        session.complete(status);
+        session
+    }
+
+    fn entry(iteration: u32) -> ActionEntry {
+        ActionEntry {
+            timestamp: Utc::now(),
+            iteration,
+            action_type: "click".to_string(),
+            action_details: serde_json::json!({"action": "click", "x": 1, "y": 1}),
+            screenshot_base64: Some(Arc::new(PNG_1X1.to_string())),
+            screenshot_file: None,
+            llm_response: String::new(),
+            success: true,
+            error_message: None,
+            result_message: None,
+            retry_count: 0,
+            cost_usd: None,
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+
+        let mut session = session("open notes", "completed", "anthropic");
+        let mut e = entry(1);
+        e.screenshot_file = Some(store.save_screenshot(&session.session_id, 1, PNG_1X1).unwrap());
+        session.add_entry(e);
+        store.save(&session).unwrap();
+
+        let raw = fs::read_to_string(dir.path().join(&session.session_id).join(SESSION_FILE)).unwrap();
+        assert!(!raw.contains(PNG_1X1), "screenshots are stored as files, not inline");
+
+        let without = store.load(&session.session_id, false).unwrap();
+        assert_eq!(without.instruction, "open notes");
+        assert_eq!(without.entries[0].screenshot_file.as_deref(), Some("screenshots/0001.png"));
+        assert!(without.entries[0].screenshot_base64.is_none());
+
+        let with = store.load(&session.session_id, true).unwrap();
+        assert_eq!(with.entries[0].screenshot_base64.as_deref().map(|s| s.as_str()), Some(PNG_1X1));
+    }
+
+    #[test]
+    fn test_screenshots_are_named_by_entry_and_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let id = "resumed-session";
+
+        // A stale file from an earlier run must not stand in for the new image
+        let stale = dir.path().join(id).join(SCREENSHOT_DIR);
+        fs::create_dir_all(&stale).unwrap();
+        fs::write(stale.join("0002.png"), b"old").unwrap();
+
+        assert_eq!(store.save_screenshot(id, 1, PNG_1X1).unwrap(), "screenshots/0001.png");
+        assert_eq!(store.save_screenshot(id, 2, PNG_1X1).unwrap(), "screenshots/0002.png");
+        assert_eq!(fs::read(stale.join("0002.png")).unwrap(), STANDARD.decode(PNG_1X1).unwrap());
+    }
+
+    #[test]
+    fn test_list_filters_and_sorts_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+
+        let mut old = session("old", "completed", "ollama");
+        old.started_at = Utc::now() - Duration::days(3);
+        let failed = session("failed", "error", "anthropic");
+        let recent = session("recent", "completed", "anthropic");
+        for s in [&old, &failed, &recent] {
+            store.save(s).unwrap();
+        }
+
+        let all = store.list(&SessionFilter::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all.last().unwrap().instruction, "old");
+
+        let completed = store
+            .list(&SessionFilter { status: Some("completed".into()), ..Default::default() })
+            .unwrap();
+        assert_eq!(completed.len(), 2);
+
+        let anthropic_ok = store
+            .list(&SessionFilter {
+                status: Some("completed".into()),
+                provider: Some("anthropic".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(anthropic_ok.len(), 1);
+        assert_eq!(anthropic_ok[0].instruction, "recent");
+
+        let before_yesterday = store
+            .list(&SessionFilter { to: Some(Utc::now() - Duration::days(1)), ..Default::default() })
+            .unwrap();
+        assert_eq!(before_yesterday.len(), 1);
+        assert_eq!(before_yesterday[0].instruction, "old");
+    }
+
+    #[test]
+    fn test_delete_and_invalid_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let s = session("task", "completed", "ollama");
+        store.save(&s).unwrap();
+
+        store.delete(&s.session_id).unwrap();
+        assert!(store.list(&SessionFilter::default()).unwrap().is_empty());
+        assert!(matches!(store.delete(&s.session_id), Err(SessionStoreError::NotFound(_))));
+        assert!(matches!(store.load("../config", false), Err(SessionStoreError::InvalidId(_))));
+    }
+
+    #[test]
+    fn test_retention_removes_oldest_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf()).with_storage_limit_mb(1);
+
+        let big = "A".repeat(400 * 1024);
+        let mut ids = Vec::new();
+        for i in 0..4 {
+            let mut s = session(&format!("task {}", i), "completed", "ollama");
+            s.started_at = Utc::now() - Duration::minutes(10 - i);
+            store.save(&s).unwrap();
+            fs::write(dir.path().join(&s.session_id).join("padding"), &big).unwrap();
+            ids.push(s.session_id);
+        }
+
+        // Protect the oldest session, as if it were still running
+        let deleted = store.enforce_retention(Some(&ids[0])).unwrap();
+        assert_eq!(deleted, vec![ids[1].clone(), ids[2].clone()]);
+        assert!(store.disk_usage() <= 1024 * 1024);
+        let remaining: Vec<_> = store
+            .list(&SessionFilter::default())
+            .unwrap()
+            .into_iter()
+            .map(|s| s.session_id)
+            .collect();
+        assert_eq!(remaining, vec![ids[3].clone(), ids[0].clone()]);
+    }
+}
@@ -1,7 +1,10 @@
 #![allow(dead_code)]
 
 use chrono::Utc;
+use super::action::Action;
+use super::budget::DailySpend;
 use super::history::HistoryManager;
+use super::plan::TaskPlan;
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
@@ -25,6 +28,20 @@ pub enum AgentStatus {
     Error,
 }
 
+impl AgentStatus {
+    /// Whether a task is in progress, including one that is paused or waiting on the user
+    pub fn is_busy(self) -> bool {
+        matches!(
+            self,
+            AgentStatus::Running
+                | AgentStatus::Recording
+                | AgentStatus::Paused
+                | AgentStatus::AwaitingConfirmation
+                | AgentStatus::Retrying
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ExecutionMode {
     Normal,
@@ -39,10 +56,23 @@ impl Default for ExecutionMode {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordedAction {
-    pub action: String,
+    pub action: Action,
     pub reasoning: Option<String>,
     pub timestamp: u64,
     pub iteration: u32,
+    /// Screen as it looked right before the action ran, compared against during replay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screenshot_base64: Option<Arc<String>>,
+}
+
+impl RecordedAction {
+    /// Copy without the screenshot, for sending to the frontend
+    pub fn without_screenshot(&self) -> Self {
+        Self {
+            screenshot_base64: None,
+            ..self.clone()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +95,8 @@ pub struct AgentState {
     pub tokens_per_second: f64,
     pub total_input_tokens: u64,
     pub total_output_tokens: u64,
+    /// Dollars spent on this task so far; stays at zero when the model's price is unknown
+    pub total_cost_usd: f64,
     pub action_history: Vec<ActionHistoryEntry>,
     pub retry_count: u32,
     pub consecutive_errors: u32,
@@ -75,6 +107,9 @@ pub struct AgentState {
     pub last_screenshot: Option<Arc<String>>,
     pub kill_switch_triggered: bool,
     pub execution_mode: ExecutionMode,
+    /// Left out of state updates because of the screenshots; the frontend
+    /// gets these through the `recorded-actions` event instead
+    #[serde(skip)]
     pub recorded_actions: Vec<RecordedAction>,
     pub last_retry_count: u32,
     pub total_retries: u32,
@@ -82,6 +117,8 @@ pub struct AgentState {
     pub can_undo: bool,
     /// Description of the last action that can be undone
     pub last_undoable_action: Option<String>,
+    /// Sub-goals of the current task, when the planner is enabled
+    pub plan: Option<TaskPlan>,
 }
 
 impl Default for AgentState {
@@ -98,6 +135,7 @@ impl Default for AgentState {
             tokens_per_second: 0.0,
             total_input_tokens: 0,
             total_output_tokens: 0,
+            total_cost_usd: 0.0,
             action_history: Vec::new(),
             retry_count: 0,
             consecutive_errors: 0,
@@ -113,6 +151,7 @@ impl Default for AgentState {
             total_retries: 0,
             can_undo: false,
             last_undoable_action: None,
+            plan: None,
         }
     }
 }
@@ -125,6 +164,8 @@ struct AtomicMetrics {
     total_output_tokens: AtomicU64,
     /// tokens_per_second stored as bits (use f64::to_bits/from_bits)
     tokens_per_second_bits: AtomicU64,
+    /// total cost in USD stored as bits, like tokens_per_second
+    total_cost_bits: AtomicU64,
     /// Consecutive error count - frequently read/written, no need for RwLock
     consecutive_errors: AtomicU32,
 }
@@ -137,6 +178,7 @@ impl AtomicMetrics {
             total_input_tokens: AtomicU64::new(0),
             total_output_tokens: AtomicU64::new(0),
             tokens_per_second_bits: AtomicU64::new(0.0_f64.to_bits()),
+            total_cost_bits: AtomicU64::new(0.0_f64.to_bits()),
             consecutive_errors: AtomicU32::new(0),
         }
     }
@@ -150,6 +192,18 @@ impl AtomicMetrics {
         f64::from_bits(self.tokens_per_second_bits.load(Ordering::Acquire))
     }
 
+    fn add_cost(&self, usd: f64) {
+        let _ = self
+            .total_cost_bits
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                Some((f64::from_bits(bits) + usd).to_bits())
+            });
+    }
+
+    fn get_total_cost(&self) -> f64 {
+        f64::from_bits(self.total_cost_bits.load(Ordering::Acquire))
+    }
+
     fn reset(&self) {
         self.iteration.store(0, Ordering::Release);
         self.max_iterations.store(50, Ordering::Release);
@@ -157,6 +211,7 @@ impl AtomicMetrics {
         self.total_output_tokens.store(0, Ordering::Release);
         self.tokens_per_second_bits
             .store(0.0_f64.to_bits(), Ordering::Release);
+        self.total_cost_bits.store(0.0_f64.to_bits(), Ordering::Release);
         self.consecutive_errors.store(0, Ordering::Release);
     }
 }
@@ -169,7 +224,11 @@ pub struct AgentStateManager {
     confirmation_tx: Arc<RwLock<Option<mpsc::Sender<ConfirmationResponse>>>>,
     confirmation_rx: Arc<RwLock<Option<mpsc::Receiver<ConfirmationResponse>>>>,
     history: HistoryManager,
+    spend: DailySpend,
     kill_switch_triggered: Arc<AtomicBool>,
+    /// Held by whoever started the current run until all of it is done, so the
+    /// gaps between queue items, when the status is already final, stay busy
+    claimed: Arc<AtomicBool>,
 }
 
 impl Clone for AgentStateManager {
@@ -182,7 +241,9 @@ impl Clone for AgentStateManager {
             confirmation_tx: Arc::clone(&self.confirmation_tx),
             confirmation_rx: Arc::clone(&self.confirmation_rx),
             history: self.history.clone(),
+            spend: self.spend.clone(),
             kill_switch_triggered: Arc::clone(&self.kill_switch_triggered),
+            claimed: Arc::clone(&self.claimed),
         }
     }
 }
@@ -198,7 +259,9 @@ impl AgentStateManager {
             confirmation_tx: Arc::new(RwLock::new(Some(tx))),
             confirmation_rx: Arc::new(RwLock::new(Some(rx))),
             history: HistoryManager::new(),
+            spend: DailySpend::new(),
             kill_switch_triggered: Arc::new(AtomicBool::new(false)),
+            claimed: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -206,6 +269,11 @@ impl AgentStateManager {
         &self.history
     }
 
+    /// What has been spent today across all tasks, for the daily cost cap
+    pub fn spend(&self) -> &DailySpend {
+        &self.spend
+    }
+
     /// Get the full state, merging atomic metrics with RwLock-protected fields.
     pub async fn get_state(&self) -> AgentState {
         let mut state = self.state.read().await.clone();
@@ -215,6 +283,7 @@ impl AgentStateManager {
         state.tokens_per_second = self.metrics.get_tokens_per_second();
         state.total_input_tokens = self.metrics.total_input_tokens.load(Ordering::Acquire);
         state.total_output_tokens = self.metrics.total_output_tokens.load(Ordering::Acquire);
+        state.total_cost_usd = self.metrics.get_total_cost();
         state.consecutive_errors = self.metrics.consecutive_errors.load(Ordering::Acquire);
         state.kill_switch_triggered = self.kill_switch_triggered.load(Ordering::SeqCst);
         state
@@ -241,11 +310,46 @@ impl AgentStateManager {
         )
     }
 
+    /// Get what the current task has cost so far, in USD
+    pub fn get_task_cost(&self) -> f64 {
+        self.metrics.get_total_cost()
+    }
+
     /// Get status (requires read lock, but minimal clone)
     pub async fn get_status(&self) -> AgentStatus {
         self.state.read().await.status
     }
 
+    /// Whether a task is in progress, including one that is paused or waiting on
+    /// the user, or the agent is claimed for a run such as a queue between items
+    pub async fn is_busy(&self) -> bool {
+        let state = self.state.read().await;
+        state.status.is_busy() || self.claimed.load(Ordering::SeqCst)
+    }
+
+    /// Claim the agent and mark it running if it isn't busy, in one step so
+    /// nothing else can start a task in between. Returns whether the agent was
+    /// claimed; the claim holds until `release_claim`.
+    pub async fn try_claim(&self) -> bool {
+        let mut state = self.state.write().await;
+        if state.status.is_busy() || self.claimed.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.claimed.store(true, Ordering::SeqCst);
+        state.status = AgentStatus::Running;
+        true
+    }
+
+    /// End a claim once its run is over; a run that never set a final status
+    /// leaves the agent idle
+    pub async fn release_claim(&self) {
+        let mut state = self.state.write().await;
+        self.claimed.store(false, Ordering::SeqCst);
+        if state.status == AgentStatus::Running {
+            state.status = AgentStatus::Idle;
+        }
+    }
+
     pub async fn set_status(&self, status: AgentStatus) {
         let mut state = self.state.write().await;
         state.status = status;
@@ -260,12 +364,36 @@ impl AgentStateManager {
     }
 
     pub async fn start_with_mode(&self, instruction: String, max_iterations: u32, mode: ExecutionMode) {
+        self.reset_for_run(instruction.clone(), max_iterations, mode).await;
+        // Start a new history session
+        self.history.start_session(instruction).await;
+    }
+
+    /// Prepare for continuing an interrupted task from its saved progress.
+    /// The caller is responsible for restoring the session history.
+    pub async fn resume_with_mode(
+        &self,
+        instruction: String,
+        max_iterations: u32,
+        mode: ExecutionMode,
+        iteration: u32,
+        input_tokens: u64,
+        output_tokens: u64,
+    ) {
+        self.reset_for_run(instruction, max_iterations, mode).await;
+        self.metrics.iteration.store(iteration, Ordering::Release);
+        self.metrics.total_input_tokens.store(input_tokens, Ordering::Release);
+        self.metrics.total_output_tokens.store(output_tokens, Ordering::Release);
+    }
+
+    async fn reset_for_run(&self, instruction: String, max_iterations: u32, mode: ExecutionMode) {
         // Reset atomic metrics first (no lock needed)
         self.metrics.iteration.store(0, Ordering::Release);
         self.metrics.max_iterations.store(max_iterations, Ordering::Release);
         self.metrics.total_input_tokens.store(0, Ordering::Release);
         self.metrics.total_output_tokens.store(0, Ordering::Release);
         self.metrics.set_tokens_per_second(0.0);
+        self.metrics.total_cost_bits.store(0.0_f64.to_bits(), Ordering::Release);
         self.metrics.consecutive_errors.store(0, Ordering::Release);
         self.should_stop.store(false, Ordering::SeqCst);
 
@@ -275,7 +403,7 @@ impl AgentStateManager {
             ExecutionMode::Normal => AgentStatus::Running,
             ExecutionMode::Recording => AgentStatus::Recording,
         };
-        state.instruction = Some(instruction.clone());
+        state.instruction = Some(instruction);
         state.iteration = 0;
         state.max_iterations = max_iterations;
         state.last_action = None;
@@ -284,21 +412,28 @@ impl AgentStateManager {
         state.tokens_per_second = 0.0;
         state.total_input_tokens = 0;
         state.total_output_tokens = 0;
+        state.total_cost_usd = 0.0;
         state.action_history.clear();
         state.retry_count = 0;
         state.consecutive_errors = 0;
         state.execution_mode = mode;
-        state.recorded_actions = Vec::new();
+        // Other runs keep the last recording around so it can be replayed
+        if mode == ExecutionMode::Recording {
+            state.recorded_actions = Vec::new();
+        }
         state.last_retry_count = 0;
         state.total_retries = 0;
+        state.plan = None;
         self.should_pause.store(false, Ordering::SeqCst);
         self.kill_switch_triggered.store(false, Ordering::SeqCst);
-        // Start a new history session
-        drop(state); // Release write lock before async call
-        self.history.start_session(instruction).await;
     }
 
-    pub async fn add_recorded_action(&self, action: String, reasoning: Option<String>) {
+    pub async fn add_recorded_action(
+        &self,
+        action: Action,
+        reasoning: Option<String>,
+        screenshot_base64: Option<Arc<String>>,
+    ) {
         let mut state = self.state.write().await;
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -310,6 +445,7 @@ impl AgentStateManager {
             reasoning,
             timestamp,
             iteration,
+            screenshot_base64,
         });
     }
 
@@ -357,6 +493,11 @@ impl AgentStateManager {
         });
     }
 
+    pub async fn set_plan(&self, plan: Option<TaskPlan>) {
+        let mut state = self.state.write().await;
+        state.plan = plan;
+    }
+
     pub async fn set_last_screenshot(&self, screenshot: Arc<String>) {
         let mut state = self.state.write().await;
         state.last_screenshot = Some(screenshot);
@@ -369,6 +510,18 @@ impl AgentStateManager {
         self.metrics.total_output_tokens.fetch_add(output_tokens, Ordering::AcqRel);
     }
 
+    /// Carry over what a resumed task had already cost. Not added to today's
+    /// spend, which counted it when it was spent.
+    pub fn restore_task_cost(&self, usd: f64) {
+        self.metrics.add_cost(usd);
+    }
+
+    /// Add the cost of an LLM call to the task total and to today's spend
+    pub fn add_cost(&self, usd: f64) {
+        self.metrics.add_cost(usd);
+        self.spend.add(usd);
+    }
+
     pub async fn update_retry_stats(&self, retry_count: u32) {
         let mut state = self.state.write().await;
         state.last_retry_count = retry_count;
@@ -0,0 +1,241 @@
+//! Spots a task that is going nowhere: the same action again and again, two
+//! actions that undo each other, or a run of steps that leave the screen as it was.
+
+use super::action::Action;
+use super::history::ActionRecord;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Coordinates within this many pixels of each other count as the same target
+const POSITION_TOLERANCE: i64 = 20;
+/// Identical actions in a row, none of which changed the screen
+const REPEAT_LIMIT: usize = 3;
+/// Alternating actions, e.g. 4 for A-B-A-B
+const OSCILLATION_LENGTH: usize = 4;
+/// Steps in a row that left the screen unchanged
+const NO_PROGRESS_LIMIT: usize = 5;
+/// Fraction of the screen that has to differ for a step to count as having changed it
+pub const SCREEN_CHANGE_THRESHOLD: f32 = 0.005;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StuckPattern {
+    Repeating { action: String, times: usize },
+    Oscillating { first: String, second: String },
+    NoProgress { steps: usize },
+}
+
+impl StuckPattern {
+    /// Note for the model, put in front of its next screenshot
+    pub fn hint(&self) -> String {
+        match self {
+            StuckPattern::Repeating { action, times } => format!(
+                "Your last {} actions were all \"{}\" and the screen did not change. That is not working; \
+                 try something different, such as another element, a keyboard shortcut, or scrolling to find the target.",
+                times, action
+            ),
+            StuckPattern::Oscillating { first, second } => format!(
+                "You keep going back and forth between \"{}\" and \"{}\". Stop and work out from the current \
+                 screen what actually moves the task forward.",
+                first, second
+            ),
+            StuckPattern::NoProgress { steps } => format!(
+                "None of your last {} actions changed the screen. Look closely at the screenshot, check your \
+                 assumptions about where things are, and try a different approach.",
+                steps
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for StuckPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StuckPattern::Repeating { action, times } => {
+                write!(f, "repeated \"{}\" {} times without the screen changing", action, times)
+            }
+            StuckPattern::Oscillating { first, second } => {
+                write!(f, "alternating between \"{}\" and \"{}\"", first, second)
+            }
+            StuckPattern::NoProgress { steps } => {
+                write!(f, "{} steps in a row left the screen unchanged", steps)
+            }
+        }
+    }
+}
+
+/// What the loop does about a stuck task, from the gentlest step to the last
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recovery {
+    /// Tell the model what it is doing
+    Hint,
+    /// Hand the task to the configured stronger model
+    SwitchModel,
+    /// Ask the user whether to keep going
+    AskUser,
+    Abort,
+}
+
+impl Recovery {
+    /// The step to take if the task gets stuck again after this one
+    pub fn next(self) -> Self {
+        match self {
+            Recovery::Hint => Recovery::SwitchModel,
+            Recovery::SwitchModel => Recovery::AskUser,
+            Recovery::AskUser | Recovery::Abort => Recovery::Abort,
+        }
+    }
+}
+
+struct Observation {
+    signature: String,
+    description: String,
+    screen_changed: bool,
+}
+
+/// Watches the actions the loop executes, as recorded in `ActionHistory`,
+/// together with whether the screen changed after each one
+#[derive(Default)]
+pub struct StuckDetector {
+    window: VecDeque<Observation>,
+}
+
+impl StuckDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an executed action and whether the next screenshot differed
+    /// from the one it was chosen on. Returns the pattern once one shows up.
+    pub fn observe(&mut self, record: &ActionRecord, screen_changed: bool) -> Option<StuckPattern> {
+        // Plan bookkeeping and terminal actions never touch the screen
+        if matches!(
+            record.action,
+            Action::StepDone { .. } | Action::Replan { .. } | Action::Complete { .. } | Action::Error { .. }
+        ) {
+            return None;
+        }
+
+        let capacity = NO_PROGRESS_LIMIT.max(OSCILLATION_LENGTH).max(REPEAT_LIMIT);
+        if self.window.len() == capacity {
+            self.window.pop_front();
+        }
+        self.window.push_back(Observation {
+            signature: signature(&record.action),
+            description: record.description.clone(),
+            screen_changed,
+        });
+        self.check()
+    }
+
+    /// Forget what was seen so far, once the loop has acted on a pattern
+    pub fn clear(&mut self) {
+        self.window.clear();
+    }
+
+    fn check(&self) -> Option<StuckPattern> {
+        let recent = |count: usize| self.window.iter().skip(self.window.len().saturating_sub(count));
+
+        if self.window.len() >= REPEAT_LIMIT {
+            let last = self.window.back()?;
+            if recent(REPEAT_LIMIT).all(|o| o.signature == last.signature && !o.screen_changed) {
+                return Some(StuckPattern::Repeating {
+                    action: last.description.clone(),
+                    times: REPEAT_LIMIT,
+                });
+            }
+        }
+
+        if self.window.len() >= OSCILLATION_LENGTH {
+            let tail: Vec<&Observation> = recent(OSCILLATION_LENGTH).collect();
+            let (a, b) = (tail[0], tail[1]);
+            let alternates = a.signature != b.signature
+                && tail.iter().enumerate().all(|(i, o)| {
+                    &o.signature == if i % 2 == 0 { &a.signature } else { &b.signature }
+                });
+            if alternates {
+                return Some(StuckPattern::Oscillating {
+                    first: a.description.clone(),
+                    second: b.description.clone(),
+                });
+            }
+        }
+
+        if self.window.len() >= NO_PROGRESS_LIMIT && recent(NO_PROGRESS_LIMIT).all(|o| !o.screen_changed) {
+            return Some(StuckPattern::NoProgress { steps: NO_PROGRESS_LIMIT });
+        }
+
+        None
+    }
+}
+
+/// The action with its coordinates rounded to `POSITION_TOLERANCE`, so clicks
+/// a few pixels apart on the same button compare equal
+fn signature(action: &Action) -> String {
+    let mut value = serde_json::to_value(action).unwrap_or_default();
+    if let Some(fields) = value.as_object_mut() {
+        for (key, field) in fields.iter_mut() {
+            let is_coordinate = matches!(key.as_str(), "x" | "y" | "start_x" | "start_y" | "end_x" | "end_y");
+            if let (true, Some(n)) = (is_coordinate, field.as_i64()) {
+                *field = (n.div_euclid(POSITION_TOLERANCE)).into();
+            }
+        }
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(x: i32, y: i32) -> ActionRecord {
+        ActionRecord::new(Action::Click { x, y, button: "left".to_string() }, true)
+    }
+
+    fn scroll(direction: &str) -> ActionRecord {
+        ActionRecord::new(
+            Action::Scroll { x: 100, y: 100, direction: direction.to_string(), amount: 3 },
+            true,
+        )
+    }
+
+    #[test]
+    fn test_repeated_action_without_change() {
+        let mut detector = StuckDetector::new();
+        assert_eq!(detector.observe(&click(100, 200), false), None);
+        assert_eq!(detector.observe(&click(104, 203), false), None);
+        let pattern = detector.observe(&click(101, 205), false).unwrap();
+        assert!(matches!(pattern, StuckPattern::Repeating { times: 3, .. }));
+
+        // The same click is fine while it keeps changing the screen
+        detector.clear();
+        for _ in 0..4 {
+            assert_eq!(detector.observe(&click(100, 200), true), None);
+        }
+    }
+
+    #[test]
+    fn test_oscillation_and_no_progress() {
+        let mut detector = StuckDetector::new();
+        assert_eq!(detector.observe(&scroll("down"), true), None);
+        assert_eq!(detector.observe(&scroll("up"), true), None);
+        assert_eq!(detector.observe(&scroll("down"), true), None);
+        assert!(matches!(
+            detector.observe(&scroll("up"), true),
+            Some(StuckPattern::Oscillating { .. })
+        ));
+
+        detector.clear();
+        for (i, x) in [10, 300, 600, 900].into_iter().enumerate() {
+            assert_eq!(detector.observe(&click(x, 50 * i as i32), false), None);
+        }
+        // Plan bookkeeping is not an attempt at the screen
+        let step_done = ActionRecord::new(Action::StepDone { step: None }, true);
+        assert_eq!(detector.observe(&step_done, false), None);
+        assert_eq!(
+            detector.observe(&click(1200, 40), false),
+            Some(StuckPattern::NoProgress { steps: 5 })
+        );
+    }
+}
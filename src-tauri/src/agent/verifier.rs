@@ -0,0 +1,131 @@
+//! Independent check of a task the model says it has finished.
+
+use super::conversation::ConversationHistory;
+use crate::capture::Screenshot;
+use crate::llm::{LlmError, LlmProvider, LlmResponse, TokenMetrics};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub satisfied: bool,
+    pub rationale: String,
+}
+
+impl Verdict {
+    /// Read a verdict that starts with YES or NO, followed by the reasons
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let first_word: String = text
+            .chars()
+            .skip_while(|c| !c.is_alphanumeric())
+            .take_while(|c| c.is_alphanumeric())
+            .collect();
+        let satisfied = match first_word.to_lowercase().as_str() {
+            "yes" => true,
+            "no" => false,
+            _ => return None,
+        };
+
+        let rationale = text
+            .trim_start_matches(|c: char| !c.is_alphanumeric())
+            .get(first_word.len()..)
+            .unwrap_or_default()
+            .trim_start_matches(|c: char| !c.is_alphanumeric() && c != '(' && c != '"')
+            .trim();
+        let rationale = if rationale.is_empty() {
+            if satisfied { "The task looks done." } else { "The task does not look done yet." }.to_string()
+        } else {
+            rationale.to_string()
+        };
+        Some(Self { satisfied, rationale })
+    }
+
+    fn from_response(response: &LlmResponse) -> Option<Self> {
+        match response {
+            LlmResponse::Text(text) => Self::parse(text),
+            // Some models answer through the tools anyway
+            LlmResponse::ToolUse { tool_use, reasoning } => {
+                if let Some(verdict) = reasoning.as_deref().and_then(Self::parse) {
+                    return Some(verdict);
+                }
+                let message = tool_use.input.get("message").and_then(|m| m.as_str()).unwrap_or_default();
+                match tool_use.name.as_str() {
+                    "complete" => Some(Self { satisfied: true, rationale: message.to_string() }),
+                    "error" => Some(Self { satisfied: false, rationale: message.to_string() }),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+fn verification_request(claim: &str) -> String {
+    format!(
+        "You are checking another agent's work, not doing the task. It reports that the task is finished: \"{}\"\n\n\
+         Look at the screenshot of the screen as it is now and judge for yourself whether the task has actually \
+         been accomplished. Answer YES or NO on the first line, then explain in one or two sentences what you see \
+         that supports your answer. Do not call a tool or return an action.",
+        claim
+    )
+}
+
+/// Ask `provider` whether `instruction` is satisfied on the current screen
+pub async fn verify_completion(
+    provider: &dyn LlmProvider,
+    instruction: &str,
+    claim: &str,
+    screenshot: &Screenshot,
+) -> Result<(Verdict, TokenMetrics), LlmError> {
+    let mut conversation = ConversationHistory::new();
+    conversation.set_original_instruction(instruction.to_string());
+    conversation.add_user_message(
+        &verification_request(claim),
+        Some(screenshot.base64.clone()),
+        Some(screenshot.width),
+        Some(screenshot.height),
+    );
+
+    let (response, metrics) = provider
+        .send_with_history(&conversation, screenshot.width, screenshot.height, Box::new(|_| {}))
+        .await?;
+    let verdict = Verdict::from_response(&response)
+        .ok_or_else(|| LlmError::ParseError("Verifier reply did not start with YES or NO".to_string()))?;
+    Ok((verdict, metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::ToolUse;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_verdict() {
+        let verdict = Verdict::parse("**NO** - the Save dialog is still open.").unwrap();
+        assert!(!verdict.satisfied);
+        assert_eq!(verdict.rationale, "the Save dialog is still open.");
+
+        let verdict = Verdict::parse("Yes.\nThe file appears in the sidebar.").unwrap();
+        assert!(verdict.satisfied);
+        assert_eq!(verdict.rationale, "The file appears in the sidebar.");
+
+        assert!(Verdict::parse("Yes").unwrap().satisfied);
+        assert!(Verdict::parse("Nope, not yet").is_none());
+        assert!(Verdict::parse("I think so").is_none());
+    }
+
+    #[test]
+    fn test_verdict_from_tool_use() {
+        let response = LlmResponse::ToolUse {
+            tool_use: ToolUse {
+                id: "1".to_string(),
+                name: "error".to_string(),
+                input: json!({"message": "Nothing was saved"}),
+                signature: None,
+            },
+            reasoning: None,
+        };
+        let verdict = Verdict::from_response(&response).unwrap();
+        assert!(!verdict.satisfied);
+        assert_eq!(verdict.rationale, "Nothing was saved");
+    }
+}
@@ -0,0 +1,18 @@
+use pia_lib::headless::{self, CliCommand};
+
+#[tokio::main]
+async fn main() {
+    let code = match headless::parse_args(std::env::args().skip(1)) {
+        Ok(CliCommand::Help) => {
+            println!("{}", headless::USAGE);
+            headless::EXIT_SUCCESS
+        }
+        Ok(CliCommand::Run(options)) => headless::run(options).await,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, headless::USAGE);
+            headless::EXIT_USAGE
+        }
+    };
+
+    std::process::exit(code);
+}
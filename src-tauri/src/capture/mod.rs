@@ -1,3 +1,5 @@
 pub mod screenshot;
+pub mod source;
 
 pub use screenshot::*;
+pub use source::*;
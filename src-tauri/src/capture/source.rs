@@ -0,0 +1,19 @@
+use super::screenshot::{capture_primary_screen_with_config, CaptureError, Screenshot, ScreenshotConfig};
+
+/// Something the agent can take screenshots of.
+///
+/// Implementations are blocking; async callers should run them on
+/// `tokio::task::spawn_blocking`.
+pub trait ScreenSource: Send + Sync {
+    fn capture(&self, config: &ScreenshotConfig) -> Result<Screenshot, CaptureError>;
+}
+
+/// The real primary monitor, captured through xcap
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrimaryScreen;
+
+impl ScreenSource for PrimaryScreen {
+    fn capture(&self, config: &ScreenshotConfig) -> Result<Screenshot, CaptureError> {
+        capture_primary_screen_with_config(config)
+    }
+}
@@ -100,6 +100,7 @@ const ANTHROPIC_VARS: &[&str] = &["ANTHROPIC_API_KEY", "CLAUDE_API_KEY"];
 const OPENAI_VARS: &[&str] = &["OPENAI_API_KEY", "OPENAI_KEY"];
 const OPENROUTER_VARS: &[&str] = &["OPENROUTER_API_KEY", "OPENROUTER_KEY"];
 const GLM_VARS: &[&str] = &["GLM_API_KEY", "ZHIPUAI_API_KEY", "GLM_KEY"];
+const GEMINI_VARS: &[&str] = &["GEMINI_API_KEY", "GOOGLE_API_KEY"];
 
 /// Common placeholder patterns that indicate a key is not real.
 const PLACEHOLDER_PATTERNS: &[&str] = &[
@@ -315,6 +316,7 @@ fn scan_file_sources() -> HashMap<String, FileSourceEntry> {
         .chain(OPENAI_VARS.iter())
         .chain(OPENROUTER_VARS.iter())
         .chain(GLM_VARS.iter())
+        .chain(GEMINI_VARS.iter())
         .copied()
         .collect();
 
@@ -438,6 +440,7 @@ pub fn validate_key_format(provider: &str, key: &str) -> bool {
             // GLM/Zhipu keys use JWT-like format with a '.' separator
             trimmed.contains('.')
         }
+        "gemini" => trimmed.starts_with("AIza"),
         "ollama" => true, // Ollama doesn't use API keys
         _ => true, // Unknown providers: pass if basic checks passed
     }
@@ -452,6 +455,8 @@ pub fn detect_provider_from_key(key: &str) -> Option<&str> {
         Some("openrouter")
     } else if trimmed.starts_with("sk-proj-") {
         Some("openai")
+    } else if trimmed.starts_with("AIza") {
+        Some("gemini")
     } else if trimmed.starts_with("sk-") {
         // Generic sk- prefix (not ant or or) — most likely OpenAI
         Some("openai")
@@ -480,6 +485,9 @@ pub async fn detect_all_credentials() -> Vec<DetectedCredential> {
     if let Some(cred) = detect_glm(&file_sources) {
         results.push(cred);
     }
+    if let Some(cred) = detect_gemini(&file_sources) {
+        results.push(cred);
+    }
     if let Some(cred) = detect_ollama().await {
         results.push(cred);
     }
@@ -495,6 +503,7 @@ pub async fn detect_credential(provider: &str) -> Option<DetectedCredential> {
         "openai" => detect_openai(&file_sources),
         "openrouter" => detect_openrouter(&file_sources),
         "glm" => detect_glm(&file_sources),
+        "gemini" => detect_gemini(&file_sources),
         "ollama" => detect_ollama().await,
         _ => None,
     }
@@ -611,6 +620,21 @@ fn detect_glm(file_sources: &HashMap<String, FileSourceEntry>) -> Option<Detecte
     None
 }
 
+fn detect_gemini(file_sources: &HashMap<String, FileSourceEntry>) -> Option<DetectedCredential> {
+    if let Some((key, source)) = lookup_var(GEMINI_VARS, file_sources) {
+        return Some(DetectedCredential {
+            provider: "gemini".to_string(),
+            api_key: key,
+            source,
+            model_hint: Some("gemini-2.5-flash".to_string()),
+            host: None,
+            available_models: None,
+        });
+    }
+
+    None
+}
+
 /// Known vision-capable model name prefixes for Ollama
 const VISION_MODEL_PREFIXES: &[&str] = &[
     "llava",
@@ -1003,6 +1027,12 @@ mod tests {
         assert!(!validate_key_format("glm", "abc123defghijklmnop456"));
     }
 
+    #[test]
+    fn test_validate_gemini() {
+        assert!(validate_key_format("gemini", "AIzaSyA1b2C3d4E5f6G7h8"));
+        assert!(!validate_key_format("gemini", "sk-abcdef1234567890"));
+    }
+
     #[test]
     fn test_validate_short_key_rejected() {
         assert!(!validate_key_format("anthropic", "sk-ant-a"));
@@ -1065,6 +1095,11 @@ mod tests {
         assert_eq!(detect_provider_from_key("abc123def456789.ghijklmnop0123456"), Some("glm"));
     }
 
+    #[test]
+    fn test_detect_provider_gemini() {
+        assert_eq!(detect_provider_from_key("AIzaSyA1b2C3d4E5f6G7h8"), Some("gemini"));
+    }
+
     #[test]
     fn test_detect_provider_unknown() {
         assert_eq!(detect_provider_from_key("xyz-unknown-key"), None);
@@ -1424,6 +1459,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_detect_gemini_from_file_sources() {
+        let mut sources = HashMap::new();
+        sources.insert(
+            "GOOGLE_API_KEY".to_string(),
+            FileSourceEntry {
+                value: "AIza-from-file".to_string(),
+                source: "file:~/.zshrc".to_string(),
+            },
+        );
+
+        if env::var("GEMINI_API_KEY").is_err() && env::var("GOOGLE_API_KEY").is_err() {
+            let cred = detect_gemini(&sources).unwrap();
+            assert_eq!(cred.provider, "gemini");
+            assert_eq!(cred.api_key, "AIza-from-file");
+            assert_eq!(cred.source, "file:~/.zshrc");
+        }
+    }
+
     // --- YAML parsing tests ---
 
     #[test]
@@ -1,4 +1,7 @@
 pub mod credentials;
+pub mod pricing;
+pub mod schedule;
 pub mod settings;
+pub mod template;
 
 pub use settings::*;
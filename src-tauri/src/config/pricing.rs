@@ -0,0 +1,211 @@
+//! Token prices used to turn usage into dollars.
+//!
+//! Built-in prices cover the hosted models Pia is commonly used with. Entries
+//! in the `[[pricing]]` section of the config take precedence, so users can fix
+//! a stale price or add one for a model the table doesn't know.
+
+use serde::{Deserialize, Serialize};
+
+/// USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+    /// Prompt tokens read back from the cache; a tenth of the input price when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_per_mtok: Option<f64>,
+    /// Prompt tokens written to the cache; 1.25x the input price when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_write_per_mtok: Option<f64>,
+}
+
+impl ModelPrice {
+    pub const FREE: ModelPrice = ModelPrice::new(0.0, 0.0);
+
+    pub const fn new(input_per_mtok: f64, output_per_mtok: f64) -> Self {
+        Self {
+            input_per_mtok,
+            output_per_mtok,
+            cache_read_per_mtok: None,
+            cache_write_per_mtok: None,
+        }
+    }
+
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> f64 {
+        (input_tokens as f64 * self.input_per_mtok + output_tokens as f64 * self.output_per_mtok) / 1_000_000.0
+    }
+
+    /// Cost of prompt tokens served from or written to a prompt cache,
+    /// which providers bill apart from (and report outside of) the input tokens
+    pub fn cache_cost(&self, read_tokens: u64, write_tokens: u64) -> f64 {
+        let read = self.cache_read_per_mtok.unwrap_or(self.input_per_mtok * 0.1);
+        let write = self.cache_write_per_mtok.unwrap_or(self.input_per_mtok * 1.25);
+        (read_tokens as f64 * read + write_tokens as f64 * write) / 1_000_000.0
+    }
+}
+
+/// A user-supplied price. `model` may end in `*` to match every model with that prefix.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceEntry {
+    pub provider: String,
+    pub model: String,
+    #[serde(flatten)]
+    pub price: ModelPrice,
+}
+
+/// (provider, model prefix, input, output); the longest matching prefix wins
+const BUILTIN_PRICES: &[(&str, &str, f64, f64)] = &[
+    ("anthropic", "claude-opus-4-5", 5.0, 25.0),
+    ("anthropic", "claude-opus-4", 15.0, 75.0),
+    ("anthropic", "claude-sonnet-4", 3.0, 15.0),
+    ("anthropic", "claude-3-7-sonnet", 3.0, 15.0),
+    ("anthropic", "claude-3-5-sonnet", 3.0, 15.0),
+    ("anthropic", "claude-haiku-4-5", 1.0, 5.0),
+    ("anthropic", "claude-3-5-haiku", 0.8, 4.0),
+    ("anthropic", "claude-3-haiku", 0.25, 1.25),
+    ("openai", "gpt-4o", 2.5, 10.0),
+    ("openai", "gpt-4o-mini", 0.15, 0.6),
+    ("openai", "gpt-4.1", 2.0, 8.0),
+    ("openai", "gpt-4.1-mini", 0.4, 1.6),
+    ("openai", "gpt-4.1-nano", 0.1, 0.4),
+    ("openai", "gpt-5", 1.25, 10.0),
+    ("openai", "gpt-5-mini", 0.25, 2.0),
+    ("openai", "gpt-5-nano", 0.05, 0.4),
+    ("openai", "o4-mini", 1.1, 4.4),
+    ("gemini", "gemini-2.5-pro", 1.25, 10.0),
+    ("gemini", "gemini-2.5-flash", 0.3, 2.5),
+    ("gemini", "gemini-2.5-flash-lite", 0.1, 0.4),
+    ("gemini", "gemini-2.0-flash", 0.1, 0.4),
+    ("glm", "glm-4.5v", 0.6, 1.8),
+];
+
+/// The provider and model that actually serve `model` on `provider`.
+///
+/// OpenRouter ids like `anthropic/claude-sonnet-4` name the upstream provider
+/// (with OpenRouter's own names for some of them), and Azure deployments run
+/// OpenAI models. Anything else is returned as given.
+pub fn upstream_provider<'a>(provider: &'a str, model: &'a str) -> (&'a str, &'a str) {
+    match provider {
+        "azure-openai" => ("openai", model),
+        "openrouter" => match model.split_once('/') {
+            Some(("z-ai", upstream_model)) => ("glm", upstream_model),
+            Some(("google", upstream_model)) => ("gemini", upstream_model),
+            Some(upstream) => upstream,
+            None => (provider, model),
+        },
+        _ => (provider, model),
+    }
+}
+
+/// Price for `model` on `provider`, or None when it isn't known.
+///
+/// Local Ollama models are free. OpenRouter model ids like
+/// `anthropic/claude-sonnet-4` fall back to the upstream provider's price, and
+/// Azure deployments to OpenAI's when they are named after the model.
+pub fn price_for(overrides: &[PriceEntry], provider: &str, model: Option<&str>) -> Option<ModelPrice> {
+    let model = model.unwrap_or_default();
+
+    let configured = overrides
+        .iter()
+        .filter(|entry| entry.provider == provider)
+        .filter_map(|entry| match entry.model.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix).then_some((prefix.len(), entry.price)),
+            // Exact matches beat any prefix
+            None => (entry.model == model).then_some((usize::MAX, entry.price)),
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, price)| price);
+    if configured.is_some() {
+        return configured;
+    }
+
+    if provider == "ollama" {
+        return Some(ModelPrice::FREE);
+    }
+    let (upstream, upstream_model) = upstream_provider(provider, model);
+    if upstream != provider {
+        return price_for(overrides, upstream, Some(upstream_model));
+    }
+
+    BUILTIN_PRICES
+        .iter()
+        .filter(|(p, prefix, _, _)| *p == provider && model.starts_with(prefix))
+        .max_by_key(|(_, prefix, _, _)| prefix.len())
+        .map(|&(_, _, input_per_mtok, output_per_mtok)| ModelPrice::new(input_per_mtok, output_per_mtok))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(provider: &str, model: &str, input: f64, output: f64) -> PriceEntry {
+        PriceEntry {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            price: ModelPrice::new(input, output),
+        }
+    }
+
+    #[test]
+    fn test_builtin_prices() {
+        let sonnet = price_for(&[], "anthropic", Some("claude-sonnet-4-20250514")).unwrap();
+        assert_eq!(sonnet.input_per_mtok, 3.0);
+        assert!((sonnet.cost(1_000_000, 100_000) - 4.5).abs() < 1e-9);
+
+        // Longest prefix wins
+        assert_eq!(price_for(&[], "openai", Some("gpt-4o-mini")).unwrap().input_per_mtok, 0.15);
+        assert_eq!(price_for(&[], "openai", Some("gpt-4o-2024-08-06")).unwrap().input_per_mtok, 2.5);
+        assert_eq!(price_for(&[], "gemini", Some("gemini-2.5-flash-lite")).unwrap().input_per_mtok, 0.1);
+
+        assert_eq!(price_for(&[], "ollama", Some("llava")), Some(ModelPrice::FREE));
+        assert_eq!(
+            price_for(&[], "openrouter", Some("anthropic/claude-sonnet-4")),
+            price_for(&[], "anthropic", Some("claude-sonnet-4"))
+        );
+        assert_eq!(
+            price_for(&[], "openrouter", Some("google/gemini-2.5-flash")),
+            price_for(&[], "gemini", Some("gemini-2.5-flash"))
+        );
+        assert_eq!(price_for(&[], "azure-openai", Some("gpt-4o-mini")).unwrap().input_per_mtok, 0.15);
+        assert_eq!(price_for(&[], "openai-compatible", Some("llava")), None);
+    }
+
+    #[test]
+    fn test_upstream_provider() {
+        assert_eq!(upstream_provider("openrouter", "z-ai/glm-4.5v"), ("glm", "glm-4.5v"));
+        assert_eq!(upstream_provider("openrouter", "openai/gpt-4o"), ("openai", "gpt-4o"));
+        assert_eq!(upstream_provider("openrouter", "auto"), ("openrouter", "auto"));
+        assert_eq!(upstream_provider("azure-openai", "gpt-4o"), ("openai", "gpt-4o"));
+        assert_eq!(upstream_provider("gemini", "gemini-2.5-pro"), ("gemini", "gemini-2.5-pro"));
+    }
+
+    #[test]
+    fn test_config_overrides() {
+        let overrides = vec![
+            entry("anthropic", "claude-*", 1.0, 2.0),
+            entry("anthropic", "claude-sonnet-4-20250514", 2.0, 4.0),
+            entry("openai-compatible", "*", 0.5, 0.5),
+        ];
+
+        assert_eq!(price_for(&overrides, "anthropic", Some("claude-sonnet-4-20250514")).unwrap().input_per_mtok, 2.0);
+        assert_eq!(price_for(&overrides, "anthropic", Some("claude-opus-4")).unwrap().input_per_mtok, 1.0);
+        assert_eq!(price_for(&overrides, "openai-compatible", None).unwrap().output_per_mtok, 0.5);
+
+        let toml = "provider = \"openai\"\nmodel = \"gpt-4o\"\ninput_per_mtok = 2.0\noutput_per_mtok = 8.0\n";
+        let parsed: PriceEntry = toml::from_str(toml).unwrap();
+        assert_eq!(parsed, entry("openai", "gpt-4o", 2.0, 8.0));
+    }
+
+    #[test]
+    fn test_cache_cost() {
+        let sonnet = price_for(&[], "anthropic", Some("claude-sonnet-4")).unwrap();
+        // $0.30 per Mtok read, $3.75 per Mtok written
+        assert!((sonnet.cache_cost(1_000_000, 0) - 0.3).abs() < 1e-9);
+        assert!((sonnet.cache_cost(0, 1_000_000) - 3.75).abs() < 1e-9);
+
+        let toml = "provider = \"anthropic\"\nmodel = \"claude-*\"\ninput_per_mtok = 2.0\noutput_per_mtok = 8.0\ncache_read_per_mtok = 0.5\n";
+        let parsed: PriceEntry = toml::from_str(toml).unwrap();
+        assert_eq!(parsed.price.cache_read_per_mtok, Some(0.5));
+        assert!((parsed.price.cache_cost(2_000_000, 1_000_000) - 3.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,312 @@
+//! Recurring tasks stored in config.
+//!
+//! A schedule fires either on a five-field cron expression
+//! (`minute hour day-of-month month day-of-week`, local time) or on a fixed
+//! interval, and starts a task template or a batch of queued instructions.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// How far ahead `CronExpr::next_after` looks before giving up,
+/// so impossible dates like February 30th don't loop forever
+const MAX_LOOKAHEAD_DAYS: i64 = 366 * 5;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ScheduleError {
+    #[error("Cron expression must have 5 fields, got {0}")]
+    FieldCount(usize),
+    #[error("Invalid cron field '{field}': {reason}")]
+    InvalidField { field: String, reason: String },
+    #[error("Interval must be at least 1 minute")]
+    InvalidInterval,
+    #[error("Schedule has nothing to run")]
+    EmptyTarget,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduleSpec {
+    /// e.g. `0 9 * * 1-5` for 9:00 on weekdays
+    Cron { expression: String },
+    Interval { minutes: u32 },
+}
+
+impl ScheduleSpec {
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        match self {
+            ScheduleSpec::Cron { expression } => CronExpr::parse(expression).map(|_| ()),
+            ScheduleSpec::Interval { minutes } if *minutes == 0 => Err(ScheduleError::InvalidInterval),
+            ScheduleSpec::Interval { .. } => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduleTarget {
+    Template {
+        template_id: String,
+        #[serde(default)]
+        variables: HashMap<String, String>,
+    },
+    /// Instructions added to the queue and run in order
+    Queue { instructions: Vec<String> },
+}
+
+/// What to do when a schedule comes due while the agent is busy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BusyPolicy {
+    /// Drop this run and wait for the next one
+    #[default]
+    Skip,
+    /// Run as soon as the agent is free
+    Defer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub spec: ScheduleSpec,
+    pub target: ScheduleTarget,
+    #[serde(default)]
+    pub when_busy: BusyPolicy,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<DateTime<Utc>>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Schedule {
+    pub fn new(name: String, spec: ScheduleSpec, target: ScheduleTarget) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            enabled: true,
+            spec,
+            target,
+            when_busy: BusyPolicy::default(),
+            created_at: Utc::now(),
+            last_run: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        self.spec.validate()?;
+        match &self.target {
+            ScheduleTarget::Template { template_id, .. } if template_id.is_empty() => {
+                Err(ScheduleError::EmptyTarget)
+            }
+            ScheduleTarget::Queue { instructions }
+                if instructions.iter().all(|i| i.trim().is_empty()) =>
+            {
+                Err(ScheduleError::EmptyTarget)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parsed five-field cron expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpr {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days_of_month: Vec<u32>,
+    months: Vec<u32>,
+    /// 0 = Sunday; 7 is accepted as Sunday too
+    days_of_week: Vec<u32>,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronExpr {
+    pub fn parse(expression: &str) -> Result<Self, ScheduleError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ScheduleError::FieldCount(fields.len()));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        if days_of_week.contains(&7) {
+            days_of_week.retain(|d| *d != 7);
+            if !days_of_week.contains(&0) {
+                days_of_week.insert(0, 0);
+            }
+        }
+
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+
+    /// First matching minute strictly after `after`
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = start + Duration::days(MAX_LOOKAHEAD_DAYS);
+        let mut t = start;
+
+        while t < limit {
+            if !self.months.contains(&t.month()) {
+                // Jump to the first minute of the next month
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = t.date().with_day(1)?.with_year(year)?.with_month(month)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.day_matches(t) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.hours.contains(&t.hour()) {
+                t = t.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes.contains(&t.minute()) {
+                t += Duration::minutes(1);
+                continue;
+            }
+            return Some(t);
+        }
+        None
+    }
+
+    /// Standard cron rule: when both day fields are restricted, either may match
+    fn day_matches(&self, t: NaiveDateTime) -> bool {
+        let dom = self.days_of_month.contains(&t.day());
+        let dow = self.days_of_week.contains(&t.weekday().num_days_from_sunday());
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            (true, false) => dom,
+            (false, true) => dow,
+            (false, false) => true,
+        }
+    }
+}
+
+/// Parse one field: `*`, `5`, `1-5`, `*/15`, `10-40/10`, or a comma-separated list of those
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, ScheduleError> {
+    let invalid = |reason: String| ScheduleError::InvalidField {
+        field: field.to_string(),
+        reason,
+    };
+    let number = |s: &str| s.parse::<u32>().map_err(|_| invalid(format!("'{}' is not a number", s)));
+
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, number(step)?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid("step must be at least 1".to_string()));
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (number(a)?, number(b)?)
+        } else {
+            let value = number(range)?;
+            // `5/10` means every 10 starting at 5
+            (value, if part.contains('/') { max } else { value })
+        };
+
+        if start < min || end > max || start > end {
+            return Err(invalid(format!("values must be between {} and {}", min, max)));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d).unwrap().and_hms_opt(h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let cron = CronExpr::parse("*/15 9-17 * * 1-5").unwrap();
+        assert_eq!(cron.minutes, vec![0, 15, 30, 45]);
+        assert_eq!(cron.hours, (9..=17).collect::<Vec<_>>());
+        assert_eq!(cron.days_of_week, vec![1, 2, 3, 4, 5]);
+
+        assert_eq!(CronExpr::parse("0 9 * * 7").unwrap().days_of_week, vec![0]);
+        assert_eq!(CronExpr::parse("5,1/20 * * * *").unwrap().minutes, vec![1, 5, 21, 41]);
+
+        assert_eq!(CronExpr::parse("0 9 * *"), Err(ScheduleError::FieldCount(4)));
+        assert!(CronExpr::parse("60 * * * *").is_err());
+        assert!(CronExpr::parse("*/0 * * * *").is_err());
+        assert!(CronExpr::parse("a * * * *").is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        // Daily at 9:00
+        let daily = CronExpr::parse("0 9 * * *").unwrap();
+        assert_eq!(daily.next_after(at(2026, 3, 2, 8, 30)), Some(at(2026, 3, 2, 9, 0)));
+        assert_eq!(daily.next_after(at(2026, 3, 2, 9, 0)), Some(at(2026, 3, 3, 9, 0)));
+
+        // Weekdays only: Friday evening rolls over to Monday (2026-03-06 is a Friday)
+        let weekdays = CronExpr::parse("30 8 * * 1-5").unwrap();
+        assert_eq!(weekdays.next_after(at(2026, 3, 6, 20, 0)), Some(at(2026, 3, 9, 8, 30)));
+
+        // Year rollover
+        let new_year = CronExpr::parse("0 0 1 1 *").unwrap();
+        assert_eq!(new_year.next_after(at(2026, 6, 1, 0, 0)), Some(at(2027, 1, 1, 0, 0)));
+
+        // Never matches
+        assert_eq!(CronExpr::parse("0 0 30 2 *").unwrap().next_after(at(2026, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_schedule_validation_and_toml_roundtrip() {
+        let schedule = Schedule::new(
+            "report".to_string(),
+            ScheduleSpec::Cron { expression: "0 9 * * *".to_string() },
+            ScheduleTarget::Queue { instructions: vec!["export report".to_string()] },
+        );
+        assert!(schedule.validate().is_ok());
+
+        let toml = toml::to_string(&schedule).unwrap();
+        let parsed: Schedule = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.spec, schedule.spec);
+        assert_eq!(parsed.target, schedule.target);
+
+        let interval = Schedule::new(
+            "check".to_string(),
+            ScheduleSpec::Interval { minutes: 0 },
+            ScheduleTarget::Queue { instructions: vec!["x".to_string()] },
+        );
+        assert_eq!(interval.validate(), Err(ScheduleError::InvalidInterval));
+
+        let empty = Schedule::new(
+            "empty".to_string(),
+            ScheduleSpec::Interval { minutes: 15 },
+            ScheduleTarget::Queue { instructions: vec![" ".to_string()] },
+        );
+        assert_eq!(empty.validate(), Err(ScheduleError::EmptyTarget));
+    }
+}
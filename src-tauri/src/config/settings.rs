@@ -1,7 +1,11 @@
+use super::pricing::PriceEntry;
+use super::schedule::Schedule;
+use super::template::{self, TemplateError, TemplateVariable};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -23,6 +27,9 @@ pub struct TaskTemplate {
     pub name: String,
     pub instruction: String,
     pub created_at: DateTime<Utc>,
+    /// Variables referenced as `{{name}}` in the instruction
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<TemplateVariable>,
 }
 
 impl TaskTemplate {
@@ -32,8 +39,24 @@ impl TaskTemplate {
             name,
             instruction,
             created_at: Utc::now(),
+            variables: Vec::new(),
         }
     }
+
+    pub fn with_variables(mut self, variables: Vec<TemplateVariable>) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    /// Check that every placeholder is declared and every default is valid
+    pub fn validate(&self) -> Result<(), TemplateError> {
+        template::validate_template(&self.instruction, &self.variables)
+    }
+
+    /// The instruction with `values` filled in
+    pub fn resolve(&self, values: &HashMap<String, String>) -> Result<String, TemplateError> {
+        template::render(&self.instruction, &self.variables, values)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +65,11 @@ pub struct Config {
     pub providers: ProvidersConfig,
     #[serde(default)]
     pub templates: Vec<TaskTemplate>,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    /// Prices that replace or extend the built-in table
+    #[serde(default)]
+    pub pricing: Vec<PriceEntry>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,8 +115,55 @@ pub struct GeneralConfig {
     pub screenshot_max_width: u32,
     #[serde(default)]
     pub max_tokens_per_task: Option<u64>,
+    /// Stop a task once it has cost this many dollars
+    #[serde(default)]
+    pub max_cost_per_task: Option<f64>,
+    /// Stop starting LLM calls once today's spend reaches this many dollars
+    #[serde(default)]
+    pub max_cost_per_day: Option<f64>,
     #[serde(default)]
     pub onboarding_complete: bool,
+    /// Write every session, with screenshots, to the sessions directory
+    #[serde(default = "default_true")]
+    pub save_sessions: bool,
+    /// Disk space the session library may use before old sessions are removed (0 = unlimited)
+    #[serde(default = "default_session_storage_limit_mb")]
+    pub session_storage_limit_mb: u64,
+    /// Compare the live screen with the recording every N replayed steps (0 = never)
+    #[serde(default = "default_replay_check_interval")]
+    pub replay_check_interval: u32,
+    /// Fraction of the screen (0.0-1.0) that may differ before a replay counts as diverged
+    #[serde(default = "default_replay_divergence_threshold")]
+    pub replay_divergence_threshold: f32,
+    /// What to do when a replay diverges: "stop" or "llm" to let the model take over
+    #[serde(default = "default_replay_on_divergence")]
+    pub replay_on_divergence: String,
+    /// Have the model break the task into sub-goals before the first action
+    #[serde(default)]
+    pub enable_planner: bool,
+    /// Have a second opinion on the final screen before accepting that a task is complete
+    #[serde(default)]
+    pub verify_completion: bool,
+    /// Provider that checks completions; the task's own provider when unset
+    #[serde(default)]
+    pub verifier_provider: Option<String>,
+    /// Model for the verifier, in place of the provider's configured one
+    #[serde(default)]
+    pub verifier_model: Option<String>,
+    /// Watch for a task repeating itself without progress and step in
+    #[serde(default = "default_true")]
+    pub stuck_detection: bool,
+    /// Provider a stuck task is handed to; the task's own provider when unset
+    #[serde(default)]
+    pub stuck_fallback_provider: Option<String>,
+    /// Stronger model a stuck task is handed to. Without this or a fallback
+    /// provider, a stuck task goes straight to asking the user.
+    #[serde(default)]
+    pub stuck_fallback_model: Option<String>,
+    /// Have the model summarize steps that drop out of the conversation,
+    /// rather than keeping a plain list of actions and results
+    #[serde(default)]
+    pub summarize_with_llm: bool,
 }
 
 fn default_global_hotkey() -> Option<String> {
@@ -127,6 +202,22 @@ fn default_true() -> bool {
     true
 }
 
+fn default_session_storage_limit_mb() -> u64 {
+    500
+}
+
+fn default_replay_check_interval() -> u32 {
+    1
+}
+
+fn default_replay_divergence_threshold() -> f32 {
+    0.25
+}
+
+fn default_replay_on_divergence() -> String {
+    "stop".to_string()
+}
+
 fn default_voice_language() -> String {
     "en-US".to_string()
 }
@@ -147,6 +238,10 @@ fn default_screenshot_max_width() -> u32 {
     1920
 }
 
+fn default_azure_api_version() -> String {
+    "2024-10-21".to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ProvidersConfig {
     #[serde(default)]
@@ -160,6 +255,10 @@ pub struct ProvidersConfig {
     #[serde(default)]
     pub glm: Option<GlmConfig>,
     #[serde(default)]
+    pub gemini: Option<GeminiConfig>,
+    #[serde(default)]
+    pub azure_openai: Option<AzureOpenAIConfig>,
+    #[serde(default)]
     pub openai_compatible: Option<OpenAICompatibleConfig>,
 }
 
@@ -203,6 +302,34 @@ pub struct GlmConfig {
     pub temperature: Option<f32>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiConfig {
+    pub api_key: String,
+    pub model: String,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Replaces https://generativelanguage.googleapis.com, e.g. for a proxy
+    #[serde(default)]
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AzureOpenAIConfig {
+    /// Resource endpoint, e.g. https://my-resource.openai.azure.com
+    pub endpoint: String,
+    pub deployment: String,
+    #[serde(default = "default_azure_api_version")]
+    pub api_version: String,
+    /// Resource key; used over `bearer_token` when both are set
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Microsoft Entra ID access token, for resources with key auth turned off
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAICompatibleConfig {
     pub base_url: String,
@@ -239,7 +366,22 @@ impl Default for Config {
                 screenshot_quality: default_screenshot_quality(),
                 screenshot_max_width: default_screenshot_max_width(),
                 max_tokens_per_task: None,
+                max_cost_per_task: None,
+                max_cost_per_day: None,
                 onboarding_complete: false,
+                save_sessions: true,
+                session_storage_limit_mb: default_session_storage_limit_mb(),
+                replay_check_interval: default_replay_check_interval(),
+                replay_divergence_threshold: default_replay_divergence_threshold(),
+                replay_on_divergence: default_replay_on_divergence(),
+                enable_planner: false,
+                verify_completion: false,
+                verifier_provider: None,
+                verifier_model: None,
+                stuck_detection: true,
+                stuck_fallback_provider: None,
+                stuck_fallback_model: None,
+                summarize_with_llm: false,
             },
             providers: ProvidersConfig {
                 ollama: Some(OllamaConfig {
@@ -251,9 +393,13 @@ impl Default for Config {
                 openai: None,
                 openrouter: None,
                 glm: None,
+                gemini: None,
+                azure_openai: None,
                 openai_compatible: None,
             },
             templates: Vec::new(),
+            schedules: Vec::new(),
+            pricing: Vec::new(),
         }
     }
 }
@@ -273,7 +419,12 @@ impl Config {
             return Ok(config);
         }
 
-        let content = fs::read_to_string(&path)?;
+        Self::load_from(&path)
+    }
+
+    /// Load config from an explicit path without creating it when missing
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)?;
         let config: Config = toml::from_str(&content)?;
         Ok(config)
     }
@@ -336,7 +487,48 @@ impl Config {
                     });
                 }
             }
+            "gemini" => {
+                if let Some(ref mut config) = self.providers.gemini {
+                    config.api_key = api_key.to_string();
+                } else {
+                    self.providers.gemini = Some(GeminiConfig {
+                        api_key: api_key.to_string(),
+                        model: "gemini-2.5-flash".to_string(),
+                        temperature: None,
+                        base_url: None,
+                    });
+                }
+            }
+            // Without an endpoint and deployment there is nothing to attach a key to
+            "azure-openai" => {
+                if let Some(ref mut config) = self.providers.azure_openai {
+                    config.api_key = Some(api_key.to_string());
+                }
+            }
             _ => {}
         }
     }
+
+    /// Change the model of a configured provider. Returns false if the
+    /// provider is unknown or has no configuration yet.
+    pub fn update_provider_model(&mut self, provider: &str, model: &str) -> bool {
+        let slot = match provider {
+            "ollama" => self.providers.ollama.as_mut().map(|c| &mut c.model),
+            "anthropic" => self.providers.anthropic.as_mut().map(|c| &mut c.model),
+            "openai" => self.providers.openai.as_mut().map(|c| &mut c.model),
+            "openrouter" => self.providers.openrouter.as_mut().map(|c| &mut c.model),
+            "glm" => self.providers.glm.as_mut().map(|c| &mut c.model),
+            "gemini" => self.providers.gemini.as_mut().map(|c| &mut c.model),
+            "azure-openai" => self.providers.azure_openai.as_mut().map(|c| &mut c.deployment),
+            "openai-compatible" => self.providers.openai_compatible.as_mut().map(|c| &mut c.model),
+            _ => None,
+        };
+        match slot {
+            Some(slot) => {
+                *slot = model.to_string();
+                true
+            }
+            None => false,
+        }
+    }
 }
@@ -0,0 +1,306 @@
+//! `{{variable}}` placeholders in task templates.
+//!
+//! A template declares each variable it uses with a type, an optional default
+//! and whether a value must be supplied. Values arrive as strings from the
+//! frontend and are checked against the declared type before substitution.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("Invalid variable name: '{0}'")]
+    InvalidName(String),
+    #[error("Variable '{0}' is declared more than once")]
+    DuplicateVariable(String),
+    #[error("Placeholder '{{{{{0}}}}}' has no matching variable")]
+    UndeclaredVariable(String),
+    #[error("Unknown variable: '{0}'")]
+    UnknownVariable(String),
+    #[error("Missing value for required variable '{0}'")]
+    MissingValue(String),
+    #[error("Variable '{name}' expects a {expected}, got '{value}'")]
+    InvalidValue {
+        name: String,
+        expected: VariableType,
+        value: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableType {
+    #[default]
+    String,
+    Number,
+    Boolean,
+    /// Calendar date as YYYY-MM-DD
+    Date,
+    /// File or directory path
+    Path,
+}
+
+impl std::fmt::Display for VariableType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            VariableType::String => "string",
+            VariableType::Number => "number",
+            VariableType::Boolean => "boolean",
+            VariableType::Date => "date (YYYY-MM-DD)",
+            VariableType::Path => "path",
+        };
+        f.write_str(name)
+    }
+}
+
+impl VariableType {
+    /// Check a value and return it in the form it is substituted as
+    fn normalize(&self, value: &str) -> Option<String> {
+        let value = value.trim();
+        match self {
+            VariableType::String => Some(value.to_string()),
+            VariableType::Number => value.parse::<f64>().ok().filter(|n| n.is_finite()).map(|_| value.to_string()),
+            VariableType::Boolean => match value.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Some("true".to_string()),
+                "false" | "no" | "0" => Some("false".to_string()),
+                _ => None,
+            },
+            VariableType::Date => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|d| d.format("%Y-%m-%d").to_string()),
+            VariableType::Path => (!value.is_empty() && !value.contains('\0')).then(|| value.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateVariable {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub var_type: VariableType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Names of the placeholders in `text`, in order of first appearance
+pub fn placeholders(text: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for_each_placeholder(text, |name| {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        None
+    });
+    names
+}
+
+/// `declared` plus a required string variable for every placeholder it misses
+pub fn infer_variables(instruction: &str, declared: &[TemplateVariable]) -> Vec<TemplateVariable> {
+    let mut variables = declared.to_vec();
+    for name in placeholders(instruction) {
+        if !variables.iter().any(|v| v.name == name) {
+            variables.push(TemplateVariable {
+                name,
+                var_type: VariableType::String,
+                default: None,
+                required: true,
+                description: None,
+            });
+        }
+    }
+    variables
+}
+
+/// Check that the declarations are well formed and cover every placeholder
+pub fn validate_template(instruction: &str, variables: &[TemplateVariable]) -> Result<(), TemplateError> {
+    let mut seen = HashSet::new();
+    for variable in variables {
+        if !is_valid_name(&variable.name) {
+            return Err(TemplateError::InvalidName(variable.name.clone()));
+        }
+        if !seen.insert(variable.name.as_str()) {
+            return Err(TemplateError::DuplicateVariable(variable.name.clone()));
+        }
+        if let Some(default) = &variable.default {
+            check_value(variable, default)?;
+        }
+    }
+
+    for name in placeholders(instruction) {
+        if !seen.contains(name.as_str()) {
+            return Err(TemplateError::UndeclaredVariable(name));
+        }
+    }
+    Ok(())
+}
+
+/// Substitute `values` into `instruction`, falling back to defaults.
+/// Optional variables without a value or default become empty.
+pub fn render(
+    instruction: &str,
+    variables: &[TemplateVariable],
+    values: &HashMap<String, String>,
+) -> Result<String, TemplateError> {
+    validate_template(instruction, variables)?;
+
+    if let Some(unknown) = values.keys().find(|k| !variables.iter().any(|v| &v.name == *k)) {
+        return Err(TemplateError::UnknownVariable(unknown.clone()));
+    }
+
+    let mut resolved = HashMap::new();
+    for variable in variables {
+        let supplied = values
+            .get(&variable.name)
+            .filter(|v| !v.trim().is_empty())
+            .or(variable.default.as_ref());
+        let value = match supplied {
+            Some(value) => check_value(variable, value)?,
+            None if variable.required => return Err(TemplateError::MissingValue(variable.name.clone())),
+            None => String::new(),
+        };
+        resolved.insert(variable.name.as_str(), value);
+    }
+
+    Ok(for_each_placeholder(instruction, |name| resolved.get(name).cloned()))
+}
+
+fn check_value(variable: &TemplateVariable, value: &str) -> Result<String, TemplateError> {
+    variable
+        .var_type
+        .normalize(value)
+        .ok_or_else(|| TemplateError::InvalidValue {
+            name: variable.name.clone(),
+            expected: variable.var_type,
+            value: value.to_string(),
+        })
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Walk `{{ name }}` placeholders, replacing each with what `replace` returns.
+/// Placeholders it returns None for, and malformed ones, are kept as written.
+fn for_each_placeholder(text: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            output.push_str(&rest[start..]);
+            return output;
+        };
+
+        let name = after[..end].trim();
+        match is_valid_name(name).then(|| replace(name)).flatten() {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, var_type: VariableType, default: Option<&str>, required: bool) -> TemplateVariable {
+        TemplateVariable {
+            name: name.to_string(),
+            var_type,
+            default: default.map(str::to_string),
+            required,
+            description: None,
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let names = placeholders("Email {{customer}} about {{ order_id }} and {{customer}}, not {{bad name}} {{");
+        assert_eq!(names, vec!["customer", "order_id"]);
+    }
+
+    #[test]
+    fn test_render_with_defaults_and_types() {
+        let variables = vec![
+            var("customer", VariableType::String, None, true),
+            var("due", VariableType::Date, None, true),
+            var("copies", VariableType::Number, Some("1"), false),
+            var("urgent", VariableType::Boolean, Some("no"), false),
+            var("note", VariableType::String, None, false),
+        ];
+        let instruction = "Invoice {{customer}} due {{ due }} x{{copies}} urgent={{urgent}}{{note}}";
+
+        let resolved = render(
+            instruction,
+            &variables,
+            &values(&[("customer", "Acme"), ("due", "2026-03-01"), ("urgent", "Yes")]),
+        )
+        .unwrap();
+        assert_eq!(resolved, "Invoice Acme due 2026-03-01 x1 urgent=true");
+    }
+
+    #[test]
+    fn test_render_rejects_bad_input() {
+        let variables = vec![
+            var("customer", VariableType::String, None, true),
+            var("due", VariableType::Date, Some("2026-01-01"), false),
+        ];
+        let instruction = "Invoice {{customer}} due {{due}}";
+
+        assert_eq!(
+            render(instruction, &variables, &values(&[])),
+            Err(TemplateError::MissingValue("customer".to_string()))
+        );
+        assert!(matches!(
+            render(instruction, &variables, &values(&[("customer", "Acme"), ("due", "March 1st")])),
+            Err(TemplateError::InvalidValue { .. })
+        ));
+        assert_eq!(
+            render(instruction, &variables, &values(&[("customer", "Acme"), ("extra", "x")])),
+            Err(TemplateError::UnknownVariable("extra".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_validate_template() {
+        let customer = var("customer", VariableType::String, None, true);
+        assert!(validate_template("Hi {{customer}}", std::slice::from_ref(&customer)).is_ok());
+        assert_eq!(
+            validate_template("Hi {{customer}} {{date}}", std::slice::from_ref(&customer)),
+            Err(TemplateError::UndeclaredVariable("date".to_string()))
+        );
+        assert_eq!(
+            validate_template("Hi", &[customer.clone(), customer.clone()]),
+            Err(TemplateError::DuplicateVariable("customer".to_string()))
+        );
+        assert!(matches!(
+            validate_template("Hi", &[var("n", VariableType::Number, Some("many"), false)]),
+            Err(TemplateError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_infer_variables_keeps_declarations() {
+        let due = var("due", VariableType::Date, None, false);
+        let variables = infer_variables("Pay {{customer}} by {{due}}", std::slice::from_ref(&due));
+        assert_eq!(variables.len(), 2);
+        assert_eq!(variables[0], due);
+        assert_eq!(variables[1], var("customer", VariableType::String, None, true));
+    }
+}
@@ -0,0 +1,373 @@
+//! Headless runner used by the `pia-cli` binary.
+//!
+//! Runs a single instruction through the agent loop without creating any
+//! windows. Progress is streamed to stdout as JSON lines and the outcome is
+//! reported through the process exit code.
+
+use crate::agent::session_store::SessionStore;
+use crate::agent::{ActionHistory, AgentLoop, AgentStateManager, LoopError};
+use crate::config::Config;
+use serde_json::json;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_STOPPED: i32 = 3;
+pub const EXIT_MAX_ITERATIONS: i32 = 4;
+pub const EXIT_PROVIDER_ERROR: i32 = 5;
+pub const EXIT_ACTION_DENIED: i32 = 6;
+pub const EXIT_BUDGET_EXCEEDED: i32 = 7;
+pub const EXIT_STUCK: i32 = 8;
+
+pub const USAGE: &str = "\
+Usage: pia-cli [OPTIONS] [INSTRUCTION]...
+
+Runs a single instruction without the desktop window.
+If no instruction is given, it is read from stdin.
+
+Options:
+  -f, --file <PATH>          Read the instruction from a file (\"-\" for stdin)
+  -p, --provider <NAME>      Override the default provider
+  -n, --max-iterations <N>   Override the iteration limit
+  -c, --config <PATH>        Load config from PATH instead of the default location
+  -y, --yes                  Do not ask for confirmation of dangerous actions
+  -h, --help                 Print this help
+
+Exit codes:
+  0  completed
+  1  other error
+  2  invalid usage
+  3  stopped
+  4  max iterations reached
+  5  provider error
+  6  action denied
+  7  token or cost budget exceeded
+  8  stuck repeating actions without progress";
+
+/// Where the instruction comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstructionSource {
+    Inline(String),
+    File(PathBuf),
+    Stdin,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadlessOptions {
+    pub source: InstructionSource,
+    pub provider: Option<String>,
+    pub max_iterations: Option<u32>,
+    pub config_path: Option<PathBuf>,
+    pub assume_yes: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliCommand {
+    Run(HeadlessOptions),
+    Help,
+}
+
+/// Parse command line arguments (without the program name)
+pub fn parse_args<I>(args: I) -> Result<CliCommand, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut words: Vec<String> = Vec::new();
+    let mut file: Option<PathBuf> = None;
+    let mut provider = None;
+    let mut max_iterations = None;
+    let mut config_path = None;
+    let mut assume_yes = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(CliCommand::Help),
+            "-y" | "--yes" => assume_yes = true,
+            "-f" | "--file" => {
+                let value = args.next().ok_or("--file requires a path")?;
+                file = Some(PathBuf::from(value));
+            }
+            "-p" | "--provider" => {
+                provider = Some(args.next().ok_or("--provider requires a name")?);
+            }
+            "-n" | "--max-iterations" => {
+                let value = args.next().ok_or("--max-iterations requires a number")?;
+                let parsed = value
+                    .parse::<u32>()
+                    .map_err(|_| format!("Invalid --max-iterations value: {}", value))?;
+                if parsed == 0 {
+                    return Err("--max-iterations must be greater than 0".to_string());
+                }
+                max_iterations = Some(parsed);
+            }
+            "-c" | "--config" => {
+                let value = args.next().ok_or("--config requires a path")?;
+                config_path = Some(PathBuf::from(value));
+            }
+            "--" => {
+                words.extend(args.by_ref());
+            }
+            other if other.starts_with('-') && other != "-" => {
+                return Err(format!("Unknown option: {}", other));
+            }
+            _ => words.push(arg),
+        }
+    }
+
+    let source = match (file, words.is_empty()) {
+        (Some(_), false) => {
+            return Err("Pass the instruction either inline or with --file, not both".to_string())
+        }
+        (Some(path), true) if path.as_os_str() == "-" => InstructionSource::Stdin,
+        (Some(path), true) => InstructionSource::File(path),
+        (None, true) => InstructionSource::Stdin,
+        (None, false) if words.len() == 1 && words[0] == "-" => InstructionSource::Stdin,
+        (None, false) => InstructionSource::Inline(words.join(" ")),
+    };
+
+    Ok(CliCommand::Run(HeadlessOptions {
+        source,
+        provider,
+        max_iterations,
+        config_path,
+        assume_yes,
+    }))
+}
+
+/// Resolve the instruction text from its source
+pub fn read_instruction(source: &InstructionSource) -> Result<String, String> {
+    let text = match source {
+        InstructionSource::Inline(text) => text.clone(),
+        InstructionSource::File(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+        InstructionSource::Stdin => {
+            let mut stdin = std::io::stdin();
+            if stdin.is_terminal() {
+                return Err("No instruction given".to_string());
+            }
+            let mut buf = String::new();
+            stdin
+                .read_to_string(&mut buf)
+                .map_err(|e| format!("Failed to read stdin: {}", e))?;
+            buf
+        }
+    };
+
+    let text = text.trim().to_string();
+    if text.is_empty() {
+        return Err("Instruction is empty".to_string());
+    }
+    Ok(text)
+}
+
+/// Map the outcome of a run to a process exit code
+pub fn exit_code(result: &Result<(), LoopError>) -> i32 {
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(LoopError::Stopped) => EXIT_STOPPED,
+        Err(LoopError::MaxIterations) => EXIT_MAX_ITERATIONS,
+        Err(LoopError::LlmError(_)) | Err(LoopError::NoProvider) => EXIT_PROVIDER_ERROR,
+        Err(LoopError::ActionDenied) => EXIT_ACTION_DENIED,
+        Err(LoopError::BudgetExceeded(_)) => EXIT_BUDGET_EXCEEDED,
+        Err(LoopError::Stuck(_)) => EXIT_STUCK,
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+fn status_label(result: &Result<(), LoopError>) -> &'static str {
+    match result {
+        Ok(()) => "completed",
+        Err(LoopError::Stopped) => "stopped",
+        Err(LoopError::MaxIterations) => "max_iterations",
+        Err(LoopError::ActionDenied) => "denied",
+        Err(LoopError::BudgetExceeded(_)) => "budget_exceeded",
+        Err(LoopError::Stuck(_)) => "stuck",
+        Err(_) => "error",
+    }
+}
+
+fn print_line(event: &str, payload: serde_json::Value) {
+    println!("{}", json!({ "event": event, "payload": payload }));
+}
+
+/// Load config, run the instruction to completion and return the exit code
+pub async fn run(options: HeadlessOptions) -> i32 {
+    let instruction = match read_instruction(&options.source) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let loaded = match &options.config_path {
+        Some(path) => Config::load_from(path),
+        None => Config::load(),
+    };
+    let mut config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            print_line("error", json!({ "message": e.to_string() }));
+            return EXIT_ERROR;
+        }
+    };
+
+    if let Some(provider) = options.provider {
+        config.general.default_provider = provider;
+    }
+    if let Some(max_iterations) = options.max_iterations {
+        config.general.max_iterations = max_iterations;
+    }
+    if options.assume_yes {
+        config.general.confirm_dangerous_actions = false;
+    }
+
+    let state = AgentStateManager::new();
+    if config.general.save_sessions {
+        let config_path = match &options.config_path {
+            Some(path) => Some(path.clone()),
+            None => Config::config_path().ok(),
+        };
+        if let Some(path) = config_path {
+            state.history().set_store(Some(
+                SessionStore::beside_config(&path)
+                    .with_storage_limit_mb(config.general.session_storage_limit_mb),
+            ));
+        }
+    }
+    let action_history = Arc::new(RwLock::new(ActionHistory::default()));
+
+    // Ctrl+C requests a graceful stop so the session is closed out properly
+    let stop_handle = state.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            stop_handle.request_stop();
+        }
+    });
+
+    let agent = AgentLoop::new_headless(state, config, action_history);
+    let result = agent.run(instruction).await;
+    let code = exit_code(&result);
+
+    print_line(
+        "finished",
+        json!({
+            "status": status_label(&result),
+            "error": result.as_ref().err().map(|e| e.to_string()),
+            "exit_code": code,
+        }),
+    );
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::budget::BudgetExceeded;
+    use crate::agent::stuck::StuckPattern;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn run_options(list: &[&str]) -> HeadlessOptions {
+        match parse_args(args(list)).unwrap() {
+            CliCommand::Run(options) => options,
+            CliCommand::Help => panic!("expected run command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_inline_instruction() {
+        let options = run_options(&["open", "the", "calculator"]);
+        assert_eq!(
+            options.source,
+            InstructionSource::Inline("open the calculator".to_string())
+        );
+        assert!(!options.assume_yes);
+    }
+
+    #[test]
+    fn test_parse_file_and_overrides() {
+        let options = run_options(&["-f", "task.txt", "-p", "ollama", "-n", "10", "--yes"]);
+        assert_eq!(options.source, InstructionSource::File(PathBuf::from("task.txt")));
+        assert_eq!(options.provider.as_deref(), Some("ollama"));
+        assert_eq!(options.max_iterations, Some(10));
+        assert!(options.assume_yes);
+    }
+
+    #[test]
+    fn test_parse_stdin_sources() {
+        assert_eq!(run_options(&[]).source, InstructionSource::Stdin);
+        assert_eq!(run_options(&["-"]).source, InstructionSource::Stdin);
+        assert_eq!(run_options(&["--file", "-"]).source, InstructionSource::Stdin);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_args(args(&["--bogus"])).is_err());
+        assert!(parse_args(args(&["-n", "abc"])).is_err());
+        assert!(parse_args(args(&["-n", "0"])).is_err());
+        assert!(parse_args(args(&["--file"])).is_err());
+        assert!(parse_args(args(&["-f", "a.txt", "do", "things"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_help() {
+        assert_eq!(parse_args(args(&["-h"])).unwrap(), CliCommand::Help);
+        assert_eq!(parse_args(args(&["do", "--help"])).unwrap(), CliCommand::Help);
+    }
+
+    #[test]
+    fn test_double_dash_keeps_dashes() {
+        let options = run_options(&["--", "-y", "is", "literal"]);
+        assert_eq!(
+            options.source,
+            InstructionSource::Inline("-y is literal".to_string())
+        );
+    }
+
+    #[test]
+    fn test_read_instruction_trims_and_rejects_empty() {
+        let inline = InstructionSource::Inline("  hello \n".to_string());
+        assert_eq!(read_instruction(&inline).unwrap(), "hello");
+        let empty = InstructionSource::Inline("   ".to_string());
+        assert!(read_instruction(&empty).is_err());
+    }
+
+    #[test]
+    fn test_read_instruction_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("task.txt");
+        std::fs::write(&path, "open settings\n").unwrap();
+        let source = InstructionSource::File(path);
+        assert_eq!(read_instruction(&source).unwrap(), "open settings");
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(exit_code(&Ok(())), EXIT_SUCCESS);
+        assert_eq!(exit_code(&Err(LoopError::Stopped)), EXIT_STOPPED);
+        assert_eq!(exit_code(&Err(LoopError::MaxIterations)), EXIT_MAX_ITERATIONS);
+        assert_eq!(exit_code(&Err(LoopError::NoProvider)), EXIT_PROVIDER_ERROR);
+        assert_eq!(
+            exit_code(&Err(LoopError::LlmError(crate::llm::LlmError::NotConfigured))),
+            EXIT_PROVIDER_ERROR
+        );
+        assert_eq!(exit_code(&Err(LoopError::ActionDenied)), EXIT_ACTION_DENIED);
+        assert_eq!(exit_code(&Err(LoopError::TooManyErrors(3))), EXIT_ERROR);
+
+        let over_budget = Err(LoopError::BudgetExceeded(BudgetExceeded::Tokens { used: 120, limit: 100 }));
+        assert_eq!(exit_code(&over_budget), EXIT_BUDGET_EXCEEDED);
+        assert_eq!(status_label(&over_budget), "budget_exceeded");
+        let stuck = Err(LoopError::Stuck(StuckPattern::NoProgress { steps: 5 }));
+        assert_eq!(exit_code(&stuck), EXIT_STUCK);
+        assert_eq!(status_label(&stuck), "stuck");
+    }
+}
@@ -0,0 +1,90 @@
+use super::keyboard::{parse_key, KeyboardController, KeyboardError, Modifier};
+use super::mouse::{MouseButton, MouseController, MouseError, ScrollDirection};
+use std::time::Duration;
+
+/// Low-level mouse and keyboard operations used to execute agent actions.
+///
+/// Methods are blocking and take `&self` so a single driver can be shared
+/// behind an `Arc`; async callers should use `tokio::task::spawn_blocking`.
+pub trait InputDriver: Send + Sync {
+    fn move_to(&self, x: i32, y: i32) -> Result<(), MouseError>;
+    fn click(&self, button: MouseButton) -> Result<(), MouseError>;
+
+    /// Move to a point, pause briefly so the target can react to hover, then click
+    fn click_at(&self, x: i32, y: i32, button: MouseButton, delay: Duration) -> Result<(), MouseError> {
+        self.move_to(x, y)?;
+        std::thread::sleep(delay);
+        self.click(button)
+    }
+
+    fn double_click(&self, button: MouseButton) -> Result<(), MouseError>;
+    fn triple_click(&self, button: MouseButton) -> Result<(), MouseError>;
+    fn scroll(&self, direction: ScrollDirection, amount: i32) -> Result<(), MouseError>;
+    fn drag(
+        &self,
+        start_x: i32,
+        start_y: i32,
+        end_x: i32,
+        end_y: i32,
+        button: MouseButton,
+        duration_ms: u32,
+    ) -> Result<(), MouseError>;
+    fn type_text(&self, text: &str) -> Result<(), KeyboardError>;
+    /// Press a key, holding the given modifiers while it is pressed
+    fn key(&self, key: &str, modifiers: &[Modifier]) -> Result<(), KeyboardError>;
+}
+
+/// Drives the real mouse and keyboard through enigo
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnigoDriver;
+
+impl InputDriver for EnigoDriver {
+    fn move_to(&self, x: i32, y: i32) -> Result<(), MouseError> {
+        MouseController::new()?.move_to(x, y)
+    }
+
+    fn click(&self, button: MouseButton) -> Result<(), MouseError> {
+        MouseController::new()?.click(button)
+    }
+
+    fn click_at(&self, x: i32, y: i32, button: MouseButton, delay: Duration) -> Result<(), MouseError> {
+        MouseController::new()?.click_at_with_delay(x, y, button, delay)
+    }
+
+    fn double_click(&self, button: MouseButton) -> Result<(), MouseError> {
+        MouseController::new()?.double_click(button)
+    }
+
+    fn triple_click(&self, button: MouseButton) -> Result<(), MouseError> {
+        MouseController::new()?.triple_click(button)
+    }
+
+    fn scroll(&self, direction: ScrollDirection, amount: i32) -> Result<(), MouseError> {
+        MouseController::new()?.scroll(direction, amount)
+    }
+
+    fn drag(
+        &self,
+        start_x: i32,
+        start_y: i32,
+        end_x: i32,
+        end_y: i32,
+        button: MouseButton,
+        duration_ms: u32,
+    ) -> Result<(), MouseError> {
+        MouseController::new()?.drag(start_x, start_y, end_x, end_y, button, duration_ms)
+    }
+
+    fn type_text(&self, text: &str) -> Result<(), KeyboardError> {
+        KeyboardController::new()?.type_text(text)
+    }
+
+    fn key(&self, key: &str, modifiers: &[Modifier]) -> Result<(), KeyboardError> {
+        let mut keyboard = KeyboardController::new()?;
+        if modifiers.is_empty() {
+            keyboard.key_press(parse_key(key)?)
+        } else {
+            keyboard.key_with_modifiers(key, modifiers)
+        }
+    }
+}
@@ -1,5 +1,7 @@
+pub mod driver;
 pub mod keyboard;
 pub mod mouse;
 
+pub use driver::*;
 pub use keyboard::*;
 pub use mouse::*;
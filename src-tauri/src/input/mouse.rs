@@ -18,7 +18,7 @@ pub struct MouseController {
     enigo: Option<Enigo>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MouseButton {
     Left,
     Right,
@@ -35,7 +35,7 @@ impl From<MouseButton> for Button {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ScrollDirection {
     Up,
     Down,
@@ -111,10 +111,6 @@ impl MouseController {
         }
     }
 
-    pub fn click_at(&mut self, x: i32, y: i32, button: MouseButton) -> Result<(), MouseError> {
-        self.click_at_with_delay(x, y, button, std::time::Duration::from_millis(50))
-    }
-
     pub fn click_at_with_delay(
         &mut self,
         x: i32,
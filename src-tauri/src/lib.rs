@@ -1,21 +1,32 @@
 mod agent;
 mod capture;
 mod config;
+pub mod headless;
 mod history;
 mod input;
 mod llm;
 mod permissions;
 
-use agent::{validate_speed_multiplier, ActionHistory, AgentLoop, AgentStateManager, AgentStatus, ConfirmationResponse, InstructionQueue, QueueFailureMode, QueueManager, RecordedAction};
+use agent::{validate_speed_multiplier, ActionHistory, AgentLoop, AgentStateManager, ConfirmationResponse, InstructionQueue, QueueFailureMode, QueueItemRules, QueueManager, QueueStore, RecordedAction, RunOverrides};
 use agent::action::execute_action;
+use agent::checkpoint::{CheckpointStore, InterruptedTask};
+use agent::budget::{DailySpend, SpendDay};
+use agent::plan::TaskPlan;
+use agent::scheduler::{resolve_template, RunLog, ScheduleRun, ScheduledTaskRunner, Scheduler};
+use agent::session_store::{SessionFilter, SessionStore, SessionSummary};
+use agent::{LoopError, SessionHistory, TemplateRun};
+use config::template::{self, TemplateVariable};
+use config::schedule::{BusyPolicy, Schedule, ScheduleSpec, ScheduleTarget};
 use config::{Config, TaskTemplate};
 use config::credentials::{self, DetectedCredentialPayload};
 use history::{HistoryEntry, InstructionHistory};
 use llm::{
-    AnthropicProvider, GlmProvider, LlmProvider, OllamaProvider, OpenAICompatibleProvider,
+    AnthropicProvider, AzureAuth, AzureOpenAIProvider, GeminiProvider, GlmProvider, LlmProvider, OllamaProvider, OpenAICompatibleProvider,
     OpenAIProvider, OpenRouterProvider,
 };
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tauri::{
     AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize, WebviewWindow, State,
@@ -31,6 +42,50 @@ struct AppState {
     history: Arc<RwLock<InstructionHistory>>,
     queue: QueueManager,
     action_history: Arc<RwLock<ActionHistory>>,
+    scheduler: Arc<Scheduler>,
+}
+
+/// Starts scheduled work through the same agent loop the UI uses
+struct AppTaskRunner {
+    app: AppHandle,
+    agent_state: AgentStateManager,
+    config: Arc<RwLock<Config>>,
+    action_history: Arc<RwLock<ActionHistory>>,
+}
+
+impl AppTaskRunner {
+    /// Scheduled runs aren't checkpointed: the one checkpoint file belongs to
+    /// what the user started, and a scheduled run would overwrite it
+    async fn agent_loop(&self) -> AgentLoop {
+        let config = self.config.read().await.clone();
+        AgentLoop::new(
+            self.agent_state.clone(),
+            config,
+            self.app.clone(),
+            self.action_history.clone(),
+        )
+    }
+}
+
+#[async_trait]
+impl ScheduledTaskRunner for AppTaskRunner {
+    async fn run_instruction(&self, instruction: String, template: Option<TemplateRun>) -> Result<(), LoopError> {
+        let mut loop_runner = self.agent_loop().await;
+        if let Some(run) = template {
+            loop_runner = loop_runner.with_template(run);
+        }
+        loop_runner.run(instruction).await
+    }
+
+    async fn run_queue(&self, instructions: Vec<String>) -> Result<(), LoopError> {
+        let queue = QueueManager::new();
+        queue.add_multiple(instructions).await;
+        self.agent_loop().await.with_queue(queue).run_queue().await
+    }
+
+    fn has_interrupted_task(&self) -> bool {
+        checkpoint_store().is_some_and(|checkpoints| matches!(checkpoints.load(), Ok(Some(_))))
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -45,6 +100,7 @@ struct AgentStatePayload {
     tokens_per_second: f64,
     total_input_tokens: u64,
     total_output_tokens: u64,
+    total_cost_usd: f64,
     queue_index: usize,
     queue_total: usize,
     queue_active: bool,
@@ -54,11 +110,16 @@ struct AgentStatePayload {
     recorded_actions_count: usize,
     can_undo: bool,
     last_undoable_action: Option<String>,
+    plan: Option<TaskPlan>,
 }
 
+/// Start the agent. When `template_id` is given, the template's instruction is
+/// filled in with `variables` and used in place of `instruction`.
 #[tauri::command]
 async fn start_agent(
     instruction: String,
+    template_id: Option<String>,
+    variables: Option<HashMap<String, String>>,
     app_handle: AppHandle,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
@@ -66,17 +127,31 @@ async fn start_agent(
     let config = state.config.read().await.clone();
     let action_history = state.action_history.clone();
 
-    let current_state = agent_state.get_state().await;
-    if current_state.status == AgentStatus::Running {
+    let (instruction, template_run) = match template_id {
+        Some(id) => {
+            let (resolved, run) = resolve_template(&config, &id, variables.unwrap_or_default())?;
+            (resolved, Some(run))
+        }
+        None => (instruction, None),
+    };
+
+    if !agent_state.try_claim().await {
         return Err("Agent is already running".to_string());
     }
 
     let app = app_handle.clone();
     tokio::spawn(async move {
-        let loop_runner = AgentLoop::new(agent_state, config, app, action_history);
+        let mut loop_runner = AgentLoop::new(agent_state.clone(), config, app, action_history);
+        if let Some(checkpoints) = checkpoint_store() {
+            loop_runner = loop_runner.with_checkpoints(checkpoints);
+        }
+        if let Some(run) = template_run {
+            loop_runner = loop_runner.with_template(run);
+        }
         if let Err(e) = loop_runner.run(instruction).await {
             log::error!("Agent loop error: {}", e);
         }
+        agent_state.release_claim().await;
     });
 
     Ok(())
@@ -110,25 +185,82 @@ async fn start_agent_recording(
     let config = state.config.read().await.clone();
     let action_history = state.action_history.clone();
 
-    let current_state = agent_state.get_state().await;
-    if current_state.status == AgentStatus::Running || current_state.status == AgentStatus::Recording {
+    if !agent_state.try_claim().await {
         return Err("Agent is already running".to_string());
     }
 
     let app = app_handle.clone();
     tokio::spawn(async move {
-        let loop_runner = AgentLoop::new(agent_state, config, app, action_history);
+        let mut loop_runner = AgentLoop::new(agent_state.clone(), config, app, action_history);
+        if let Some(checkpoints) = checkpoint_store() {
+            loop_runner = loop_runner.with_checkpoints(checkpoints);
+        }
         if let Err(e) = loop_runner.run_recording(instruction).await {
             log::error!("Agent recording loop error: {}", e);
         }
+        agent_state.release_claim().await;
     });
 
     Ok(())
 }
 
+/// Where the running task is checkpointed, beside the config file
+fn checkpoint_store() -> Option<CheckpointStore> {
+    Config::config_path()
+        .ok()
+        .map(|path| CheckpointStore::beside_config(&path))
+}
+
+#[tauri::command]
+async fn get_interrupted_task() -> Result<Option<InterruptedTask>, String> {
+    let Some(checkpoints) = checkpoint_store() else {
+        return Ok(None);
+    };
+    let checkpoint = checkpoints.load().map_err(|e| e.to_string())?;
+    Ok(checkpoint.as_ref().map(InterruptedTask::from))
+}
+
+#[tauri::command]
+async fn resume_task(app_handle: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let agent_state = state.agent_state.clone();
+    let config = state.config.read().await.clone();
+    let action_history = state.action_history.clone();
+
+    let checkpoints = checkpoint_store().ok_or("Could not locate checkpoint file")?;
+    let checkpoint = checkpoints
+        .load()
+        .map_err(|e| e.to_string())?
+        .ok_or("No interrupted task to resume")?;
+
+    if !agent_state.try_claim().await {
+        return Err("Agent is already running".to_string());
+    }
+
+    let app = app_handle.clone();
+    tokio::spawn(async move {
+        let loop_runner =
+            AgentLoop::new(agent_state.clone(), config, app, action_history).with_checkpoints(checkpoints);
+        if let Err(e) = loop_runner.resume(checkpoint).await {
+            log::error!("Agent loop error: {}", e);
+        }
+        agent_state.release_claim().await;
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn discard_interrupted_task() -> Result<(), String> {
+    match checkpoint_store() {
+        Some(checkpoints) => checkpoints.clear().map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
 #[tauri::command]
 async fn get_recorded_actions(state: State<'_, AppState>) -> Result<Vec<RecordedAction>, String> {
-    Ok(state.agent_state.get_recorded_actions().await)
+    let actions = state.agent_state.get_recorded_actions().await;
+    Ok(actions.iter().map(RecordedAction::without_screenshot).collect())
 }
 
 #[tauri::command]
@@ -137,6 +269,35 @@ async fn clear_recorded_actions(state: State<'_, AppState>) -> Result<(), String
     Ok(())
 }
 
+#[tauri::command]
+async fn replay_recording(app_handle: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    let agent_state = state.agent_state.clone();
+    let config = state.config.read().await.clone();
+    let action_history = state.action_history.clone();
+
+    let steps = agent_state.get_recorded_actions().await;
+    if steps.is_empty() {
+        return Err("No recorded actions to replay".to_string());
+    }
+    let instruction = agent_state.get_state().await.instruction.unwrap_or_default();
+
+    if !agent_state.try_claim().await {
+        return Err("Agent is already running".to_string());
+    }
+
+    let app = app_handle.clone();
+    tokio::spawn(async move {
+        let loop_runner = AgentLoop::new(agent_state.clone(), config, app, action_history);
+        match loop_runner.replay(instruction, steps).await {
+            Ok(outcome) => log::info!("Replay finished: {:?}", outcome),
+            Err(e) => log::error!("Replay error: {}", e),
+        }
+        agent_state.release_claim().await;
+    });
+
+    Ok(())
+}
+
 #[tauri::command]
 async fn get_agent_state(state: State<'_, AppState>) -> Result<AgentStatePayload, String> {
     let s = state.agent_state.get_state().await;
@@ -151,6 +312,7 @@ async fn get_agent_state(state: State<'_, AppState>) -> Result<AgentStatePayload
         tokens_per_second: s.tokens_per_second,
         total_input_tokens: s.total_input_tokens,
         total_output_tokens: s.total_output_tokens,
+        total_cost_usd: s.total_cost_usd,
         queue_index: s.queue_index,
         queue_total: s.queue_total,
         queue_active: s.queue_active,
@@ -160,6 +322,7 @@ async fn get_agent_state(state: State<'_, AppState>) -> Result<AgentStatePayload
         recorded_actions_count: s.recorded_actions.len(),
         can_undo: s.can_undo,
         last_undoable_action: s.last_undoable_action,
+        plan: s.plan,
     })
 }
 
@@ -196,7 +359,16 @@ async fn save_config(mut config: Config, app_handle: AppHandle, state: State<'_,
         }
     }
 
+    // Schedules are edited through their own commands and carry run state
+    config.schedules = state.config.read().await.schedules.clone();
+
+    // Price overrides are only set in the config file; keep them when the settings UI leaves them out
+    if config.pricing.is_empty() {
+        config.pricing = state.config.read().await.pricing.clone();
+    }
+
     config.save().map_err(|e| e.to_string())?;
+    state.agent_state.history().set_store(session_store_for(&config));
     *state.config.write().await = config;
 
     // Update overlay window visibility based on setting
@@ -249,8 +421,7 @@ async fn undo_last_action(
     state: State<'_, AppState>,
 ) -> Result<String, String> {
     // Check if agent is running - can't undo during execution
-    let current_state = state.agent_state.get_state().await;
-    if current_state.status == AgentStatus::Running {
+    if state.agent_state.is_busy().await {
         return Err("Cannot undo while agent is running".to_string());
     }
 
@@ -334,15 +505,178 @@ async fn clear_session_history(state: State<'_, AppState>) -> Result<(), String>
     Ok(())
 }
 
+/// The on-disk session library, sized according to the config
+fn session_library(config: &Config) -> Result<SessionStore, String> {
+    let config_path = Config::config_path().map_err(|e| e.to_string())?;
+    Ok(SessionStore::beside_config(&config_path)
+        .with_storage_limit_mb(config.general.session_storage_limit_mb))
+}
+
+/// The store new sessions are written to, or None when saving is turned off
+fn session_store_for(config: &Config) -> Option<SessionStore> {
+    if !config.general.save_sessions {
+        return None;
+    }
+    session_library(config).ok()
+}
+
+#[tauri::command]
+async fn list_sessions(
+    filter: Option<SessionFilter>,
+    state: State<'_, AppState>,
+) -> Result<Vec<SessionSummary>, String> {
+    let library = session_library(&*state.config.read().await)?;
+    library
+        .list(&filter.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_saved_session(
+    session_id: String,
+    include_screenshots: bool,
+    state: State<'_, AppState>,
+) -> Result<SessionHistory, String> {
+    let library = session_library(&*state.config.read().await)?;
+    library
+        .load(&session_id, include_screenshots)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_saved_session(
+    session_id: String,
+    format: String,
+    include_screenshots: bool,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let library = session_library(&*state.config.read().await)?;
+    let session = library
+        .load(&session_id, include_screenshots && format == "json")
+        .map_err(|e| e.to_string())?;
+    match format.as_str() {
+        "json" => Ok(session.to_json(include_screenshots).to_string()),
+        "text" => Ok(session.to_text()),
+        other => Err(format!("Unknown export format: {}", other)),
+    }
+}
+
+#[tauri::command]
+async fn delete_session(session_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let current = state.agent_state.history().get_session().await;
+    if let Some(current) = current {
+        if current.session_id == session_id && current.ended_at.is_none() {
+            return Err("Cannot delete a session that is still running".to_string());
+        }
+    }
+    let library = session_library(&*state.config.read().await)?;
+    library.delete(&session_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_session_storage_usage(state: State<'_, AppState>) -> Result<u64, String> {
+    let library = session_library(&*state.config.read().await)?;
+    Ok(library.disk_usage())
+}
+
+#[tauri::command]
+async fn get_schedules(state: State<'_, AppState>) -> Result<Vec<Schedule>, String> {
+    Ok(state.config.read().await.schedules.clone())
+}
+
+/// Create a schedule, or update the one with `id` while keeping its run state
+#[tauri::command]
+async fn save_schedule(
+    id: Option<String>,
+    name: String,
+    spec: ScheduleSpec,
+    target: ScheduleTarget,
+    when_busy: Option<BusyPolicy>,
+    enabled: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<Schedule, String> {
+    if name.trim().is_empty() {
+        return Err("Schedule name cannot be empty".to_string());
+    }
+
+    let mut config = state.config.write().await;
+    if let ScheduleTarget::Template { template_id, .. } = &target {
+        if !config.templates.iter().any(|t| t.id == *template_id) {
+            return Err("Template not found".to_string());
+        }
+    }
+
+    let existing = id.and_then(|id| config.schedules.iter().position(|s| s.id == id));
+    let mut schedule = match existing {
+        Some(index) => {
+            let mut schedule = config.schedules[index].clone();
+            schedule.name = name;
+            schedule.spec = spec;
+            schedule.target = target;
+            schedule
+        }
+        None => Schedule::new(name, spec, target),
+    };
+    if let Some(when_busy) = when_busy {
+        schedule.when_busy = when_busy;
+    }
+    if let Some(enabled) = enabled {
+        schedule.enabled = enabled;
+    }
+    schedule.validate().map_err(|e| e.to_string())?;
+
+    match existing {
+        Some(index) => config.schedules[index] = schedule.clone(),
+        None => config.schedules.push(schedule.clone()),
+    }
+    config.save().map_err(|e| e.to_string())?;
+
+    Ok(schedule)
+}
+
+#[tauri::command]
+async fn delete_schedule(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let mut config = state.config.write().await;
+    config.schedules.retain(|s| s.id != id);
+    config.save().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Most recent scheduled runs first
+#[tauri::command]
+async fn get_schedule_runs(limit: Option<usize>, state: State<'_, AppState>) -> Result<Vec<ScheduleRun>, String> {
+    Ok(state.scheduler.recent_runs(limit.unwrap_or(50)))
+}
+
+/// What LLM calls have cost today, across all tasks
+#[tauri::command]
+async fn get_daily_spend(state: State<'_, AppState>) -> Result<SpendDay, String> {
+    Ok(state.agent_state.spend().today())
+}
+
 #[tauri::command]
 async fn get_templates(state: State<'_, AppState>) -> Result<Vec<TaskTemplate>, String> {
     Ok(state.config.read().await.templates.clone())
 }
 
+/// Preview a template's instruction with the given values filled in
+#[tauri::command]
+async fn resolve_template_instruction(
+    template_id: String,
+    variables: HashMap<String, String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let config = state.config.read().await;
+    resolve_template(&config, &template_id, variables).map(|(instruction, _)| instruction)
+}
+
+/// Save a template. Without explicit `variables`, each `{{placeholder}}`
+/// becomes a required string variable.
 #[tauri::command]
 async fn save_template(
     name: String,
     instruction: String,
+    variables: Option<Vec<TemplateVariable>>,
     state: State<'_, AppState>,
 ) -> Result<TaskTemplate, String> {
     if name.len() > 50 {
@@ -353,7 +687,9 @@ async fn save_template(
         return Err("Instruction cannot be empty".to_string());
     }
 
-    let template = TaskTemplate::new(name, instruction);
+    let variables = variables.unwrap_or_else(|| template::infer_variables(&instruction, &[]));
+    let template = TaskTemplate::new(name, instruction).with_variables(variables);
+    template.validate().map_err(|e| e.to_string())?;
     let mut config = state.config.write().await;
     config.templates.push(template.clone());
     config.save().map_err(|e| e.to_string())?;
@@ -380,6 +716,7 @@ async fn update_template(
     id: String,
     name: String,
     instruction: String,
+    variables: Option<Vec<TemplateVariable>>,
     state: State<'_, AppState>,
 ) -> Result<TaskTemplate, String> {
     if name.len() > 50 {
@@ -397,9 +734,14 @@ async fn update_template(
         .find(|t| t.id == id)
         .ok_or_else(|| "Template not found".to_string())?;
 
-    template.name = name;
-    template.instruction = instruction;
-    let updated = template.clone();
+    // Keep existing declarations unless new ones were sent
+    let variables = variables.unwrap_or_else(|| template::infer_variables(&instruction, &template.variables));
+    let mut updated = template.clone();
+    updated.name = name;
+    updated.instruction = instruction;
+    updated.variables = variables;
+    updated.validate().map_err(|e| e.to_string())?;
+    *template = updated.clone();
 
     config.save().map_err(|e| e.to_string())?;
     Ok(updated)
@@ -674,12 +1016,64 @@ async fn add_to_history(
 #[tauri::command]
 async fn add_to_queue(
     instruction: String,
+    rules: Option<QueueItemRules>,
+    overrides: Option<RunOverrides>,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
-    let id = state.queue.add(instruction).await;
+    if let Some(overrides) = &overrides {
+        check_run_overrides(&*state.config.read().await, overrides)?;
+    }
+    let id = match rules {
+        Some(rules) => state.queue.add_with_rules(instruction, rules).await.map_err(|e| e.to_string())?,
+        None => state.queue.add(instruction).await,
+    };
+    if let Some(overrides) = overrides {
+        state.queue.set_overrides(&id, overrides).await.map_err(|e| e.to_string())?;
+    }
     Ok(id)
 }
 
+/// Set the iteration limit, timeout, retries and provider a queued item runs with
+#[tauri::command]
+async fn set_queue_item_overrides(
+    id: String,
+    overrides: RunOverrides,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    check_run_overrides(&*state.config.read().await, &overrides)?;
+    state.queue.set_overrides(&id, overrides).await.map_err(|e| e.to_string())
+}
+
+/// Make sure an overridden provider and model can actually be used
+fn check_run_overrides(config: &Config, overrides: &RunOverrides) -> Result<(), String> {
+    overrides.validate().map_err(|e| e.to_string())?;
+    if !overrides.changes_provider() {
+        return Ok(());
+    }
+
+    let mut config = config.clone();
+    let provider = overrides
+        .provider
+        .clone()
+        .unwrap_or_else(|| config.general.default_provider.clone());
+    if let Some(model) = &overrides.model {
+        if !config.update_provider_model(&provider, model) {
+            return Err(format!("Provider '{}' is not configured", provider));
+        }
+    }
+    create_provider_from_config(&provider, &config).map(|_| ())
+}
+
+/// Set the dependencies and success/failure branches of a queued item
+#[tauri::command]
+async fn set_queue_item_rules(
+    id: String,
+    rules: QueueItemRules,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.queue.set_rules(&id, rules).await.map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn add_multiple_to_queue(
     instructions: Vec<String>,
@@ -742,21 +1136,25 @@ async fn start_queue(
     let action_history = state.action_history.clone();
     let queue = state.queue.clone();
 
-    let current_state = agent_state.get_state().await;
-    if current_state.status == AgentStatus::Running {
-        return Err("Agent is already running".to_string());
+    if !queue.has_pending().await && queue.interrupted_count().await == 0 {
+        return Err("Queue is empty".to_string());
     }
 
-    if !queue.has_pending().await {
-        return Err("Queue is empty".to_string());
+    // Held across the whole queue so nothing scheduled starts between items
+    if !agent_state.try_claim().await {
+        return Err("Agent is already running".to_string());
     }
 
     let app = app_handle.clone();
     tokio::spawn(async move {
-        let loop_runner = AgentLoop::new(agent_state, config, app, action_history).with_queue(queue);
+        let mut loop_runner = AgentLoop::new(agent_state.clone(), config, app, action_history).with_queue(queue);
+        if let Some(checkpoints) = checkpoint_store() {
+            loop_runner = loop_runner.with_checkpoints(checkpoints);
+        }
         if let Err(e) = loop_runner.run_queue().await {
             log::error!("Queue processing error: {}", e);
         }
+        agent_state.release_claim().await;
     });
 
     Ok(())
@@ -906,6 +1304,41 @@ fn create_provider_from_config(
                 cfg.temperature,
             )))
         }
+        "gemini" => {
+            let cfg = config
+                .providers
+                .gemini
+                .as_ref()
+                .ok_or("Gemini not configured")?;
+            let provider = GeminiProvider::new(
+                cfg.api_key.clone(),
+                cfg.model.clone(),
+                cfg.temperature,
+            );
+            Ok(Box::new(match &cfg.base_url {
+                Some(base_url) => provider.with_base_url(base_url),
+                None => provider,
+            }))
+        }
+        "azure-openai" => {
+            let cfg = config
+                .providers
+                .azure_openai
+                .as_ref()
+                .ok_or("Azure OpenAI not configured")?;
+            let auth = match (&cfg.api_key, &cfg.bearer_token) {
+                (Some(key), _) => AzureAuth::ApiKey(key.clone()),
+                (None, Some(token)) => AzureAuth::Bearer(token.clone()),
+                (None, None) => return Err("Azure OpenAI needs an API key or bearer token".to_string()),
+            };
+            Ok(Box::new(AzureOpenAIProvider::new(
+                cfg.endpoint.clone(),
+                cfg.deployment.clone(),
+                cfg.api_version.clone(),
+                auth,
+                cfg.temperature,
+            )))
+        }
         "openai-compatible" => {
             let cfg = config
                 .providers
@@ -964,12 +1397,37 @@ pub fn run() {
             }
 
             let agent_state = AgentStateManager::new();
+            agent_state.history().set_store(session_store_for(&config));
+            if let Ok(path) = Config::config_path() {
+                agent_state.spend().set_file(Some(DailySpend::path_beside_config(&path)));
+            }
+            let config = Arc::new(RwLock::new(config));
+            let queue = match Config::config_path() {
+                Ok(path) => QueueManager::with_store(QueueStore::beside_config(&path)),
+                Err(_) => QueueManager::new(),
+            };
+            let action_history = Arc::new(RwLock::new(ActionHistory::default()));
+
+            let runner = AppTaskRunner {
+                app: app.handle().clone(),
+                agent_state: agent_state.clone(),
+                config: config.clone(),
+                action_history: action_history.clone(),
+            };
+            let mut scheduler = Scheduler::new(config.clone(), agent_state.clone(), Arc::new(runner)).saving_config();
+            if let Ok(path) = Config::config_path() {
+                scheduler = scheduler.with_run_log(RunLog::beside_config(&path));
+            }
+            let scheduler = Arc::new(scheduler);
+            scheduler.clone().spawn();
+
             let state = AppState {
                 agent_state,
-                config: Arc::new(RwLock::new(config)),
+                config,
                 history: Arc::new(RwLock::new(history)),
-                queue: QueueManager::new(),
-                action_history: Arc::new(RwLock::new(ActionHistory::default())),
+                queue,
+                action_history,
+                scheduler,
             };
             app.manage(state);
 
@@ -1106,6 +1564,7 @@ pub fn run() {
             start_agent_recording,
             get_recorded_actions,
             clear_recorded_actions,
+            replay_recording,
             get_agent_state,
             get_config,
             save_config,
@@ -1128,12 +1587,22 @@ pub fn run() {
             export_session_text,
             get_session_history_count,
             clear_session_history,
+            list_sessions,
+            get_saved_session,
+            export_saved_session,
+            delete_session,
+            get_session_storage_usage,
+            get_interrupted_task,
+            resume_task,
+            discard_interrupted_task,
             get_instruction_history,
             add_to_history,
             clear_history,
             remove_from_history,
             add_to_queue,
             add_multiple_to_queue,
+            set_queue_item_rules,
+            set_queue_item_overrides,
             remove_from_queue,
             clear_queue,
             reorder_queue,
@@ -1144,6 +1613,12 @@ pub fn run() {
             save_template,
             delete_template,
             update_template,
+            resolve_template_instruction,
+            get_schedules,
+            save_schedule,
+            delete_schedule,
+            get_schedule_runs,
+            get_daily_spend,
             undo_last_action,
             detect_credentials,
             apply_detected_credential,
@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
+use super::capabilities::{capabilities_for, estimate_text_tokens, fit_history};
 use super::provider::{
-    build_system_prompt_for_tools_with_context,
-    build_tools, ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics, Tool, ToolUse,
-    history_to_messages,
+    build_tool_prompt_sections,
+    build_plan_tools, build_tools, ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics, Tool, ToolUse,
+    history_to_turns_within,
 };
 use super::sse::append_bytes_to_buffer;
 use crate::agent::conversation::ConversationHistory;
@@ -14,20 +15,23 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+
 pub struct AnthropicProvider {
     client: Client,
     api_key: String,
     model: String,
     temperature: Option<f32>,
+    base_url: String,
 }
 
 #[derive(Serialize)]
 struct AnthropicRequest {
     model: String,
     max_tokens: u32,
-    system: String,
+    system: Vec<SystemBlock>,
     messages: Vec<AnthropicMessage>,
-    tools: Vec<Tool>,
+    tools: Vec<CachedTool>,
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
@@ -39,13 +43,78 @@ struct AnthropicMessage {
     content: Vec<AnthropicContent>,
 }
 
+/// Marks the end of a prompt prefix Anthropic should cache
+#[derive(Serialize, Clone, Copy)]
+struct CacheControl {
+    #[serde(rename = "type")]
+    control_type: &'static str,
+}
+
+impl CacheControl {
+    const EPHEMERAL: CacheControl = CacheControl { control_type: "ephemeral" };
+}
+
+#[derive(Serialize)]
+struct SystemBlock {
+    #[serde(rename = "type")]
+    block_type: &'static str,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+#[derive(Serialize)]
+struct CachedTool {
+    #[serde(flatten)]
+    tool: Tool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
 #[derive(Serialize)]
 #[serde(tag = "type")]
 enum AnthropicContent {
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     #[serde(rename = "image")]
-    Image { source: ImageSource },
+    Image {
+        source: ImageSource,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    #[serde(rename = "tool_use")]
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    #[serde(rename = "tool_result")]
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        is_error: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+}
+
+impl AnthropicContent {
+    fn set_cache_control(&mut self) {
+        match self {
+            AnthropicContent::Text { cache_control, .. }
+            | AnthropicContent::Image { cache_control, .. }
+            | AnthropicContent::ToolUse { cache_control, .. }
+            | AnthropicContent::ToolResult { cache_control, .. } => {
+                *cache_control = Some(CacheControl::EPHEMERAL);
+            }
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -100,6 +169,25 @@ struct MessageInfo {
 struct UsageInfo {
     input_tokens: Option<u64>,
     output_tokens: Option<u64>,
+    #[serde(default)]
+    cache_creation_input_tokens: Option<u64>,
+    #[serde(default)]
+    cache_read_input_tokens: Option<u64>,
+}
+
+/// Put a cache breakpoint at the end of the history that will still be sent
+/// unchanged next step: everything before the oldest screenshot, since that
+/// screenshot is dropped once a newer one arrives.
+fn mark_history_prefix(messages: &mut [AnthropicMessage]) {
+    let oldest_screenshot = messages.iter().position(|m| {
+        m.content.iter().any(|c| matches!(c, AnthropicContent::Image { .. }))
+    });
+    let Some(end) = oldest_screenshot.and_then(|i| i.checked_sub(1)) else {
+        return;
+    };
+    if let Some(block) = messages[end].content.last_mut() {
+        block.set_cache_control();
+    }
 }
 
 impl AnthropicProvider {
@@ -109,6 +197,7 @@ impl AnthropicProvider {
             api_key,
             model,
             temperature,
+            base_url: DEFAULT_BASE_URL.to_string(),
         }
     }
 
@@ -123,6 +212,7 @@ impl AnthropicProvider {
             api_key,
             model,
             temperature,
+            base_url: DEFAULT_BASE_URL.to_string(),
         }
     }
 
@@ -130,6 +220,12 @@ impl AnthropicProvider {
         self.temperature = temperature;
         self
     }
+
+    /// Send requests somewhere other than api.anthropic.com, e.g. a proxy
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
 }
 
 #[async_trait]
@@ -143,19 +239,59 @@ impl LlmProvider for AnthropicProvider {
     ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
         let start = Instant::now();
         let instruction = history.original_instruction().map(|s| s.to_string());
-        let system_prompt = build_system_prompt_for_tools_with_context(
+        // The screen and task stay the same for the whole run and are cached
+        // with the tools; step count, plan and progress note change every step,
+        // so they go with the latest message instead, after the cached history.
+        let (stable_prompt, step_context) = build_tool_prompt_sections(
             screen_width,
             screen_height,
             instruction.as_deref(),
             history.iteration,
             history.max_iterations,
+            history.plan(),
+            history.progress_note(),
         );
-        let tools = build_tools();
+        let step_context = step_context.trim().to_string();
+        let mut tools = build_tools();
+        if history.plan().is_some() {
+            tools.extend(build_plan_tools());
+        }
+
+        let capabilities = capabilities_for(self.name(), Some(&self.model));
+        let fixed_tokens = estimate_text_tokens(&stable_prompt)
+            + estimate_text_tokens(&step_context)
+            + estimate_text_tokens(&serde_json::to_string(&tools).unwrap_or_default());
+        let fit = fit_history(&capabilities, history, fixed_tokens, screen_width, screen_height)?;
 
         // Convert conversation history to Anthropic message format
-        let messages: Vec<AnthropicMessage> = history_to_messages(history)
+        let mut messages: Vec<AnthropicMessage> = history_to_turns_within(history, &fit)
             .into_iter()
-            .map(|(role, text, image_base64)| {
+            .map(|turn| {
+                // Tool calls and their results go as tool_use and tool_result blocks
+                if let Some(tool_use) = turn.tool_use {
+                    let mut content = Vec::new();
+                    if !turn.text.trim().is_empty() {
+                        content.push(AnthropicContent::Text { text: turn.text, cache_control: None });
+                    }
+                    content.push(AnthropicContent::ToolUse {
+                        id: tool_use.id,
+                        name: tool_use.name,
+                        input: tool_use.input,
+                        cache_control: None,
+                    });
+                    return AnthropicMessage { role: turn.role, content };
+                }
+                if let Some(result) = turn.tool_result {
+                    let content = vec![AnthropicContent::ToolResult {
+                        tool_use_id: result.tool_use_id,
+                        content: result.content,
+                        is_error: result.is_error,
+                        cache_control: None,
+                    }];
+                    return AnthropicMessage { role: turn.role, content };
+                }
+
+                let (role, text, image_base64) = (turn.role, turn.text, turn.image);
                 let mut content = Vec::new();
 
                 // Add image first if present (Anthropic prefers image before text)
@@ -166,6 +302,7 @@ impl LlmProvider for AnthropicProvider {
                             media_type: "image/png".to_string(),
                             data: img_data,
                         },
+                        cache_control: None,
                     });
                 }
 
@@ -178,16 +315,35 @@ impl LlmProvider for AnthropicProvider {
                 } else {
                     text
                 };
-                content.push(AnthropicContent::Text { text: text_content });
+                content.push(AnthropicContent::Text { text: text_content, cache_control: None });
 
                 AnthropicMessage { role, content }
             })
             .collect();
 
+        mark_history_prefix(&mut messages);
+        if let Some(last) = messages.last_mut().filter(|m| m.role == "user" && !step_context.is_empty()) {
+            last.content.push(AnthropicContent::Text { text: step_context, cache_control: None });
+        }
+
+        let tool_count = tools.len();
+        let tools = tools
+            .into_iter()
+            .enumerate()
+            .map(|(i, tool)| CachedTool {
+                tool,
+                cache_control: (i + 1 == tool_count).then_some(CacheControl::EPHEMERAL),
+            })
+            .collect();
+
         let request = AnthropicRequest {
             model: self.model.clone(),
             max_tokens: 1024,
-            system: system_prompt,
+            system: vec![SystemBlock {
+                block_type: "text",
+                text: stable_prompt,
+                cache_control: Some(CacheControl::EPHEMERAL),
+            }],
             messages,
             tools,
             stream: true,
@@ -196,7 +352,7 @@ impl LlmProvider for AnthropicProvider {
 
         let response = self
             .client
-            .post("https://api.anthropic.com/v1/messages")
+            .post(format!("{}/v1/messages", self.base_url))
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
@@ -214,6 +370,8 @@ impl LlmProvider for AnthropicProvider {
         let _full_response = String::with_capacity(4096);
         let mut input_tokens = 0u64;
         let mut output_tokens = 0u64;
+        let mut cache_read_tokens = 0u64;
+        let mut cache_write_tokens = 0u64;
         let mut buffer = String::new();
 
         // Track tool_use blocks as they stream
@@ -237,6 +395,8 @@ impl LlmProvider for AnthropicProvider {
                                     if let Some(msg) = event.message {
                                         if let Some(usage) = msg.usage {
                                             input_tokens = usage.input_tokens.unwrap_or(0);
+                                            cache_read_tokens = usage.cache_read_input_tokens.unwrap_or(0);
+                                            cache_write_tokens = usage.cache_creation_input_tokens.unwrap_or(0);
                                         }
                                     }
                                 }
@@ -287,6 +447,8 @@ impl LlmProvider for AnthropicProvider {
         let metrics = TokenMetrics {
             input_tokens,
             output_tokens,
+            cache_read_tokens,
+            cache_write_tokens,
             total_duration: start.elapsed(),
         };
 
@@ -303,7 +465,7 @@ impl LlmProvider for AnthropicProvider {
             };
 
             Ok((
-                LlmResponse::ToolUse { tool_use: ToolUse { id, name, input }, reasoning },
+                LlmResponse::ToolUse { tool_use: ToolUse { id, name, input, signature: None }, reasoning },
                 metrics,
             ))
         } else {
@@ -314,7 +476,7 @@ impl LlmProvider for AnthropicProvider {
     async fn health_check(&self) -> Result<bool, LlmError> {
         let response = self
             .client
-            .get("https://api.anthropic.com/v1/models")
+            .get(format!("{}/v1/models", self.base_url))
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
             .send()
@@ -325,7 +487,7 @@ impl LlmProvider for AnthropicProvider {
     async fn list_models(&self) -> Result<Vec<String>, LlmError> {
         let response = self
             .client
-            .get("https://api.anthropic.com/v1/models")
+            .get(format!("{}/v1/models", self.base_url))
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
             .send()
@@ -353,4 +515,139 @@ impl LlmProvider for AnthropicProvider {
     fn name(&self) -> &str {
         "anthropic"
     }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::test_server::{Reply, TestServer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_request_marks_cache_breakpoints_and_reads_cache_usage() {
+        let server = TestServer::start(vec![Reply::sse(&[
+            json!({"type": "message_start", "message": {"usage": {
+                "input_tokens": 50, "cache_read_input_tokens": 1200, "cache_creation_input_tokens": 300
+            }}}),
+            json!({"type": "content_block_start", "index": 0,
+                   "content_block": {"type": "tool_use", "id": "toolu_1", "name": "click"}}),
+            json!({"type": "content_block_delta", "index": 0,
+                   "delta": {"type": "input_json_delta", "partial_json": "{\"x\": 5, \"y\": 6}"}}),
+            json!({"type": "message_delta", "usage": {"output_tokens": 20}}),
+            json!({"type": "message_stop"}),
+        ])])
+        .await;
+
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("Open Notes".to_string());
+        history.iteration = Some(3);
+        history.max_iterations = Some(10);
+        for i in 0..3 {
+            history.add_user_message(&format!("step {}", i), Some(Arc::new("img".to_string())), None, None);
+            history.add_assistant_message("{\"action\": \"wait\"}");
+            history.add_tool_result(true, None, None);
+        }
+        history.add_user_message("now", Some(Arc::new("img".to_string())), None, None);
+
+        let provider = AnthropicProvider::new("key".to_string(), "claude-sonnet-4".to_string(), None)
+            .with_base_url(&server.url);
+        let (response, metrics) = provider
+            .send_with_history(&history, 1024, 768, Box::new(|_| {}))
+            .await
+            .unwrap();
+        assert!(matches!(response, LlmResponse::ToolUse { ref tool_use, .. } if tool_use.name == "click"));
+        assert_eq!(
+            (metrics.input_tokens, metrics.output_tokens, metrics.cache_read_tokens, metrics.cache_write_tokens),
+            (50, 20, 1200, 300)
+        );
+
+        let requests = server.requests();
+        assert_eq!((requests[0].method.as_str(), requests[0].path.as_str()), ("POST", "/v1/messages"));
+        assert_eq!(requests[0].header("x-api-key"), Some("key"));
+        let body = requests[0].json();
+
+        // Tools and the task are cached; the step counter is not part of it
+        let system = body["system"].as_array().unwrap();
+        assert_eq!(system.len(), 1);
+        assert_eq!(system[0]["cache_control"]["type"], "ephemeral");
+        assert!(system[0]["text"].as_str().unwrap().contains("Open Notes"));
+        assert!(!system[0]["text"].as_str().unwrap().contains("Step 3 of 10"));
+        let tools = body["tools"].as_array().unwrap();
+        assert_eq!(tools.last().unwrap()["cache_control"]["type"], "ephemeral");
+        assert!(tools[..tools.len() - 1].iter().all(|t| t.get("cache_control").is_none()));
+
+        // History is cached up to the message before the oldest screenshot sent
+        let messages = body["messages"].as_array().unwrap();
+        let marked: Vec<usize> = messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m["content"].as_array().unwrap().iter().any(|c| c.get("cache_control").is_some()))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(marked, vec![5]);
+        assert_eq!(messages[6]["content"][0]["type"], "image");
+
+        let last = messages.last().unwrap()["content"].as_array().unwrap();
+        assert!(last.last().unwrap()["text"].as_str().unwrap().contains("Step 3 of 10"));
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_go_back_as_tool_use_and_tool_result_blocks() {
+        let server = TestServer::start(vec![Reply::sse(&[
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Done"}}),
+            json!({"type": "message_stop"}),
+        ])])
+        .await;
+
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("Open Notes".to_string());
+        history.add_user_message("Open Notes", Some(Arc::new("img".to_string())), None, None);
+        history.add_assistant_response(&LlmResponse::ToolUse {
+            tool_use: ToolUse { id: "toolu_9".to_string(), name: "click".to_string(), input: json!({"x": 1, "y": 2}), signature: None },
+            reasoning: Some("Clicking the icon".to_string()),
+        });
+        history.add_tool_result(false, None, Some("Missed".to_string()));
+        history.add_user_message("now", Some(Arc::new("img".to_string())), None, None);
+
+        let provider = AnthropicProvider::new("key".to_string(), "claude-sonnet-4".to_string(), None)
+            .with_base_url(&server.url);
+        provider
+            .send_with_history(&history, 1024, 768, Box::new(|_| {}))
+            .await
+            .unwrap();
+
+        let body = server.requests()[0].json();
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(messages[1]["content"][0]["text"], "Clicking the icon");
+        assert_eq!(messages[1]["content"][1]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][1]["id"], "toolu_9");
+        assert_eq!(messages[1]["content"][1]["input"], json!({"x": 1, "y": 2}));
+
+        let result = &messages[2]["content"][0];
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(result["type"], "tool_result");
+        assert_eq!(result["tool_use_id"], "toolu_9");
+        assert_eq!(result["is_error"], true);
+        assert!(result["content"].as_str().unwrap().contains("Missed"));
+    }
+
+    #[tokio::test]
+    async fn test_list_models_uses_base_url() {
+        let server = TestServer::start(vec![Reply::json(json!({
+            "data": [{"id": "claude-sonnet-4"}, {"id": "claude-haiku-4-5"}]
+        }))])
+        .await;
+        let provider = AnthropicProvider::new("key".to_string(), "claude-sonnet-4".to_string(), None)
+            .with_base_url(&format!("{}/", server.url));
+
+        let models = provider.list_models().await.unwrap();
+        assert_eq!(models, vec!["claude-sonnet-4", "claude-haiku-4-5"]);
+        let requests = server.requests();
+        assert_eq!((requests[0].method.as_str(), requests[0].path.as_str()), ("GET", "/v1/models"));
+    }
 }
@@ -0,0 +1,233 @@
+use super::provider::{ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics};
+use super::sse::{send_chat, ChatEndpoint, ToolSupport};
+use crate::agent::conversation::ConversationHistory;
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use std::time::Duration;
+
+/// How requests to the Azure resource are authenticated
+#[derive(Debug, Clone, PartialEq)]
+pub enum AzureAuth {
+    /// A resource key, sent in the `api-key` header
+    ApiKey(String),
+    /// A Microsoft Entra ID access token, sent as `Authorization: Bearer`
+    Bearer(String),
+}
+
+/// Azure OpenAI: requests go to a deployment on the user's own resource
+/// rather than naming a model, and the API version is a query parameter.
+pub struct AzureOpenAIProvider {
+    client: Client,
+    /// Resource endpoint, e.g. `https://my-resource.openai.azure.com`
+    endpoint: String,
+    deployment: String,
+    api_version: String,
+    auth: AzureAuth,
+    temperature: Option<f32>,
+    tools: ToolSupport,
+}
+
+impl AzureOpenAIProvider {
+    pub fn new(
+        endpoint: String,
+        deployment: String,
+        api_version: String,
+        auth: AzureAuth,
+        temperature: Option<f32>,
+    ) -> Self {
+        Self::with_client(Client::new(), endpoint, deployment, api_version, auth, temperature)
+    }
+
+    pub fn with_timeouts(
+        endpoint: String,
+        deployment: String,
+        api_version: String,
+        auth: AzureAuth,
+        temperature: Option<f32>,
+        connect_timeout: Duration,
+        response_timeout: Duration,
+    ) -> Self {
+        let client = Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(response_timeout)
+            .build()
+            .unwrap_or_else(|_| Client::new());
+        Self::with_client(client, endpoint, deployment, api_version, auth, temperature)
+    }
+
+    fn with_client(
+        client: Client,
+        endpoint: String,
+        deployment: String,
+        api_version: String,
+        auth: AzureAuth,
+        temperature: Option<f32>,
+    ) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            deployment,
+            api_version,
+            auth,
+            temperature,
+            tools: ToolSupport::default(),
+        }
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            AzureAuth::ApiKey(key) => request.header("api-key", key),
+            AzureAuth::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AzureOpenAIProvider {
+    async fn send_with_history(
+        &self,
+        history: &ConversationHistory,
+        screen_width: u32,
+        screen_height: u32,
+        on_chunk: ChunkCallback,
+    ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
+        // The deployment in the URL decides the model, so the request names none
+        let endpoint = ChatEndpoint {
+            provider: self.name(),
+            model: &self.deployment,
+            model_in_url: true,
+            temperature: self.temperature,
+            include_usage: true,
+            tools: &self.tools,
+        };
+        send_chat(endpoint, history, screen_width, screen_height, on_chunk, || {
+            let url = format!(
+                "{}/openai/deployments/{}/chat/completions?api-version={}",
+                self.endpoint, self.deployment, self.api_version
+            );
+            self.authorize(self.client.post(url))
+        })
+        .await
+    }
+
+    /// Checks the endpoint and credentials; whether the deployment exists
+    /// only shows on the first request
+    async fn health_check(&self) -> Result<bool, LlmError> {
+        let url = format!("{}/openai/models?api-version={}", self.endpoint, self.api_version);
+        let response = self.authorize(self.client.get(url)).send().await?;
+        Ok(response.status().is_success())
+    }
+
+    /// Deployments can't be listed with resource credentials (only through
+    /// Azure management), so the configured one is the only choice
+    async fn list_models(&self) -> Result<Vec<String>, LlmError> {
+        Ok(vec![self.deployment.clone()])
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.tools.available()
+    }
+
+    fn name(&self) -> &str {
+        "azure-openai"
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.deployment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::ToolUse;
+    use crate::llm::test_server::{Reply, TestServer};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn provider(endpoint: &str, auth: AzureAuth) -> AzureOpenAIProvider {
+        AzureOpenAIProvider::new(
+            format!("{}/", endpoint),
+            "gpt-4o-prod".to_string(),
+            "2024-10-21".to_string(),
+            auth,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_posts_to_deployment_with_api_key_and_tools() {
+        let server = TestServer::start(vec![Reply::sse(&[
+            // Azure opens the stream with content filter results and no choices
+            json!({"choices": [], "prompt_filter_results": [{"prompt_index": 0}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_7", "type": "function",
+                "function": {"name": "click", "arguments": "{\"x\": 3, \"y\": 4}"}}]}}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 40, "completion_tokens": 9}}),
+        ])])
+        .await;
+
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("Open Notes".to_string());
+        history.add_user_message("Open Notes", Some(Arc::new("img".to_string())), None, None);
+        history.add_assistant_response(&LlmResponse::ToolUse {
+            tool_use: ToolUse { id: "call_6".to_string(), name: "wait".to_string(), input: json!({}), signature: None },
+            reasoning: None,
+        });
+        history.add_tool_result(true, None, None);
+        history.add_user_message("now", Some(Arc::new("img".to_string())), None, None);
+
+        let (response, metrics) = provider(&server.url, AzureAuth::ApiKey("azkey".to_string()))
+            .send_with_history(&history, 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+        assert!(matches!(response, LlmResponse::ToolUse { ref tool_use, .. } if tool_use.id == "call_7"));
+        assert_eq!((metrics.input_tokens, metrics.output_tokens), (40, 9));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/openai/deployments/gpt-4o-prod/chat/completions?api-version=2024-10-21");
+        assert_eq!(request.header("api-key"), Some("azkey"));
+        assert_eq!(request.header("authorization"), None);
+        let body = request.json();
+        assert!(body.get("model").is_none());
+        assert_eq!(body["tools"][0]["type"], "function");
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[2]["tool_calls"][0]["id"], "call_6");
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(messages[3]["tool_call_id"], "call_6");
+    }
+
+    #[tokio::test]
+    async fn test_bearer_auth_and_health_check() {
+        let server = TestServer::start(vec![Reply::json(json!({"data": []})), Reply::error(401, "{}")]).await;
+        let provider = provider(&server.url, AzureAuth::Bearer("entra-token".to_string()));
+
+        assert!(provider.health_check().await.unwrap());
+        assert!(!provider.health_check().await.unwrap());
+        assert_eq!(provider.list_models().await.unwrap(), vec!["gpt-4o-prod"]);
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/openai/models?api-version=2024-10-21");
+        assert_eq!(request.header("authorization"), Some("Bearer entra-token"));
+        assert_eq!(request.header("api-key"), None);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_text_when_deployment_rejects_tools() {
+        let server = TestServer::start(vec![
+            Reply::error(400, r#"{"error": {"message": "tools is not supported with this model"}}"#),
+            Reply::sse(&[json!({"choices": [{"delta": {"content": "{\"action\": \"wait\"}"}}]})]),
+        ])
+        .await;
+        let provider = provider(&server.url, AzureAuth::ApiKey("azkey".to_string()));
+        let mut history = ConversationHistory::new();
+        history.add_user_message("Open Notes", Some(Arc::new("img".to_string())), None, None);
+
+        let (response, _) = provider
+            .send_with_history(&history, 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+        assert!(matches!(response, LlmResponse::Text(ref text) if text.contains("wait")));
+        assert!(!provider.supports_tools());
+        assert!(server.requests()[1].json().get("tools").is_none());
+    }
+}
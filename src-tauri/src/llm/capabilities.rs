@@ -0,0 +1,275 @@
+//! How much each model can take in one request, and a rough estimate of how
+//! much a request will use, so history can be trimmed before it is sent.
+//!
+//! Figures are approximate on purpose: the estimate only has to be close
+//! enough to keep requests inside the window, not to predict billing.
+
+use super::provider::{omitted_messages_note, LlmError};
+use crate::agent::conversation::{ConversationHistory, Message};
+use crate::config::pricing::upstream_provider;
+
+/// Tokens kept free for the model's reply (requests ask for up to 1024)
+const RESERVED_OUTPUT_TOKENS: u32 = 1024;
+/// Role markers and the text providers wrap around each message
+const MESSAGE_OVERHEAD_TOKENS: u32 = 16;
+/// Most screenshots sent with one request; older ones only cost tokens
+pub const MAX_SCREENSHOTS: usize = 2;
+
+/// How a model charges for an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageCost {
+    /// One token per this many pixels
+    PerPixels(u32),
+    /// OpenAI style: a base cost plus a cost per 512px tile
+    Tiles { base: u32, per_tile: u32 },
+    /// The same number of tokens whatever the size
+    Fixed(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelCapabilities {
+    /// Total tokens the model accepts, prompt and reply together
+    pub context_tokens: u32,
+    pub image_cost: ImageCost,
+    /// Images with a longer edge are scaled down to this before they are charged
+    pub max_image_edge: u32,
+}
+
+impl ModelCapabilities {
+    /// Used for models the table doesn't know
+    pub const DEFAULT: ModelCapabilities = ModelCapabilities {
+        context_tokens: 32_000,
+        image_cost: ImageCost::PerPixels(750),
+        max_image_edge: 1568,
+    };
+
+    /// Tokens one image of this size costs
+    pub fn image_tokens(&self, width: u32, height: u32) -> u32 {
+        let longest = width.max(height).max(1);
+        let scale = (self.max_image_edge as f64 / longest as f64).min(1.0);
+        let width = (width as f64 * scale).round().max(1.0);
+        let height = (height as f64 * scale).round().max(1.0);
+
+        match self.image_cost {
+            ImageCost::PerPixels(pixels) => ((width * height) / pixels.max(1) as f64).ceil() as u32,
+            ImageCost::Tiles { base, per_tile } => {
+                // The shortest side is scaled down to 768px before tiling
+                let scale = (768.0 / width.min(height)).min(1.0);
+                let tiles = ((width * scale) / 512.0).ceil() * ((height * scale) / 512.0).ceil();
+                base + per_tile * tiles as u32
+            }
+            ImageCost::Fixed(tokens) => tokens,
+        }
+    }
+}
+
+const fn caps(context_tokens: u32, image_cost: ImageCost, max_image_edge: u32) -> ModelCapabilities {
+    ModelCapabilities { context_tokens, image_cost, max_image_edge }
+}
+
+/// (provider, model prefix, capabilities); the longest matching prefix wins
+const BUILTIN_CAPABILITIES: &[(&str, &str, ModelCapabilities)] = &[
+    ("anthropic", "claude-", caps(200_000, ImageCost::PerPixels(750), 1568)),
+    ("openai", "gpt-4o", caps(128_000, ImageCost::Tiles { base: 85, per_tile: 170 }, 2048)),
+    ("openai", "gpt-4o-mini", caps(128_000, ImageCost::Tiles { base: 2833, per_tile: 5667 }, 2048)),
+    ("openai", "gpt-4.1", caps(1_000_000, ImageCost::Tiles { base: 85, per_tile: 170 }, 2048)),
+    ("openai", "gpt-5", caps(400_000, ImageCost::Tiles { base: 70, per_tile: 140 }, 2048)),
+    ("openai", "o4-mini", caps(200_000, ImageCost::Tiles { base: 85, per_tile: 170 }, 2048)),
+    // Images are charged per 768px tile, 258 tokens each
+    ("gemini", "gemini-", caps(1_048_576, ImageCost::PerPixels(2286), 3072)),
+    ("glm", "glm-4.5v", caps(64_000, ImageCost::PerPixels(784), 2048)),
+    ("glm", "glm-4.6v", caps(128_000, ImageCost::PerPixels(784), 2048)),
+    // Local models get the context size Pia asks Ollama for
+    ("ollama", "", caps(8_192, ImageCost::PerPixels(784), 1280)),
+    ("ollama", "llava", caps(8_192, ImageCost::Fixed(576), 672)),
+    ("ollama", "llama3.2-vision", caps(8_192, ImageCost::Fixed(1601), 1120)),
+    ("ollama", "gemma3", caps(8_192, ImageCost::Fixed(256), 896)),
+];
+
+/// Capabilities of `model` on `provider`; `ModelCapabilities::DEFAULT` when unknown.
+/// OpenRouter and Azure models use the entry of the provider behind them
+/// (see `upstream_provider`).
+pub fn capabilities_for(provider: &str, model: Option<&str>) -> ModelCapabilities {
+    let model = model.unwrap_or_default();
+    let (upstream, upstream_model) = upstream_provider(provider, model);
+    if upstream != provider {
+        return capabilities_for(upstream, Some(upstream_model));
+    }
+
+    BUILTIN_CAPABILITIES
+        .iter()
+        .filter(|(p, prefix, _)| *p == provider && model.starts_with(prefix))
+        .max_by_key(|(_, prefix, _)| prefix.len())
+        .map(|&(_, _, capabilities)| capabilities)
+        .unwrap_or(ModelCapabilities::DEFAULT)
+}
+
+/// Rough token count for text: about four characters per token
+pub fn estimate_text_tokens(text: &str) -> u32 {
+    text.len().div_ceil(4) as u32
+}
+
+/// What part of a conversation goes into a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContextFit {
+    /// How many of the most recent screenshots are attached
+    pub screenshots: usize,
+    /// Messages left out after the first one, oldest first
+    pub skipped_messages: usize,
+    pub estimated_tokens: u32,
+}
+
+impl Default for ContextFit {
+    fn default() -> Self {
+        Self {
+            screenshots: MAX_SCREENSHOTS,
+            skipped_messages: 0,
+            estimated_tokens: 0,
+        }
+    }
+}
+
+fn message_text_tokens(message: &Message) -> u32 {
+    let text = match message {
+        Message::User { instruction, .. } => estimate_text_tokens(instruction),
+        Message::Assistant { content, tool_use } => {
+            estimate_text_tokens(content)
+                + tool_use
+                    .as_ref()
+                    .map_or(0, |tool_use| estimate_text_tokens(&tool_use.input.to_string()) + MESSAGE_OVERHEAD_TOKENS)
+        }
+        Message::ToolResult { message, error, .. } => {
+            estimate_text_tokens(message.as_deref().unwrap_or_default())
+                + estimate_text_tokens(error.as_deref().unwrap_or_default())
+        }
+    };
+    text + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Decide how many screenshots and how much history fit in the model's window
+/// next to `fixed_tokens` (system prompt, tool definitions).
+///
+/// Older screenshots go first, then the oldest messages after the first one.
+/// Skipped messages are replaced by a short note saying how many are missing.
+/// The first message and the latest one, with its screenshot, are always sent;
+/// when even they don't fit, the request is refused.
+pub fn fit_history(
+    capabilities: &ModelCapabilities,
+    history: &ConversationHistory,
+    fixed_tokens: u32,
+    screen_width: u32,
+    screen_height: u32,
+) -> Result<ContextFit, LlmError> {
+    let limit = capabilities.context_tokens.saturating_sub(RESERVED_OUTPUT_TOKENS);
+    let messages: Vec<&Message> = history.messages().collect();
+
+    let text: Vec<u32> = messages.iter().map(|m| message_text_tokens(m)).collect();
+    // Image cost per message, for the ones that carry a screenshot
+    let images: Vec<Option<u32>> = messages
+        .iter()
+        .map(|m| match m {
+            Message::User {
+                screenshot_base64: Some(_),
+                screen_width: width,
+                screen_height: height,
+                ..
+            } => Some(capabilities.image_tokens(width.unwrap_or(screen_width), height.unwrap_or(screen_height))),
+            _ => None,
+        })
+        .collect();
+
+    let estimate = |screenshots: usize, skipped: usize| -> u32 {
+        let kept = |i: &usize| *i == 0 || *i > skipped;
+        let text_tokens: u32 = (0..messages.len()).filter(kept).map(|i| text[i]).sum();
+        let image_tokens: u32 = (0..messages.len())
+            .rev()
+            .filter(kept)
+            .filter_map(|i| images[i])
+            .take(screenshots)
+            .sum();
+        let note_tokens = if skipped > 0 { estimate_text_tokens(&omitted_messages_note(skipped)) } else { 0 };
+        fixed_tokens + text_tokens + image_tokens + note_tokens
+    };
+
+    let available = images.iter().flatten().count().min(MAX_SCREENSHOTS);
+    // Fewer screenshots before less text; the latest screenshot always goes
+    for screenshots in (available.min(1)..=available).rev() {
+        let tokens = estimate(screenshots, 0);
+        if tokens <= limit {
+            return Ok(ContextFit { screenshots, skipped_messages: 0, estimated_tokens: tokens });
+        }
+    }
+
+    let screenshots = available.min(1);
+    let skippable = messages.len().saturating_sub(2);
+    let mut needed = estimate(screenshots, 0);
+    for skipped in 1..=skippable {
+        needed = estimate(screenshots, skipped);
+        if needed <= limit {
+            return Ok(ContextFit { screenshots, skipped_messages: skipped, estimated_tokens: needed });
+        }
+    }
+
+    Err(LlmError::ContextOverflow { needed, limit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_capabilities_lookup_and_image_cost() {
+        let sonnet = capabilities_for("anthropic", Some("claude-sonnet-4-20250514"));
+        assert_eq!(sonnet.context_tokens, 200_000);
+        // 1568x980 after scaling a 2560x1600 screen down
+        assert_eq!(sonnet.image_tokens(2560, 1600), 2049);
+
+        let mini = capabilities_for("openai", Some("gpt-4o-mini"));
+        assert_eq!(mini.image_tokens(1024, 1024), 2833 + 5667 * 4);
+        assert_eq!(capabilities_for("openai", Some("gpt-4o")).image_tokens(1024, 1024), 85 + 170 * 4);
+
+        assert_eq!(capabilities_for("openrouter", Some("anthropic/claude-3-5-sonnet")), sonnet);
+        assert_eq!(capabilities_for("ollama", Some("llava:13b")).image_cost, ImageCost::Fixed(576));
+        assert_eq!(capabilities_for("ollama", Some("qwen2.5vl")).context_tokens, 8_192);
+        assert_eq!(capabilities_for("openai-compatible", Some("my-model")), ModelCapabilities::DEFAULT);
+        assert_eq!(capabilities_for("openrouter", Some("google/gemini-2.5-pro")).context_tokens, 1_048_576);
+        assert_eq!(capabilities_for("azure-openai", Some("gpt-4o")), capabilities_for("openai", Some("gpt-4o")));
+    }
+
+    fn conversation(steps: usize) -> ConversationHistory {
+        let mut history = ConversationHistory::new();
+        for i in 0..steps {
+            let screenshot = Some(Arc::new(String::from("image")));
+            history.add_user_message(&format!("step {}", i), screenshot, Some(1000), Some(750));
+            history.add_assistant_message(&"x".repeat(400));
+            history.add_tool_result(true, None, None);
+        }
+        history.add_user_message("now", Some(Arc::new(String::from("image"))), Some(1000), Some(750));
+        history
+    }
+
+    #[test]
+    fn test_fit_history_trims_screenshots_then_text() {
+        let capabilities = ModelCapabilities {
+            context_tokens: 6_000,
+            image_cost: ImageCost::PerPixels(750),
+            max_image_edge: 1568,
+        };
+        // Each screenshot costs 1000 tokens and each step about 150 of text
+        let history = conversation(3);
+        let fit = fit_history(&capabilities, &history, 1_000, 1000, 750).unwrap();
+        assert_eq!((fit.screenshots, fit.skipped_messages), (2, 0));
+
+        let fit = fit_history(&capabilities, &history, 3_500, 1000, 750).unwrap();
+        assert_eq!((fit.screenshots, fit.skipped_messages), (1, 0));
+
+        let fit = fit_history(&capabilities, &history, 3_800, 1000, 750).unwrap();
+        assert_eq!(fit.screenshots, 1);
+        assert!(fit.skipped_messages > 0);
+        assert!(fit.estimated_tokens <= 6_000 - RESERVED_OUTPUT_TOKENS);
+
+        let overflow = fit_history(&capabilities, &history, 5_000, 1000, 750);
+        assert!(matches!(overflow, Err(LlmError::ContextOverflow { .. })));
+    }
+}
@@ -0,0 +1,591 @@
+use super::capabilities::{capabilities_for, estimate_text_tokens, fit_history};
+use super::provider::{
+    build_plan_tools, build_system_prompt_for_tools_with_context, build_tools, history_to_turns_within,
+    ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics, ToolUse,
+};
+use super::sse::append_bytes_to_buffer;
+use crate::agent::conversation::ConversationHistory;
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+
+pub struct GeminiProvider {
+    client: Client,
+    api_key: String,
+    model: String,
+    temperature: Option<f32>,
+    base_url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiRequest {
+    system_instruction: GeminiContent,
+    contents: Vec<GeminiContent>,
+    tools: Vec<GeminiTools>,
+    generation_config: GenerationConfig,
+}
+
+#[derive(Serialize)]
+struct GeminiContent {
+    /// "user" or "model"; left out for the system instruction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiPart {
+    #[serde(flatten)]
+    data: PartData,
+    /// Thinking models sign their function calls and reject a history that
+    /// replays a call without its signature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thought_signature: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+enum PartData {
+    Text(String),
+    InlineData(InlineData),
+    FunctionCall(FunctionCall),
+    FunctionResponse(FunctionResponse),
+}
+
+impl From<PartData> for GeminiPart {
+    fn from(data: PartData) -> Self {
+        Self { data, thought_signature: None }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlineData {
+    mime_type: &'static str,
+    data: Arc<String>,
+}
+
+#[derive(Serialize)]
+struct FunctionCall {
+    name: String,
+    args: Value,
+}
+
+#[derive(Serialize)]
+struct FunctionResponse {
+    /// Name of the function that was called; Gemini matches results by name
+    name: String,
+    response: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTools {
+    function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Serialize)]
+struct FunctionDeclaration {
+    name: String,
+    description: String,
+    parameters: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    /// Thinking models spend part of this before they answer
+    max_output_tokens: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiStreamChunk {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(default)]
+    usage_metadata: Option<UsageMetadata>,
+    #[serde(default)]
+    prompt_feedback: Option<PromptFeedback>,
+}
+
+#[derive(Deserialize)]
+struct Candidate {
+    #[serde(default)]
+    content: Option<CandidateContent>,
+}
+
+#[derive(Deserialize)]
+struct CandidateContent {
+    #[serde(default)]
+    parts: Vec<ResponsePart>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResponsePart {
+    #[serde(default)]
+    text: Option<String>,
+    /// Set on thought summaries, which are not part of the answer
+    #[serde(default)]
+    thought: bool,
+    #[serde(default)]
+    function_call: Option<ResponseFunctionCall>,
+    #[serde(default)]
+    thought_signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseFunctionCall {
+    #[serde(default)]
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+/// Token counts; every chunk repeats the running totals
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+    #[serde(default)]
+    thoughts_token_count: u64,
+    /// Part of `prompt_token_count` served from the context cache
+    #[serde(default)]
+    cached_content_token_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    #[serde(default)]
+    block_reason: Option<String>,
+}
+
+/// What has arrived so far of a streamed `streamGenerateContent` reply
+#[derive(Default)]
+struct GeminiStream {
+    text: String,
+    tool_use: Option<ToolUse>,
+    usage: UsageMetadata,
+    block_reason: Option<String>,
+}
+
+impl GeminiStream {
+    /// Apply the JSON of one `data:` event
+    fn apply(&mut self, data: &str, on_chunk: &dyn Fn(&str)) {
+        let Ok(chunk) = serde_json::from_str::<GeminiStreamChunk>(data) else {
+            return;
+        };
+        let parts = chunk
+            .candidates
+            .into_iter()
+            .next()
+            .and_then(|candidate| candidate.content)
+            .map(|content| content.parts)
+            .unwrap_or_default();
+        for part in parts {
+            if let Some(text) = part.text.filter(|text| !text.is_empty() && !part.thought) {
+                self.text.push_str(&text);
+                on_chunk(&text);
+            }
+            // One action per step; later calls in the same reply are dropped
+            if let Some(call) = part.function_call.filter(|_| self.tool_use.is_none()) {
+                on_chunk(&format!("[Using tool: {}]", call.name));
+                self.tool_use = Some(ToolUse {
+                    id: call.id.unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple())),
+                    name: call.name,
+                    input: if call.args.is_object() { call.args } else { serde_json::json!({}) },
+                    signature: part.thought_signature,
+                });
+            }
+        }
+        if let Some(usage) = chunk.usage_metadata {
+            self.usage = usage;
+        }
+        if let Some(reason) = chunk.prompt_feedback.and_then(|feedback| feedback.block_reason) {
+            self.block_reason = Some(reason);
+        }
+    }
+
+    /// Gemini counts cached tokens inside the prompt and thinking apart from
+    /// the answer; both thoughts and answer are billed as output
+    fn metrics(&self, total_duration: Duration) -> TokenMetrics {
+        let usage = self.usage;
+        TokenMetrics {
+            input_tokens: usage.prompt_token_count.saturating_sub(usage.cached_content_token_count),
+            output_tokens: usage.candidates_token_count + usage.thoughts_token_count,
+            cache_read_tokens: usage.cached_content_token_count,
+            total_duration,
+            ..Default::default()
+        }
+    }
+
+    fn into_response(self) -> Result<LlmResponse, LlmError> {
+        match (self.tool_use, self.block_reason) {
+            (Some(tool_use), _) => {
+                let reasoning = if self.text.trim().is_empty() { None } else { Some(self.text) };
+                Ok(LlmResponse::ToolUse { tool_use, reasoning })
+            }
+            (None, Some(reason)) if self.text.trim().is_empty() => {
+                Err(LlmError::ApiError(format!("Gemini blocked the prompt: {}", reason)))
+            }
+            (None, _) => Ok(LlmResponse::Text(self.text)),
+        }
+    }
+}
+
+impl GeminiProvider {
+    pub fn new(api_key: String, model: String, temperature: Option<f32>) -> Self {
+        Self {
+            client: Client::new(),
+            api_key,
+            model,
+            temperature,
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    pub fn with_timeouts(api_key: String, model: String, temperature: Option<f32>, connect_timeout: Duration, response_timeout: Duration) -> Self {
+        let client = Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(response_timeout)
+            .build()
+            .unwrap_or_else(|_| Client::new());
+        Self {
+            client,
+            api_key,
+            model,
+            temperature,
+            base_url: DEFAULT_BASE_URL.to_string(),
+        }
+    }
+
+    /// Send requests somewhere other than generativelanguage.googleapis.com
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Model id without the `models/` prefix the API uses in names
+    fn model_id(&self) -> &str {
+        self.model.strip_prefix("models/").unwrap_or(&self.model)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    async fn send_with_history(
+        &self,
+        history: &ConversationHistory,
+        screen_width: u32,
+        screen_height: u32,
+        on_chunk: ChunkCallback,
+    ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
+        let start = Instant::now();
+        let instruction = history.original_instruction().map(|s| s.to_string());
+        let system_prompt = build_system_prompt_for_tools_with_context(
+            screen_width,
+            screen_height,
+            instruction.as_deref(),
+            history.iteration,
+            history.max_iterations,
+            history.plan(),
+            history.progress_note(),
+        );
+        let mut tools = build_tools();
+        if history.plan().is_some() {
+            tools.extend(build_plan_tools());
+        }
+        let declarations: Vec<FunctionDeclaration> = tools
+            .into_iter()
+            .map(|tool| FunctionDeclaration {
+                name: tool.name,
+                description: tool.description,
+                parameters: tool.input_schema,
+            })
+            .collect();
+
+        let capabilities = capabilities_for(self.name(), Some(self.model_id()));
+        let fixed_tokens = estimate_text_tokens(&system_prompt)
+            + estimate_text_tokens(&serde_json::to_string(&declarations).unwrap_or_default());
+        let fit = fit_history(&capabilities, history, fixed_tokens, screen_width, screen_height)?;
+
+        let mut contents = Vec::new();
+        // Function responses name the function; it is the call in the turn before
+        let mut last_call: Option<String> = None;
+        for turn in history_to_turns_within(history, &fit) {
+            let role = if turn.role == "assistant" { "model" } else { "user" };
+            let mut parts = Vec::new();
+
+            if let Some(tool_use) = turn.tool_use {
+                if !turn.text.trim().is_empty() {
+                    parts.push(PartData::Text(turn.text).into());
+                }
+                last_call = Some(tool_use.name.clone());
+                parts.push(GeminiPart {
+                    data: PartData::FunctionCall(FunctionCall { name: tool_use.name, args: tool_use.input }),
+                    thought_signature: tool_use.signature,
+                });
+            } else if let Some(result) = turn.tool_result {
+                let key = if result.is_error { "error" } else { "output" };
+                parts.push(
+                    PartData::FunctionResponse(FunctionResponse {
+                        name: last_call.take().unwrap_or_default(),
+                        response: serde_json::json!({ key: result.content }),
+                    })
+                    .into(),
+                );
+            } else if let Some(img_data) = turn.image {
+                parts.push(PartData::InlineData(InlineData { mime_type: "image/png", data: img_data }).into());
+                parts.push(
+                    PartData::Text(format!(
+                        "User instruction: {}\n\nAnalyze the screenshot and respond with a single JSON action.",
+                        turn.text
+                    ))
+                    .into(),
+                );
+            } else {
+                parts.push(PartData::Text(turn.text).into());
+            }
+
+            contents.push(GeminiContent { role: Some(role), parts });
+        }
+
+        let request = GeminiRequest {
+            system_instruction: GeminiContent {
+                role: None,
+                parts: vec![PartData::Text(system_prompt).into()],
+            },
+            contents,
+            tools: vec![GeminiTools { function_declarations: declarations }],
+            generation_config: GenerationConfig {
+                temperature: self.temperature,
+                max_output_tokens: 8192,
+            },
+        };
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/v1beta/models/{}:streamGenerateContent?alt=sse",
+                self.base_url,
+                self.model_id()
+            ))
+            .header("x-goog-api-key", &self.api_key)
+            .json(&request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(LlmError::ApiError(error_text));
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut reply = GeminiStream::default();
+        let mut buffer = String::new();
+
+        while let Some(chunk_result) = stream.next().await {
+            let chunk = chunk_result?;
+            append_bytes_to_buffer(&mut buffer, &chunk);
+
+            // Events are single `data:` lines; partial lines wait for the next chunk
+            while let Some(pos) = buffer.find('\n') {
+                if let Some(data) = buffer[..pos].trim_end_matches('\r').strip_prefix("data: ") {
+                    reply.apply(data, &*on_chunk);
+                }
+                buffer.drain(..pos + 1);
+            }
+        }
+        if let Some(data) = buffer.trim_end().strip_prefix("data: ") {
+            reply.apply(data, &*on_chunk);
+        }
+
+        let metrics = reply.metrics(start.elapsed());
+        Ok((reply.into_response()?, metrics))
+    }
+
+    async fn health_check(&self) -> Result<bool, LlmError> {
+        let response = self
+            .client
+            .get(format!("{}/v1beta/models", self.base_url))
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await?;
+        Ok(response.status().is_success())
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, LlmError> {
+        let response = self
+            .client
+            .get(format!("{}/v1beta/models?pageSize=1000", self.base_url))
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(LlmError::ApiError(format!(
+                "Failed to list models: HTTP {}",
+                response.status()
+            )));
+        }
+        let body: Value = response.json().await.map_err(|e| {
+            LlmError::ParseError(format!("Failed to parse model list: {}", e))
+        })?;
+        // Embedding and other models can't chat; keep the ones that generate content
+        let models = body["models"]
+            .as_array()
+            .map(|arr| {
+                arr.iter()
+                    .filter(|m| {
+                        m["supportedGenerationMethods"]
+                            .as_array()
+                            .is_some_and(|methods| methods.iter().any(|method| method == "generateContent"))
+                    })
+                    .filter_map(|m| m["name"].as_str())
+                    .map(|name| name.strip_prefix("models/").unwrap_or(name).to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(models)
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::test_server::{Reply, TestServer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_streams_function_call_with_images_and_usage() {
+        let server = TestServer::start(vec![Reply::sse(&[
+            json!({"candidates": [{"content": {"role": "model", "parts": [
+                {"text": "Weighing options", "thought": true},
+                {"text": "Opening Notes"}
+            ]}}]}),
+            json!({"candidates": [{"content": {"role": "model", "parts": [
+                {"functionCall": {"name": "click", "args": {"x": 5, "y": 6}}, "thoughtSignature": "c2lnLTI="}
+            ]}, "finishReason": "STOP"}],
+             "usageMetadata": {"promptTokenCount": 1300, "candidatesTokenCount": 20,
+                               "thoughtsTokenCount": 30, "cachedContentTokenCount": 1000}}),
+        ])])
+        .await;
+
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("Open Notes".to_string());
+        history.add_user_message("Open Notes", Some(Arc::new("img".to_string())), None, None);
+        history.add_assistant_response(&LlmResponse::ToolUse {
+            tool_use: ToolUse { id: "call_1".to_string(), name: "key".to_string(), input: json!({"key": "space"}), signature: Some("c2lnLTE=".to_string()) },
+            reasoning: None,
+        });
+        history.add_tool_result(false, None, Some("Spotlight did not open".to_string()));
+        history.add_user_message("now", Some(Arc::new("img2".to_string())), None, None);
+
+        let provider = GeminiProvider::new("gk".to_string(), "models/gemini-2.5-flash".to_string(), Some(0.2))
+            .with_base_url(&format!("{}/", server.url));
+        let (response, metrics) = provider
+            .send_with_history(&history, 1024, 768, Box::new(|_| {}))
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::ToolUse { tool_use, reasoning } => {
+                assert_eq!(tool_use.name, "click");
+                assert_eq!(tool_use.input, json!({"x": 5, "y": 6}));
+                assert_eq!(tool_use.signature.as_deref(), Some("c2lnLTI="));
+                assert_eq!(reasoning.as_deref(), Some("Opening Notes"));
+            }
+            other => panic!("expected a tool call, got {:?}", other),
+        }
+        assert_eq!(
+            (metrics.input_tokens, metrics.output_tokens, metrics.cache_read_tokens),
+            (300, 50, 1000)
+        );
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse");
+        assert_eq!(request.header("x-goog-api-key"), Some("gk"));
+        let body = request.json();
+        assert!(body["systemInstruction"]["parts"][0]["text"].as_str().unwrap().contains("Open Notes"));
+        let declarations = body["tools"][0]["functionDeclarations"].as_array().unwrap();
+        assert!(declarations.iter().any(|d| d["name"] == "click" && d["parameters"]["properties"]["x"].is_object()));
+        assert_eq!(body["generationConfig"]["temperature"].as_f64().map(|t| t as f32), Some(0.2));
+
+        let contents = body["contents"].as_array().unwrap();
+        assert_eq!(contents[0]["parts"][0]["inlineData"], json!({"mimeType": "image/png", "data": "img"}));
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[1]["parts"][0]["functionCall"], json!({"name": "key", "args": {"key": "space"}}));
+        assert_eq!(contents[1]["parts"][0]["thoughtSignature"], "c2lnLTE=");
+        assert!(contents[0]["parts"][0].get("thoughtSignature").is_none());
+        let result = &contents[2]["parts"][0]["functionResponse"];
+        assert_eq!(contents[2]["role"], "user");
+        assert_eq!(result["name"], "key");
+        assert!(result["response"]["error"].as_str().unwrap().contains("Spotlight did not open"));
+        assert_eq!(contents[3]["parts"][0]["inlineData"]["data"], "img2");
+    }
+
+    #[tokio::test]
+    async fn test_blocked_prompt_is_an_error() {
+        let server = TestServer::start(vec![Reply::sse(&[json!({
+            "promptFeedback": {"blockReason": "SAFETY"},
+            "usageMetadata": {"promptTokenCount": 10}
+        })])])
+        .await;
+        let mut history = ConversationHistory::new();
+        history.add_user_message("hello", None, None, None);
+
+        let provider = GeminiProvider::new("gk".to_string(), "gemini-2.5-flash".to_string(), None)
+            .with_base_url(&server.url);
+        let result = provider.send_with_history(&history, 800, 600, Box::new(|_| {})).await;
+        assert!(matches!(result, Err(LlmError::ApiError(message)) if message.contains("SAFETY")));
+    }
+
+    #[tokio::test]
+    async fn test_list_models_keeps_chat_models() {
+        let server = TestServer::start(vec![
+            Reply::json(json!({"models": [
+                {"name": "models/gemini-2.5-pro", "supportedGenerationMethods": ["generateContent", "countTokens"]},
+                {"name": "models/text-embedding-004", "supportedGenerationMethods": ["embedContent"]}
+            ]})),
+            Reply::error(400, r#"{"error": {"message": "API key not valid"}}"#),
+        ])
+        .await;
+        let provider = GeminiProvider::new("gk".to_string(), "gemini-2.5-pro".to_string(), None)
+            .with_base_url(&server.url);
+
+        assert_eq!(provider.list_models().await.unwrap(), vec!["gemini-2.5-pro"]);
+        assert!(!provider.health_check().await.unwrap());
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/v1beta/models?pageSize=1000");
+        assert_eq!(requests[1].path, "/v1beta/models");
+        assert_eq!(requests[1].header("x-goog-api-key"), Some("gk"));
+    }
+}
@@ -0,0 +1,230 @@
+//! Scripted provider for driving the agent loop in tests.
+//!
+//! Each call to `send_with_history` pops the next reply off the script and
+//! records the conversation it was given, so tests can assert both on what the
+//! loop did with a response and on what it sent back to the model.
+
+use super::provider::{ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics, ToolUse};
+use crate::agent::conversation::ConversationHistory;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+enum ScriptedReply {
+    Response(LlmResponse),
+    Error(LlmError),
+}
+
+pub struct ScriptedProvider {
+    script: Mutex<VecDeque<ScriptedReply>>,
+    histories: Mutex<Vec<ConversationHistory>>,
+    input_tokens: u64,
+    output_tokens: u64,
+    next_id: Mutex<u32>,
+}
+
+impl Default for ScriptedProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedProvider {
+    pub fn new() -> Self {
+        Self {
+            script: Mutex::new(VecDeque::new()),
+            histories: Mutex::new(Vec::new()),
+            input_tokens: 10,
+            output_tokens: 5,
+            next_id: Mutex::new(0),
+        }
+    }
+
+    fn push(self, reply: ScriptedReply) -> Self {
+        self.script.lock().unwrap().push_back(reply);
+        self
+    }
+
+    /// Queue a native tool use, e.g. `tool_use("click", json!({"x": 10, "y": 20}))`
+    pub fn tool_use(self, name: &str, input: Value) -> Self {
+        let id = {
+            let mut next = self.next_id.lock().unwrap();
+            *next += 1;
+            format!("toolu_{}", next)
+        };
+        self.push(ScriptedReply::Response(LlmResponse::ToolUse {
+            tool_use: ToolUse {
+                id,
+                name: name.to_string(),
+                input,
+                signature: None,
+            },
+            reasoning: None,
+        }))
+    }
+
+    /// Queue a `complete` tool use
+    pub fn complete(self, message: &str) -> Self {
+        self.tool_use("complete", json!({ "message": message }))
+    }
+
+    /// Queue a raw text response
+    pub fn text(self, text: &str) -> Self {
+        self.push(ScriptedReply::Response(LlmResponse::Text(text.to_string())))
+    }
+
+    /// Queue a text response that does not contain a parseable action
+    pub fn malformed(self) -> Self {
+        self.text("I will click the button {\"action\": \"click\", \"x\": ")
+    }
+
+    /// Queue an error the loop classifies as rate limiting
+    pub fn rate_limited(self) -> Self {
+        self.error(LlmError::ApiError(
+            "429 Too Many Requests: rate limit exceeded".to_string(),
+        ))
+    }
+
+    /// Queue a transient server-side failure with the given status code
+    pub fn server_error(self, status: u16) -> Self {
+        self.error(LlmError::ApiError(format!(
+            "{}: service temporarily unavailable",
+            status
+        )))
+    }
+
+    pub fn error(self, error: LlmError) -> Self {
+        self.push(ScriptedReply::Error(error))
+    }
+
+    /// Token usage reported for every successful response
+    pub fn with_token_usage(mut self, input_tokens: u64, output_tokens: u64) -> Self {
+        self.input_tokens = input_tokens;
+        self.output_tokens = output_tokens;
+        self
+    }
+
+    /// Conversations received so far, in call order
+    pub fn histories(&self) -> Vec<ConversationHistory> {
+        self.histories.lock().unwrap().clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.histories.lock().unwrap().len()
+    }
+
+    /// Number of replies that have not been consumed yet
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ScriptedProvider {
+    async fn send_with_history(
+        &self,
+        history: &ConversationHistory,
+        _screen_width: u32,
+        _screen_height: u32,
+        on_chunk: ChunkCallback,
+    ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
+        self.histories.lock().unwrap().push(history.clone());
+
+        let reply = self.script.lock().unwrap().pop_front();
+        match reply {
+            Some(ScriptedReply::Response(response)) => {
+                on_chunk(&response.to_string_repr());
+                let metrics = TokenMetrics {
+                    input_tokens: self.input_tokens,
+                    output_tokens: self.output_tokens,
+                    total_duration: Duration::from_millis(10),
+                    ..Default::default()
+                };
+                Ok((response, metrics))
+            }
+            Some(ScriptedReply::Error(error)) => Err(error),
+            None => Err(LlmError::ApiError("Script exhausted".to_string())),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "mock"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::recovery::{classify_llm_error, ErrorClassification};
+
+    fn no_chunks() -> ChunkCallback {
+        Box::new(|_| {})
+    }
+
+    #[tokio::test]
+    async fn test_replies_in_order_and_records_history() {
+        let provider = ScriptedProvider::new()
+            .text("first")
+            .complete("done");
+
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("task".to_string());
+        history.add_user_message("task", None, None, None);
+
+        let (first, _) = provider
+            .send_with_history(&history, 100, 100, no_chunks())
+            .await
+            .unwrap();
+        assert!(matches!(first, LlmResponse::Text(ref t) if t == "first"));
+
+        history.add_assistant_message("first");
+        let (second, metrics) = provider
+            .send_with_history(&history, 100, 100, no_chunks())
+            .await
+            .unwrap();
+        match second {
+            LlmResponse::ToolUse { tool_use, .. } => {
+                assert_eq!(tool_use.id, "toolu_1");
+                assert_eq!(tool_use.name, "complete");
+            }
+            other => panic!("expected tool use, got {:?}", other),
+        }
+        assert_eq!(metrics.input_tokens, 10);
+
+        let histories = provider.histories();
+        assert_eq!(histories.len(), 2);
+        assert_eq!(histories[0].len(), 1);
+        assert_eq!(histories[1].len(), 2);
+        assert_eq!(provider.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_error_helpers_classify_as_intended() {
+        let provider = ScriptedProvider::new().rate_limited().server_error(503);
+        let history = ConversationHistory::new();
+
+        let err = provider
+            .send_with_history(&history, 100, 100, no_chunks())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            classify_llm_error(&err),
+            ErrorClassification::RateLimited { .. }
+        ));
+
+        let err = provider
+            .send_with_history(&history, 100, 100, no_chunks())
+            .await
+            .unwrap_err();
+        assert_eq!(classify_llm_error(&err), ErrorClassification::Retryable);
+
+        // An exhausted script fails fatally instead of hanging the loop
+        let err = provider
+            .send_with_history(&history, 100, 100, no_chunks())
+            .await
+            .unwrap_err();
+        assert_eq!(classify_llm_error(&err), ErrorClassification::Fatal);
+    }
+}
@@ -1,13 +1,22 @@
 pub mod anthropic;
+pub mod azure_openai;
+pub mod capabilities;
+pub mod gemini;
 pub mod glm;
+#[cfg(test)]
+pub mod mock;
 pub mod ollama;
 pub mod openai;
 pub mod openai_compatible;
 pub mod openrouter;
 pub mod provider;
 pub mod sse;
+#[cfg(test)]
+pub mod test_server;
 
 pub use anthropic::*;
+pub use azure_openai::*;
+pub use gemini::*;
 pub use glm::*;
 pub use ollama::*;
 pub use openai::*;
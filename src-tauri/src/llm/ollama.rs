@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 
+use super::capabilities::{capabilities_for, estimate_text_tokens, fit_history};
 use super::provider::{
-    build_system_prompt_with_context, history_to_messages, ChunkCallback,
-    LlmError, LlmProvider, LlmResponse, TokenMetrics,
+    build_system_prompt_for_tools_with_context, build_system_prompt_with_context, history_to_turns_within,
+    ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics, ToolUse,
 };
-use super::sse::append_bytes_to_buffer;
+use super::sse::{append_bytes_to_buffer, function_tools, tools_rejected, FunctionTool};
 use serde_json::Value;
 use crate::agent::conversation::ConversationHistory;
 use async_trait::async_trait;
@@ -18,6 +19,8 @@ pub struct OllamaProvider {
     host: String,
     model: String,
     temperature: Option<f32>,
+    /// Whether the model takes `tools`, once `/api/show` has answered
+    tool_support: parking_lot::Mutex<Option<bool>>,
 }
 
 #[derive(Serialize)]
@@ -26,15 +29,29 @@ struct OllamaChatMessage {
     content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall>,
 }
 
 #[derive(Serialize)]
 struct OllamaChatRequest {
     model: String,
     messages: Vec<OllamaChatMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<FunctionTool>,
+    /// `json` constrains models without tools to reply with valid JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
+    options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    /// Context window to load the model with; matches what the request was trimmed to
+    num_ctx: u32,
 }
 
 #[derive(Deserialize)]
@@ -52,6 +69,87 @@ struct OllamaChatMessageResponse {
     #[allow(dead_code)]
     role: Option<String>,
     content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+/// Ollama sends each tool call whole, with the arguments already parsed
+#[derive(Serialize, Deserialize)]
+struct OllamaToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    function: OllamaFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+/// What has arrived so far of a streamed `/api/chat` reply
+#[derive(Default)]
+struct ChatStream {
+    text: String,
+    tool_use: Option<ToolUse>,
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl ChatStream {
+    /// Apply one line of the newline-delimited JSON stream
+    fn apply(&mut self, line: &str, on_chunk: &dyn Fn(&str)) {
+        let Ok(parsed) = serde_json::from_str::<OllamaChatStreamResponse>(line) else {
+            return;
+        };
+        if let Some(msg) = parsed.message {
+            if let Some(content) = msg.content.filter(|c| !c.is_empty()) {
+                self.text.push_str(&content);
+                on_chunk(&content);
+            }
+            // One action per step; later calls in the same reply are dropped
+            if let Some(call) = msg.tool_calls.into_iter().next().filter(|_| self.tool_use.is_none()) {
+                on_chunk(&format!("[Using tool: {}]", call.function.name));
+                let input = match call.function.arguments {
+                    Value::Object(_) => call.function.arguments,
+                    // Some models send the arguments as a JSON string
+                    Value::String(text) => serde_json::from_str(&text).unwrap_or_else(|_| serde_json::json!({})),
+                    _ => serde_json::json!({}),
+                };
+                self.tool_use = Some(ToolUse {
+                    id: call.id.unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple())),
+                    name: call.function.name,
+                    input,
+                    signature: None,
+                });
+            }
+        }
+        if parsed.done {
+            self.output_tokens = parsed.eval_count.unwrap_or(0);
+            self.input_tokens = parsed.prompt_eval_count.unwrap_or(0);
+        }
+    }
+
+    fn into_response(self) -> LlmResponse {
+        match self.tool_use {
+            Some(tool_use) => {
+                let reasoning = if self.text.trim().is_empty() { None } else { Some(self.text) };
+                LlmResponse::ToolUse { tool_use, reasoning }
+            }
+            None => LlmResponse::Text(self.text),
+        }
+    }
+}
+
+/// Whether an `/api/show` reply describes a model that can call tools.
+/// Newer servers list `capabilities`; older ones only show the chat
+/// template, which mentions `.Tools` when the model takes them.
+fn shows_tool_support(show: &Value) -> bool {
+    match show["capabilities"].as_array() {
+        Some(capabilities) => capabilities.iter().any(|c| c == "tools"),
+        None => show["template"].as_str().is_some_and(|t| t.contains(".Tools")),
+    }
 }
 
 impl OllamaProvider {
@@ -61,6 +159,7 @@ impl OllamaProvider {
             host,
             model,
             temperature,
+            tool_support: parking_lot::Mutex::new(None),
         }
     }
 
@@ -75,6 +174,7 @@ impl OllamaProvider {
             host,
             model,
             temperature,
+            tool_support: parking_lot::Mutex::new(None),
         }
     }
 
@@ -82,27 +182,77 @@ impl OllamaProvider {
         self.temperature = temperature;
         self
     }
-}
 
-#[async_trait]
-impl LlmProvider for OllamaProvider {
-    async fn send_with_history(
+    /// Whether the model takes `tools`, asking `/api/show` until it answers.
+    /// Only an answer is kept: when the server can't be asked, this request
+    /// gets JSON mode and the next one asks again.
+    async fn tool_support(&self) -> bool {
+        if let Some(supported) = *self.tool_support.lock() {
+            return supported;
+        }
+        let response = self
+            .client
+            .post(format!("{}/api/show", self.host))
+            .json(&serde_json::json!({ "model": self.model }))
+            .send()
+            .await;
+        let show = match response {
+            Ok(response) if response.status().is_success() => response.json::<Value>().await,
+            Ok(response) => {
+                log::warn!("Ollama /api/show failed for {}: HTTP {}", self.model, response.status());
+                return false;
+            }
+            Err(e) => {
+                log::warn!("Ollama /api/show failed for {}: {}", self.model, e);
+                return false;
+            }
+        };
+        match show {
+            Ok(show) => {
+                let supported = shows_tool_support(&show);
+                *self.tool_support.lock() = Some(supported);
+                supported
+            }
+            Err(e) => {
+                log::warn!("Ollama /api/show reply for {} could not be read: {}", self.model, e);
+                false
+            }
+        }
+    }
+
+    /// Build and send the chat request: with the action tools when `use_tools`
+    /// is set, otherwise asking for JSON output
+    async fn post_chat(
         &self,
         history: &ConversationHistory,
         screen_width: u32,
         screen_height: u32,
-        on_chunk: ChunkCallback,
-    ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
-        let start = Instant::now();
+        use_tools: bool,
+    ) -> Result<reqwest::Response, LlmError> {
         let instruction = history.original_instruction().map(|s| s.to_string());
-        let system_prompt = build_system_prompt_with_context(
+        let build_prompt = if use_tools {
+            build_system_prompt_for_tools_with_context
+        } else {
+            build_system_prompt_with_context
+        };
+        let system_prompt = build_prompt(
             screen_width,
             screen_height,
             instruction.as_deref(),
             history.iteration,
             history.max_iterations,
+            history.plan(),
+            history.progress_note(),
         );
 
+        let tools = if use_tools { function_tools(history.plan().is_some()) } else { Vec::new() };
+
+        // Trim what is sent to what the model's context window can take
+        let capabilities = capabilities_for(self.name(), Some(&self.model));
+        let fixed_tokens = estimate_text_tokens(&system_prompt)
+            + estimate_text_tokens(&serde_json::to_string(&tools).unwrap_or_default());
+        let fit = fit_history(&capabilities, history, fixed_tokens, screen_width, screen_height)?;
+
         let mut messages = Vec::new();
 
         // System message
@@ -110,10 +260,37 @@ impl LlmProvider for OllamaProvider {
             role: "system".to_string(),
             content: system_prompt,
             images: None,
+            tool_calls: Vec::new(),
         });
 
         // Convert conversation history to chat messages
-        for (role, text, image_base64) in history_to_messages(history) {
+        for turn in history_to_turns_within(history, &fit) {
+            // With tools on, tool calls and their results go back as such
+            if use_tools {
+                if let Some(tool_use) = turn.tool_use {
+                    messages.push(OllamaChatMessage {
+                        role: turn.role,
+                        content: turn.text,
+                        images: None,
+                        tool_calls: vec![OllamaToolCall {
+                            id: Some(tool_use.id),
+                            function: OllamaFunctionCall { name: tool_use.name, arguments: tool_use.input },
+                        }],
+                    });
+                    continue;
+                }
+                if let Some(result) = turn.tool_result {
+                    messages.push(OllamaChatMessage {
+                        role: "tool".to_string(),
+                        content: result.content,
+                        images: None,
+                        tool_calls: Vec::new(),
+                    });
+                    continue;
+                }
+            }
+
+            let (role, text, image_base64) = (turn.role.clone(), turn.flat_text(), turn.image);
             let (content, images) = if let Some(img_data) = image_base64 {
                 (
                     format!("[Screenshot attached]\n{}\n\nAnalyze the screenshot and respond with a single JSON action.", text),
@@ -127,22 +304,55 @@ impl LlmProvider for OllamaProvider {
                 role,
                 content,
                 images,
+                tool_calls: Vec::new(),
             });
         }
 
         let request = OllamaChatRequest {
             model: self.model.clone(),
             messages,
+            tools,
+            format: (!use_tools).then_some("json"),
             stream: true,
             temperature: self.temperature,
+            options: OllamaOptions {
+                num_ctx: capabilities.context_tokens,
+            },
         };
 
-        let response = self
+        Ok(self
             .client
             .post(format!("{}/api/chat", self.host))
             .json(&request)
             .send()
-            .await?;
+            .await?)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    async fn send_with_history(
+        &self,
+        history: &ConversationHistory,
+        screen_width: u32,
+        screen_height: u32,
+        on_chunk: ChunkCallback,
+    ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
+        let start = Instant::now();
+        let use_tools = self.tool_support().await;
+        let mut response = self.post_chat(history, screen_width, screen_height, use_tools).await?;
+
+        // If the server refuses tools after all, use JSON mode from now on
+        if use_tools && !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_default();
+            if !tools_rejected(status, &error_text) {
+                return Err(LlmError::ApiError(error_text));
+            }
+            log::warn!("Ollama rejected tools for {}, asking for JSON instead: {}", self.model, error_text);
+            *self.tool_support.lock() = Some(false);
+            response = self.post_chat(history, screen_width, screen_height, false).await?;
+        }
 
         if !response.status().is_success() {
             let error_text = response.text().await.unwrap_or_default();
@@ -150,10 +360,7 @@ impl LlmProvider for OllamaProvider {
         }
 
         let mut stream = response.bytes_stream();
-        // Pre-allocate response buffer with typical response size (~4KB)
-        let mut full_response = String::with_capacity(4096);
-        let mut output_tokens = 0u64;
-        let mut input_tokens = 0u64;
+        let mut reply = ChatStream::default();
         let mut buffer = String::new();
 
         while let Some(chunk_result) = stream.next().await {
@@ -163,25 +370,9 @@ impl LlmProvider for OllamaProvider {
             // Process complete newline-delimited JSON lines; partial lines
             // are left in the buffer for the next chunk.
             while let Some(pos) = buffer.find('\n') {
-                {
-                    let line = &buffer[..pos];
-                    if !line.is_empty() {
-                        if let Ok(parsed) = serde_json::from_str::<OllamaChatStreamResponse>(line) {
-                            if let Some(msg) = &parsed.message {
-                                if let Some(content) = &msg.content {
-                                    if !content.is_empty() {
-                                        full_response.push_str(content);
-                                        on_chunk(content);
-                                    }
-                                }
-                            }
-
-                            if parsed.done {
-                                output_tokens = parsed.eval_count.unwrap_or(0);
-                                input_tokens = parsed.prompt_eval_count.unwrap_or(0);
-                            }
-                        }
-                    }
+                let line = &buffer[..pos];
+                if !line.is_empty() {
+                    reply.apply(line, &*on_chunk);
                 }
                 buffer.drain(..pos + 1);
             }
@@ -189,30 +380,17 @@ impl LlmProvider for OllamaProvider {
 
         // Process any trailing data left in the buffer (no final newline)
         if !buffer.is_empty() {
-            if let Ok(parsed) = serde_json::from_str::<OllamaChatStreamResponse>(&buffer) {
-                if let Some(msg) = &parsed.message {
-                    if let Some(content) = &msg.content {
-                        if !content.is_empty() {
-                            full_response.push_str(content);
-                            on_chunk(content);
-                        }
-                    }
-                }
-
-                if parsed.done {
-                    output_tokens = parsed.eval_count.unwrap_or(0);
-                    input_tokens = parsed.prompt_eval_count.unwrap_or(0);
-                }
-            }
+            reply.apply(&buffer, &*on_chunk);
         }
 
         let metrics = TokenMetrics {
-            input_tokens,
-            output_tokens,
+            input_tokens: reply.input_tokens,
+            output_tokens: reply.output_tokens,
             total_duration: start.elapsed(),
+            ..Default::default()
         };
 
-        Ok((LlmResponse::Text(full_response), metrics))
+        Ok((reply.into_response(), metrics))
     }
 
     async fn health_check(&self) -> Result<bool, LlmError> {
@@ -244,15 +422,26 @@ impl LlmProvider for OllamaProvider {
         Ok(models)
     }
 
+    fn supports_tools(&self) -> bool {
+        *self.tool_support.lock() == Some(true)
+    }
+
     fn name(&self) -> &str {
         "ollama"
     }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::llm::provider::history_to_messages;
+    use crate::llm::test_server::{Reply, TestServer};
     use serde_json::json;
+    use std::sync::Arc;
 
     #[test]
     fn test_chat_message_serialization_without_images() {
@@ -260,6 +449,7 @@ mod tests {
             role: "user".to_string(),
             content: "Hello".to_string(),
             images: None,
+            tool_calls: Vec::new(),
         };
         let json = serde_json::to_value(&msg).unwrap();
         assert_eq!(json["role"], "user");
@@ -273,6 +463,7 @@ mod tests {
             role: "user".to_string(),
             content: "Describe this".to_string(),
             images: Some(vec!["base64data".to_string()]),
+            tool_calls: Vec::new(),
         };
         let json = serde_json::to_value(&msg).unwrap();
         assert_eq!(json["role"], "user");
@@ -289,18 +480,26 @@ mod tests {
                     role: "system".to_string(),
                     content: "You are a helper.".to_string(),
                     images: None,
+                    tool_calls: Vec::new(),
                 },
                 OllamaChatMessage {
                     role: "user".to_string(),
                     content: "Click the button".to_string(),
                     images: Some(vec!["screenshot_data".to_string()]),
+                    tool_calls: Vec::new(),
                 },
             ],
+            tools: Vec::new(),
+            format: Some("json"),
             stream: true,
             temperature: None,
+            options: OllamaOptions { num_ctx: 8192 },
         };
         let json = serde_json::to_value(&request).unwrap();
         assert_eq!(json["model"], "llava");
+        assert_eq!(json["options"]["num_ctx"], 8192);
+        assert_eq!(json["format"], "json");
+        assert!(json.get("tools").is_none());
         assert_eq!(json["stream"], true);
         assert_eq!(json["messages"].as_array().unwrap().len(), 2);
         assert_eq!(json["messages"][0]["role"], "system");
@@ -376,6 +575,7 @@ mod tests {
             role: "system".to_string(),
             content: "System prompt".to_string(),
             images: None,
+            tool_calls: Vec::new(),
         });
 
         for (role, text, image_base64) in raw_messages {
@@ -387,7 +587,7 @@ mod tests {
             } else {
                 (text, None)
             };
-            chat_messages.push(OllamaChatMessage { role, content, images });
+            chat_messages.push(OllamaChatMessage { role, content, images, tool_calls: Vec::new() });
         }
 
         // 1 system + 4 conversation messages
@@ -415,6 +615,125 @@ mod tests {
         assert!(chat_messages[4].images.is_none());
     }
 
+    #[test]
+    fn test_shows_tool_support() {
+        assert!(shows_tool_support(&json!({"capabilities": ["completion", "vision", "tools"]})));
+        assert!(!shows_tool_support(&json!({"capabilities": ["completion", "vision"]})));
+        // Servers from before `capabilities` only have the template to go on
+        assert!(shows_tool_support(&json!({"template": "{{- if .Tools }}tools{{ end }}"})));
+        assert!(!shows_tool_support(&json!({"template": "{{ .Prompt }}"})));
+    }
+
+    fn screenshot_history() -> ConversationHistory {
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("Open Notes".to_string());
+        history.add_user_message("Open Notes", Some(Arc::new("img".to_string())), None, None);
+        history
+    }
+
+    #[tokio::test]
+    async fn test_tool_capable_model_gets_tools() {
+        let server = TestServer::start(vec![
+            Reply::json(json!({"capabilities": ["completion", "vision", "tools"]})),
+            Reply::ndjson(&[
+                json!({"message": {"role": "assistant", "content": "",
+                    "tool_calls": [{"function": {"name": "click", "arguments": {"x": 10, "y": 20}}}]}, "done": false}),
+                json!({"message": {"role": "assistant", "content": ""}, "done": true,
+                    "prompt_eval_count": 300, "eval_count": 15}),
+            ]),
+        ])
+        .await;
+        let provider = OllamaProvider::new(server.url.clone(), "qwen2.5vl".to_string(), None);
+
+        let (response, metrics) = provider
+            .send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::ToolUse { tool_use, reasoning } => {
+                assert_eq!(tool_use.name, "click");
+                assert_eq!(tool_use.input, json!({"x": 10, "y": 20}));
+                assert!(!tool_use.id.is_empty());
+                assert!(reasoning.is_none());
+            }
+            other => panic!("expected a tool call, got {:?}", other),
+        }
+        assert_eq!((metrics.input_tokens, metrics.output_tokens), (300, 15));
+        assert!(provider.supports_tools());
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/api/show");
+        assert_eq!(requests[0].json()["model"], "qwen2.5vl");
+        let chat = requests[1].json();
+        assert_eq!(requests[1].path, "/api/chat");
+        assert_eq!(chat["tools"][0]["function"]["name"], "click");
+        assert!(chat.get("format").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_model_without_tools_gets_json_mode() {
+        let text_reply = || {
+            Reply::ndjson(&[json!({"message": {"role": "assistant", "content": "{\"action\": \"wait\"}"}, "done": true})])
+        };
+        let server = TestServer::start(vec![
+            Reply::json(json!({"capabilities": ["completion", "vision"]})),
+            text_reply(),
+            text_reply(),
+        ])
+        .await;
+        let provider = OllamaProvider::new(server.url.clone(), "llava".to_string(), None);
+
+        for _ in 0..2 {
+            let (response, _) = provider
+                .send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {}))
+                .await
+                .unwrap();
+            assert!(matches!(response, LlmResponse::Text(ref text) if text.contains("wait")));
+        }
+
+        // Support is looked up once per provider
+        let requests = server.requests();
+        let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["/api/show", "/api/chat", "/api/chat"]);
+        let chat = requests[1].json();
+        assert_eq!(chat["format"], "json");
+        assert!(chat.get("tools").is_none());
+        assert!(!provider.supports_tools());
+    }
+
+    #[tokio::test]
+    async fn test_failed_show_is_asked_again() {
+        let server = TestServer::start(vec![
+            Reply::error(500, r#"{"error": "model is loading"}"#),
+            Reply::ndjson(&[json!({"message": {"role": "assistant", "content": "{\"action\": \"wait\"}"}, "done": true})]),
+            Reply::json(json!({"capabilities": ["completion", "vision", "tools"]})),
+            Reply::ndjson(&[json!({"message": {"role": "assistant", "content": "",
+                "tool_calls": [{"function": {"name": "wait", "arguments": {}}}]}, "done": true})]),
+        ])
+        .await;
+        let provider = OllamaProvider::new(server.url.clone(), "qwen2.5vl".to_string(), None);
+
+        let (response, _) = provider
+            .send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+        assert!(matches!(response, LlmResponse::Text(_)));
+        assert!(!provider.supports_tools());
+
+        let (response, _) = provider
+            .send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+        assert!(matches!(response, LlmResponse::ToolUse { .. }));
+        assert!(provider.supports_tools());
+
+        let requests = server.requests();
+        let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["/api/show", "/api/chat", "/api/show", "/api/chat"]);
+        assert_eq!(requests[1].json()["format"], "json");
+        assert!(requests[3].json()["tools"].is_array());
+    }
+
     #[test]
     fn test_provider_name() {
         let provider = OllamaProvider::new("http://localhost:11434".to_string(), "llava".to_string(), None);
@@ -1,66 +1,19 @@
 #![allow(dead_code)]
 
-use super::provider::{
-    build_system_prompt_with_context, history_to_messages, ChunkCallback,
-    LlmError, LlmProvider, LlmResponse, TokenMetrics,
-};
-use super::sse::{append_bytes_to_buffer, process_sse_buffer};
+use super::provider::{ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics};
+use super::sse::{send_chat, ChatEndpoint, ToolSupport};
 use serde_json::Value;
 use crate::agent::conversation::ConversationHistory;
 use async_trait::async_trait;
-use futures::StreamExt;
 use reqwest::Client;
-use serde::Serialize;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 pub struct OpenAIProvider {
     client: Client,
     api_key: String,
     model: String,
     temperature: Option<f32>,
-}
-
-#[derive(Serialize)]
-struct OpenAIRequest {
-    model: String,
-    max_tokens: u32,
-    messages: Vec<OpenAIMessage>,
-    stream: bool,
-    stream_options: StreamOptions,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-}
-
-#[derive(Serialize)]
-struct StreamOptions {
-    include_usage: bool,
-}
-
-#[derive(Serialize)]
-struct OpenAIMessage {
-    role: String,
-    content: OpenAIContent,
-}
-
-#[derive(Serialize)]
-#[serde(untagged)]
-enum OpenAIContent {
-    Text(String),
-    Parts(Vec<OpenAIPart>),
-}
-
-#[derive(Serialize)]
-#[serde(tag = "type")]
-enum OpenAIPart {
-    #[serde(rename = "text")]
-    Text { text: String },
-    #[serde(rename = "image_url")]
-    ImageUrl { image_url: ImageUrl },
-}
-
-#[derive(Serialize)]
-struct ImageUrl {
-    url: String,
+    tools: ToolSupport,
 }
 
 impl OpenAIProvider {
@@ -70,6 +23,7 @@ impl OpenAIProvider {
             api_key,
             model,
             temperature,
+            tools: ToolSupport::default(),
         }
     }
 
@@ -84,6 +38,7 @@ impl OpenAIProvider {
             api_key,
             model,
             temperature,
+            tools: ToolSupport::default(),
         }
     }
 
@@ -102,96 +57,20 @@ impl LlmProvider for OpenAIProvider {
         screen_height: u32,
         on_chunk: ChunkCallback,
     ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
-        let start = Instant::now();
-        let instruction = history.original_instruction().map(|s| s.to_string());
-        let system_prompt = build_system_prompt_with_context(
-            screen_width,
-            screen_height,
-            instruction.as_deref(),
-            history.iteration,
-            history.max_iterations,
-        );
-
-        // Build messages from conversation history
-        let mut messages = vec![OpenAIMessage {
-            role: "system".to_string(),
-            content: OpenAIContent::Text(system_prompt),
-        }];
-
-        for (role, text, image_base64) in history_to_messages(history) {
-            let content = if let Some(img_data) = image_base64 {
-                OpenAIContent::Parts(vec![
-                    OpenAIPart::ImageUrl {
-                        image_url: ImageUrl {
-                            url: format!("data:image/png;base64,{}", &*img_data),
-                        },
-                    },
-                    OpenAIPart::Text {
-                        text: format!(
-                            "User instruction: {}\n\nAnalyze the screenshot and respond with a single JSON action.",
-                            text
-                        ),
-                    },
-                ])
-            } else {
-                OpenAIContent::Text(text)
-            };
-
-            messages.push(OpenAIMessage { role, content });
-        }
-
-        let request = OpenAIRequest {
-            model: self.model.clone(),
-            max_tokens: 1024,
-            messages,
-            stream: true,
-            stream_options: StreamOptions {
-                include_usage: true,
-            },
+        let endpoint = ChatEndpoint {
+            provider: self.name(),
+            model: &self.model,
+            model_in_url: false,
             temperature: self.temperature,
+            include_usage: true,
+            tools: &self.tools,
         };
-
-        let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(LlmError::ApiError(error_text));
-        }
-
-        let mut stream = response.bytes_stream();
-        // Pre-allocate response buffer with typical response size (~4KB)
-        let mut full_response = String::with_capacity(4096);
-        let mut input_tokens = 0u64;
-        let mut output_tokens = 0u64;
-        let mut buffer = String::new();
-
-        while let Some(chunk_result) = stream.next().await {
-            let chunk = chunk_result?;
-            append_bytes_to_buffer(&mut buffer, &chunk);
-
-            let result = process_sse_buffer(&mut buffer, &mut full_response, &*on_chunk);
-            if let Some(t) = result.input_tokens {
-                input_tokens = t;
-            }
-            if let Some(t) = result.output_tokens {
-                output_tokens = t;
-            }
-        }
-
-        let metrics = TokenMetrics {
-            input_tokens,
-            output_tokens,
-            total_duration: start.elapsed(),
-        };
-
-        Ok((LlmResponse::Text(full_response), metrics))
+        send_chat(endpoint, history, screen_width, screen_height, on_chunk, || {
+            self.client
+                .post("https://api.openai.com/v1/chat/completions")
+                .header("Authorization", format!("Bearer {}", self.api_key))
+        })
+        .await
     }
 
     async fn health_check(&self) -> Result<bool, LlmError> {
@@ -231,7 +110,15 @@ impl LlmProvider for OpenAIProvider {
         Ok(models)
     }
 
+    fn supports_tools(&self) -> bool {
+        self.tools.available()
+    }
+
     fn name(&self) -> &str {
         "openai"
     }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
 }
@@ -1,16 +1,11 @@
 #![allow(dead_code)]
 
-use super::provider::{
-    build_system_prompt_with_context, history_to_messages, ChunkCallback,
-    LlmError, LlmProvider, LlmResponse, TokenMetrics,
-};
+use super::provider::{ChunkCallback, LlmError, LlmProvider, LlmResponse, TokenMetrics};
+use super::sse::{send_chat, ChatEndpoint, ToolSupport};
 use serde_json::Value;
 use crate::agent::conversation::ConversationHistory;
 use async_trait::async_trait;
-use futures::StreamExt;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use std::time::Instant;
 
 pub struct OpenAICompatibleProvider {
     client: Client,
@@ -18,66 +13,7 @@ pub struct OpenAICompatibleProvider {
     api_key: Option<String>,
     model: String,
     temperature: Option<f32>,
-}
-
-#[derive(Serialize)]
-struct ChatRequest {
-    model: String,
-    max_tokens: u32,
-    messages: Vec<ChatMessage>,
-    stream: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-}
-
-#[derive(Serialize)]
-struct ChatMessage {
-    role: String,
-    content: ChatContent,
-}
-
-#[derive(Serialize)]
-#[serde(untagged)]
-enum ChatContent {
-    Text(String),
-    Parts(Vec<ChatPart>),
-}
-
-#[derive(Serialize)]
-#[serde(tag = "type")]
-enum ChatPart {
-    #[serde(rename = "text")]
-    Text { text: String },
-    #[serde(rename = "image_url")]
-    ImageUrl { image_url: ImageUrl },
-}
-
-#[derive(Serialize)]
-struct ImageUrl {
-    url: String,
-}
-
-#[derive(Deserialize)]
-struct StreamChunk {
-    choices: Vec<StreamChoice>,
-    #[serde(default)]
-    usage: Option<UsageInfo>,
-}
-
-#[derive(Deserialize)]
-struct StreamChoice {
-    delta: Option<DeltaContent>,
-}
-
-#[derive(Deserialize)]
-struct DeltaContent {
-    content: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct UsageInfo {
-    prompt_tokens: Option<u64>,
-    completion_tokens: Option<u64>,
+    tools: ToolSupport,
 }
 
 impl OpenAICompatibleProvider {
@@ -90,6 +26,7 @@ impl OpenAICompatibleProvider {
             api_key,
             model,
             temperature,
+            tools: ToolSupport::default(),
         }
     }
 
@@ -108,109 +45,23 @@ impl LlmProvider for OpenAICompatibleProvider {
         screen_height: u32,
         on_chunk: ChunkCallback,
     ) -> Result<(LlmResponse, TokenMetrics), LlmError> {
-        let start = Instant::now();
-        let instruction = history.original_instruction().map(|s| s.to_string());
-        let system_prompt = build_system_prompt_with_context(
-            screen_width,
-            screen_height,
-            instruction.as_deref(),
-            history.iteration,
-            history.max_iterations,
-        );
-
-        let mut messages = vec![ChatMessage {
-            role: "system".to_string(),
-            content: ChatContent::Text(system_prompt),
-        }];
-
-        for (role, text, image_base64) in history_to_messages(history) {
-            let content = if let Some(img_data) = image_base64 {
-                ChatContent::Parts(vec![
-                    ChatPart::ImageUrl {
-                        image_url: ImageUrl {
-                            url: format!("data:image/png;base64,{}", img_data),
-                        },
-                    },
-                    ChatPart::Text {
-                        text: format!(
-                            "User instruction: {}\n\nAnalyze the screenshot and respond with a single JSON action.",
-                            text
-                        ),
-                    },
-                ])
-            } else {
-                ChatContent::Text(text)
-            };
-
-            messages.push(ChatMessage { role, content });
-        }
-
-        let request = ChatRequest {
-            model: self.model.clone(),
-            max_tokens: 1024,
-            messages,
-            stream: true,
+        let endpoint = ChatEndpoint {
+            provider: self.name(),
+            model: &self.model,
+            model_in_url: false,
             temperature: self.temperature,
+            include_usage: false,
+            tools: &self.tools,
         };
-
-        let url = format!("{}/v1/chat/completions", self.base_url);
-        let mut req_builder = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json");
-
-        if let Some(ref api_key) = self.api_key {
-            req_builder = req_builder.header("Authorization", format!("Bearer {}", api_key));
-        }
-
-        let response = req_builder.json(&request).send().await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(LlmError::ApiError(error_text));
-        }
-
-        let mut stream = response.bytes_stream();
-        let mut full_response = String::with_capacity(4096);
-        let mut input_tokens = 0u64;
-        let mut output_tokens = 0u64;
-        let mut buffer = String::new();
-
-        while let Some(chunk_result) = stream.next().await {
-            let chunk = chunk_result?;
-            buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-            while let Some(pos) = buffer.find('\n') {
-                if let Some(data) = buffer[..pos].strip_prefix("data: ") {
-                    if data != "[DONE]" {
-                        if let Ok(chunk) = serde_json::from_str::<StreamChunk>(data) {
-                            for choice in chunk.choices {
-                                if let Some(delta) = choice.delta {
-                                    if let Some(content) = delta.content {
-                                        full_response.push_str(&content);
-                                        on_chunk(&content);
-                                    }
-                                }
-                            }
-
-                            if let Some(usage) = chunk.usage {
-                                input_tokens = usage.prompt_tokens.unwrap_or(0);
-                                output_tokens = usage.completion_tokens.unwrap_or(0);
-                            }
-                        }
-                    }
-                }
-                buffer.drain(..pos + 1);
+        send_chat(endpoint, history, screen_width, screen_height, on_chunk, || {
+            let url = format!("{}/v1/chat/completions", self.base_url);
+            let request = self.client.post(url);
+            match &self.api_key {
+                Some(api_key) => request.header("Authorization", format!("Bearer {}", api_key)),
+                None => request,
             }
-        }
-
-        let metrics = TokenMetrics {
-            input_tokens,
-            output_tokens,
-            total_duration: start.elapsed(),
-        };
-
-        Ok((LlmResponse::Text(full_response), metrics))
+        })
+        .await
     }
 
     async fn health_check(&self) -> Result<bool, LlmError> {
@@ -250,14 +101,27 @@ impl LlmProvider for OpenAICompatibleProvider {
         Ok(models)
     }
 
+    fn supports_tools(&self) -> bool {
+        self.tools.available()
+    }
+
     fn name(&self) -> &str {
         "openai-compatible"
     }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::llm::provider::ToolUse;
+    use crate::llm::sse::SseStreamChunk;
+    use crate::llm::test_server::{Reply, TestServer};
+    use serde_json::json;
+    use std::sync::Arc;
 
     #[test]
     fn test_new_strips_trailing_slash() {
@@ -304,51 +168,124 @@ mod tests {
         assert_eq!(provider.name(), "openai-compatible");
     }
 
-    #[test]
-    fn test_chat_request_serialization() {
-        let request = ChatRequest {
-            model: "test-model".to_string(),
-            max_tokens: 1024,
-            messages: vec![ChatMessage {
-                role: "system".to_string(),
-                content: ChatContent::Text("Hello".to_string()),
-            }],
-            stream: true,
-            temperature: None,
-        };
+    fn screenshot_history() -> ConversationHistory {
+        let mut history = ConversationHistory::new();
+        history.set_original_instruction("Open Notes".to_string());
+        history.add_user_message("Open Notes", Some(Arc::new("img".to_string())), None, None);
+        history
+    }
 
-        let json = serde_json::to_value(&request).unwrap();
-        assert_eq!(json["model"], "test-model");
-        assert_eq!(json["max_tokens"], 1024);
-        assert_eq!(json["stream"], true);
-        assert_eq!(json["messages"][0]["role"], "system");
-        assert_eq!(json["messages"][0]["content"], "Hello");
+    fn tool_call_stream() -> Reply {
+        Reply::sse(&[
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "type": "function",
+                "function": {"name": "click", "arguments": "{\"x\": 3,"}}]}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": " \"y\": 4}"}}]}}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 12, "completion_tokens": 7}}),
+        ])
     }
 
-    #[test]
-    fn test_multipart_content_serialization() {
-        let content = ChatContent::Parts(vec![
-            ChatPart::ImageUrl {
-                image_url: ImageUrl {
-                    url: "data:image/png;base64,abc123".to_string(),
-                },
-            },
-            ChatPart::Text {
-                text: "Describe this image".to_string(),
-            },
-        ]);
-
-        let json = serde_json::to_value(&content).unwrap();
-        assert_eq!(json[0]["type"], "image_url");
-        assert_eq!(json[0]["image_url"]["url"], "data:image/png;base64,abc123");
-        assert_eq!(json[1]["type"], "text");
-        assert_eq!(json[1]["text"], "Describe this image");
+    #[tokio::test]
+    async fn test_sends_tools_and_returns_tool_use() {
+        let server = TestServer::start(vec![tool_call_stream()]).await;
+        let provider = OpenAICompatibleProvider::new(server.url.clone(), Some("sk".to_string()), "model".to_string(), None);
+
+        let (response, metrics) = provider
+            .send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::ToolUse { tool_use, .. } => {
+                assert_eq!(tool_use.name, "click");
+                assert_eq!(tool_use.input, json!({"x": 3, "y": 4}));
+            }
+            other => panic!("expected a tool call, got {:?}", other),
+        }
+        assert_eq!((metrics.input_tokens, metrics.output_tokens), (12, 7));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/v1/chat/completions");
+        assert_eq!(request.header("authorization"), Some("Bearer sk"));
+        let body = request.json();
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "click");
+        assert!(body["tools"][0]["function"]["parameters"]["properties"]["x"].is_object());
+        // Plan tools only come with a plan
+        assert!(!body["tools"].as_array().unwrap().iter().any(|t| t["function"]["name"] == "step_done"));
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_and_results_are_sent_back_natively() {
+        let server = TestServer::start(vec![tool_call_stream()]).await;
+        let provider = OpenAICompatibleProvider::new(server.url.clone(), None, "model".to_string(), None);
+
+        let mut history = screenshot_history();
+        history.add_assistant_response(&LlmResponse::ToolUse {
+            tool_use: ToolUse { id: "call_0".to_string(), name: "click".to_string(), input: json!({"x": 1, "y": 2}), signature: None },
+            reasoning: None,
+        });
+        history.add_tool_result(true, Some("Clicked".to_string()), None);
+        history.add_user_message("now", Some(Arc::new("img".to_string())), None, None);
+        provider
+            .send_with_history(&history, 800, 600, Box::new(|_| {}))
+            .await
+            .unwrap();
+
+        let body = server.requests()[0].json();
+        let messages = body["messages"].as_array().unwrap();
+        let call = &messages[2]["tool_calls"][0];
+        assert_eq!(messages[2]["role"], "assistant");
+        assert_eq!(call["id"], "call_0");
+        assert_eq!(call["function"]["name"], "click");
+        let arguments: serde_json::Value = serde_json::from_str(call["function"]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(arguments, json!({"x": 1, "y": 2}));
+
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(messages[3]["tool_call_id"], "call_0");
+        assert!(messages[3]["content"].as_str().unwrap().contains("Clicked"));
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_text_when_tools_are_rejected() {
+        let text_reply = || Reply::sse(&[json!({"choices": [{"delta": {"content": "{\"action\": \"wait\"}"}}]})]);
+        let server = TestServer::start(vec![
+            Reply::error(400, r#"{"error": {"message": "This model does not support tools"}}"#),
+            text_reply(),
+            text_reply(),
+        ])
+        .await;
+        let provider = OpenAICompatibleProvider::new(server.url.clone(), None, "model".to_string(), None);
+
+        for _ in 0..2 {
+            let (response, _) = provider
+                .send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {}))
+                .await
+                .unwrap();
+            assert!(matches!(response, LlmResponse::Text(ref text) if text.contains("wait")));
+        }
+
+        // Retried without tools, and the requests after it don't offer them either
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].json().get("tools").is_some());
+        assert!(requests[1..].iter().all(|r| r.json().get("tools").is_none()));
+        assert!(requests[1].json()["messages"][0]["content"].as_str().unwrap().contains("JSON"));
+        assert!(!provider.supports_tools());
+    }
+
+    #[tokio::test]
+    async fn test_api_errors_unrelated_to_tools_are_returned() {
+        let server = TestServer::start(vec![Reply::error(401, "Invalid API key")]).await;
+        let provider = OpenAICompatibleProvider::new(server.url.clone(), None, "model".to_string(), None);
+
+        let result = provider.send_with_history(&screenshot_history(), 800, 600, Box::new(|_| {})).await;
+        assert!(matches!(result, Err(LlmError::ApiError(ref text)) if text == "Invalid API key"));
+        assert_eq!(server.requests().len(), 1);
     }
 
     #[test]
     fn test_stream_chunk_deserialization() {
         let json = r#"{"choices":[{"delta":{"content":"Hello"}}]}"#;
-        let chunk: StreamChunk = serde_json::from_str(json).unwrap();
+        let chunk: SseStreamChunk = serde_json::from_str(json).unwrap();
         assert_eq!(chunk.choices.len(), 1);
         assert_eq!(
             chunk.choices[0].delta.as_ref().unwrap().content.as_ref().unwrap(),
@@ -360,7 +297,7 @@ mod tests {
     #[test]
     fn test_stream_chunk_with_usage() {
         let json = r#"{"choices":[],"usage":{"prompt_tokens":10,"completion_tokens":20}}"#;
-        let chunk: StreamChunk = serde_json::from_str(json).unwrap();
+        let chunk: SseStreamChunk = serde_json::from_str(json).unwrap();
         let usage = chunk.usage.unwrap();
         assert_eq!(usage.prompt_tokens, Some(10));
         assert_eq!(usage.completion_tokens, Some(20));
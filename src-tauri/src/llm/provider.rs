@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
 use crate::agent::conversation::{ConversationHistory, Message};
+use crate::agent::plan::TaskPlan;
+use super::capabilities::ContextFit;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -20,12 +22,21 @@ pub enum LlmError {
     StreamError(String),
     #[error("Provider not configured")]
     NotConfigured,
+    #[error("Request needs about {needed} tokens but the model's context window allows {limit}")]
+    ContextOverflow { needed: u32, limit: u32 },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TokenMetrics {
+    /// Prompt tokens billed at the full input price
     pub input_tokens: u64,
     pub output_tokens: u64,
+    /// Prompt tokens served from the provider's prompt cache
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    /// Prompt tokens written to the provider's prompt cache
+    #[serde(default)]
+    pub cache_write_tokens: u64,
     pub total_duration: Duration,
 }
 
@@ -55,12 +66,16 @@ pub struct ToolUse {
     pub id: String,
     pub name: String,
     pub input: Value,
+    /// Opaque token the provider attached to the call and expects back with
+    /// it in the history (Gemini's `thoughtSignature`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 /// Response from an LLM provider - can be either a tool use or raw text
 #[derive(Debug, Clone)]
 pub enum LlmResponse {
-    /// Native tool use response (from Anthropic), with optional reasoning text
+    /// Native tool use response (Anthropic tool_use or OpenAI function calling), with optional reasoning text
     ToolUse { tool_use: ToolUse, reasoning: Option<String> },
     /// Raw text response (fallback for JSON parsing)
     Text(String),
@@ -321,6 +336,40 @@ pub fn build_tools() -> Vec<Tool> {
     ]
 }
 
+/// Tools for working through a task plan, offered only while there is one
+pub fn build_plan_tools() -> Vec<Tool> {
+    vec![
+        Tool {
+            name: "step_done".to_string(),
+            description: "Mark a sub-goal of the plan as achieved".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "step": {
+                        "type": "integer",
+                        "description": "Number of the sub-goal; defaults to the current one"
+                    }
+                },
+                "required": []
+            }),
+        },
+        Tool {
+            name: "replan".to_string(),
+            description: "Ask for a new plan for the rest of the task when the current one no longer fits".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "reason": {
+                        "type": "string",
+                        "description": "What changed or why the plan does not work"
+                    }
+                },
+                "required": ["reason"]
+            }),
+        },
+    ]
+}
+
 /// Represents a tool result to be sent back to the LLM
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolResult {
@@ -429,72 +478,230 @@ pub trait LlmProvider: Send + Sync {
     }
 
     fn name(&self) -> &str;
+
+    /// Model requests are sent to, when the provider has a fixed one
+    fn model(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Helper to convert conversation history to provider-specific message format.
 /// Returns a Vec of tuples: (role, text_content, optional_image_base64)
 /// The image_base64 is Arc-wrapped to avoid cloning large screenshot strings.
 ///
-/// To reduce token usage, only the 2 most recent User messages retain their screenshots;
+/// To reduce token usage, only the 2 most recent screenshots are kept;
 /// older screenshots are stripped (replaced with None).
 pub fn history_to_messages(history: &ConversationHistory) -> Vec<(String, String, Option<Arc<String>>)> {
-    // Count total User messages to determine which ones keep screenshots
-    let user_msg_count = history.messages().filter(|m| matches!(m, Message::User { .. })).count();
-    let keep_threshold = user_msg_count.saturating_sub(2);
-
-    let mut user_index = 0usize;
-    history
-        .messages()
-        .map(|msg| match msg {
-            Message::User {
-                instruction,
-                screenshot_base64,
-                ..
-            } => {
-                let keep_screenshot = user_index >= keep_threshold;
-                user_index += 1;
-                (
-                    "user".to_string(),
-                    instruction.clone(),
-                    if keep_screenshot { screenshot_base64.clone() } else { None },
-                )
+    history_to_messages_within(history, &ContextFit::default())
+}
+
+/// Like `history_to_messages`, but with only what `fit` allows: the
+/// `fit.screenshots` most recent screenshots, and none of the skipped messages.
+pub fn history_to_messages_within(
+    history: &ConversationHistory,
+    fit: &ContextFit,
+) -> Vec<(String, String, Option<Arc<String>>)> {
+    history_to_turns_within(history, fit)
+        .into_iter()
+        .map(|turn| {
+            let text = turn.flat_text();
+            (turn.role, text, turn.image)
+        })
+        .collect()
+}
+
+/// One message of the history as it goes into a request
+#[derive(Debug, Clone)]
+pub struct HistoryTurn {
+    /// "user" or "assistant"
+    pub role: String,
+    /// The message text; for a tool call, the reasoning that came with it
+    pub text: String,
+    pub image: Option<Arc<String>>,
+    /// The tool call this assistant turn made; only set when the next turn carries its result
+    pub tool_use: Option<ToolUse>,
+    /// The result this user turn reports for the tool call in the turn before it
+    pub tool_result: Option<ToolResult>,
+}
+
+impl HistoryTurn {
+    /// The turn as plain text, for requests sent without native tool calls
+    pub fn flat_text(&self) -> String {
+        match &self.tool_use {
+            Some(tool_use) => LlmResponse::ToolUse {
+                tool_use: tool_use.clone(),
+                reasoning: Some(self.text.clone()).filter(|text| !text.trim().is_empty()),
             }
-            Message::Assistant { content } => ("assistant".to_string(), content.clone(), None),
-            Message::ToolResult {
-                success,
-                message,
-                error,
-            } => {
-                let text = if *success {
-                    format!(
-                        "Action executed successfully. {}",
-                        message.as_deref().unwrap_or("")
-                    )
-                } else {
-                    format!(
-                        "Action failed. {}",
-                        error.as_deref().unwrap_or("Unknown error")
-                    )
-                };
-                ("user".to_string(), text, None)
+            .to_string_repr(),
+            None => self.text.clone(),
+        }
+    }
+}
+
+fn tool_result_text(success: bool, message: Option<&str>, error: Option<&str>) -> String {
+    if success {
+        format!("Action executed successfully. {}", message.unwrap_or(""))
+    } else {
+        format!("Action failed. {}", error.unwrap_or("Unknown error"))
+    }
+}
+
+/// Like `history_to_messages_within`, keeping tool calls and their results
+/// as such. A call and its result are only paired when both are sent, one
+/// right after the other; anything else is sent as text, since providers
+/// reject a tool call without its result and a result without its call.
+/// When messages are skipped, the first turn says how many are missing after it.
+pub fn history_to_turns_within(history: &ConversationHistory, fit: &ContextFit) -> Vec<HistoryTurn> {
+    let kept = |i: usize| i == 0 || i > fit.skipped_messages;
+    let messages: Vec<(usize, &Message)> = history.messages().enumerate().filter(|(i, _)| kept(*i)).collect();
+    let with_screenshot: Vec<usize> = messages
+        .iter()
+        .filter(|(_, m)| matches!(m, Message::User { screenshot_base64: Some(_), .. }))
+        .map(|(i, _)| *i)
+        .collect();
+    let first_screenshot = with_screenshot
+        .get(with_screenshot.len().saturating_sub(fit.screenshots))
+        .copied()
+        .unwrap_or(usize::MAX);
+
+    let answers = |call: Option<&Message>, result: Option<&Message>| match (call, result) {
+        (
+            Some(Message::Assistant { tool_use: Some(tool_use), .. }),
+            Some(Message::ToolResult { tool_use_id: Some(id), .. }),
+        ) => tool_use.id == *id,
+        _ => false,
+    };
+
+    let mut turns: Vec<HistoryTurn> = messages
+        .iter()
+        .enumerate()
+        .map(|(pos, &(i, msg))| {
+            let previous = pos.checked_sub(1).map(|p| messages[p].1);
+            let next = messages.get(pos + 1).map(|(_, m)| *m);
+            match msg {
+                Message::User {
+                    instruction,
+                    screenshot_base64,
+                    ..
+                } => {
+                    let keep_screenshot = fit.screenshots > 0 && i >= first_screenshot;
+                    HistoryTurn {
+                        role: "user".to_string(),
+                        text: instruction.clone(),
+                        image: if keep_screenshot { screenshot_base64.clone() } else { None },
+                        tool_use: None,
+                        tool_result: None,
+                    }
+                }
+                Message::Assistant { content, tool_use } => {
+                    let paired = answers(Some(msg), next);
+                    let turn = HistoryTurn {
+                        role: "assistant".to_string(),
+                        text: content.clone(),
+                        image: None,
+                        tool_use: tool_use.clone(),
+                        tool_result: None,
+                    };
+                    if paired {
+                        turn
+                    } else {
+                        HistoryTurn { text: turn.flat_text(), tool_use: None, ..turn }
+                    }
+                }
+                Message::ToolResult {
+                    success,
+                    message,
+                    error,
+                    tool_use_id,
+                } => {
+                    let text = tool_result_text(*success, message.as_deref(), error.as_deref());
+                    let tool_result = match tool_use_id {
+                        Some(id) if answers(previous, Some(msg)) => Some(if *success {
+                            ToolResult::success(id.clone(), text.clone())
+                        } else {
+                            ToolResult::error(id.clone(), text.clone())
+                        }),
+                        _ => None,
+                    };
+                    HistoryTurn {
+                        role: "user".to_string(),
+                        text,
+                        image: None,
+                        tool_use: None,
+                        tool_result,
+                    }
+                }
             }
         })
-        .collect()
+        .collect();
+
+    if fit.skipped_messages > 0 {
+        if let Some(first) = turns.first_mut() {
+            first.text.push_str(&omitted_messages_note(fit.skipped_messages));
+        }
+    }
+    turns
+}
+
+/// Stands in for the messages `fit_history` left out, so the model knows the
+/// conversation jumps ahead
+pub fn omitted_messages_note(skipped: usize) -> String {
+    format!(
+        "\n\n[{} earlier message{} of this task left out to fit the context window]",
+        skipped,
+        if skipped == 1 { "" } else { "s" }
+    )
+}
+
+/// Earlier steps that no longer fit in the conversation, summarized
+fn progress_note_section(note: &str) -> String {
+    format!(
+        "\n\n## Progress So Far\nOlder steps of this task are no longer shown in full. What they did:\n{}",
+        note
+    )
 }
 
 /// Build system prompt for tool-based providers (simplified, tools are defined via API)
 pub fn build_system_prompt_for_tools(screen_width: u32, screen_height: u32) -> String {
-    build_system_prompt_for_tools_with_context(screen_width, screen_height, None, None, None)
+    build_system_prompt_for_tools_with_context(screen_width, screen_height, None, None, None, None, None)
 }
 
-/// Build system prompt for tool-based providers with optional task context and progress info
+/// Build system prompt for tool-based providers with optional task context, progress info, plan
+/// and a note summarizing earlier steps
 pub fn build_system_prompt_for_tools_with_context(
     screen_width: u32,
     screen_height: u32,
     instruction: Option<&str>,
     iteration: Option<u32>,
     max_iterations: Option<u32>,
+    plan: Option<&TaskPlan>,
+    progress_note: Option<&str>,
 ) -> String {
+    let (mut prompt, step) = build_tool_prompt_sections(
+        screen_width,
+        screen_height,
+        instruction,
+        iteration,
+        max_iterations,
+        plan,
+        progress_note,
+    );
+    prompt.push_str(&step);
+    prompt
+}
+
+/// The tool-based system prompt as two parts: what stays the same for the whole
+/// run (guidelines, screen and task) and what changes every step (progress note,
+/// step count and plan). Appending the second to the first gives the full prompt.
+pub fn build_tool_prompt_sections(
+    screen_width: u32,
+    screen_height: u32,
+    instruction: Option<&str>,
+    iteration: Option<u32>,
+    max_iterations: Option<u32>,
+    plan: Option<&TaskPlan>,
+    progress_note: Option<&str>,
+) -> (String, String) {
     let mut prompt = format!(
         r#"You are a computer use agent. You can see the user's screen and control their mouse and keyboard to complete tasks.
 
@@ -515,23 +722,47 @@ Use one of the provided tools to perform your next action."#
         prompt.push_str(&format!("\n\n## Current Task\n{}", instr));
     }
 
+    (prompt, step_sections(iteration, max_iterations, plan, progress_note, true))
+}
+
+/// Progress note, step count and plan sections, in the order both prompts use
+fn step_sections(
+    iteration: Option<u32>,
+    max_iterations: Option<u32>,
+    plan: Option<&TaskPlan>,
+    progress_note: Option<&str>,
+    for_tools: bool,
+) -> String {
+    let mut sections = String::new();
+
+    if let Some(note) = progress_note {
+        sections.push_str(&progress_note_section(note));
+    }
+
     if let (Some(iter), Some(max)) = (iteration, max_iterations) {
-        prompt.push_str(&format!("\n\n## Progress\nStep {} of {}.", iter, max));
+        sections.push_str(&format!("\n\n## Progress\nStep {} of {}.", iter, max));
         if max > 0 && iter > (max * 3) / 4 {
-            prompt.push_str("\nYou are running low on steps. Focus on completing the task efficiently.");
+            sections.push_str("\nYou are running low on steps. Focus on completing the task efficiently.");
         }
     }
 
-    prompt
+    if let Some(plan) = plan {
+        sections.push_str(&plan.prompt_section(for_tools));
+    }
+
+    sections
 }
 
-/// Build system prompt for JSON-based providers with optional task context and progress info
+/// Build system prompt for JSON-based providers with optional task context, progress info, plan
+/// and a note summarizing earlier steps
 pub fn build_system_prompt_with_context(
     screen_width: u32,
     screen_height: u32,
     instruction: Option<&str>,
     iteration: Option<u32>,
     max_iterations: Option<u32>,
+    plan: Option<&TaskPlan>,
+    progress_note: Option<&str>,
 ) -> String {
     let mut prompt = build_system_prompt(screen_width, screen_height);
 
@@ -539,13 +770,7 @@ pub fn build_system_prompt_with_context(
         prompt.push_str(&format!("\n\n## Current Task\n{}", instr));
     }
 
-    if let (Some(iter), Some(max)) = (iteration, max_iterations) {
-        prompt.push_str(&format!("\n\n## Progress\nStep {} of {}.", iter, max));
-        if max > 0 && iter > (max * 3) / 4 {
-            prompt.push_str("\nYou are running low on steps. Focus on completing the task efficiently.");
-        }
-    }
-
+    prompt.push_str(&step_sections(iteration, max_iterations, plan, progress_note, false));
     prompt
 }
 
@@ -625,7 +850,8 @@ Guidelines:
 - Use "complete" when the task is done
 - Use "error" if you cannot proceed
 
-Note: Actions are automatically retried up to 3 times if they fail or have no visible effect.
+Note: Actions are automatically retried up to 3 times if they fail, and clicks also when they have no visible effect.
+Typing, key presses, scrolls and drags are never repeated for you: if the screen did not change, the result says so. Check whether they took effect before doing them again.
 If an action consistently fails, try:
 - Adjusting coordinates slightly (elements may have shifted)
 - Using a different approach (e.g., keyboard navigation instead of clicking)
@@ -659,6 +885,7 @@ mod tests {
             input_tokens: 100,
             output_tokens: 50,
             total_duration: Duration::from_secs(2),
+            ..Default::default()
         };
         assert!((metrics.tokens_per_second() - 25.0).abs() < 0.001);
     }
@@ -669,6 +896,7 @@ mod tests {
             input_tokens: 100,
             output_tokens: 50,
             total_duration: Duration::from_secs(0),
+            ..Default::default()
         };
         assert_eq!(metrics.tokens_per_second(), 0.0);
     }
@@ -679,6 +907,7 @@ mod tests {
             id: "tool_abc".to_string(),
             name: "click".to_string(),
             input: json!({"x": 100, "y": 200}),
+            signature: None,
         };
         let json_str = serde_json::to_string(&tu).unwrap();
         let parsed: ToolUse = serde_json::from_str(&json_str).unwrap();
@@ -745,6 +974,48 @@ mod tests {
         assert!(prompt.contains("2560x1440"));
     }
 
+    #[test]
+    fn test_system_prompt_includes_plan() {
+        let mut plan = TaskPlan::parse("1. Open Notes\n2. Create a note").unwrap();
+        plan.mark_done(None).unwrap();
+
+        let prompt = build_system_prompt_for_tools_with_context(800, 600, Some("make a note"), None, None, Some(&plan), None);
+        assert!(prompt.contains("Completed:\n1. Open Notes"));
+        assert!(prompt.contains("Current sub-goal: 2. Create a note"));
+        assert!(prompt.contains("\"step_done\" tool"));
+
+        let prompt = build_system_prompt_with_context(800, 600, Some("make a note"), None, None, Some(&plan), None);
+        assert!(prompt.contains("{\"action\": \"replan\""));
+        assert!(!build_system_prompt_with_context(800, 600, None, None, None, None, None).contains("## Plan"));
+    }
+
+    #[test]
+    fn test_system_prompt_includes_progress_note() {
+        let note = "- Click left at (10, 20): ok";
+        for prompt in [
+            build_system_prompt_for_tools_with_context(800, 600, Some("task"), None, None, None, Some(note)),
+            build_system_prompt_with_context(800, 600, Some("task"), None, None, None, Some(note)),
+        ] {
+            let section = prompt.split("## Progress So Far\n").nth(1).unwrap();
+            assert!(section.contains(note));
+        }
+        assert!(!build_system_prompt_for_tools(800, 600).contains("## Progress So Far"));
+    }
+
+    #[test]
+    fn test_tool_prompt_sections_split_per_step_context() {
+        let plan = TaskPlan::parse("1. Open Notes\n2. Create a note").unwrap();
+        let (stable, step) =
+            build_tool_prompt_sections(800, 600, Some("make a note"), Some(4), Some(5), Some(&plan), Some("- earlier"));
+        assert!(stable.contains("800x600") && stable.contains("## Current Task\nmake a note"));
+        assert!(!stable.contains("## Progress"));
+        assert!(step.contains("Step 4 of 5") && step.contains("- earlier") && step.contains("Current sub-goal"));
+        assert_eq!(
+            stable + &step,
+            build_system_prompt_for_tools_with_context(800, 600, Some("make a note"), Some(4), Some(5), Some(&plan), Some("- earlier"))
+        );
+    }
+
     #[test]
     fn test_llm_response_to_string_repr_text() {
         let resp = LlmResponse::Text("hello".to_string());
@@ -758,6 +1029,7 @@ mod tests {
                 id: "id1".to_string(),
                 name: "click".to_string(),
                 input: json!({"x": 1}),
+                signature: None,
             },
             reasoning: None,
         };
@@ -837,6 +1109,72 @@ mod tests {
         assert!(messages.is_empty());
     }
 
+    #[test]
+    fn test_history_to_messages_within_fit() {
+        let mut history = ConversationHistory::new();
+        for i in 0..3 {
+            history.add_user_message(&format!("Step {}", i), Some(format!("screenshot{}", i).into()), None, None);
+            history.add_assistant_message(r#"{"action": "wait", "duration_ms": 100}"#);
+        }
+        history.add_user_message("Now", Some("screenshot3".to_string().into()), None, None);
+
+        let screenshots = |messages: &[(String, String, Option<Arc<String>>)]| -> Vec<String> {
+            messages.iter().filter_map(|(_, _, image)| image.as_deref().cloned()).collect()
+        };
+        assert_eq!(screenshots(&history_to_messages(&history)), vec!["screenshot2", "screenshot3"]);
+
+        let fit = ContextFit { screenshots: 1, skipped_messages: 3, estimated_tokens: 0 };
+        let messages = history_to_messages_within(&history, &fit);
+        let texts: Vec<&str> = messages.iter().map(|(_, text, _)| text.as_str()).collect();
+        assert_eq!(texts[0], format!("Step 0{}", omitted_messages_note(3)));
+        assert!(texts[0].contains("3 earlier messages"));
+        assert_eq!(texts[1], "Step 2");
+        assert_eq!(messages.len(), 4);
+        assert_eq!(screenshots(&messages), vec!["screenshot3"]);
+    }
+
+    fn click_response(id: &str) -> LlmResponse {
+        LlmResponse::ToolUse {
+            tool_use: ToolUse { id: id.to_string(), name: "click".to_string(), input: json!({"x": 1, "y": 2}), signature: None },
+            reasoning: None,
+        }
+    }
+
+    #[test]
+    fn test_history_to_turns_pairs_tool_calls_with_results() {
+        let mut history = ConversationHistory::new();
+        history.add_user_message("Open Notes", Some("screenshot".to_string().into()), None, None);
+        history.add_assistant_response(&click_response("toolu_1"));
+        history.add_tool_result(false, None, Some("Missed".to_string()));
+        history.add_user_message("Next", None, None, None);
+        // Never answered, e.g. in preview mode
+        history.add_assistant_response(&click_response("toolu_2"));
+        history.add_user_message("Next", None, None, None);
+
+        let turns = history_to_turns_within(&history, &ContextFit::default());
+        assert_eq!(turns[1].tool_use.as_ref().map(|t| t.id.as_str()), Some("toolu_1"));
+        let result = turns[2].tool_result.as_ref().unwrap();
+        assert_eq!(result.tool_use_id, "toolu_1");
+        assert!(result.is_error);
+        assert!(result.content.contains("Missed"));
+        assert!(turns[4].tool_use.is_none());
+        assert!(turns[4].text.contains("toolu_2"));
+
+        // Dropping the call leaves its result as plain text
+        let fit = ContextFit { screenshots: 1, skipped_messages: 1, estimated_tokens: 0 };
+        let turns = history_to_turns_within(&history, &fit);
+        assert_eq!(turns[1].role, "user");
+        assert!(turns[1].tool_result.is_none());
+        assert!(turns[1].text.contains("Action failed. Missed"));
+        assert!(turns[0].text.ends_with(&omitted_messages_note(1)));
+        assert!(turns[0].text.contains("1 earlier message of"));
+
+        // The flat form is what text-only requests get
+        let messages = history_to_messages(&history);
+        assert!(messages[1].1.contains("\"name\":\"click\""));
+        assert!(messages[2].1.starts_with("Action failed."));
+    }
+
     #[test]
     fn test_build_system_prompt_contains_all_action_types() {
         let prompt = build_system_prompt(1920, 1080);
@@ -1,7 +1,19 @@
-use serde::Deserialize;
+use super::capabilities::{capabilities_for, estimate_text_tokens, fit_history};
+use super::provider::{
+    build_plan_tools, build_system_prompt_for_tools_with_context, build_system_prompt_with_context, build_tools,
+    history_to_turns_within, ChunkCallback, LlmError, LlmResponse, TokenMetrics, ToolUse,
+};
+use crate::agent::conversation::ConversationHistory;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
 
 /// Shared SSE (Server-Sent Events) stream parser for OpenAI-compatible APIs.
 /// Used by OpenAI, OpenRouter, and any other provider using the `data: ` line protocol.
+/// Also holds the chat request and function calling types those APIs share,
+/// and `send_chat`, which the providers call with their own URL and headers.
 
 #[derive(Deserialize)]
 pub struct SseStreamChunk {
@@ -18,6 +30,24 @@ pub struct SseStreamChoice {
 #[derive(Deserialize)]
 pub struct SseDeltaContent {
     pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<SseToolCallDelta>>,
+}
+
+/// A fragment of a streamed tool call. The id and name come with the first
+/// fragment of each call; the arguments JSON arrives in pieces.
+#[derive(Deserialize)]
+pub struct SseToolCallDelta {
+    #[serde(default)]
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<SseFunctionDelta>,
+}
+
+#[derive(Deserialize)]
+pub struct SseFunctionDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -26,6 +56,174 @@ pub struct SseUsageInfo {
     pub completion_tokens: Option<u64>,
 }
 
+/// A tool in the OpenAI `tools` request format
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionTool {
+    #[serde(rename = "type")]
+    tool_type: &'static str,
+    function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FunctionDefinition {
+    name: String,
+    description: String,
+    parameters: Value,
+}
+
+/// The action tools as OpenAI functions, with the plan tools when the task has a plan
+pub fn function_tools(with_plan: bool) -> Vec<FunctionTool> {
+    let mut tools = build_tools();
+    if with_plan {
+        tools.extend(build_plan_tools());
+    }
+    tools
+        .into_iter()
+        .map(|tool| FunctionTool {
+            tool_type: "function",
+            function: FunctionDefinition {
+                name: tool.name,
+                description: tool.description,
+                parameters: tool.input_schema,
+            },
+        })
+        .collect()
+}
+
+/// A tool call as sent back in an assistant message's `tool_calls`
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionCall {
+    id: String,
+    #[serde(rename = "type")]
+    call_type: &'static str,
+    function: FunctionCallBody,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FunctionCallBody {
+    name: String,
+    /// The arguments as a JSON string, the way the API returned them
+    arguments: String,
+}
+
+impl From<&ToolUse> for FunctionCall {
+    fn from(tool_use: &ToolUse) -> Self {
+        Self {
+            id: tool_use.id.clone(),
+            call_type: "function",
+            function: FunctionCallBody {
+                name: tool_use.name.clone(),
+                arguments: tool_use.input.to_string(),
+            },
+        }
+    }
+}
+
+/// Error messages servers send when a model or endpoint has no function calling
+const TOOLS_UNSUPPORTED: &[&str] = &[
+    "does not support tool",
+    "does not support function",
+    "doesn't support tool",
+    "tools is not supported",
+    "tools are not supported",
+    "tool use is not supported",
+    "tool calling is not supported",
+    "function calling is not supported",
+    "functions are not supported",
+    "no endpoints found that support tool use",
+    "unrecognized request argument supplied: tools",
+    "unknown field `tools`",
+    "unknown field: tools",
+];
+
+/// Whether an error response means the endpoint or model doesn't do function
+/// calling, as opposed to a problem with the request as a whole
+pub fn tools_rejected(status: u16, body: &str) -> bool {
+    let body = body.to_lowercase();
+    matches!(status, 400 | 404 | 422 | 501) && TOOLS_UNSUPPORTED.iter().any(|m| body.contains(m))
+}
+
+/// Requests sent as JSON text after an endpoint rejects `tools`, before they are offered again
+const TEXT_REQUESTS_AFTER_REJECTION: u32 = 20;
+
+/// Whether to offer an endpoint the action tools. A rejection switches it to
+/// JSON text for a while rather than for good, since it may have come from a
+/// model or deployment that has since changed.
+#[derive(Default)]
+pub struct ToolSupport {
+    text_requests_left: AtomicU32,
+}
+
+impl ToolSupport {
+    /// Whether the next request will offer tools
+    pub fn available(&self) -> bool {
+        self.text_requests_left.load(Ordering::Relaxed) == 0
+    }
+
+    /// Decide for one request, counting down the text-only requests
+    fn use_for_request(&self) -> bool {
+        self.text_requests_left
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1))
+            .is_err()
+    }
+
+    fn rejected(&self) {
+        self.text_requests_left.store(TEXT_REQUESTS_AFTER_REJECTION, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Tool calls assembled from streamed `tool_calls` deltas
+#[derive(Default)]
+pub struct StreamedToolCalls {
+    calls: Vec<PartialToolCall>,
+}
+
+impl StreamedToolCalls {
+    /// Add one delta; returns the tool name when it starts a new call
+    fn push(&mut self, delta: &SseToolCallDelta) -> Option<String> {
+        if self.calls.len() <= delta.index {
+            self.calls.resize_with(delta.index + 1, PartialToolCall::default);
+        }
+        let call = &mut self.calls[delta.index];
+        if let Some(id) = &delta.id {
+            call.id.clone_from(id);
+        }
+        let function = delta.function.as_ref()?;
+        if let Some(arguments) = &function.arguments {
+            call.arguments.push_str(arguments);
+        }
+        match &function.name {
+            Some(name) if call.name.is_empty() => {
+                call.name.clone_from(name);
+                Some(name.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// The first complete tool call as a `ToolUse` response, with `text` as
+    /// its reasoning; plain text when no tool was called. The agent runs one
+    /// action per step, so any further calls are dropped.
+    pub fn into_response(self, text: String) -> LlmResponse {
+        let Some(call) = self.calls.into_iter().find(|call| !call.name.is_empty()) else {
+            return LlmResponse::Text(text);
+        };
+        let input = serde_json::from_str(&call.arguments).unwrap_or_else(|_| serde_json::json!({}));
+        let reasoning = if text.trim().is_empty() { None } else { Some(text) };
+        LlmResponse::ToolUse {
+            tool_use: ToolUse { id: call.id, name: call.name, input, signature: None },
+            reasoning,
+        }
+    }
+}
+
 /// Result of processing buffered SSE data. Returned token counts are cumulative
 /// (last usage event wins), so callers should overwrite rather than accumulate.
 pub struct SseProcessResult {
@@ -33,14 +231,16 @@ pub struct SseProcessResult {
     pub output_tokens: Option<u64>,
 }
 
-/// Process all complete SSE lines in `buffer`, appending content to `full_response`
-/// and invoking `on_chunk` for each content delta. Drains processed lines from the
-/// buffer, leaving any trailing incomplete line for the next call.
+/// Process all complete SSE lines in `buffer`, appending content to `full_response`,
+/// collecting tool calls into `tool_calls` and invoking `on_chunk` for each content
+/// delta. Drains processed lines from the buffer, leaving any trailing incomplete
+/// line for the next call.
 ///
 /// Returns aggregated token usage from the last usage event seen (if any).
 pub fn process_sse_buffer(
     buffer: &mut String,
     full_response: &mut String,
+    tool_calls: &mut StreamedToolCalls,
     on_chunk: &dyn Fn(&str),
 ) -> SseProcessResult {
     let mut result = SseProcessResult {
@@ -60,6 +260,11 @@ pub fn process_sse_buffer(
                                     full_response.push_str(content);
                                     on_chunk(content);
                                 }
+                                for call in delta.tool_calls.iter().flatten() {
+                                    if let Some(name) = tool_calls.push(call) {
+                                        on_chunk(&format!("[Using tool: {}]", name));
+                                    }
+                                }
                             }
                         }
 
@@ -77,6 +282,228 @@ pub fn process_sse_buffer(
     result
 }
 
+/// Chat completion request body
+#[derive(Serialize)]
+pub struct ChatRequest {
+    /// Left out when the URL already picks the model, as with Azure deployments
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    max_tokens: u32,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<FunctionTool>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Serialize)]
+struct ChatMessage {
+    role: String,
+    content: ChatContent,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<FunctionCall>,
+    /// Set on `tool` messages: the call they answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    fn new(role: String, content: ChatContent) -> Self {
+        Self { role, content, tool_calls: Vec::new(), tool_call_id: None }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatPart>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum ChatPart {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(rename = "image_url")]
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize)]
+struct ImageUrl {
+    url: String,
+}
+
+/// What an OpenAI-style provider brings to `send_chat` besides its URL and headers
+pub struct ChatEndpoint<'a> {
+    /// Provider name, for the context window table and log messages
+    pub provider: &'a str,
+    /// Looked up in the context window table, and sent as `model` unless `model_in_url`
+    pub model: &'a str,
+    pub model_in_url: bool,
+    pub temperature: Option<f32>,
+    /// Ask for token usage at the end of the stream (`stream_options`)
+    pub include_usage: bool,
+    /// Whether to offer the action tools; updated when the endpoint rejects them
+    pub tools: &'a ToolSupport,
+}
+
+/// Build the chat request for `history`: with the action tools and tool call
+/// messages when `use_tools` is set, otherwise as text asking for a JSON action
+fn build_chat_request(
+    endpoint: &ChatEndpoint<'_>,
+    history: &ConversationHistory,
+    screen_width: u32,
+    screen_height: u32,
+    use_tools: bool,
+) -> Result<ChatRequest, LlmError> {
+    let instruction = history.original_instruction().map(|s| s.to_string());
+    let build_prompt = if use_tools {
+        build_system_prompt_for_tools_with_context
+    } else {
+        build_system_prompt_with_context
+    };
+    let system_prompt = build_prompt(
+        screen_width,
+        screen_height,
+        instruction.as_deref(),
+        history.iteration,
+        history.max_iterations,
+        history.plan(),
+        history.progress_note(),
+    );
+
+    let tools = if use_tools { function_tools(history.plan().is_some()) } else { Vec::new() };
+
+    // Trim what is sent to what the model's context window can take
+    let capabilities = capabilities_for(endpoint.provider, Some(endpoint.model));
+    let fixed_tokens = estimate_text_tokens(&system_prompt)
+        + estimate_text_tokens(&serde_json::to_string(&tools).unwrap_or_default());
+    let fit = fit_history(&capabilities, history, fixed_tokens, screen_width, screen_height)?;
+
+    let mut messages = vec![ChatMessage::new("system".to_string(), ChatContent::Text(system_prompt))];
+    for turn in history_to_turns_within(history, &fit) {
+        // With tools on, tool calls and their results go back as such
+        if use_tools {
+            if let Some(tool_use) = &turn.tool_use {
+                messages.push(ChatMessage {
+                    tool_calls: vec![FunctionCall::from(tool_use)],
+                    ..ChatMessage::new(turn.role, ChatContent::Text(turn.text))
+                });
+                continue;
+            }
+            if let Some(result) = turn.tool_result {
+                messages.push(ChatMessage {
+                    tool_call_id: Some(result.tool_use_id),
+                    ..ChatMessage::new("tool".to_string(), ChatContent::Text(result.content))
+                });
+                continue;
+            }
+        }
+
+        let text = turn.flat_text();
+        let content = match turn.image {
+            Some(img_data) => ChatContent::Parts(vec![
+                ChatPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: format!("data:image/png;base64,{}", img_data),
+                    },
+                },
+                ChatPart::Text {
+                    text: format!(
+                        "User instruction: {}\n\nAnalyze the screenshot and respond with a single JSON action.",
+                        text
+                    ),
+                },
+            ]),
+            None => ChatContent::Text(text),
+        };
+        messages.push(ChatMessage::new(turn.role, content));
+    }
+
+    Ok(ChatRequest {
+        model: (!endpoint.model_in_url).then(|| endpoint.model.to_string()),
+        max_tokens: 1024,
+        messages,
+        tools,
+        stream: true,
+        stream_options: endpoint.include_usage.then_some(StreamOptions { include_usage: true }),
+        temperature: endpoint.temperature,
+    })
+}
+
+/// Send `history` to an OpenAI-style chat endpoint and read the streamed reply.
+/// `post` starts a POST to the endpoint's URL with its auth headers. Endpoints
+/// that turn out not to do function calling get the request again without tools.
+pub async fn send_chat(
+    endpoint: ChatEndpoint<'_>,
+    history: &ConversationHistory,
+    screen_width: u32,
+    screen_height: u32,
+    on_chunk: ChunkCallback,
+    post: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<(LlmResponse, TokenMetrics), LlmError> {
+    let start = Instant::now();
+    let use_tools = endpoint.tools.use_for_request();
+    let request = build_chat_request(&endpoint, history, screen_width, screen_height, use_tools)?;
+    let mut response = post().json(&request).send().await?;
+
+    if use_tools && !response.status().is_success() {
+        let status = response.status().as_u16();
+        let error_text = response.text().await.unwrap_or_default();
+        if !tools_rejected(status, &error_text) {
+            return Err(LlmError::ApiError(error_text));
+        }
+        log::warn!("{} rejected function calling, asking for JSON actions instead: {}", endpoint.provider, error_text);
+        endpoint.tools.rejected();
+        let request = build_chat_request(&endpoint, history, screen_width, screen_height, false)?;
+        response = post().json(&request).send().await?;
+    }
+
+    if !response.status().is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(LlmError::ApiError(error_text));
+    }
+
+    let mut stream = response.bytes_stream();
+    // Pre-allocate response buffer with typical response size (~4KB)
+    let mut full_response = String::with_capacity(4096);
+    let mut input_tokens = 0u64;
+    let mut output_tokens = 0u64;
+    let mut buffer = String::new();
+    let mut tool_calls = StreamedToolCalls::default();
+
+    while let Some(chunk_result) = stream.next().await {
+        let chunk = chunk_result?;
+        append_bytes_to_buffer(&mut buffer, &chunk);
+
+        let result = process_sse_buffer(&mut buffer, &mut full_response, &mut tool_calls, &*on_chunk);
+        if let Some(t) = result.input_tokens {
+            input_tokens = t;
+        }
+        if let Some(t) = result.output_tokens {
+            output_tokens = t;
+        }
+    }
+
+    let metrics = TokenMetrics {
+        input_tokens,
+        output_tokens,
+        total_duration: start.elapsed(),
+        ..Default::default()
+    };
+
+    Ok((tool_calls.into_response(full_response), metrics))
+}
+
 /// Append raw bytes to a string buffer, preferring zero-copy `from_utf8` and
 /// falling back to `from_utf8_lossy` only when the chunk contains invalid UTF-8.
 #[inline]
@@ -100,7 +527,7 @@ mod tests {
         let mut response = String::new();
         let chunks = RefCell::new(Vec::new());
 
-        let result = process_sse_buffer(&mut buffer, &mut response, &|c| {
+        let result = process_sse_buffer(&mut buffer, &mut response, &mut StreamedToolCalls::default(), &|c| {
             chunks.borrow_mut().push(c.to_string());
         });
 
@@ -115,7 +542,7 @@ mod tests {
         let mut buffer = String::from("data: [DONE]\n");
         let mut response = String::new();
 
-        process_sse_buffer(&mut buffer, &mut response, &|_| {});
+        process_sse_buffer(&mut buffer, &mut response, &mut StreamedToolCalls::default(), &|_| {});
 
         assert_eq!(response, "");
         assert!(buffer.is_empty());
@@ -128,7 +555,7 @@ mod tests {
         );
         let mut response = String::new();
 
-        let result = process_sse_buffer(&mut buffer, &mut response, &|_| {});
+        let result = process_sse_buffer(&mut buffer, &mut response, &mut StreamedToolCalls::default(), &|_| {});
 
         assert_eq!(result.input_tokens, Some(10));
         assert_eq!(result.output_tokens, Some(20));
@@ -141,7 +568,7 @@ mod tests {
         );
         let mut response = String::new();
 
-        process_sse_buffer(&mut buffer, &mut response, &|_| {});
+        process_sse_buffer(&mut buffer, &mut response, &mut StreamedToolCalls::default(), &|_| {});
 
         assert_eq!(response, "hi");
         assert_eq!(buffer, "data: {\"choi");
@@ -155,7 +582,7 @@ mod tests {
         let mut response = String::new();
         let chunks = RefCell::new(Vec::new());
 
-        process_sse_buffer(&mut buffer, &mut response, &|c| {
+        process_sse_buffer(&mut buffer, &mut response, &mut StreamedToolCalls::default(), &|c| {
             chunks.borrow_mut().push(c.to_string());
         });
 
@@ -171,11 +598,112 @@ mod tests {
         );
         let mut response = String::new();
 
-        process_sse_buffer(&mut buffer, &mut response, &|_| {});
+        process_sse_buffer(&mut buffer, &mut response, &mut StreamedToolCalls::default(), &|_| {});
 
         assert_eq!(response, "x");
     }
 
+    #[test]
+    fn test_tool_call_deltas_accumulate() {
+        let mut buffer = String::from(concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Opening the menu\"}}]}\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",",
+            "\"function\":{\"name\":\"click\",\"arguments\":\"\"}}]}}]}\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"x\\\": 10, \"}}]}}]}\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"y\\\": 20}\"}}]}}]}\n",
+            "data: [DONE]\n",
+        ));
+        let mut response = String::new();
+        let mut tool_calls = StreamedToolCalls::default();
+        let chunks = RefCell::new(Vec::new());
+
+        process_sse_buffer(&mut buffer, &mut response, &mut tool_calls, &|c| {
+            chunks.borrow_mut().push(c.to_string());
+        });
+
+        assert_eq!(chunks.into_inner(), vec!["Opening the menu", "[Using tool: click]"]);
+        match tool_calls.into_response(response) {
+            LlmResponse::ToolUse { tool_use, reasoning } => {
+                assert_eq!((tool_use.id.as_str(), tool_use.name.as_str()), ("call_1", "click"));
+                assert_eq!(tool_use.input, serde_json::json!({"x": 10, "y": 20}));
+                assert_eq!(reasoning.as_deref(), Some("Opening the menu"));
+            }
+            other => panic!("expected a tool call, got {:?}", other),
+        }
+
+        assert!(matches!(
+            StreamedToolCalls::default().into_response("plain".to_string()),
+            LlmResponse::Text(text) if text == "plain"
+        ));
+    }
+
+    #[test]
+    fn test_chat_request_serialization() {
+        let request = ChatRequest {
+            model: Some("test-model".to_string()),
+            max_tokens: 1024,
+            messages: vec![ChatMessage::new("system".to_string(), ChatContent::Text("Hello".to_string()))],
+            tools: Vec::new(),
+            stream: true,
+            stream_options: None,
+            temperature: None,
+        };
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["model"], "test-model");
+        assert_eq!(json["max_tokens"], 1024);
+        assert_eq!(json["stream"], true);
+        assert_eq!(json["messages"][0]["role"], "system");
+        assert_eq!(json["messages"][0]["content"], "Hello");
+        assert!(json.get("tools").is_none());
+        assert!(json.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn test_multipart_content_serialization() {
+        let content = ChatContent::Parts(vec![
+            ChatPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: "data:image/png;base64,abc123".to_string(),
+                },
+            },
+            ChatPart::Text {
+                text: "Describe this image".to_string(),
+            },
+        ]);
+
+        let json = serde_json::to_value(&content).unwrap();
+        assert_eq!(json[0]["type"], "image_url");
+        assert_eq!(json[0]["image_url"]["url"], "data:image/png;base64,abc123");
+        assert_eq!(json[1]["type"], "text");
+        assert_eq!(json[1]["text"], "Describe this image");
+    }
+
+    #[test]
+    fn test_tools_rejected() {
+        assert!(tools_rejected(400, r#"{"error": {"message": "tools is not supported for this model"}}"#));
+        assert!(tools_rejected(422, "Unknown field: tools"));
+        assert!(!tools_rejected(400, "Invalid image"));
+        assert!(!tools_rejected(401, "Invalid API key for tool access"));
+        // Mentioning tools isn't enough: these are about the request, not function calling
+        assert!(!tools_rejected(400, "messages with role 'tool' must be a response to a preceding message with 'tool_calls'"));
+        assert!(!tools_rejected(400, "Invalid schema for function 'click'"));
+    }
+
+    #[test]
+    fn test_tool_support_comes_back_after_text_requests() {
+        let tools = ToolSupport::default();
+        assert!(tools.use_for_request());
+
+        tools.rejected();
+        assert!(!tools.available());
+        for _ in 0..TEXT_REQUESTS_AFTER_REJECTION {
+            assert!(!tools.use_for_request());
+        }
+        assert!(tools.available());
+        assert!(tools.use_for_request());
+    }
+
     #[test]
     fn test_append_bytes_valid_utf8() {
         let mut buffer = String::new();
@@ -101,6 +101,8 @@ const providerSettings = {
   openai: document.getElementById('openai-settings'),
   openrouter: document.getElementById('openrouter-settings'),
   glm: document.getElementById('glm-settings'),
+  gemini: document.getElementById('gemini-settings'),
+  'azure-openai': document.getElementById('azure-openai-settings'),
   'openai-compatible': document.getElementById('openai-compatible-settings'),
 };
 
@@ -452,6 +454,14 @@ function updateSettingsUI() {
   if (retryDelayEl) retryDelayEl.value = currentConfig.general.retry_delay_ms ?? 1000;
   const selfCorrectionEl = document.getElementById('enable-self-correction');
   if (selfCorrectionEl) selfCorrectionEl.checked = currentConfig.general.enable_self_correction !== false;
+  const plannerEl = document.getElementById('enable-planner');
+  if (plannerEl) plannerEl.checked = currentConfig.general.enable_planner === true;
+  const verifyEl = document.getElementById('verify-completion');
+  if (verifyEl) verifyEl.checked = currentConfig.general.verify_completion === true;
+  const stuckEl = document.getElementById('stuck-detection');
+  if (stuckEl) stuckEl.checked = currentConfig.general.stuck_detection !== false;
+  const summarizeEl = document.getElementById('summarize-with-llm');
+  if (summarizeEl) summarizeEl.checked = currentConfig.general.summarize_with_llm === true;
   const connectTimeoutEl = document.getElementById('connect-timeout');
   if (connectTimeoutEl) connectTimeoutEl.value = currentConfig.general.connect_timeout_secs ?? 30;
   const responseTimeoutEl = document.getElementById('response-timeout');
@@ -489,6 +499,22 @@ function updateSettingsUI() {
     document.getElementById('glm-model').value = currentConfig.providers.glm.model || '';
   }
 
+  // Set Gemini settings
+  if (currentConfig.providers.gemini) {
+    document.getElementById('gemini-key').value = currentConfig.providers.gemini.api_key || '';
+    document.getElementById('gemini-model').value = currentConfig.providers.gemini.model || '';
+  }
+
+  // Set Azure OpenAI settings
+  if (currentConfig.providers.azure_openai) {
+    const azure = currentConfig.providers.azure_openai;
+    document.getElementById('azure-openai-endpoint').value = azure.endpoint || '';
+    document.getElementById('azure-openai-deployment').value = azure.deployment || '';
+    document.getElementById('azure-openai-api-version').value = azure.api_version || '';
+    document.getElementById('azure-openai-auth').value = azure.api_key || !azure.bearer_token ? 'api-key' : 'bearer';
+    document.getElementById('azure-openai-key').value = azure.api_key || azure.bearer_token || '';
+  }
+
   // Set OpenAI Compatible settings
   if (currentConfig.providers.openai_compatible) {
     document.getElementById('openai-compatible-url').value = currentConfig.providers.openai_compatible.base_url || '';
@@ -822,6 +848,28 @@ function setupEventListeners() {
     } catch (e) { showToast('Save failed: ' + e, 'error'); }
   });
 
+  // Gemini test connection and refresh models
+  document.getElementById('gemini-test-connection')?.addEventListener('click', async function() {
+    try {
+      await saveConfigQuiet();
+      await testConnection('gemini', document.getElementById('gemini-connection-status'), this);
+    } catch (e) { showToast('Save failed: ' + e, 'error'); }
+  });
+  document.getElementById('gemini-refresh-models')?.addEventListener('click', async function() {
+    try {
+      await saveConfigQuiet();
+      await refreshModels('gemini', 'gemini-model-list', 'gemini-model', this);
+    } catch (e) { showToast('Save failed: ' + e, 'error'); }
+  });
+
+  // Azure OpenAI test connection
+  document.getElementById('azure-openai-test-connection')?.addEventListener('click', async function() {
+    try {
+      await saveConfigQuiet();
+      await testConnection('azure-openai', document.getElementById('azure-openai-connection-status'), this);
+    } catch (e) { showToast('Save failed: ' + e, 'error'); }
+  });
+
   // Speed slider
   speedSlider.addEventListener('input', (e) => {
     const value = Math.min(3.0, Math.max(0.25, parseFloat(e.target.value)));
@@ -1152,6 +1200,24 @@ async function setupTauriListeners() {
     showToast(`Token budget reached (${event.payload.total_tokens.toLocaleString()}). Consider stopping the agent.`, 'warning');
   }));
 
+  // Dollar limit reached
+  tauriUnlisteners.push(await listen('cost-budget-exceeded', (event) => {
+    const { kind, spent_usd, limit_usd } = event.payload;
+    const scope = kind === 'daily_cost' ? 'Daily' : 'Task';
+    showToast(`${scope} cost limit reached (${formatCost(spent_usd)} of ${formatCost(limit_usd)}). The agent was stopped.`, 'warning');
+  }));
+
+  tauriUnlisteners.push(await listen('stuck-detected', (event) => {
+    const { message, recovery } = event.payload;
+    const actions = {
+      hint: 'Nudging it to try something else.',
+      switch_model: 'Handing the task to the fallback model.',
+      ask_user: 'Continuing as you asked.',
+      abort: 'The task was stopped.',
+    };
+    showToast(`Agent seems stuck: ${message}. ${actions[recovery] || ''}`, recovery === 'abort' ? 'warning' : 'info');
+  }));
+
   // Session summary
   tauriUnlisteners.push(await listen('session-summary', (event) => {
     const s = event.payload;
@@ -1287,19 +1353,14 @@ async function executeRecordedActions() {
     return;
   }
 
-  showToast('Re-running instruction...', 'info');
+  showToast('Replaying recorded actions...', 'info');
 
-  // Start normal agent with the same instruction
-  const state = await invoke('get_agent_state');
-  if (state.instruction) {
-    try {
-      await invoke('clear_recorded_actions');
-      await invoke('start_agent', { instruction: state.instruction });
-      recordingPanel.classList.add('hidden');
-    } catch (error) {
-      console.error('Failed to execute:', error);
-      showToast('Failed to execute', 'error');
-    }
+  try {
+    await invoke('replay_recording');
+    recordingPanel.classList.add('hidden');
+  } catch (error) {
+    console.error('Failed to replay:', error);
+    showToast('Failed to replay recording', 'error');
   }
 }
 
@@ -1315,10 +1376,9 @@ function updateRecordedActionsDisplay() {
   recordedActionsList.innerHTML = recordedActions.map((action, index) => {
     let actionDesc = 'Unknown action';
     try {
-      const parsed = JSON.parse(action.action);
-      actionDesc = formatAction(parsed);
+      actionDesc = formatAction(action.action);
     } catch {
-      actionDesc = action.action;
+      actionDesc = JSON.stringify(action.action);
     }
 
     const reasoning = action.reasoning
@@ -1709,8 +1769,13 @@ function updateAgentState(state) {
       model = currentConfig.providers[provider].model || '';
     } else if (provider === 'openai-compatible' && currentConfig.providers?.openai_compatible) {
       model = currentConfig.providers.openai_compatible.model || '';
+    } else if (provider === 'azure-openai' && currentConfig.providers?.azure_openai) {
+      model = currentConfig.providers.azure_openai.deployment || '';
     }
-    const cost = estimateCost(provider, model, state.total_input_tokens, state.total_output_tokens);
+    // The backend tracks cost when it knows the model's price
+    const cost = state.total_cost_usd > 0
+      ? formatCost(state.total_cost_usd)
+      : estimateCost(provider, model, state.total_input_tokens, state.total_output_tokens);
     costValue.textContent = cost;
   }
 
@@ -1825,6 +1890,8 @@ async function saveConfigQuiet() {
 
   const config = {
     general: {
+      // Keep settings that have no control here, such as cost limits
+      ...currentConfig?.general,
       default_provider: providerSelect.value,
       max_iterations: maxIterations,
       confirm_dangerous_actions: confirmDangerous.checked,
@@ -1838,6 +1905,10 @@ async function saveConfigQuiet() {
       max_retries: parseInt(document.getElementById('max-retries')?.value, 10) || 3,
       retry_delay_ms: parseInt(document.getElementById('retry-delay-ms')?.value, 10) || 1000,
       enable_self_correction: document.getElementById('enable-self-correction')?.checked !== false,
+      enable_planner: document.getElementById('enable-planner')?.checked === true,
+      verify_completion: document.getElementById('verify-completion')?.checked === true,
+      stuck_detection: document.getElementById('stuck-detection')?.checked !== false,
+      summarize_with_llm: document.getElementById('summarize-with-llm')?.checked === true,
       connect_timeout_secs: parseInt(document.getElementById('connect-timeout')?.value, 10) || 30,
       response_timeout_secs: parseInt(document.getElementById('response-timeout')?.value, 10) || 300,
       max_tokens_per_task: parseInt(document.getElementById('max-tokens-per-task')?.value, 10) || null,
@@ -1869,6 +1940,20 @@ async function saveConfigQuiet() {
         model: document.getElementById('glm-model').value || 'glm-4v',
         temperature: document.getElementById('temperature-slider') ? parseFloat(document.getElementById('temperature-slider').value) : null,
       } : null,
+      gemini: document.getElementById('gemini-key').value ? {
+        api_key: document.getElementById('gemini-key').value,
+        model: document.getElementById('gemini-model').value || 'gemini-2.5-flash',
+        temperature: document.getElementById('temperature-slider') ? parseFloat(document.getElementById('temperature-slider').value) : null,
+        base_url: currentConfig?.providers?.gemini?.base_url || null,
+      } : null,
+      azure_openai: document.getElementById('azure-openai-endpoint').value && document.getElementById('azure-openai-deployment').value ? {
+        endpoint: document.getElementById('azure-openai-endpoint').value,
+        deployment: document.getElementById('azure-openai-deployment').value,
+        api_version: document.getElementById('azure-openai-api-version').value || '2024-10-21',
+        api_key: document.getElementById('azure-openai-auth').value === 'api-key' ? document.getElementById('azure-openai-key').value || null : null,
+        bearer_token: document.getElementById('azure-openai-auth').value === 'bearer' ? document.getElementById('azure-openai-key').value || null : null,
+        temperature: document.getElementById('temperature-slider') ? parseFloat(document.getElementById('temperature-slider').value) : null,
+      } : null,
       openai_compatible: document.getElementById('openai-compatible-url').value ? {
         base_url: document.getElementById('openai-compatible-url').value,
         api_key: document.getElementById('openai-compatible-key').value || null,
@@ -2138,9 +2223,13 @@ function estimateCost(provider, model, inputTokens, outputTokens) {
   const pricing = MODEL_PRICING[model];
   if (!pricing) return '~$?';
   const cost = (inputTokens / 1_000_000) * pricing.input + (outputTokens / 1_000_000) * pricing.output;
-  if (cost < 0.01) return `~$${cost.toFixed(4)}`;
-  if (cost < 1.0) return `~$${cost.toFixed(3)}`;
-  return `~$${cost.toFixed(2)}`;
+  return `~${formatCost(cost)}`;
+}
+
+function formatCost(cost) {
+  if (cost < 0.01) return `$${cost.toFixed(4)}`;
+  if (cost < 1.0) return `$${cost.toFixed(3)}`;
+  return `$${cost.toFixed(2)}`;
 }
 
 // Update error log panel UI
@@ -2551,7 +2640,7 @@ function debouncedRenderQueue() {
 
 // Render queue UI
 function renderQueue() {
-  const pendingItems = queueItems.filter(i => i.status === 'Pending');
+  const pendingItems = queueItems.filter(i => i.status === 'Pending' || i.status === 'Interrupted');
   const completedItems = queueItems.filter(i => i.status === 'Completed');
   const runningItem = queueItems.find(i => i.status === 'Running');
 